@@ -0,0 +1,236 @@
+use crate::bookmarks::Bookmark;
+use crate::collab::Edit;
+use crate::settings::{SETTINGS_PATH, Settings};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// The `[audit]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditSettings {
+    /// Append every bookmark and annotation change to the log.
+    #[serde(default)]
+    pub enabled: bool,
+    /// JSON Lines file the log is appended to.
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// Name recorded with local changes, none if empty.
+    #[serde(default)]
+    pub author: String,
+}
+
+fn default_path() -> String {
+    "audit.jsonl".to_string()
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_path(),
+            author: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Create,
+    Edit,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Object {
+    Bookmark,
+    Marker,
+    RangeRings,
+    Route,
+}
+
+/// One line of the log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since 1970.
+    pub time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub operation: Operation,
+    pub object: Object,
+    /// Bookmark name.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    /// (lat, lon) of the object, both ends for a route.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub points: Vec<[f64; 2]>,
+    /// What changed, for edits, or where a change came from.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+impl AuditEntry {
+    pub fn new(operation: Operation, object: Object) -> Self {
+        Self {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            author: None,
+            operation,
+            object,
+            name: String::new(),
+            points: Vec::new(),
+            detail: String::new(),
+        }
+    }
+
+    pub fn bookmark(operation: Operation, bookmark: &Bookmark) -> Self {
+        Self {
+            name: bookmark.name.clone(),
+            points: vec![[bookmark.lat, bookmark.lon]],
+            ..Self::new(operation, Object::Bookmark)
+        }
+    }
+
+    /// The entry for an annotation added or cleared on the map.
+    pub fn annotation(edit: &Edit) -> Self {
+        match *edit {
+            Edit::Marker { lat, lon } => Self {
+                points: vec![[lat, lon]],
+                ..Self::new(Operation::Create, Object::Marker)
+            },
+            Edit::RangeRings { lat, lon } => Self {
+                points: vec![[lat, lon]],
+                ..Self::new(Operation::Create, Object::RangeRings)
+            },
+            Edit::ClearRangeRings => Self {
+                detail: "all".to_string(),
+                ..Self::new(Operation::Delete, Object::RangeRings)
+            },
+            Edit::Route { from, to } => Self {
+                points: vec![from, to],
+                ..Self::new(Operation::Create, Object::Route)
+            },
+        }
+    }
+
+    pub fn detail(self, detail: impl Into<String>) -> Self {
+        Self {
+            detail: detail.into(),
+            ..self
+        }
+    }
+
+    pub fn by(self, author: impl Into<String>) -> Self {
+        Self {
+            author: Some(author.into()),
+            ..self
+        }
+    }
+}
+
+/// Append-only record of who changed which bookmark or annotation when,
+/// for teams sharing a map. Entries are only ever added to the end of the
+/// file, one JSON object a line.
+pub struct AuditLog {
+    /// None while the log is off or its file couldn't be opened.
+    file: Option<File>,
+    author: Option<String>,
+}
+
+impl AuditLog {
+    pub fn open(settings: &AuditSettings) -> Self {
+        let file = settings.enabled.then(|| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&settings.path)
+                .map_err(|e| warn!("Failed to open {}: {}", settings.path, e))
+                .ok()
+        });
+        let author = settings.author.trim();
+        Self {
+            file: file.flatten(),
+            author: (!author.is_empty()).then(|| author.to_string()),
+        }
+    }
+
+    /// Appends `entry`, credited to the configured author unless it names one.
+    pub fn record(&mut self, mut entry: AuditEntry) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        if entry.author.is_none() {
+            entry.author = self.author.clone();
+        }
+        let written = serde_json::to_string(&entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(file, "{}", line).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            warn!("Failed to write the audit log: {}", e);
+        }
+    }
+}
+
+/// Reads every entry of the log at `path`, skipping lines that don't parse.
+pub fn read_log(path: &Path) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping line {} of {}: {}", i + 1, path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/// `RustOpenGLMap audit [--since <unix time>] [--author <name>] [--out <file>]`
+///
+/// Exports the audit log as one JSON array.
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Only changes at or after this many seconds since 1970.
+    #[arg(long)]
+    since: Option<u64>,
+    /// Only changes by this author.
+    #[arg(long)]
+    author: Option<String>,
+    /// File to write, standard output if not given.
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+pub fn run_audit_command(args: AuditArgs) -> Result<(), String> {
+    let settings = Settings::load_or_default(SETTINGS_PATH);
+    let path = Path::new(&settings.audit.path);
+    let entries: Vec<AuditEntry> = read_log(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .into_iter()
+        .filter(|entry| args.since.is_none_or(|since| entry.time >= since))
+        .filter(|entry| {
+            args.author
+                .as_ref()
+                .is_none_or(|author| entry.author.as_ref() == Some(author))
+        })
+        .collect();
+    let json = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+    match &args.out {
+        Some(out) => {
+            std::fs::write(out, json)
+                .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+            eprintln!("Exported {} changes to {}", entries.len(), out.display());
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use crate::geo;
+use crate::tile::TilePos;
+use image::{Rgba, RgbaImage};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+/// `TilePos::m` of basemap tiles. Sources in sources.toml can't use it.
+pub const SOURCE_ID: u8 = 255;
+/// Deepest zoom the basemap is drawn at; closer views stretch its tiles.
+pub const MAX_ZOOM: u8 = 4;
+/// Samples per pixel row, so coastlines come out smooth.
+const SUBROWS: u32 = 4;
+
+/// Coastline rings compiled into the binary, in normalised Web Mercator.
+static LAND: Lazy<Vec<Vec<(f64, f64)>>> = Lazy::new(|| {
+    parse_rings(include_str!("../basemap.txt")).expect("bundled basemap.txt is invalid")
+});
+
+static SETTINGS: Lazy<RwLock<BasemapSettings>> =
+    Lazy::new(|| RwLock::new(BasemapSettings::default()));
+
+/// The `[basemap]` table of settings.toml: a coarse world map drawn from
+/// data inside the binary wherever the base layer has nothing cached yet,
+/// so a first run without network still shows where things are.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BasemapSettings {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_land")]
+    pub land: [u8; 3],
+    #[serde(default = "default_water")]
+    pub water: [u8; 3],
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_land() -> [u8; 3] {
+    [236, 232, 222]
+}
+
+fn default_water() -> [u8; 3] {
+    [178, 208, 232]
+}
+
+impl Default for BasemapSettings {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            land: default_land(),
+            water: default_water(),
+        }
+    }
+}
+
+/// Uses `settings` for the basemap tiles drawn from now on.
+pub fn configure(settings: BasemapSettings) {
+    *SETTINGS.write().unwrap() = settings;
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.read().unwrap().enabled
+}
+
+/// The basemap tile covering `tile` and the part of it that does, as
+/// (u, v, size) in texture coordinates with flipped rows.
+pub fn covering(tile: &TilePos) -> (TilePos, (f32, f32, f32)) {
+    let levels = tile.z.saturating_sub(MAX_ZOOM);
+    let base = TilePos {
+        z: tile.z - levels,
+        x: tile.x >> levels,
+        y: tile.y >> levels,
+        m: SOURCE_ID,
+    };
+    let size = 1.0 / (1u32 << levels) as f32;
+    let u = (tile.x - (base.x << levels)) as f32 * size;
+    let v = (tile.y - (base.y << levels)) as f32 * size;
+    // texture rows are flipped, v = 1 is the top of the image
+    (base, (u, 1.0 - v - size, size))
+}
+
+/// Draws basemap tile `tile`, 256 pixels square and north up.
+pub fn render(tile: &TilePos) -> RgbaImage {
+    let settings = *SETTINGS.read().unwrap();
+    let n = geo::world_tiles(tile.z);
+    let mut image = RgbaImage::new(256, 256);
+    let mut coverage = [0.0f32; 256];
+    let mut crossings = Vec::new();
+    for row in 0..256u32 {
+        coverage.fill(0.0);
+        for sub in 0..SUBROWS {
+            let y = (f64::from(tile.y)
+                + (f64::from(row) + (f64::from(sub) + 0.5) / f64::from(SUBROWS)) / 256.0)
+                / n;
+            crossings.clear();
+            for ring in LAND.iter() {
+                for (i, a) in ring.iter().enumerate() {
+                    let b = ring[(i + 1) % ring.len()];
+                    if (a.1 > y) != (b.1 > y) {
+                        let x = a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1);
+                        crossings.push((x * n - f64::from(tile.x)) * 256.0);
+                    }
+                }
+            }
+            crossings.sort_by(f64::total_cmp);
+            // even-odd: land between every other pair of crossings
+            for span in crossings.chunks_exact(2) {
+                add_span(&mut coverage, span[0], span[1], 1.0 / SUBROWS as f32);
+            }
+        }
+        for (column, covered) in coverage.iter().enumerate() {
+            let t = covered.min(1.0);
+            let mix = |i: usize| {
+                let (water, land) = (f32::from(settings.water[i]), f32::from(settings.land[i]));
+                (water + (land - water) * t).round() as u8
+            };
+            image.put_pixel(column as u32, row, Rgba([mix(0), mix(1), mix(2), 255]));
+        }
+    }
+    image
+}
+
+/// Adds `weight` times how much of each pixel lies between `left` and `right`.
+fn add_span(coverage: &mut [f32; 256], left: f64, right: f64, weight: f32) {
+    let (left, right) = (left.max(0.0), right.min(256.0));
+    if left >= right {
+        return;
+    }
+    let pixels = coverage
+        .iter_mut()
+        .enumerate()
+        .take(right.ceil() as usize)
+        .skip(left.floor() as usize);
+    for (column, cell) in pixels {
+        let inside = right.min(column as f64 + 1.0) - left.max(column as f64);
+        *cell += inside as f32 * weight;
+    }
+}
+
+/// Reads rings of `lon,lat` pairs, one per line, and projects them.
+fn parse_rings(text: &str) -> Result<Vec<Vec<(f64, f64)>>, String> {
+    let mut rings = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut ring = Vec::new();
+        for pair in line.split_whitespace() {
+            let (lon, lat) = pair
+                .split_once(',')
+                .and_then(|(lon, lat)| Some((lon.parse().ok()?, lat.parse().ok()?)))
+                .ok_or_else(|| format!("not a lon,lat pair: {}", pair))?;
+            ring.push(geo::project(lat, lon));
+        }
+        rings.push(ring);
+    }
+    Ok(rings)
+}
@@ -0,0 +1,122 @@
+use crate::geocoder::{self, GEOCODE_CACHE_PATH, GeocodeCache, Throttle};
+use crate::settings::{SETTINGS_PATH, Settings};
+use clap::Args;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Address column used when `--column` isn't given and the file has one.
+const DEFAULT_COLUMN: &str = "address";
+
+/// Geocodes the `column` of every row of `input` and writes the rows with
+/// `lat` and `lon` appended to `output`. Returns (found, total).
+pub fn geocode_csv(
+    input: &Path,
+    output: &Path,
+    column: Option<&str>,
+    url: &str,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(input)?;
+    let headers = reader.headers()?.clone();
+    let index = match column {
+        Some(name) => headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("{} has no column {}", input.display(), name))?,
+        None => headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(DEFAULT_COLUMN))
+            .unwrap_or(0),
+    };
+    let mut writer = csv::Writer::from_path(output)?;
+    let mut out_headers = headers.clone();
+    out_headers.push_field("lat");
+    out_headers.push_field("lon");
+    writer.write_record(&out_headers)?;
+
+    let mut cache = GeocodeCache::load_or_default(GEOCODE_CACHE_PATH);
+    let mut throttle = Throttle::default();
+    let (mut found, mut total) = (0, 0);
+    for record in reader.records() {
+        let mut record = record?;
+        let query = record.get(index).unwrap_or("").to_string();
+        total += 1;
+        let point = match cache.get(&query) {
+            Some(point) => point,
+            None if query.trim().is_empty() => None,
+            None => {
+                throttle.wait();
+                match geocoder::search(url, &query) {
+                    Ok(places) => {
+                        let point = places.first().map(|place| (place.lat, place.lon));
+                        cache.insert(&query, point);
+                        cache.save(GEOCODE_CACHE_PATH)?;
+                        point
+                    }
+                    Err(e) => {
+                        // left out of the cache so a later run tries again
+                        eprintln!("{}: {}", query, e);
+                        None
+                    }
+                }
+            }
+        };
+        match point {
+            Some((lat, lon)) => {
+                found += 1;
+                println!("{:>5}  {:.6}, {:.6}  {}", total, lat, lon, query);
+                record.push_field(&format!("{:.7}", lat));
+                record.push_field(&format!("{:.7}", lon));
+            }
+            None => {
+                println!("{:>5}  no match              {}", total, query);
+                record.push_field("");
+                record.push_field("");
+            }
+        }
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok((found, total))
+}
+
+/// `addresses.csv` → `addresses.geocoded.csv`
+fn default_output(input: &Path) -> PathBuf {
+    let stem = input
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("points");
+    input.with_file_name(format!("{}.geocoded.csv", stem))
+}
+
+/// `RustOpenGLMap geocode <addresses.csv> [--column <name>] [--out <file.csv>]`
+#[derive(Debug, Args)]
+pub struct GeocodeArgs {
+    /// CSV file with an address column.
+    input: PathBuf,
+    /// Column holding the addresses, `address` or the first one by default.
+    #[arg(long)]
+    column: Option<String>,
+    /// Where to write the geocoded rows, next to the input by default.
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+pub fn run_geocode_command(args: GeocodeArgs) -> Result<(), String> {
+    let output = args.out.unwrap_or_else(|| default_output(&args.input));
+    let settings = Settings::load_or_default(SETTINGS_PATH);
+    let (found, total) = geocode_csv(
+        &args.input,
+        &output,
+        args.column.as_deref(),
+        &settings.geocoder.url,
+    )
+    .map_err(|e| e.to_string())?;
+    println!(
+        "Geocoded {} of {} rows into {}, open it with RustOpenGLMap {}",
+        found,
+        total,
+        output.display(),
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::viewport::Viewport;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+pub const BOOKMARKS_PATH: &str = "bookmarks.toml";
+
+/// A named view: position, zoom and the source it was saved with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: u8,
+    #[serde(default)]
+    pub source: u8,
+    /// Tiles around it are never evicted from the disk cache.
+    #[serde(default)]
+    pub protected: bool,
+}
+
+impl Bookmark {
+    /// Bookmarks what `viewport` shows of `source`. A blank name becomes the coordinates.
+    pub fn capture(name: &str, viewport: &Viewport, source: u8) -> Self {
+        let (lat, lon) = viewport.center_latlon();
+        let name = match name.trim() {
+            "" => format!("{:.5}, {:.5} z{}", lat, lon, viewport.z),
+            name => name.to_string(),
+        };
+        Self {
+            name,
+            lat,
+            lon,
+            zoom: viewport.z,
+            source,
+            protected: false,
+        }
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        Viewport::centered_on(self.lat, self.lon, self.zoom.min(19))
+    }
+}
+
+/// Saved places, kept in `bookmarks.toml` in the order they were added.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Bookmarks {
+    #[serde(default, rename = "bookmark")]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// Reads `path` if it exists, otherwise starts empty.
+    pub fn load_or_default(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::default();
+        }
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                warn!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.bookmarks.len()
+    }
+
+    /// Appends those of `others` not already saved; returns how many that was.
+    pub fn merge(&mut self, others: &[Bookmark]) -> usize {
+        let before = self.bookmarks.len();
+        for bookmark in others {
+            if !self.bookmarks.contains(bookmark) {
+                self.bookmarks.push(bookmark.clone());
+            }
+        }
+        self.bookmarks.len() - before
+    }
+
+    /// Indexes of the bookmarks whose name contains `filter`, ignoring case.
+    pub fn matching(&self, filter: &str) -> Vec<usize> {
+        matching(&self.bookmarks, filter)
+    }
+}
+
+/// Indexes into `bookmarks` of those whose name contains `filter`, ignoring case.
+pub fn matching(bookmarks: &[Bookmark], filter: &str) -> Vec<usize> {
+    let filter = filter.trim().to_lowercase();
+    (0..bookmarks.len())
+        .filter(|&i| bookmarks[i].name.to_lowercase().contains(&filter))
+        .collect()
+}
+
+/// The bookmark or recent places list: a filter typed to narrow it and the
+/// highlighted row among the matches.
+#[derive(Debug, Clone, Default)]
+pub struct BookmarkPicker {
+    pub filter: String,
+    pub selected: usize,
+}
+
+impl BookmarkPicker {
+    pub fn type_text(&mut self, text: &str) {
+        self.filter.push_str(text);
+        self.selected = 0;
+    }
+
+    pub fn backspace(&mut self) {
+        self.filter.pop();
+        self.selected = 0;
+    }
+
+    /// Moves the highlight `steps` rows down (negative: up) among `count` matches.
+    pub fn move_selection(&mut self, steps: i32, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected as i32 + steps).clamp(0, count as i32 - 1) as usize;
+        }
+    }
+
+    /// Index into `bookmarks` of the highlighted row.
+    pub fn chosen(&self, bookmarks: &[Bookmark]) -> Option<usize> {
+        matching(bookmarks, &self.filter)
+            .get(self.selected)
+            .copied()
+    }
+}
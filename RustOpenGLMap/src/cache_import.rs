@@ -0,0 +1,221 @@
+use crate::disk_cache::{DISK_CACHE, tile_dir};
+use crate::tile::TilePos;
+use crate::tile_source::SOURCES;
+use clap::{Args, ValueEnum};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Picked tiles are exported below this directory, one folder per source.
+pub const EXPORT_DIR: &str = "Export";
+
+/// Directory layouts used by other tile tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CacheLayout {
+    /// `z/x/y.png`, as written by Leaflet/OpenLayers offline plugins.
+    Xyz,
+    /// `z/x/y.png` with TMS (bottom-up) row numbers.
+    Tms,
+    /// TileCache / MapProxy `tc`: `zz/xxx/xxx/xxx/yyy/yyy/yyy.png`, TMS rows.
+    #[value(name = "tc", alias = "tilecache")]
+    TileCache,
+    /// MapProxy `mp`: `zz/xxxx/xxxx/yyyy/yyyy.png`, TMS rows.
+    #[value(name = "mp", alias = "mapproxy")]
+    MapProxy,
+}
+
+impl CacheLayout {
+    /// Path components below the cache root, file name included.
+    fn depth(self) -> usize {
+        match self {
+            Self::Xyz | Self::Tms => 3,
+            Self::TileCache => 7,
+            Self::MapProxy => 5,
+        }
+    }
+
+    fn flipped_y(self) -> bool {
+        self != Self::Xyz
+    }
+
+    /// Reads (z, x, y) in XYZ order from the components of a relative path.
+    fn parse(self, parts: &[&str]) -> Option<(u8, u32, u32)> {
+        if parts.len() != self.depth() {
+            return None;
+        }
+        let z: u8 = parts[0].parse().ok()?;
+        let digits = |range: &[&str]| range.concat().parse::<u32>().ok();
+        let (x, y) = match self {
+            Self::Xyz | Self::Tms => (parts[1].parse().ok()?, parts[2].parse().ok()?),
+            Self::TileCache => (digits(&parts[1..4])?, digits(&parts[4..7])?),
+            Self::MapProxy => (digits(&parts[1..3])?, digits(&parts[3..5])?),
+        };
+        if z > 30 {
+            return None;
+        }
+        let max = (1u64 << z) as u32;
+        if x >= max || y >= max {
+            return None;
+        }
+        let y = if self.flipped_y() { max - 1 - y } else { y };
+        Some((z, x, y))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub imported: usize,
+    pub skipped_existing: usize,
+    pub failed: usize,
+}
+
+/// Guesses the layout from how deep the first tile image sits below `root`.
+pub fn detect_layout(root: &Path) -> Option<CacheLayout> {
+    let first = tile_files(root).into_iter().next()?;
+    match first.strip_prefix(root).ok()?.components().count() {
+        3 => Some(CacheLayout::Xyz),
+        5 => Some(CacheLayout::MapProxy),
+        7 => Some(CacheLayout::TileCache),
+        _ => None,
+    }
+}
+
+/// Copies every tile under `root` into the tile directory as tiles of source `m`.
+/// Tiles already in the cache are left alone; non-PNG images are re-encoded.
+pub fn import_cache(
+    root: &Path,
+    layout: CacheLayout,
+    m: u8,
+) -> Result<ImportStats, Box<dyn Error>> {
+    if !SOURCES.read().unwrap().contains(m) {
+        return Err(Box::from(format!("Unknown tile source {}", m)));
+    }
+    fs::create_dir_all(tile_dir())?;
+
+    let mut stats = ImportStats::default();
+    for file in tile_files(root) {
+        let Ok(relative) = file.strip_prefix(root) else {
+            continue;
+        };
+        let parts: Vec<String> = relative
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+        let Some((z, x, y)) = layout.parse(&parts) else {
+            continue;
+        };
+
+        let target = SOURCES.read().unwrap().file_path(&TilePos { z, x, y, m });
+        if target.exists() {
+            stats.skipped_existing += 1;
+            continue;
+        }
+        match copy_tile(&file, &target) {
+            Ok(()) => {
+                DISK_CACHE.lock().unwrap().record(&target);
+                stats.imported += 1;
+            }
+            Err(e) => {
+                warn!("Failed to import {}: {}", file.display(), e);
+                stats.failed += 1;
+            }
+        }
+        if (stats.imported + stats.failed) % 1000 == 0 {
+            println!("Imported {} tiles...", stats.imported);
+        }
+    }
+    Ok(stats)
+}
+
+/// Copies the cached `tiles` into `root` in the `xyz` layout, so another
+/// tool or `import` can read them. Returns how many were copied; tiles that
+/// aren't cached are skipped.
+pub fn export_tiles(tiles: &[TilePos], root: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut exported = 0;
+    for tile in tiles {
+        let source = SOURCES.read().unwrap().file_path(tile);
+        if !source.exists() {
+            continue;
+        }
+        let dir = root.join(tile.z.to_string()).join(tile.x.to_string());
+        fs::create_dir_all(&dir)?;
+        fs::copy(&source, dir.join(format!("{}.png", tile.y)))?;
+        exported += 1;
+    }
+    Ok(exported)
+}
+
+fn copy_tile(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    let bytes = fs::read(from)?;
+    if bytes.starts_with(b"\x89PNG") {
+        fs::write(to, bytes)?;
+    } else {
+        image::load_from_memory(&bytes)?.to_rgba8().save(to)?;
+    }
+    Ok(())
+}
+
+/// All image files below `root`, sorted so imports are deterministic.
+fn tile_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("png" | "jpg" | "jpeg" | "webp")
+            ) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// `RustOpenGLMap import <dir> [--layout xyz|tms|tc|mp] [--source <id>]`
+/// `RustOpenGLMap import <dir> [--layout <layout>] [--source <id>]`
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// Root of the tile cache to copy in.
+    dir: PathBuf,
+    /// How the cache is laid out, detected from its files when left out.
+    #[arg(long)]
+    layout: Option<CacheLayout>,
+    /// Source id the tiles are stored as.
+    #[arg(long, default_value_t = 0)]
+    source: u8,
+}
+
+pub fn run_import_command(args: ImportArgs) -> Result<(), String> {
+    let ImportArgs {
+        dir,
+        layout,
+        source,
+    } = args;
+    let layout = layout
+        .or_else(|| detect_layout(&dir))
+        .ok_or_else(|| format!("Couldn't detect the cache layout of {}", dir.display()))?;
+    println!(
+        "Importing {} as {:?} into source {}",
+        dir.display(),
+        layout,
+        source
+    );
+
+    let stats = import_cache(&dir, layout, source).map_err(|e| e.to_string())?;
+    println!(
+        "Imported {} tiles, {} already cached, {} failed",
+        stats.imported, stats.skipped_existing, stats.failed
+    );
+    Ok(())
+}
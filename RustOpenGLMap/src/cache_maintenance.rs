@@ -0,0 +1,281 @@
+use crate::bookmarks::Bookmark;
+use crate::disk_cache::{self, CacheSettings, DISK_CACHE, tile_dir};
+use crate::opengl_helper::{self, get_file_path};
+use crate::settings::HomeView;
+use crate::tile::TilePos;
+use crate::tile_source::SOURCES;
+use crate::{crash_report, geo, rate_limit, retry};
+use image::ImageReader;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Time without input after which the app counts as idle.
+pub const IDLE_AFTER: Duration = Duration::from_secs(20);
+/// Pause between two maintenance steps, so the disk and the tile servers
+/// only ever see a trickle.
+const STEP_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the idle flag is checked while the user is busy.
+const BUSY_POLL: Duration = Duration::from_millis(500);
+/// Files looked up in the index per step.
+const INDEX_BATCH: usize = 64;
+/// Least time between two scans of the tile directory for unindexed files.
+const RESCAN_EVERY: Duration = Duration::from_secs(15 * 60);
+/// Tiles around a bookmark fetched at its zoom, in each direction.
+const PREFETCH_RADIUS: i64 = 1;
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Validate,
+    Index,
+    Trim,
+    Prefetch,
+}
+
+const STEPS: [Step; 4] = [Step::Validate, Step::Index, Step::Trim, Step::Prefetch];
+
+/// Tidies the disk cache in the background while nobody is using the map:
+/// checks random tiles for damage, indexes files that were written behind
+/// the index's back, trims the cache to its limit and downloads the areas
+/// around bookmarks. Stops between steps as soon as there is input again.
+pub struct CacheMaintenance {
+    idle: Arc<AtomicBool>,
+    prefetch_tx: Sender<Vec<TilePos>>,
+}
+
+impl CacheMaintenance {
+    pub fn spawn() -> Self {
+        let idle = Arc::new(AtomicBool::new(false));
+        let (prefetch_tx, prefetch_rx) = channel();
+        let flag = idle.clone();
+        thread::spawn(move || Worker::new(prefetch_rx).run(&flag));
+        Self { idle, prefetch_tx }
+    }
+
+    /// Lets the maintenance run, or holds it from the next step on.
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::Relaxed);
+    }
+
+    /// Replaces the areas to keep cached with the ones around `bookmarks`.
+    pub fn prefetch(&self, bookmarks: &[Bookmark]) {
+        let _ = self.prefetch_tx.send(bookmark_tiles(bookmarks));
+    }
+}
+
+/// The tiles around each bookmark at its zoom, in its source.
+fn bookmark_tiles(bookmarks: &[Bookmark]) -> Vec<TilePos> {
+    let mut tiles = Vec::new();
+    for bookmark in bookmarks {
+        let (x, y) = geo::project(bookmark.lat, bookmark.lon);
+        let n = geo::world_tiles(bookmark.zoom);
+        let (cx, cy) = ((x * n) as i64, (y * n) as i64);
+        for dy in -PREFETCH_RADIUS..=PREFETCH_RADIUS {
+            for dx in -PREFETCH_RADIUS..=PREFETCH_RADIUS {
+                let (tx, ty) = (cx + dx, cy + dy);
+                if (0..n as i64).contains(&ty) {
+                    tiles.push(TilePos {
+                        z: bookmark.zoom,
+                        x: tx.rem_euclid(n as i64) as u32,
+                        y: ty as u32,
+                        m: bookmark.source,
+                    });
+                }
+            }
+        }
+    }
+    tiles
+}
+
+/// Files of the tiles within `settings.protect_radius_km` of each protected
+/// bookmark, and of home if `settings.protect_home`, from zoom 0 down to
+/// `settings.protect_max_zoom`, in the source each was saved with.
+pub fn protected_tiles(
+    bookmarks: &[Bookmark],
+    home: Option<HomeView>,
+    settings: &CacheSettings,
+) -> HashSet<PathBuf> {
+    let home = home
+        .filter(|_| settings.protect_home)
+        .map(|home| (home.lat, home.lon, home.source));
+    let places = bookmarks
+        .iter()
+        .filter(|bookmark| bookmark.protected)
+        .map(|bookmark| (bookmark.lat, bookmark.lon, bookmark.source))
+        .chain(home);
+    let mut paths = HashSet::new();
+    for (lat, lon, m) in places {
+        let dlat = (settings.protect_radius_km * 1000.0 / geo::EARTH_RADIUS_M).to_degrees();
+        let dlon = dlat / lat.to_radians().cos().max(0.01);
+        let (x0, y0) = geo::project(lat + dlat, lon - dlon);
+        let (x1, y1) = geo::project(lat - dlat, lon + dlon);
+        for z in 0..=settings.protect_max_zoom.min(19) {
+            let n = geo::world_tiles(z);
+            let last = n as i64 - 1;
+            let (ty0, ty1) = ((y0 * n) as i64, ((y1 * n) as i64).min(last));
+            let (tx0, tx1) = ((x0 * n).floor() as i64, (x1 * n).floor() as i64);
+            for ty in ty0.max(0)..=ty1 {
+                for tx in tx0..=tx1.min(tx0 + last) {
+                    paths.insert(get_file_path(TilePos {
+                        z,
+                        x: tx.rem_euclid(n as i64) as u32,
+                        y: ty as u32,
+                        m,
+                    }));
+                }
+            }
+        }
+    }
+    paths
+}
+
+struct Worker {
+    prefetch_rx: Receiver<Vec<TilePos>>,
+    prefetch: VecDeque<TilePos>,
+    /// Files found on the last scan that haven't been looked up yet.
+    unindexed: Vec<PathBuf>,
+    scanned: Option<Instant>,
+    next_step: usize,
+}
+
+impl Worker {
+    fn new(prefetch_rx: Receiver<Vec<TilePos>>) -> Self {
+        Self {
+            prefetch_rx,
+            prefetch: VecDeque::new(),
+            unindexed: Vec::new(),
+            scanned: None,
+            next_step: 0,
+        }
+    }
+
+    fn run(mut self, idle: &AtomicBool) {
+        loop {
+            match self.prefetch_rx.try_recv() {
+                Ok(tiles) => self.prefetch = tiles.into(),
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
+            }
+            // tiles the user is waiting for go first
+            if !idle.load(Ordering::Relaxed) || opengl_helper::pending_downloads() > 0 {
+                thread::sleep(BUSY_POLL);
+                continue;
+            }
+            let step = STEPS[self.next_step % STEPS.len()];
+            self.next_step += 1;
+            match step {
+                Step::Validate => validate_random_tile(),
+                Step::Index => self.index_batch(),
+                Step::Trim => {
+                    let removed = DISK_CACHE.lock().unwrap().trim();
+                    if removed > 0 {
+                        info!(
+                            "Evicted {} tiles to stay under the disk cache limit",
+                            removed
+                        );
+                    }
+                }
+                Step::Prefetch => self.prefetch_one(),
+            }
+            thread::sleep(STEP_INTERVAL);
+        }
+    }
+
+    /// Adds the next few files on disk the index doesn't know about,
+    /// scanning the tile directory again once in a while.
+    fn index_batch(&mut self) {
+        if self.unindexed.is_empty() {
+            if self.scanned.is_some_and(|at| at.elapsed() < RESCAN_EVERY) {
+                return;
+            }
+            self.scanned = Some(Instant::now());
+            let Ok(entries) = fs::read_dir(tile_dir()) else {
+                return;
+            };
+            self.unindexed = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("png"))
+                .collect();
+        }
+        let start = self.unindexed.len().saturating_sub(INDEX_BATCH);
+        let mut cache = DISK_CACHE.lock().unwrap();
+        let mut added = 0;
+        for path in self.unindexed.drain(start..) {
+            if !cache.contains(&path) {
+                cache.record(&path);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            crash_report::log_event(format!("indexed {} cached tiles", added));
+        }
+    }
+
+    /// Downloads the next bookmarked tile that isn't cached yet, if its
+    /// source may be sent a request right now.
+    fn prefetch_one(&mut self) {
+        while let Some(tile) = self.prefetch.pop_front() {
+            let source = SOURCES
+                .read()
+                .unwrap()
+                .get(tile.m)
+                .map(|source| (source.has_zoom(tile.z), source.rate_limit));
+            let Some((true, limit)) = source else {
+                continue;
+            };
+            if get_file_path(tile).exists() {
+                continue;
+            }
+            if opengl_helper::downloads_paused()
+                || retry::network_blocked_for().is_some()
+                || retry::source_blocked_for(tile.m).is_some()
+                || rate_limit::source_throttled_for(tile.m, limit).is_some()
+            {
+                // try again on a later step
+                self.prefetch.push_front(tile);
+                return;
+            }
+            let result = opengl_helper::fetch_tile_from_server(&tile);
+            retry::record_result(tile.m, &result);
+            match result {
+                Ok(_) => {
+                    debug!("Prefetched {:?}", tile);
+                    crash_report::log_event(format!("prefetched {:?}", tile))
+                }
+                Err(e) => warn!("Failed to prefetch {:?}: {}", tile, e),
+            }
+            return;
+        }
+    }
+}
+
+/// Decodes one random cached tile and deletes it if it is damaged, so it
+/// is downloaded again the next time it is shown.
+fn validate_random_tile() {
+    let Some(path) = DISK_CACHE.lock().unwrap().sample() else {
+        return;
+    };
+    if !path.exists() {
+        DISK_CACHE.lock().unwrap().forget(&path);
+        return;
+    }
+    if let Err(e) = decode(&path) {
+        warn!("Removing damaged tile {}: {}", path.display(), e);
+        if let Err(e) = disk_cache::delete_tile(&path) {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn decode(path: &Path) -> Result<(), Box<dyn Error>> {
+    ImageReader::open(path)?.with_guessed_format()?.decode()?;
+    Ok(())
+}
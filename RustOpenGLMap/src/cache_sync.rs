@@ -0,0 +1,217 @@
+use crate::disk_cache::{DISK_CACHE, tile_dir};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// One file of a tile cache directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub size: u64,
+    /// Last download or revalidation, in unix seconds.
+    pub modified: u64,
+}
+
+/// What a tile cache holds, keyed by file name. Tiles and their `.meta`
+/// validators are both listed, so a sync carries the validators along.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: HashMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Lists the tile files directly inside `dir`; a missing directory is empty.
+    pub fn scan(dir: &Path) -> Self {
+        let mut files = HashMap::new();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Self { files };
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("png" | "meta")
+            ) {
+                continue;
+            }
+            let (Ok(meta), Some(name)) = (entry.metadata(), path.file_name()) else {
+                continue;
+            };
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            files.insert(
+                name.to_string_lossy().into_owned(),
+                ManifestEntry {
+                    size: meta.len(),
+                    modified,
+                },
+            );
+        }
+        Self { files }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Names of the files that `target` lacks or holds an older copy of,
+    /// sorted so a sync copies in a stable order.
+    pub fn missing_from(&self, target: &Manifest) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .files
+            .iter()
+            .filter(|(name, entry)| {
+                target
+                    .files
+                    .get(*name)
+                    .is_none_or(|theirs| theirs.modified < entry.modified)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn bytes(&self, names: &[String]) -> u64 {
+        names
+            .iter()
+            .filter_map(|name| self.files.get(name))
+            .map(|entry| entry.size)
+            .sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncStats {
+    pub copied: usize,
+    pub bytes: u64,
+    pub failed: usize,
+}
+
+/// Copies the files `from` has and `target` (its manifest) lacks into `to`,
+/// keeping their modification times so tile TTLs carry over.
+pub fn sync(from: &Path, to: &Path, target: &Manifest) -> Result<SyncStats, Box<dyn Error>> {
+    let source = Manifest::scan(from);
+    let names = source.missing_from(target);
+    println!(
+        "{} of {} files to copy, {:.1} MiB",
+        names.len(),
+        source.files.len(),
+        source.bytes(&names) as f64 / (1024.0 * 1024.0)
+    );
+    fs::create_dir_all(to)?;
+    // tiles copied into our own cache have to be known to its size limit
+    let into_cache = to.canonicalize().ok() == tile_dir().canonicalize().ok();
+
+    let mut stats = SyncStats::default();
+    for name in &names {
+        let (src, dst): (PathBuf, PathBuf) = (from.join(name), to.join(name));
+        match copy_file(&src, &dst, source.files[name].modified) {
+            Ok(bytes) => {
+                stats.copied += 1;
+                stats.bytes += bytes;
+                if into_cache && name.ends_with(".png") {
+                    DISK_CACHE.lock().unwrap().record(&dst);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to copy {}: {}", src.display(), e);
+                stats.failed += 1;
+            }
+        }
+        if (stats.copied + stats.failed) % 1000 == 0 {
+            println!("Copied {} files...", stats.copied);
+        }
+    }
+    Ok(stats)
+}
+
+fn copy_file(from: &Path, to: &Path, modified: u64) -> Result<u64, Box<dyn Error>> {
+    let bytes = fs::copy(from, to)?;
+    fs::File::options()
+        .write(true)
+        .open(to)?
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))?;
+    Ok(bytes)
+}
+
+/// `RustOpenGLMap manifest [<dir>] [--out <file>]`
+#[derive(Debug, Args)]
+pub struct ManifestArgs {
+    /// Cache directory to list, the tile directory by default.
+    dir: Option<PathBuf>,
+    /// Where to write the manifest.
+    #[arg(long, default_value = "cache.manifest.json")]
+    out: PathBuf,
+}
+
+pub fn run_manifest_command(args: ManifestArgs) -> Result<(), String> {
+    let dir = args.dir.unwrap_or_else(|| tile_dir().to_path_buf());
+    let manifest = Manifest::scan(&dir);
+    manifest.save(&args.out).map_err(|e| e.to_string())?;
+    println!(
+        "Listed {} files of {} in {}",
+        manifest.files.len(),
+        dir.display(),
+        args.out.display()
+    );
+    Ok(())
+}
+
+/// `RustOpenGLMap sync <target> [--from <dir>] [--manifest <file>]`
+///
+/// Without `--manifest` the target directory is scanned; with one, it
+/// describes the cache being synced to, e.g. one written by `manifest` on
+/// another machine, and only its missing tiles end up in `<target>`.
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    /// Directory to copy the missing tiles into.
+    target: PathBuf,
+    /// Cache to copy from, the tile directory by default.
+    #[arg(long)]
+    from: Option<PathBuf>,
+    /// Manifest of what the target already has, instead of scanning it.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+}
+
+pub fn run_sync_command(args: SyncArgs) -> Result<(), String> {
+    let SyncArgs {
+        target,
+        from,
+        manifest,
+    } = args;
+    let from = from.unwrap_or_else(|| tile_dir().to_path_buf());
+    let known = match &manifest {
+        Some(path) => {
+            Manifest::load(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        }
+        None => Manifest::scan(&target),
+    };
+    println!(
+        "Syncing {} to {} ({} files there already)",
+        from.display(),
+        target.display(),
+        known.files.len()
+    );
+    let stats = sync(&from, &target, &known).map_err(|e| e.to_string())?;
+    println!(
+        "Copied {} files, {:.1} MiB, {} failed",
+        stats.copied,
+        stats.bytes as f64 / (1024.0 * 1024.0),
+        stats.failed
+    );
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use crate::opengl_helper::download_tile_image;
+use crate::tile::TilePos;
+use crate::tile_source::{SOURCES, Scheme, TileSource};
+use image::{RgbaImage, imageops};
+use std::error::Error;
+
+/// Fraction of a tile's height averaged when looking for Antarctica.
+const POLE_BAND: f64 = 1.0 / 16.0;
+
+/// What the z=1 tiles of a source say about its orientation.
+#[derive(Debug)]
+pub struct Calibration {
+    pub flip_rows: bool,
+    pub flip_image: bool,
+    /// Mean colour difference across the row seam as requested (0 = seamless).
+    pub seam_error: f64,
+    /// The same seam with the two rows swapped.
+    pub swapped_seam_error: f64,
+    /// Mean colour difference across the column seam, high values hint at mirrored columns.
+    pub column_seam_error: f64,
+}
+
+/// Fetches the four z=1 tiles with all orientation flags off and works out
+/// which flags make them line up.
+///
+/// Row order comes from which edges continue across the horizontal seam.
+/// That can't tell "no flips" from "both flips", so the brighter pole band
+/// (Antarctica, in both maps and imagery) decides which way is south.
+pub fn calibrate(source: &TileSource) -> Result<(Calibration, RgbaImage), Box<dyn Error>> {
+    let raw = TileSource {
+        scheme: Scheme::Xyz,
+        flip_rows: false,
+        flip_image: false,
+        ..source.clone()
+    };
+    let tile = |x, y| {
+        download_tile_image(
+            &raw,
+            &TilePos {
+                z: 1,
+                x,
+                y,
+                m: source.id,
+            },
+        )
+    };
+    let tiles = [[tile(0, 0)?, tile(0, 1)?], [tile(1, 0)?, tile(1, 1)?]];
+
+    let mut seam_error = 0.0;
+    let mut swapped_seam_error = 0.0;
+    for [upper, lower] in &tiles {
+        seam_error += row_difference(upper, upper.height() - 1, lower, 0);
+        swapped_seam_error += row_difference(lower, lower.height() - 1, upper, 0);
+    }
+    seam_error /= 2.0;
+    swapped_seam_error /= 2.0;
+    let column_seam_error = (column_difference(&tiles[0][0], &tiles[1][0])
+        + column_difference(&tiles[0][1], &tiles[1][1]))
+        / 2.0;
+
+    let band_top = |img: &RgbaImage| band_brightness(img, true);
+    let band_bottom = |img: &RgbaImage| band_brightness(img, false);
+    let top_of_first = (band_top(&tiles[0][0]) + band_top(&tiles[1][0])) / 2.0;
+    let bottom_of_first = (band_bottom(&tiles[0][0]) + band_bottom(&tiles[1][0])) / 2.0;
+    let top_of_second = (band_top(&tiles[0][1]) + band_top(&tiles[1][1])) / 2.0;
+    let bottom_of_second = (band_bottom(&tiles[0][1]) + band_bottom(&tiles[1][1])) / 2.0;
+
+    let (flip_rows, flip_image) = if seam_error <= swapped_seam_error {
+        // rows and images agree: either both right or both upside down
+        if bottom_of_second >= top_of_first {
+            (false, false)
+        } else {
+            (true, true)
+        }
+    } else if bottom_of_first >= top_of_second {
+        // y=0 is the southern row but its images are upright: TMS numbering
+        (true, false)
+    } else {
+        (false, true)
+    };
+
+    let mut preview = RgbaImage::new(
+        tiles[0][0].width() + tiles[1][0].width(),
+        tiles[0][0].height() + tiles[0][1].height(),
+    );
+    for (x, column) in tiles.iter().enumerate() {
+        for (y, img) in column.iter().enumerate() {
+            imageops::overlay(
+                &mut preview,
+                img,
+                (x as u32 * tiles[0][0].width()) as i64,
+                (y as u32 * tiles[0][0].height()) as i64,
+            );
+        }
+    }
+
+    Ok((
+        Calibration {
+            flip_rows,
+            flip_image,
+            seam_error,
+            swapped_seam_error,
+            column_seam_error,
+        },
+        preview,
+    ))
+}
+
+/// Mean per-channel difference (0..1) between row `ya` of `a` and row `yb` of `b`.
+fn row_difference(a: &RgbaImage, ya: u32, b: &RgbaImage, yb: u32) -> f64 {
+    let width = a.width().min(b.width());
+    let mut total = 0.0;
+    for x in 0..width {
+        total += pixel_difference(a.get_pixel(x, ya).0, b.get_pixel(x, yb).0);
+    }
+    total / width.max(1) as f64
+}
+
+/// Difference between the right edge of `left` and the left edge of `right`.
+fn column_difference(left: &RgbaImage, right: &RgbaImage) -> f64 {
+    let height = left.height().min(right.height());
+    let mut total = 0.0;
+    for y in 0..height {
+        total += pixel_difference(
+            left.get_pixel(left.width() - 1, y).0,
+            right.get_pixel(0, y).0,
+        );
+    }
+    total / height.max(1) as f64
+}
+
+fn pixel_difference(a: [u8; 4], b: [u8; 4]) -> f64 {
+    (0..3)
+        .map(|c| (a[c] as f64 - b[c] as f64).abs())
+        .sum::<f64>()
+        / (3.0 * 255.0)
+}
+
+/// Mean luminance (0..1) of the top or bottom `POLE_BAND` of `img`.
+fn band_brightness(img: &RgbaImage, top: bool) -> f64 {
+    let rows = ((img.height() as f64 * POLE_BAND) as u32).max(1);
+    let start = if top { 0 } else { img.height() - rows };
+    let mut total = 0.0;
+    for y in start..start + rows {
+        for x in 0..img.width() {
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            total += (0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64) / 255.0;
+        }
+    }
+    total / (rows * img.width()).max(1) as f64
+}
+
+/// `RustOpenGLMap calibrate <source-id>`
+pub fn run_calibrate_command(id: u8) -> Result<(), String> {
+    let source = SOURCES
+        .read()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| format!("Unknown tile source {}", id))?;
+
+    println!("Fetching the z=1 tiles of {} ...", source.name);
+    let (calibration, preview) = calibrate(&source).map_err(|e| e.to_string())?;
+    let preview_path = format!("calibration_{}.png", id);
+    preview.save(&preview_path).map_err(|e| e.to_string())?;
+
+    println!(
+        "Row seam error {:.3}, swapped {:.3}, column seam error {:.3}",
+        calibration.seam_error, calibration.swapped_seam_error, calibration.column_seam_error
+    );
+    if calibration.column_seam_error > 0.25 {
+        println!("Warning: the columns don't line up, the source may not be Web Mercator.");
+    }
+    println!("Raw z=1 tiles written to {}", preview_path);
+    println!("Suggested settings for [[source]] id = {}:", id);
+    println!("flip_rows = {}", calibration.flip_rows);
+    println!("flip_image = {}", calibration.flip_image);
+    if calibration.flip_rows != source.flip_rows || calibration.flip_image != source.flip_image {
+        println!(
+            "(currently flip_rows = {}, flip_image = {})",
+            source.flip_rows, source.flip_image
+        );
+    }
+    Ok(())
+}
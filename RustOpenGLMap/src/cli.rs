@@ -0,0 +1,137 @@
+use crate::audit::AuditArgs;
+use crate::batch_geocode::GeocodeArgs;
+use crate::cache_import::ImportArgs;
+use crate::cache_sync::{ManifestArgs, SyncArgs};
+use crate::frame_capture::CaptureArgs;
+use crate::region_download::DownloadArgs;
+use crate::static_map::RenderArgs;
+use crate::tile_pack::{ExportPackArgs, ImportPackArgs};
+use crate::wms::WmsArgs;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Slippy map viewer. Without a subcommand it opens the map window.
+#[derive(Debug, Parser)]
+#[command(name = "RustOpenGLMap", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Latitude to open at, instead of where the last session left off.
+    #[arg(long, requires = "lon", allow_negative_numbers = true, value_parser = latitude)]
+    pub lat: Option<f64>,
+    #[arg(long, requires = "lat", allow_negative_numbers = true, value_parser = longitude)]
+    pub lon: Option<f64>,
+    /// Zoom level to open at.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=19))]
+    pub zoom: Option<u8>,
+    /// Base map, a preset key, source name or id.
+    #[arg(long)]
+    pub source: Option<String>,
+    /// Preset base map and overlays, see `presets`.
+    #[arg(long)]
+    pub combo: Option<String>,
+    /// Window width in pixels.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(200..))]
+    pub width: Option<u32>,
+    /// Window height in pixels.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(200..))]
+    pub height: Option<u32>,
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Directory tiles are cached in.
+    #[arg(long, global = true, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Log more: -v adds tile loads, downloads and evictions, -vv everything.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// Log one JSON object per line instead of plain text.
+    #[arg(long, global = true)]
+    pub log_json: bool,
+    /// Start with downloads paused, only cached tiles are shown (P resumes).
+    #[arg(long)]
+    pub offline: bool,
+    /// Route to draw between two points.
+    #[arg(long, value_name = "LAT,LON:LAT,LON", value_parser = route)]
+    pub route: Vec<Route>,
+    /// GPX, GeoJSON or CSV file to overlay in a colour of its own, can be
+    /// repeated. The map opens fitted to them unless --lat, --zoom or a permalink is given.
+    #[arg(long, value_name = "FILE")]
+    pub overlay: Vec<PathBuf>,
+    /// GPX, GeoJSON, CSV and TLE files to show, a `.mapsession` to pick up
+    /// where it was saved, or a `#map=` permalink to open.
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Copy another tool's tile cache into the tile directory.
+    Import(ImportArgs),
+    /// Check the row order of a source's tiles.
+    Calibrate {
+        /// Id of the source to check.
+        source: u8,
+    },
+    /// Download every tile of a region for offline use.
+    Download(DownloadArgs),
+    /// Pack a region's cached tiles into one file to copy to another machine.
+    ExportPack(ExportPackArgs),
+    /// Unpack a tile pack into the tile directory.
+    ImportPack(ImportPackArgs),
+    /// List the built-in sources and combos.
+    Presets,
+    /// Look up the addresses in a CSV file.
+    Geocode(GeocodeArgs),
+    /// Write a manifest of what a tile cache holds.
+    Manifest(ManifestArgs),
+    /// Copy the tiles another cache lacks into it.
+    Sync(SyncArgs),
+    /// Render views offscreen and diff them against an earlier capture,
+    /// to check a change to the rendering path.
+    Capture(CaptureArgs),
+    /// Write a PNG of an area without opening the map.
+    Render(RenderArgs),
+    /// List the layers of a WMS server and print a source for them.
+    Wms(WmsArgs),
+    /// Export the log of bookmark and annotation changes as JSON.
+    Audit(AuditArgs),
+}
+
+/// Start and end of a `--route`, (lat, lon) each.
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    pub from: (f64, f64),
+    pub to: (f64, f64),
+}
+
+fn degrees(value: &str, limit: f64) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|d| d.abs() <= limit)
+        .ok_or_else(|| format!("needs degrees between -{} and {}", limit, limit))
+}
+
+fn latitude(value: &str) -> Result<f64, String> {
+    degrees(value, 90.0)
+}
+
+fn longitude(value: &str) -> Result<f64, String> {
+    degrees(value, 180.0)
+}
+
+fn route(value: &str) -> Result<Route, String> {
+    let point = |p: &str| {
+        let (lat, lon) = p.split_once(',')?;
+        Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?))
+    };
+    value
+        .split_once(':')
+        .and_then(|(from, to)| {
+            Some(Route {
+                from: point(from)?,
+                to: point(to)?,
+            })
+        })
+        .ok_or_else(|| "needs lat,lon:lat,lon".to_string())
+}
@@ -0,0 +1,408 @@
+use crate::geo;
+use crate::text::{self, TextRenderer};
+use crate::theme::Theme;
+use crate::viewport::Viewport;
+use crate::wake;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message as Frame, WebSocket};
+
+/// How long a read waits before queued messages get their turn to go out.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Wait before connecting again after the relay dropped or refused us,
+/// doubling up to the maximum while it stays away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Least time between two cursor positions sent, 20 a second.
+const CURSOR_INTERVAL: Duration = Duration::from_millis(50);
+/// The view is sent again this often even if it didn't change, so peers
+/// know we're still there.
+const HEARTBEAT: Duration = Duration::from_secs(5);
+/// Peers not heard from for this long are taken off the map.
+const PEER_TIMEOUT: Duration = Duration::from_secs(20);
+/// Colours peers are drawn in, by order of arrival.
+const PEER_COLORS: [[f32; 4]; 6] = [
+    [0.90, 0.30, 0.20, 1.0],
+    [0.20, 0.55, 0.90, 1.0],
+    [0.25, 0.70, 0.30, 1.0],
+    [0.85, 0.55, 0.10, 1.0],
+    [0.65, 0.35, 0.85, 1.0],
+    [0.10, 0.70, 0.70, 1.0],
+];
+
+/// The `[collab]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollabSettings {
+    /// Connect to the relay and share the view with everyone in the room.
+    #[serde(default)]
+    pub enabled: bool,
+    /// WebSocket relay that passes each message on to the other clients
+    /// connected to the same path.
+    #[serde(default = "default_relay")]
+    pub relay: String,
+    /// Path on the relay; only instances in the same room see each other.
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// Shown next to our cursor on the other maps, the user name if empty.
+    #[serde(default)]
+    pub name: String,
+}
+
+fn default_relay() -> String {
+    "ws://localhost:9001".to_string()
+}
+
+fn default_room() -> String {
+    "map".to_string()
+}
+
+impl Default for CollabSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            relay: default_relay(),
+            room: default_room(),
+            name: String::new(),
+        }
+    }
+}
+
+impl CollabSettings {
+    fn url(&self) -> String {
+        format!(
+            "{}/{}",
+            self.relay.trim_end_matches('/'),
+            self.room.trim_matches('/')
+        )
+    }
+
+    fn display_name(&self) -> String {
+        if !self.name.trim().is_empty() {
+            return self.name.trim().to_string();
+        }
+        std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "map".to_string())
+    }
+}
+
+/// An annotation added on one map and repeated on the others.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Edit {
+    Marker { lat: f64, lon: f64 },
+    RangeRings { lat: f64, lon: f64 },
+    ClearRangeRings,
+    Route { from: [f64; 2], to: [f64; 2] },
+}
+
+/// What goes over the relay, as JSON text frames.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// Sent on connecting and in answer to a newcomer's hello.
+    Hello {
+        name: String,
+    },
+    Cursor {
+        lat: f64,
+        lon: f64,
+    },
+    /// South, west, north, east of what the window shows.
+    View {
+        bounds: [f64; 4],
+    },
+    Edit {
+        edit: Edit,
+    },
+    Bye,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Envelope {
+    /// Random id of the sending instance.
+    peer: u64,
+    #[serde(flatten)]
+    message: Message,
+}
+
+/// Another instance in the room.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub name: String,
+    pub color: [f32; 4],
+    pub cursor: Option<(f64, f64)>,
+    pub view: Option<[f64; 4]>,
+    seen: Instant,
+}
+
+/// Shares the cursor, the view and new annotations with other instances
+/// through a WebSocket relay, and keeps track of theirs. The connection
+/// lives on a thread of its own, which reconnects when the relay drops.
+pub struct Collab {
+    peer: u64,
+    name: String,
+    out_tx: Sender<Message>,
+    in_rx: Receiver<Envelope>,
+    peers: HashMap<u64, Peer>,
+    arrivals: usize,
+    last_cursor: Option<Instant>,
+    last_view: Option<([f64; 4], Instant)>,
+}
+
+impl Collab {
+    /// Starts connecting to the relay, or None unless collaboration is enabled.
+    pub fn connect(settings: &CollabSettings) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+        let peer = fastrand::u64(..);
+        let name = settings.display_name();
+        let url = settings.url();
+        let (out_tx, out_rx) = channel();
+        let (in_tx, in_rx) = channel();
+        let hello = Message::Hello { name: name.clone() };
+        thread::spawn(move || run(&url, peer, &hello, &out_rx, &in_tx));
+        Some(Self {
+            peer,
+            name,
+            out_tx,
+            in_rx,
+            peers: HashMap::new(),
+            arrivals: 0,
+            last_cursor: None,
+            last_view: None,
+        })
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+
+    /// Sends where the mouse is, at most 20 times a second.
+    pub fn send_cursor(&mut self, lat: f64, lon: f64) {
+        if self
+            .last_cursor
+            .is_some_and(|sent| sent.elapsed() < CURSOR_INTERVAL)
+        {
+            return;
+        }
+        self.last_cursor = Some(Instant::now());
+        self.send(Message::Cursor { lat, lon });
+    }
+
+    /// Sends the view when it changed, or as a heartbeat every few seconds.
+    pub fn send_view(&mut self, bounds: [f64; 4]) {
+        if let Some((sent, at)) = self.last_view
+            && sent == bounds
+            && at.elapsed() < HEARTBEAT
+        {
+            return;
+        }
+        self.last_view = Some((bounds, Instant::now()));
+        self.send(Message::View { bounds });
+    }
+
+    /// Repeats a local annotation edit on the other maps.
+    pub fn send_edit(&self, edit: Edit) {
+        self.send(Message::Edit { edit });
+    }
+
+    fn send(&self, message: Message) {
+        let _ = self.out_tx.send(message);
+    }
+
+    /// Takes in what the peers sent. Returns the edits to apply with the
+    /// name of whoever made them, or None if nothing changed on screen.
+    pub fn poll(&mut self) -> Option<Vec<(String, Edit)>> {
+        let mut changed = false;
+        let mut edits = Vec::new();
+        for Envelope { peer, message } in self.in_rx.try_iter() {
+            if peer == self.peer {
+                continue;
+            }
+            changed = true;
+            if message == Message::Bye {
+                if let Some(gone) = self.peers.remove(&peer) {
+                    info!("{} left", gone.name);
+                }
+                continue;
+            }
+            if !self.peers.contains_key(&peer) {
+                // introduce ourselves to the newcomer
+                let _ = self.out_tx.send(Message::Hello {
+                    name: self.name.clone(),
+                });
+                if let Some((bounds, _)) = self.last_view {
+                    let _ = self.out_tx.send(Message::View { bounds });
+                }
+                self.arrivals += 1;
+            }
+            let arrivals = self.arrivals;
+            let known = self.peers.entry(peer).or_insert_with(|| Peer {
+                name: format!("peer {}", arrivals),
+                color: PEER_COLORS[(arrivals - 1) % PEER_COLORS.len()],
+                cursor: None,
+                view: None,
+                seen: Instant::now(),
+            });
+            known.seen = Instant::now();
+            match message {
+                Message::Hello { name } => {
+                    if known.name != name {
+                        info!("{} joined", name);
+                    }
+                    known.name = name;
+                }
+                Message::Cursor { lat, lon } => known.cursor = Some((lat, lon)),
+                Message::View { bounds } => known.view = Some(bounds),
+                Message::Edit { edit } => edits.push((known.name.clone(), edit)),
+                Message::Bye => {}
+            }
+        }
+        let before = self.peers.len();
+        self.peers
+            .retain(|_, peer| peer.seen.elapsed() < PEER_TIMEOUT);
+        changed |= self.peers.len() != before;
+        changed.then_some(edits)
+    }
+}
+
+impl Drop for Collab {
+    fn drop(&mut self) {
+        let _ = self.out_tx.send(Message::Bye);
+    }
+}
+
+/// Connects to `url` and passes messages both ways until the map closes,
+/// reconnecting whenever the connection drops.
+fn run(
+    url: &str,
+    peer: u64,
+    hello: &Message,
+    out_rx: &Receiver<Message>,
+    in_tx: &Sender<Envelope>,
+) {
+    let mut wait = RECONNECT_DELAY;
+    loop {
+        match tungstenite::connect(url) {
+            Ok((mut socket, _)) => {
+                info!("Connected to the collaboration relay {}", url);
+                wait = RECONNECT_DELAY;
+                if let MaybeTlsStream::Plain(stream) = socket.get_mut()
+                    && let Err(e) = stream.set_read_timeout(Some(POLL_INTERVAL))
+                {
+                    warn!("Failed to set a read timeout: {}", e);
+                }
+                match exchange(&mut socket, peer, hello, out_rx, in_tx) {
+                    Ok(()) => return,
+                    Err(e) => warn!("Lost the collaboration relay: {}", e),
+                }
+            }
+            Err(e) => warn!("Failed to connect to {}: {}", url, e),
+        }
+        // drop what piled up meanwhile, except a goodbye
+        loop {
+            match out_rx.try_recv() {
+                Ok(Message::Bye) | Err(TryRecvError::Disconnected) => return,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        thread::sleep(wait);
+        wait = (wait * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Sends what the map queued and hands on what arrives. Returns Ok once
+/// the map said goodbye, an error if the connection broke.
+fn exchange(
+    socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+    peer: u64,
+    hello: &Message,
+    out_rx: &Receiver<Message>,
+    in_tx: &Sender<Envelope>,
+) -> Result<(), Box<dyn Error>> {
+    let send = |socket: &mut WebSocket<_>, message: Message| -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string(&Envelope { peer, message })?;
+        socket.send(Frame::Text(json))?;
+        Ok(())
+    };
+    send(socket, hello.clone())?;
+    loop {
+        loop {
+            match out_rx.try_recv() {
+                Ok(Message::Bye) | Err(TryRecvError::Disconnected) => {
+                    let _ = send(socket, Message::Bye);
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    return Ok(());
+                }
+                Ok(message) => send(socket, message)?,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        match socket.read() {
+            Ok(Frame::Text(json)) => match serde_json::from_str::<Envelope>(&json) {
+                Ok(envelope) => {
+                    if in_tx.send(envelope).is_err() {
+                        return Ok(());
+                    }
+                    wake::wake();
+                }
+                Err(e) => warn!("Ignoring a relay message: {}", e),
+            },
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+}
+
+/// Outlines what each peer sees and draws their cursor with their name.
+pub fn draw_peers(
+    text: &TextRenderer,
+    theme: &Theme,
+    collab: &Collab,
+    vp: &Viewport,
+    win: (u32, u32),
+) {
+    let to_pixel = |lat: f64, lon: f64| {
+        let (x, y) = vp.world_to_pixel(geo::project(lat, lon), win);
+        (x as f32, y as f32)
+    };
+    for peer in collab.peers() {
+        if let Some([south, west, north, east]) = peer.view {
+            let (x0, y0) = to_pixel(north, west);
+            let (x1, y1) = to_pixel(south, east);
+            let line = 2.0;
+            for rect in [
+                [x0, y0, x1, y0 + line],
+                [x0, y1 - line, x1, y1],
+                [x0, y0, x0 + line, y1],
+                [x1 - line, y0, x1, y1],
+            ] {
+                text.fill_rect(
+                    rect,
+                    [peer.color[0], peer.color[1], peer.color[2], 0.7],
+                    win,
+                );
+            }
+        }
+        if let Some((lat, lon)) = peer.cursor {
+            let (x, y) = to_pixel(lat, lon);
+            text.fill_rect([x - 5.0, y - 5.0, x + 5.0, y + 5.0], theme.panel, win);
+            text.fill_rect([x - 3.0, y - 3.0, x + 3.0, y + 3.0], peer.color, win);
+            text::draw_place_name(text, theme, &peer.name, (x + 8.0, y + 4.0), win);
+        }
+    }
+}
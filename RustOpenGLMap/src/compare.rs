@@ -0,0 +1,57 @@
+/// How close to the divider (in pixels) a click grabs it.
+const GRAB_DISTANCE: i32 = 8;
+
+/// Swipe comparison: the usual layers left of a vertical divider, the same
+/// layers with the base swapped for `right` on its right.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compare {
+    /// Base source shown right of the divider.
+    pub right: u8,
+    /// Divider position as a fraction of the window width.
+    pub split: f32,
+    dragging: bool,
+}
+
+impl Compare {
+    pub fn new(right: u8) -> Self {
+        Self {
+            right,
+            split: 0.5,
+            dragging: false,
+        }
+    }
+
+    pub fn divider_x(&self, win_w: u32) -> i32 {
+        (self.split * win_w as f32).round() as i32
+    }
+
+    /// Starts dragging if window x `x` is on the divider.
+    pub fn grab(&mut self, x: i32, win_w: u32) -> bool {
+        self.dragging = (x - self.divider_x(win_w)).abs() <= GRAB_DISTANCE;
+        self.dragging
+    }
+
+    /// Follows the pointer while dragging. Returns true if the divider moved.
+    pub fn drag(&mut self, x: i32, win_w: u32) -> bool {
+        if !self.dragging || win_w == 0 {
+            return false;
+        }
+        self.split = (x as f32 / win_w as f32).clamp(0.0, 1.0);
+        true
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = false;
+    }
+
+    /// Window rect [x, y, w, h] (GL convention, origin bottom-left) of the
+    /// left or right half.
+    pub fn half(&self, win: (u32, u32), left: bool) -> [i32; 4] {
+        let x = self.divider_x(win.0);
+        if left {
+            [0, 0, x, win.1 as i32]
+        } else {
+            [x, 0, win.0 as i32 - x, win.1 as i32]
+        }
+    }
+}
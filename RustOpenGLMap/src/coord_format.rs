@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+/// How a latitude/longitude pair is written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Notation {
+    /// `51.47790, -0.00150`
+    #[default]
+    Decimal,
+    /// `51°28'40.4"N 0°0'5.4"W`
+    Dms,
+}
+
+/// Notation and precision of displayed coordinates, the `[coordinates]`
+/// table of settings.toml.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CoordFormat {
+    #[serde(default)]
+    pub notation: Notation,
+    /// Decimal places of the degrees, or of the seconds in DMS.
+    #[serde(default = "default_decimals")]
+    pub decimals: u8,
+}
+
+fn default_decimals() -> u8 {
+    5
+}
+
+impl Default for CoordFormat {
+    fn default() -> Self {
+        Self {
+            notation: Notation::default(),
+            decimals: default_decimals(),
+        }
+    }
+}
+
+impl CoordFormat {
+    /// Smallest step the format can show, in units per degree.
+    fn steps_per_degree(&self) -> f64 {
+        let steps = 10f64.powi(i32::from(self.decimals.min(9)));
+        match self.notation {
+            Notation::Decimal => steps,
+            Notation::Dms => steps * 3600.0,
+        }
+    }
+
+    /// Rounds (lat, lon) onto the grid of values the format can show, so a
+    /// point reads back exactly as it was displayed.
+    pub fn snap(&self, (lat, lon): (f64, f64)) -> (f64, f64) {
+        let steps = self.steps_per_degree();
+        ((lat * steps).round() / steps, (lon * steps).round() / steps)
+    }
+
+    /// Decimal places that print a snapped value without rounding noise.
+    pub fn decimal_places(&self) -> usize {
+        match self.notation {
+            Notation::Decimal => usize::from(self.decimals),
+            // a second is 1/3600 of a degree, four more places cover it
+            Notation::Dms => usize::from(self.decimals) + 4,
+        }
+    }
+
+    pub fn format(&self, (lat, lon): (f64, f64)) -> String {
+        match self.notation {
+            Notation::Decimal => {
+                let places = usize::from(self.decimals);
+                format!("{:.*}, {:.*}", places, lat, places, lon)
+            }
+            Notation::Dms => format!(
+                "{} {}",
+                self.dms(lat, ('N', 'S')),
+                self.dms(lon, ('E', 'W'))
+            ),
+        }
+    }
+
+    fn dms(&self, degrees: f64, hemispheres: (char, char)) -> String {
+        let hemisphere = if degrees < 0.0 {
+            hemispheres.1
+        } else {
+            hemispheres.0
+        };
+        // count whole steps so rounding the seconds can't show 60"
+        let second_steps = 10u64.pow(u32::from(self.decimals.min(9)));
+        let steps = (degrees.abs() * self.steps_per_degree()).round() as u64;
+        let seconds = steps % (60 * second_steps);
+        let minutes = steps / (60 * second_steps) % 60;
+        let whole = steps / (3600 * second_steps);
+        let places = usize::from(self.decimals);
+        format!(
+            "{}°{}'{:.*}\"{}",
+            whole,
+            minutes,
+            places,
+            seconds as f64 / second_steps as f64,
+            hemisphere
+        )
+    }
+}
@@ -0,0 +1,123 @@
+use crate::viewport::Viewport;
+use once_cell::sync::Lazy;
+use sdl2::messagebox::{MessageBoxFlag, show_simple_message_box};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::panic::PanicHookInfo;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How many pipeline events a crash report includes.
+const EVENT_HISTORY: usize = 64;
+
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Most recent tile pipeline events, oldest first.
+static EVENTS: Lazy<Mutex<VecDeque<String>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(EVENT_HISTORY)));
+
+/// Viewport and source id of the last frame.
+static APP_STATE: Mutex<Option<(Viewport, u8)>> = Mutex::new(None);
+
+/// Remembers something the tile pipeline did, for the next crash report.
+pub fn log_event(event: impl Into<String>) {
+    let line = format!(
+        "{:>10.3}s {}",
+        STARTED.elapsed().as_secs_f64(),
+        event.into()
+    );
+    let mut events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    if events.len() == EVENT_HISTORY {
+        events.pop_front();
+    }
+    events.push_back(line);
+}
+
+/// Records what the user is looking at, called once per frame.
+pub fn set_state(viewport: Viewport, map: u8) {
+    *APP_STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some((viewport, map));
+}
+
+/// Replaces the default panic output with a crash report file and a dialog
+/// telling the user where to find it.
+pub fn install() {
+    Lazy::force(&STARTED);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let report = build_report(info);
+        let path = format!(
+            "crash_{}.txt",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        );
+        let message = match std::fs::write(&path, &report) {
+            Ok(()) => {
+                eprintln!("Crash report written to {}", path);
+                format!(
+                    "RustOpenGLMap crashed.\n\nA report was written to {}, please attach it when reporting the problem.",
+                    path
+                )
+            }
+            Err(e) => format!(
+                "RustOpenGLMap crashed and the report couldn't be saved: {}",
+                e
+            ),
+        };
+        // SDL can show this even before (or without) a window
+        let _ = show_simple_message_box(MessageBoxFlag::ERROR, "RustOpenGLMap", &message, None);
+    }));
+}
+
+fn build_report(info: &PanicHookInfo) -> String {
+    let mut report = String::new();
+    let thread = std::thread::current();
+    let _ = writeln!(
+        report,
+        "{} {} crash report",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(report, "Uptime: {:.3}s", STARTED.elapsed().as_secs_f64());
+    let _ = writeln!(report, "Thread: {}", thread.name().unwrap_or("<unnamed>"));
+    let _ = writeln!(report, "Panic: {}", info);
+    let _ = writeln!(report);
+
+    // the panic may have happened while one of these was locked
+    match APP_STATE.try_lock().map(|s| *s) {
+        Ok(Some((vp, map))) => {
+            let _ = writeln!(
+                report,
+                "Viewport: z={} x={:.3} y={:.3}",
+                vp.z, vp.center_x, vp.center_y
+            );
+            let _ = writeln!(report, "Source: {}", map);
+        }
+        Ok(None) => {
+            let _ = writeln!(report, "Viewport: not drawn yet");
+        }
+        Err(_) => {
+            let _ = writeln!(report, "Viewport: unavailable");
+        }
+    }
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "Recent events:");
+    match EVENTS.try_lock() {
+        Ok(events) => {
+            for event in events.iter() {
+                let _ = writeln!(report, "{}", event);
+            }
+        }
+        Err(_) => {
+            let _ = writeln!(report, "unavailable");
+        }
+    }
+    let _ = writeln!(report);
+
+    let _ = writeln!(report, "Backtrace:\n{}", Backtrace::force_capture());
+    report
+}
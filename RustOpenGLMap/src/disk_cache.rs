@@ -0,0 +1,333 @@
+use lru::LruCache;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+/// Directory tiles are cached in unless `--cache-dir` names another.
+const DEFAULT_TILE_DIR: &str = "Tiles";
+
+static TILE_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// Size limit used when `MAP_DISK_CACHE_MB` isn't set.
+const DEFAULT_LIMIT: u64 = 1024 * 1024 * 1024;
+
+/// Tiles on disk, scanned on first use and kept up to date by the loaders.
+pub static DISK_CACHE: Lazy<Mutex<DiskCache>> =
+    Lazy::new(|| Mutex::new(DiskCache::open(tile_dir(), limit_from_env())));
+
+/// Tiles eviction leaves alone, whatever their age: those around home and
+/// protected bookmarks.
+static PROTECTED: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// The `[cache]` table of settings.toml.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CacheSettings {
+    /// Keep the tiles around home as well as around protected bookmarks.
+    #[serde(default)]
+    pub protect_home: bool,
+    /// Kilometres around a protected place whose tiles are kept.
+    #[serde(default = "default_protect_radius")]
+    pub protect_radius_km: f64,
+    /// Deepest zoom kept around protected places.
+    #[serde(default = "default_protect_zoom")]
+    pub protect_max_zoom: u8,
+}
+
+fn default_protect_radius() -> f64 {
+    2.0
+}
+
+fn default_protect_zoom() -> u8 {
+    16
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            protect_home: false,
+            protect_radius_km: default_protect_radius(),
+            protect_max_zoom: default_protect_zoom(),
+        }
+    }
+}
+
+/// Replaces the set of tile files eviction must keep.
+pub fn protect(paths: HashSet<PathBuf>) {
+    *PROTECTED.lock().unwrap() = paths;
+}
+
+/// Directory all tile sources cache their PNGs in.
+pub fn tile_dir() -> &'static Path {
+    TILE_DIR.get_or_init(|| PathBuf::from(DEFAULT_TILE_DIR))
+}
+
+/// Moves the cache to `dir`. Only works before the first tile is looked up.
+pub fn set_tile_dir(dir: PathBuf) -> Result<(), String> {
+    TILE_DIR
+        .set(dir)
+        .map_err(|_| "The tile directory is already in use".to_string())
+}
+
+fn limit_from_env() -> u64 {
+    std::env::var("MAP_DISK_CACHE_MB")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(DEFAULT_LIMIT)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskCacheStats {
+    pub tiles: usize,
+    pub bytes: u64,
+    pub limit: u64,
+    /// Cached tiles in protected areas and their size.
+    pub protected_tiles: usize,
+    pub protected_bytes: u64,
+}
+
+impl fmt::Display for DiskCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        write!(
+            f,
+            "{} tiles, {:.1} of {:.0} MiB",
+            self.tiles,
+            mib(self.bytes),
+            mib(self.limit)
+        )?;
+        if self.protected_tiles > 0 {
+            write!(
+                f,
+                ", {} tiles ({:.1} MiB) protected",
+                self.protected_tiles,
+                mib(self.protected_bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// HTTP validators of a cached tile, kept next to its PNG.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TileMeta {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl TileMeta {
+    fn path(tile: &Path) -> PathBuf {
+        tile.with_extension("meta")
+    }
+
+    pub fn load(tile: &Path) -> Option<Self> {
+        let text = fs::read_to_string(Self::path(tile)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    /// Writes the validators, or removes stale ones if the server sent none.
+    pub fn save(&self, tile: &Path) -> std::io::Result<()> {
+        let path = Self::path(tile);
+        if self.etag.is_none() && self.last_modified.is_none() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+}
+
+/// Whether the file at `path` was last downloaded or revalidated more than `age` ago.
+pub fn is_older_than(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed > age)
+}
+
+/// Restarts the TTL of a tile the server confirmed as unchanged.
+pub fn mark_fresh(path: &Path) -> std::io::Result<()> {
+    fs::File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Deletes a cached tile and its validators. False if it wasn't cached.
+pub fn delete_tile(path: &Path) -> Result<bool, Box<dyn Error>> {
+    DISK_CACHE.lock().unwrap().forget(path);
+    let _ = fs::remove_file(TileMeta::path(path));
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Byte-limited view of the tile directory. Files are ordered by last access
+/// and the least recently used ones are deleted once the limit is exceeded,
+/// except for protected ones.
+pub struct DiskCache {
+    limit: u64,
+    bytes: u64,
+    entries: LruCache<PathBuf, u64>,
+}
+
+impl DiskCache {
+    /// Indexes the PNGs in `dir`, oldest access first, and trims them to `limit`.
+    pub fn open(dir: &Path, limit: u64) -> Self {
+        let mut files: Vec<(SystemTime, PathBuf, u64)> = Vec::new();
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("png") {
+                    continue;
+                }
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                // filesystems mounted noatime report no access time
+                let used = meta
+                    .accessed()
+                    .or_else(|_| meta.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push((used, path, meta.len()));
+            }
+        }
+        files.sort();
+
+        let mut cache = Self {
+            limit,
+            bytes: 0,
+            entries: LruCache::unbounded(),
+        };
+        for (_, path, size) in files {
+            cache.bytes += size;
+            cache.entries.put(path, size);
+        }
+        let removed = cache.evict();
+        if removed > 0 {
+            info!(
+                "Evicted {} tiles to stay under the disk cache limit",
+                removed
+            );
+        }
+        cache
+    }
+
+    /// Marks `path` as just used so it is evicted last.
+    pub fn touch(&mut self, path: &Path) {
+        if self.entries.get(path).is_none() {
+            self.record(path);
+        }
+    }
+
+    /// Adds a freshly written file and makes room for it.
+    pub fn record(&mut self, path: &Path) {
+        let Ok(meta) = fs::metadata(path) else {
+            return;
+        };
+        if let Some(old) = self.entries.put(path.to_path_buf(), meta.len()) {
+            self.bytes -= old;
+        }
+        self.bytes += meta.len();
+        self.evict();
+    }
+
+    /// Whether `path` is cached, without counting as a use.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries.contains(path)
+    }
+
+    /// Stops tracking a file that was deleted elsewhere.
+    pub fn forget(&mut self, path: &Path) {
+        if let Some(size) = self.entries.pop(path) {
+            self.bytes -= size;
+        }
+    }
+
+    /// A cached file picked at random, for spot checks.
+    pub fn sample(&self) -> Option<PathBuf> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let index = fastrand::usize(..self.entries.len());
+        self.entries.iter().nth(index).map(|(path, _)| path.clone())
+    }
+
+    /// Evicts down to the limit, for files that were added behind the
+    /// index's back. Returns how many were removed.
+    pub fn trim(&mut self) -> usize {
+        self.evict()
+    }
+
+    /// Deletes least recently used tiles until the cache fits its limit,
+    /// skipping protected ones; those may keep it over the limit. The newest
+    /// tile is always kept. Returns how many files were removed.
+    fn evict(&mut self) -> usize {
+        if self.bytes <= self.limit {
+            return 0;
+        }
+        let protected = PROTECTED.lock().unwrap();
+        let mut excess = self.bytes - self.limit;
+        let mut victims = Vec::new();
+        // oldest first, leaving out the newest
+        for (path, &size) in self
+            .entries
+            .iter()
+            .rev()
+            .take(self.entries.len().saturating_sub(1))
+        {
+            if excess == 0 {
+                break;
+            }
+            if !protected.contains(path) {
+                victims.push(path.clone());
+                excess = excess.saturating_sub(size);
+            }
+        }
+        drop(protected);
+        let removed = victims.len();
+        for path in victims {
+            let Some(size) = self.entries.pop(&path) else {
+                continue;
+            };
+            if let Err(e) = fs::remove_file(&path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!("Failed to evict {}: {}", path.display(), e);
+            }
+            let _ = fs::remove_file(TileMeta::path(&path));
+            debug!("Evicted {}", path.display());
+            self.bytes -= size;
+        }
+        removed
+    }
+
+    pub fn stats(&self) -> DiskCacheStats {
+        let protected = PROTECTED.lock().unwrap();
+        let (protected_tiles, protected_bytes) = self
+            .entries
+            .iter()
+            .filter(|(path, _)| protected.contains(*path))
+            .fold((0, 0), |(tiles, bytes), (_, size)| {
+                (tiles + 1, bytes + size)
+            });
+        DiskCacheStats {
+            tiles: self.entries.len(),
+            bytes: self.bytes,
+            limit: self.limit,
+            protected_tiles,
+            protected_bytes,
+        }
+    }
+}
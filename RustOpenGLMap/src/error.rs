@@ -0,0 +1,43 @@
+use thiserror::Error;
+
+/// What can go wrong getting a tile or a shader onto the GPU. Each is
+/// reported and the tile or layer left out, so none of them ends the app.
+#[derive(Debug, Error)]
+pub enum MapError {
+    /// The request got no answer: DNS, connection, TLS or a timeout.
+    #[error("network error: {0}")]
+    Network(#[from] curl::Error),
+    /// The server answered with a status other than 200 or 304.
+    #[error("HTTP error: {0}")]
+    Http(u32),
+    /// The server answered with something that isn't a usable tile.
+    #[error("bad tile: {0}")]
+    BadTile(String),
+    /// Bytes that don't decode as an image, on disk or from the server.
+    #[error("decode error: {0}")]
+    Decode(#[from] image::ImageError),
+    /// Reading or writing the disk cache failed.
+    #[error("disk error: {0}")]
+    Disk(#[from] std::io::Error),
+    /// A shader that doesn't compile or link, or a GL object that can't be made.
+    #[error("GL error: {0}")]
+    Gl(String),
+    /// The source can't be asked: unknown, without the zoom, missing its key
+    /// or with downloads paused.
+    #[error("{0}")]
+    Source(String),
+}
+
+impl MapError {
+    /// 5xx and 429: the server is struggling or asking us to slow down.
+    pub fn is_server_error(&self) -> bool {
+        matches!(self, Self::Http(status) if *status == 429 || (500..600).contains(status))
+    }
+}
+
+/// For the many GL setup functions that report errors as text.
+impl From<MapError> for String {
+    fn from(error: MapError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,753 @@
+use crate::geo;
+use crate::labels::{self, PlaceLabel};
+use crate::layers::Shape;
+use crate::units;
+use crate::viewport::Viewport;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use image::{ImageFormat, RgbaImage};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// Size of place names and measurement labels, in pixels.
+const LABEL_PX: f32 = 12.0;
+/// Size of graticule degrees and the attribution, in pixels.
+const SMALL_PX: f32 = 9.0;
+/// Average glyph advance of Helvetica as a share of the font size, to place
+/// labels without the fonts the viewer will use.
+const ADVANCE: f32 = 0.55;
+/// Width of lines and polygon outlines, in pixels.
+const LINE_PX: f32 = 2.5;
+/// Points per window pixel in a PDF: a 96 dpi screen on 72 pt paper.
+const PT_PER_PX: f32 = 0.75;
+/// Graticule spacings in degrees to pick from.
+const GRATICULE_STEPS: [f64; 17] = [
+    0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0,
+    45.0,
+];
+/// Fewest graticule lines wanted across the wider extent of the view.
+const GRATICULE_LINES: f64 = 3.0;
+/// Points per graticule line, so they bend with the projection.
+const GRATICULE_SEGMENTS: usize = 64;
+/// Longest the scale bar gets, as a share of the figure width.
+const SCALE_BAR_SHARE: f64 = 0.25;
+/// Distance of the scale bar and the attribution from the figure edges.
+const EDGE_PX: f32 = 12.0;
+const TEXT_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+const HALO_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.9];
+const GRATICULE_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 0.5];
+
+/// Something drawn over the backdrop, in window pixels with y down.
+#[derive(Debug, Clone)]
+enum Mark {
+    /// Polylines, or with a fill, polygons whose later runs are holes.
+    Path {
+        runs: Vec<Vec<(f32, f32)>>,
+        fill: Option<[f32; 4]>,
+        stroke: Option<[f32; 4]>,
+        width: f32,
+    },
+    /// A marker, outlined in white.
+    Dot {
+        at: (f32, f32),
+        radius: f32,
+        color: [f32; 4],
+    },
+    /// Dark text with a light halo, `at` its top-left corner.
+    Text {
+        at: (f32, f32),
+        text: String,
+        size: f32,
+    },
+}
+
+/// The map as a print figure: the tiles as a picture, with the overlays,
+/// labels, a graticule, a scale bar and the attribution as vector graphics
+/// on top, saved as SVG or PDF.
+#[derive(Debug, Clone)]
+pub struct Figure {
+    /// Window size in pixels; the backdrop may be finer on HiDPI displays.
+    size: (u32, u32),
+    backdrop: RgbaImage,
+    marks: Vec<Mark>,
+}
+
+impl Figure {
+    pub fn new(backdrop: RgbaImage, size: (u32, u32)) -> Self {
+        Self {
+            size,
+            backdrop,
+            marks: Vec::new(),
+        }
+    }
+
+    /// Adds `shapes` as `vp` shows them, leaving out those off the figure.
+    pub fn add_shapes(&mut self, shapes: &[Shape], vp: &Viewport) {
+        let pixel = |world: (f64, f64)| {
+            let (x, y) = vp.world_to_pixel(world, self.size);
+            (x as f32, y as f32)
+        };
+        let mut marks = Vec::new();
+        for shape in shapes {
+            let mark = match shape {
+                Shape::Line { points, color } => Mark::Path {
+                    runs: vec![points.iter().copied().map(pixel).collect()],
+                    fill: None,
+                    stroke: Some(*color),
+                    width: LINE_PX,
+                },
+                Shape::Polygon {
+                    rings,
+                    fill,
+                    stroke,
+                } => Mark::Path {
+                    runs: rings
+                        .iter()
+                        .map(|ring| ring.iter().copied().map(pixel).collect())
+                        .collect(),
+                    fill: Some(*fill),
+                    stroke: Some(*stroke),
+                    width: LINE_PX / 2.0,
+                },
+                Shape::Dot { at, color, radius } => Mark::Dot {
+                    at: pixel(*at),
+                    radius: *radius,
+                    color: *color,
+                },
+            };
+            if self.shows(&mark) {
+                marks.push(mark);
+            }
+        }
+        self.marks.extend(marks);
+    }
+
+    /// Adds the place `names` that fit, like they are placed on screen, and
+    /// the measurement `tags` centred on their points.
+    pub fn add_labels(
+        &mut self,
+        names: &[PlaceLabel],
+        tags: &[((f64, f64), String)],
+        vp: &Viewport,
+    ) {
+        for (name, at) in labels::place(names, vp, self.size, |name| text_size(name, LABEL_PX)) {
+            self.add_text(name, at, LABEL_PX);
+        }
+        for (world, tag) in tags {
+            let (x, y) = vp.world_to_pixel(*world, self.size);
+            let (w, h) = text_size(tag, LABEL_PX);
+            self.add_text(tag, (x as f32 - w / 2.0, y as f32 - h / 2.0), LABEL_PX);
+        }
+    }
+
+    /// Adds lines of latitude and longitude at a round spacing for the
+    /// view's extent, labelled in degrees along the top and left edges.
+    pub fn add_graticule(&mut self, vp: &Viewport) {
+        let (w, h) = (f64::from(self.size.0), f64::from(self.size.1));
+        let mut lat = (f64::MAX, f64::MIN);
+        let mut lon = (f64::MAX, f64::MIN);
+        for i in 0..=8 {
+            for j in 0..=8 {
+                let px = (w * f64::from(i) / 8.0, h * f64::from(j) / 8.0);
+                let (a, o) = vp.pixel_to_latlon(px, self.size);
+                lat = (lat.0.min(a), lat.1.max(a));
+                lon = (lon.0.min(o), lon.1.max(o));
+            }
+        }
+        if vp.projection.is_polar() {
+            // every meridian meets at the pole
+            lon = (-180.0, 180.0);
+        }
+        let lat = (lat.0.max(-geo::MAX_LATITUDE), lat.1.min(geo::MAX_LATITUDE));
+        let span = (lat.1 - lat.0).max(lon.1 - lon.0);
+        let step = GRATICULE_STEPS
+            .iter()
+            .copied()
+            .rfind(|step| span / step >= GRATICULE_LINES)
+            .unwrap_or(GRATICULE_STEPS[0]);
+        let decimals = (-step.log10().floor()).max(0.0) as usize;
+
+        let pixel = |lat: f64, lon: f64| {
+            let (x, y) = vp.world_to_pixel(geo::project(lat, lon), self.size);
+            (x as f32, y as f32)
+        };
+        let inside = |p: &(f32, f32)| {
+            p.0 >= 0.0 && p.1 >= 0.0 && p.0 <= self.size.0 as f32 && p.1 <= self.size.1 as f32
+        };
+        let mut lines = Vec::new();
+        let mut tags = Vec::new();
+        for k in (lon.0 / step).ceil() as i64..=(lon.1 / step).floor() as i64 {
+            let value = k as f64 * step;
+            let run: Vec<(f32, f32)> = (0..=GRATICULE_SEGMENTS)
+                .map(|i| {
+                    let t = i as f64 / GRATICULE_SEGMENTS as f64;
+                    pixel(lat.0 + (lat.1 - lat.0) * t, value)
+                })
+                .collect();
+            // labelled where the meridian is highest in the figure
+            if let Some(top) = run
+                .iter()
+                .filter(|p| inside(p))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+            {
+                tags.push((
+                    degrees(value, decimals, 'E', 'W'),
+                    (top.0 + 2.0, top.1 + 2.0),
+                ));
+            }
+            lines.push(run);
+        }
+        for k in (lat.0 / step).ceil() as i64..=(lat.1 / step).floor() as i64 {
+            let value = k as f64 * step;
+            let run: Vec<(f32, f32)> = (0..=GRATICULE_SEGMENTS)
+                .map(|i| {
+                    let t = i as f64 / GRATICULE_SEGMENTS as f64;
+                    pixel(value, lon.0 + (lon.1 - lon.0) * t)
+                })
+                .collect();
+            // labelled where the parallel is leftmost in the figure
+            if let Some(left) = run
+                .iter()
+                .filter(|p| inside(p))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+            {
+                tags.push((
+                    degrees(value, decimals, 'N', 'S'),
+                    (left.0 + 2.0, left.1 + 2.0),
+                ));
+            }
+            lines.push(run);
+        }
+        self.marks.push(Mark::Path {
+            runs: lines,
+            fill: None,
+            stroke: Some(GRATICULE_COLOR),
+            width: 0.75,
+        });
+        for (tag, at) in tags {
+            self.add_text(&tag, at, SMALL_PX);
+        }
+    }
+
+    /// Adds a bar of a round length in the current units in the bottom-left
+    /// corner, measured across the centre of the view.
+    pub fn add_scale_bar(&mut self, vp: &Viewport) {
+        let (w, h) = (f64::from(self.size.0), f64::from(self.size.1));
+        let a = vp.pixel_to_latlon((w / 2.0 - 50.0, h / 2.0), self.size);
+        let b = vp.pixel_to_latlon((w / 2.0 + 50.0, h / 2.0), self.size);
+        let metres_per_px = geo::distance(a, b) / 100.0;
+        if metres_per_px <= 0.0 || !metres_per_px.is_finite() {
+            return;
+        }
+        let longest = w * SCALE_BAR_SHARE * metres_per_px;
+        let (length, tag) = units::current().scale_length(longest);
+        let bar = (length / metres_per_px) as f32;
+        let (tag_w, tag_h) = text_size(&tag, LABEL_PX);
+        let (left, bottom) = (EDGE_PX, self.size.1 as f32 - EDGE_PX);
+        self.marks.push(Mark::Path {
+            runs: vec![rect(
+                left - 4.0,
+                bottom - tag_h - 14.0,
+                left + bar.max(tag_w) + 4.0,
+                bottom + 4.0,
+            )],
+            fill: Some(HALO_COLOR),
+            stroke: None,
+            width: 0.0,
+        });
+        self.marks.push(Mark::Path {
+            runs: vec![rect(left, bottom - 4.0, left + bar, bottom)],
+            fill: Some(TEXT_COLOR),
+            stroke: None,
+            width: 0.0,
+        });
+        self.add_text(&tag, (left, bottom - tag_h - 8.0), LABEL_PX);
+    }
+
+    /// Adds `text` in the bottom-right corner, as the tile licences ask.
+    pub fn add_attribution(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let (w, h) = text_size(text, SMALL_PX);
+        let (right, bottom) = (self.size.0 as f32 - EDGE_PX, self.size.1 as f32 - EDGE_PX);
+        self.marks.push(Mark::Path {
+            runs: vec![rect(
+                right - w - 4.0,
+                bottom - h - 4.0,
+                right + 4.0,
+                bottom + 4.0,
+            )],
+            fill: Some(HALO_COLOR),
+            stroke: None,
+            width: 0.0,
+        });
+        self.add_text(text, (right - w, bottom - h), SMALL_PX);
+    }
+
+    /// Writes the figure to `path`, as PDF if it ends in .pdf and as SVG
+    /// otherwise.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let pdf = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if pdf {
+            fs::write(path, self.to_pdf()?)?;
+        } else {
+            fs::write(path, self.to_svg()?)?;
+        }
+        Ok(())
+    }
+
+    fn add_text(&mut self, text: &str, at: (f32, f32), size: f32) {
+        self.marks.push(Mark::Text {
+            at,
+            text: text.to_string(),
+            size,
+        });
+    }
+
+    /// False for marks entirely off the figure.
+    fn shows(&self, mark: &Mark) -> bool {
+        let (w, h) = (self.size.0 as f32, self.size.1 as f32);
+        match mark {
+            Mark::Path { runs, .. } => {
+                let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+                for &(x, y) in runs.iter().flatten() {
+                    bounds = [
+                        bounds[0].min(x),
+                        bounds[1].min(y),
+                        bounds[2].max(x),
+                        bounds[3].max(y),
+                    ];
+                }
+                bounds[0] <= w && bounds[1] <= h && bounds[2] >= 0.0 && bounds[3] >= 0.0
+            }
+            Mark::Dot { at, radius, .. } => {
+                at.0 >= -radius && at.1 >= -radius && at.0 <= w + radius && at.1 <= h + radius
+            }
+            Mark::Text { .. } => true,
+        }
+    }
+
+    fn to_svg(&self) -> Result<String, Box<dyn Error>> {
+        let (w, h) = self.size;
+        let mut png = Vec::new();
+        self.backdrop
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        let mut svg = String::new();
+        writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#
+        )?;
+        writeln!(
+            svg,
+            r#"<defs><clipPath id="view"><rect width="{w}" height="{h}"/></clipPath></defs>"#
+        )?;
+        writeln!(
+            svg,
+            r#"<image width="{w}" height="{h}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+            base64(&png)
+        )?;
+        writeln!(
+            svg,
+            r#"<g clip-path="url(#view)" stroke-linejoin="round" stroke-linecap="round" font-family="Helvetica, Arial, sans-serif">"#
+        )?;
+        for mark in &self.marks {
+            match mark {
+                Mark::Path {
+                    runs,
+                    fill,
+                    stroke,
+                    width,
+                } => {
+                    let mut d = String::new();
+                    for run in runs.iter().filter(|run| !run.is_empty()) {
+                        for (i, (x, y)) in run.iter().enumerate() {
+                            let op = if i == 0 { 'M' } else { 'L' };
+                            write!(d, "{}{:.2},{:.2} ", op, x, y)?;
+                        }
+                        if fill.is_some() {
+                            d.push_str("Z ");
+                        }
+                    }
+                    let fill = match fill {
+                        Some(color) => svg_paint("fill", *color),
+                        None => r#"fill="none""#.to_string(),
+                    };
+                    let stroke = match stroke {
+                        Some(color) => {
+                            format!(
+                                r#"{} stroke-width="{}""#,
+                                svg_paint("stroke", *color),
+                                width
+                            )
+                        }
+                        None => String::new(),
+                    };
+                    writeln!(
+                        svg,
+                        r#"<path d="{}" fill-rule="evenodd" {} {}/>"#,
+                        d.trim_end(),
+                        fill,
+                        stroke
+                    )?;
+                }
+                Mark::Dot { at, radius, color } => writeln!(
+                    svg,
+                    r#"<circle cx="{:.2}" cy="{:.2}" r="{}" {} stroke="white" stroke-width="1.5"/>"#,
+                    at.0,
+                    at.1,
+                    radius,
+                    svg_paint("fill", *color)
+                )?,
+                Mark::Text { at, text, size } => writeln!(
+                    svg,
+                    r#"<text x="{:.2}" y="{:.2}" font-size="{}" {} {} stroke-width="3" paint-order="stroke">{}</text>"#,
+                    at.0,
+                    baseline(at.1, *size),
+                    size,
+                    svg_paint("fill", TEXT_COLOR),
+                    svg_paint("stroke", HALO_COLOR),
+                    xml_escape(text)
+                )?,
+            }
+        }
+        writeln!(svg, "</g>")?;
+        writeln!(svg, "</svg>")?;
+        Ok(svg)
+    }
+
+    /// A single-page PDF 1.4: the backdrop as a Flate-compressed RGB image,
+    /// the marks as paths and Helvetica text, one window pixel to 0.75 pt.
+    fn to_pdf(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (w, h) = (self.size.0 as f32, self.size.1 as f32);
+        let mut content = Vec::new();
+        // pixel coordinates with y down, like everything else here
+        writeln!(
+            content,
+            "{} 0 0 {} 0 {:.2} cm",
+            PT_PER_PX,
+            -PT_PER_PX,
+            h * PT_PER_PX
+        )?;
+        writeln!(content, "q {} 0 0 {} 0 {} cm /Backdrop Do Q", w, -h, h)?;
+        writeln!(content, "0 0 {} {} re W n 1 j 1 J", w, h)?;
+        let mut alphas = BTreeSet::new();
+        let mut gs = |content: &mut Vec<u8>, fill: f32, stroke: f32| -> std::io::Result<()> {
+            let key = (percent(fill), percent(stroke));
+            alphas.insert(key);
+            writeln!(content, "/A{}_{} gs", key.0, key.1)
+        };
+        for mark in &self.marks {
+            writeln!(content, "q")?;
+            match mark {
+                Mark::Path {
+                    runs,
+                    fill,
+                    stroke,
+                    width,
+                } => {
+                    gs(
+                        &mut content,
+                        fill.map_or(1.0, |c| c[3]),
+                        stroke.map_or(1.0, |c| c[3]),
+                    )?;
+                    if let Some(color) = fill {
+                        writeln!(content, "{} rg", pdf_rgb(*color))?;
+                    }
+                    if let Some(color) = stroke {
+                        writeln!(content, "{} RG {} w", pdf_rgb(*color), width)?;
+                    }
+                    for run in runs.iter().filter(|run| !run.is_empty()) {
+                        for (i, (x, y)) in run.iter().enumerate() {
+                            let op = if i == 0 { 'm' } else { 'l' };
+                            writeln!(content, "{:.2} {:.2} {}", x, y, op)?;
+                        }
+                        if fill.is_some() {
+                            writeln!(content, "h")?;
+                        }
+                    }
+                    let op = match (fill, stroke) {
+                        (Some(_), Some(_)) => "B*",
+                        (Some(_), None) => "f*",
+                        _ => "S",
+                    };
+                    writeln!(content, "{}", op)?;
+                }
+                Mark::Dot { at, radius, color } => {
+                    gs(&mut content, color[3], 1.0)?;
+                    writeln!(content, "{} rg 1 1 1 RG 1.5 w", pdf_rgb(*color))?;
+                    content.extend(circle(*at, *radius).as_bytes());
+                    writeln!(content, "B")?;
+                }
+                Mark::Text { at, text, size } => {
+                    gs(&mut content, TEXT_COLOR[3], HALO_COLOR[3])?;
+                    let text = pdf_string(text);
+                    let y = baseline(at.1, *size);
+                    // the halo is the glyph outlines stroked wide, then the glyphs
+                    // filled over it
+                    write!(
+                        content,
+                        "BT /Label {} Tf 1 0 0 -1 {:.2} {:.2} Tm {} RG 3 w 1 Tr ",
+                        size,
+                        at.0,
+                        y,
+                        pdf_rgb(HALO_COLOR)
+                    )?;
+                    content.extend(&text);
+                    write!(
+                        content,
+                        " Tj 1 0 0 -1 {:.2} {:.2} Tm {} rg 0 Tr ",
+                        at.0,
+                        y,
+                        pdf_rgb(TEXT_COLOR)
+                    )?;
+                    content.extend(&text);
+                    writeln!(content, " Tj ET")?;
+                }
+            }
+            writeln!(content, "Q")?;
+        }
+
+        let (bw, bh) = self.backdrop.dimensions();
+        let rgb: Vec<u8> = self
+            .backdrop
+            .pixels()
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect();
+        let image = deflate(&rgb)?;
+        let content = deflate(&content)?;
+        let mut states = String::new();
+        for (fill, stroke) in &alphas {
+            write!(
+                states,
+                "/A{fill}_{stroke} << /ca {} /CA {} >> ",
+                f32::from(*fill) / 100.0,
+                f32::from(*stroke) / 100.0
+            )?;
+        }
+
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents 4 0 R \
+                 /Resources << /XObject << /Backdrop 5 0 R >> /Font << /Label 6 0 R >> \
+                 /ExtGState << {}>> >> >>",
+                w * PT_PER_PX,
+                h * PT_PER_PX,
+                states
+            )
+            .into_bytes(),
+        ];
+        objects.push(stream(
+            &format!("/Length {} /Filter /FlateDecode", content.len()),
+            &content,
+        ));
+        objects.push(stream(
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+                 /BitsPerComponent 8 /Length {} /Filter /FlateDecode",
+                bw,
+                bh,
+                image.len()
+            ),
+            &image,
+        ));
+        objects.push(
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .to_vec(),
+        );
+
+        let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            writeln!(pdf, "{} 0 obj", i + 1)?;
+            pdf.extend(object);
+            pdf.extend(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1)?;
+        for offset in offsets {
+            writeln!(pdf, "{:010} 00000 n ", offset)?;
+        }
+        write!(
+            pdf,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )?;
+        Ok(pdf)
+    }
+}
+
+/// Reads the back buffer, `size` drawable pixels, top row first.
+pub fn read_back_buffer(size: (u32, u32)) -> RgbaImage {
+    let (w, h) = size;
+    let mut pixels = vec![0u8; (w * h * 4) as usize];
+    unsafe {
+        gl::ReadBuffer(gl::BACK);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            w as i32,
+            h as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr().cast(),
+        );
+    }
+    let mut image = RgbaImage::from_raw(w, h, pixels).expect("back buffer size");
+    image::imageops::flip_vertical_in_place(&mut image);
+    image
+}
+
+/// Roughly how big `text` comes out at `size` pixels in Helvetica.
+fn text_size(text: &str, size: f32) -> (f32, f32) {
+    (text.chars().count() as f32 * size * ADVANCE, size)
+}
+
+/// Baseline of text whose top is at `top`.
+fn baseline(top: f32, size: f32) -> f32 {
+    top + size * 0.8
+}
+
+/// "12.5°N", "0°" or "120°W".
+fn degrees(value: f64, decimals: usize, positive: char, negative: char) -> String {
+    let value = (value * 1e6).round() / 1e6;
+    let hemisphere = if value > 0.0 {
+        positive.to_string()
+    } else if value < 0.0 {
+        negative.to_string()
+    } else {
+        String::new()
+    };
+    format!("{:.*}°{}", decimals, value.abs(), hemisphere)
+}
+
+fn rect(left: f32, top: f32, right: f32, bottom: f32) -> Vec<(f32, f32)> {
+    vec![(left, top), (right, top), (right, bottom), (left, bottom)]
+}
+
+fn svg_paint(attribute: &str, color: [f32; 4]) -> String {
+    let [r, g, b] = [color[0], color[1], color[2]].map(|c| (c.clamp(0.0, 1.0) * 255.0).round());
+    format!(
+        r#"{attribute}="rgb({},{},{})" {attribute}-opacity="{:.2}""#,
+        r, g, b, color[3]
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn percent(alpha: f32) -> u8 {
+    (alpha.clamp(0.0, 1.0) * 100.0).round() as u8
+}
+
+fn pdf_rgb(color: [f32; 4]) -> String {
+    format!("{:.3} {:.3} {:.3}", color[0], color[1], color[2])
+}
+
+/// `text` as a PDF string in WinAnsi, which matches Latin-1 for everything
+/// but a few punctuation marks; other characters become '?'.
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for c in text.chars() {
+        let byte = u8::try_from(u32::from(c)).unwrap_or(b'?');
+        if matches!(byte, b'(' | b')' | b'\\') {
+            out.push(b'\\');
+        }
+        out.push(byte);
+    }
+    out.push(b')');
+    out
+}
+
+/// A closed path around a circle, from four Bézier quarters.
+fn circle(at: (f32, f32), radius: f32) -> String {
+    // control point distance that makes a cubic quarter circle
+    let k = radius * 0.552_284_8;
+    let (x, y, r) = (at.0, at.1, radius);
+    format!(
+        "{:.2} {:.2} m\n\
+         {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c\n\
+         {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c\n\
+         {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c\n\
+         {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c\n",
+        x + r,
+        y,
+        x + r,
+        y + k,
+        x + k,
+        y + r,
+        x,
+        y + r,
+        x - k,
+        y + r,
+        x - r,
+        y + k,
+        x - r,
+        y,
+        x - r,
+        y - k,
+        x - k,
+        y - r,
+        x,
+        y - r,
+        x + k,
+        y - r,
+        x + r,
+        y - k,
+        x + r,
+        y
+    )
+}
+
+fn stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+    let mut out = format!("<< {} >>\nstream\n", dictionary).into_bytes();
+    out.extend(data);
+    out.extend(b"\nendstream");
+    out
+}
+
+fn deflate(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,166 @@
+use crate::bookmarks::{BOOKMARKS_PATH, Bookmark, Bookmarks};
+use crate::offscreen::Offscreen;
+use crate::tile_layers::TileLayer;
+use clap::Args;
+use image::{Rgba, RgbaImage};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `RustOpenGLMap capture --out <dir> [--views <file>] [--compare <dir>]`
+///
+/// Renders each view through the same tile path as the window, from the
+/// disk cache only, so two builds capturing the same views see the same
+/// tiles. Overlays and the HUD aren't drawn.
+#[derive(Debug, Args)]
+pub struct CaptureArgs {
+    /// Directory the frames are written to, one PNG per view.
+    #[arg(long)]
+    out: PathBuf,
+    /// Views to render, in the format of bookmarks.toml.
+    #[arg(long, default_value = BOOKMARKS_PATH)]
+    views: String,
+    /// Earlier capture to diff against; diff images and report.txt go to --out.
+    #[arg(long, value_name = "DIR")]
+    compare: Option<PathBuf>,
+    #[arg(long, default_value_t = 800)]
+    width: u32,
+    #[arg(long, default_value_t = 600)]
+    height: u32,
+    /// Largest channel difference that still counts as the same pixel.
+    #[arg(long, default_value_t = 0)]
+    tolerance: u8,
+}
+
+/// How two captures of a view differ.
+pub struct FrameDiff {
+    pub changed: u64,
+    pub total: u64,
+    pub max_delta: u8,
+    /// Changed pixels in red over a faded copy of the new frame.
+    pub image: RgbaImage,
+}
+
+/// Compares two frames of the same size pixel by pixel.
+pub fn diff_frames(
+    before: &RgbaImage,
+    after: &RgbaImage,
+    tolerance: u8,
+) -> Result<FrameDiff, String> {
+    if before.dimensions() != after.dimensions() {
+        return Err(format!(
+            "sizes differ, {:?} before and {:?} after",
+            before.dimensions(),
+            after.dimensions()
+        ));
+    }
+    let mut diff = FrameDiff {
+        changed: 0,
+        total: u64::from(after.width()) * u64::from(after.height()),
+        max_delta: 0,
+        image: RgbaImage::new(after.width(), after.height()),
+    };
+    for (x, y, new) in after.enumerate_pixels() {
+        let old = before.get_pixel(x, y);
+        let delta = (0..4).map(|c| old[c].abs_diff(new[c])).max().unwrap_or(0);
+        diff.max_delta = diff.max_delta.max(delta);
+        let pixel = if delta > tolerance {
+            diff.changed += 1;
+            Rgba([128 + delta / 2, 0, 0, 255])
+        } else {
+            let luma = ((u16::from(new[0]) + u16::from(new[1]) + u16::from(new[2])) / 3) as u8;
+            let faded = 170 + luma / 3;
+            Rgba([faded, faded, faded, 255])
+        };
+        diff.image.put_pixel(x, y, pixel);
+    }
+    Ok(diff)
+}
+
+/// `07_harbour.png` for the eighth view, named after the bookmark.
+fn frame_name(index: usize, view: &Bookmark) -> String {
+    let name: String = view
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{:02}_{}.png", index, name)
+}
+
+pub fn run_capture_command(args: CaptureArgs) -> Result<(), String> {
+    let views = Bookmarks::load_or_default(&args.views);
+    if views.bookmarks.is_empty() {
+        return Err(format!("No views in {}", args.views));
+    }
+    fs::create_dir_all(&args.out).map_err(|e| e.to_string())?;
+
+    let offscreen = Offscreen::new((args.width, args.height))?;
+
+    let mut report = String::new();
+    let mut differing = 0;
+    for (i, view) in views.bookmarks.iter().enumerate() {
+        let name = frame_name(i, view);
+        let (frame, _) = offscreen.render(view.viewport(), &[TileLayer::base(view.source)], false);
+        frame
+            .save(args.out.join(&name))
+            .map_err(|e| format!("Failed to write {}: {}", name, e))?;
+        let Some(compare) = &args.compare else {
+            println!("Captured {}", name);
+            continue;
+        };
+        let line = match compare_frame(&compare.join(&name), &frame, args.tolerance) {
+            Ok(diff) => {
+                if diff.changed > 0 {
+                    differing += 1;
+                    let diff_path = args.out.join(format!("diff_{}", name));
+                    diff.image
+                        .save(&diff_path)
+                        .map_err(|e| format!("Failed to write {}: {}", diff_path.display(), e))?;
+                }
+                format!(
+                    "{:<32} {:>7.3}% changed  {:>8} px  max delta {}",
+                    name,
+                    diff.changed as f64 * 100.0 / diff.total as f64,
+                    diff.changed,
+                    diff.max_delta
+                )
+            }
+            Err(e) => {
+                differing += 1;
+                format!("{:<32} {}", name, e)
+            }
+        };
+        println!("{}", line);
+        let _ = writeln!(report, "{}", line);
+    }
+
+    let Some(compare) = &args.compare else {
+        return Ok(());
+    };
+    let summary = format!(
+        "{} of {} views differ from {}",
+        differing,
+        views.len(),
+        compare.display()
+    );
+    let _ = writeln!(report, "{}", summary);
+    fs::write(args.out.join("report.txt"), report).map_err(|e| e.to_string())?;
+    if differing > 0 {
+        return Err(summary);
+    }
+    println!("{}", summary);
+    Ok(())
+}
+
+fn compare_frame(before: &Path, after: &RgbaImage, tolerance: u8) -> Result<FrameDiff, String> {
+    let before = image::open(before)
+        .map_err(|e| format!("no earlier frame: {}", e))?
+        .to_rgba8();
+    diff_frames(&before, after, tolerance)
+}
@@ -0,0 +1,205 @@
+use sdl2::VideoSubsystem;
+use sdl2::video::SwapInterval;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Rate assumed when the display doesn't report one.
+const FALLBACK_HZ: i32 = 60;
+/// Frame rate caps F4 steps through after the display's own rate.
+const CAPS: [u32; 3] = [30, 60, 120];
+/// Weight of the newest frame in the measured frame rate.
+const FPS_SMOOTHING: f64 = 0.05;
+
+/// Whether buffer swaps wait for the display's vertical blank.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VSync {
+    #[default]
+    On,
+    /// Swaps right away, tearing but never waiting.
+    Off,
+    /// Waits for the blank unless the frame is already late, then tears.
+    /// Falls back to `On` where the driver can't do it.
+    Adaptive,
+}
+
+impl VSync {
+    pub fn next(self) -> Self {
+        match self {
+            Self::On => Self::Off,
+            Self::Off => Self::Adaptive,
+            Self::Adaptive => Self::On,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::On => "on",
+            Self::Off => "off",
+            Self::Adaptive => "adaptive",
+        }
+    }
+}
+
+/// The `[display]` table of settings.toml.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplaySettings {
+    #[serde(default)]
+    pub vsync: VSync,
+    /// Most frames drawn a second; 0 follows the display's refresh rate.
+    #[serde(default)]
+    pub max_fps: u32,
+    /// Draws frames back to back without any cap, for benchmarking.
+    #[serde(default)]
+    pub uncapped: bool,
+}
+
+impl DisplaySettings {
+    pub fn cap(&self) -> FrameCap {
+        match (self.uncapped, self.max_fps) {
+            (true, _) => FrameCap::Uncapped,
+            (false, 0) => FrameCap::Display,
+            (false, fps) => FrameCap::Fps(fps),
+        }
+    }
+}
+
+/// How often the main loop may draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCap {
+    /// Once per refresh of the display the window is on.
+    Display,
+    Fps(u32),
+    /// As fast as it can, redrawing even a static view.
+    Uncapped,
+}
+
+impl FrameCap {
+    /// The display's rate, then each of `CAPS`, then uncapped.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Display => Self::Fps(CAPS[0]),
+            Self::Fps(fps) => match CAPS.iter().find(|&&cap| cap > fps) {
+                Some(&cap) => Self::Fps(cap),
+                None => Self::Uncapped,
+            },
+            Self::Uncapped => Self::Display,
+        }
+    }
+
+    pub fn describe(self) -> String {
+        match self {
+            Self::Display => "the display's refresh rate".to_string(),
+            Self::Fps(fps) => format!("{} fps", fps),
+            Self::Uncapped => "uncapped".to_string(),
+        }
+    }
+}
+
+/// Makes buffer swaps follow `vsync`, returning the mode the driver took.
+pub fn set_vsync(video: &VideoSubsystem, vsync: VSync) -> VSync {
+    let interval = match vsync {
+        VSync::On => SwapInterval::VSync,
+        VSync::Off => SwapInterval::Immediate,
+        VSync::Adaptive => SwapInterval::LateSwapTearing,
+    };
+    match video.gl_set_swap_interval(interval) {
+        Ok(()) => vsync,
+        Err(e) if vsync == VSync::Adaptive => {
+            warn!("Adaptive vsync unavailable ({}), using vsync", e);
+            set_vsync(video, VSync::On)
+        }
+        Err(e) => {
+            warn!("Failed to set vsync {}: {}", vsync.name(), e);
+            vsync
+        }
+    }
+}
+
+/// Runs the main loop once per refresh of the display the window is on, or
+/// at the rate `FrameCap` asks for.
+///
+/// Frames are timed from where the last one started rather than by sleeping
+/// a fixed time after it, so slow frames don't add up and animations advance
+/// by what really passed between frames.
+#[derive(Debug, Clone, Copy)]
+pub struct FramePacer {
+    refresh_hz: i32,
+    cap: FrameCap,
+    /// Least time between frame starts, none when uncapped.
+    period: Option<Duration>,
+    /// When the current frame started.
+    frame: Instant,
+    /// Smoothed seconds between frame starts.
+    average: f64,
+}
+
+impl FramePacer {
+    pub fn new(refresh_hz: i32, cap: FrameCap) -> Self {
+        let mut pacer = Self {
+            refresh_hz,
+            cap,
+            period: None,
+            frame: Instant::now(),
+            average: 0.0,
+        };
+        pacer.set_refresh_rate(refresh_hz);
+        pacer
+    }
+
+    /// Follows the window to a display refreshing `hz` times a second;
+    /// 0 or less (unknown) counts as 60 Hz.
+    pub fn set_refresh_rate(&mut self, hz: i32) {
+        self.refresh_hz = if hz > 0 { hz } else { FALLBACK_HZ };
+        self.set_cap(self.cap);
+    }
+
+    pub fn cap(&self) -> FrameCap {
+        self.cap
+    }
+
+    pub fn set_cap(&mut self, cap: FrameCap) {
+        self.cap = cap;
+        self.period = match cap {
+            FrameCap::Display => Some(Duration::from_secs(1) / self.refresh_hz as u32),
+            FrameCap::Fps(fps) => Some(Duration::from_secs(1) / fps.max(1)),
+            FrameCap::Uncapped => None,
+        };
+    }
+
+    /// Starts a frame, returning its timestamp for everything that moves.
+    pub fn begin(&mut self) -> Instant {
+        let now = Instant::now();
+        let elapsed = (now - self.frame).as_secs_f64();
+        self.average = if self.average > 0.0 {
+            self.average + (elapsed - self.average) * FPS_SMOOTHING
+        } else {
+            elapsed
+        };
+        self.frame = now;
+        self.frame
+    }
+
+    /// Frames started a second, averaged over the last few dozen.
+    pub fn fps(&self) -> f64 {
+        if self.average > 0.0 {
+            1.0 / self.average
+        } else {
+            0.0
+        }
+    }
+
+    /// Sleeps out what is left of the frame's period; returns at once when
+    /// uncapped.
+    pub fn wait(&self) {
+        let Some(period) = self.period else {
+            return;
+        };
+        let deadline = self.frame + period;
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        }
+    }
+}
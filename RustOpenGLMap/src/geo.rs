@@ -0,0 +1,134 @@
+use std::f64::consts::PI;
+
+/// Latitude at which Web Mercator becomes a square world.
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Half the width of the Web Mercator plane in metres.
+pub const MERCATOR_EXTENT_M: f64 = 20_037_508.342_789_244;
+
+/// Projects WGS84 degrees into normalised Web Mercator, both axes in [0, 1].
+/// X grows east and Y grows *south*, matching OSM tile numbering.
+pub fn project(lat: f64, lon: f64) -> (f64, f64) {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.0) / 360.0;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0;
+    (x, y)
+}
+
+/// Inverse of `project`, returns (lat, lon) in degrees.
+pub fn unproject(x: f64, y: f64) -> (f64, f64) {
+    let lon = x * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees();
+    (lat, lon)
+}
+
+/// (south, west, north, east) box around projected `points`, `None` if there are none.
+pub fn bounds(points: impl IntoIterator<Item = (f64, f64)>) -> Option<[f64; 4]> {
+    let mut points = points.into_iter();
+    let first = points.next()?;
+    let (x0, y0, x1, y1) = points.fold(
+        (first.0, first.1, first.0, first.1),
+        |(x0, y0, x1, y1), (x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+    );
+    // y grows south, so the top left corner is the north-west one
+    let (north, west) = unproject(x0, y0);
+    let (south, east) = unproject(x1, y1);
+    Some([south, west, north, east])
+}
+
+/// Number of tiles along one axis at zoom `z`.
+pub fn world_tiles(z: u8) -> f64 {
+    (1u64 << z) as f64
+}
+
+/// Mean Earth radius in metres, as used for great-circle distances.
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+fn to_unit_vector(lat: f64, lon: f64) -> [f64; 3] {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+/// Great-circle distance in metres between two (lat, lon) points in degrees.
+pub fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.1 - a.1).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().min(1.0).asin()
+}
+
+/// Area in square metres enclosed by the ring of (lat, lon) points, on the
+/// sphere. The ring closes itself; winding order doesn't matter.
+pub fn polygon_area(ring: &[(f64, f64)]) -> f64 {
+    if ring.len() < 3 {
+        return 0.0;
+    }
+    // Chamberlain & Duquette, "Some algorithms for polygons on a sphere"
+    let mut sum = 0.0;
+    for (i, a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        let mut dlon = (b.1 - a.1).to_radians();
+        // take the short way round across the antimeridian
+        if dlon > PI {
+            dlon -= 2.0 * PI;
+        } else if dlon < -PI {
+            dlon += 2.0 * PI;
+        }
+        sum += dlon * (2.0 + a.0.to_radians().sin() + b.0.to_radians().sin());
+    }
+    (sum * EARTH_RADIUS_M * EARTH_RADIUS_M / 2.0).abs()
+}
+
+/// `steps + 1` (lat, lon) points along the shortest path from `a` to `b`,
+/// so the arc bends the way it should once projected to Mercator.
+pub fn great_circle(a: (f64, f64), b: (f64, f64), steps: usize) -> Vec<(f64, f64)> {
+    let (va, vb) = (to_unit_vector(a.0, a.1), to_unit_vector(b.0, b.1));
+    let dot = (va[0] * vb[0] + va[1] * vb[1] + va[2] * vb[2]).clamp(-1.0, 1.0);
+    let angle = dot.acos();
+    if angle < 1e-12 {
+        return vec![a, b];
+    }
+    (0..=steps)
+        .map(|i| {
+            // spherical linear interpolation between the two unit vectors
+            let t = i as f64 / steps as f64;
+            let wa = ((1.0 - t) * angle).sin() / angle.sin();
+            let wb = (t * angle).sin() / angle.sin();
+            let v = [0, 1, 2].map(|k| wa * va[k] + wb * vb[k]);
+            (
+                v[2].atan2(v[0].hypot(v[1])).to_degrees(),
+                v[1].atan2(v[0]).to_degrees(),
+            )
+        })
+        .collect()
+}
+
+/// The (lat, lon) reached from `from` after `metres` along the great circle
+/// leaving it at `bearing` degrees clockwise from north.
+pub fn destination(from: (f64, f64), bearing: f64, metres: f64) -> (f64, f64) {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (sin_b, cos_b) = bearing.to_radians().sin_cos();
+    let angle = metres / EARTH_RADIUS_M;
+    let sin_lat2 = lat1.sin() * angle.cos() + lat1.cos() * angle.sin() * cos_b;
+    let lat2 = sin_lat2.clamp(-1.0, 1.0).asin();
+    let lon2 = lon1 + (sin_b * angle.sin() * lat1.cos()).atan2(angle.cos() - lat1.sin() * sin_lat2);
+    (
+        lat2.to_degrees(),
+        (lon2.to_degrees() + 540.0).rem_euclid(360.0) - 180.0,
+    )
+}
+
+/// Splits a run of (lat, lon) points wherever it jumps across the antimeridian.
+pub fn split_at_antimeridian(points: &[(f64, f64)]) -> Vec<Vec<(f64, f64)>> {
+    let mut runs = vec![Vec::new()];
+    for (i, point) in points.iter().enumerate() {
+        if i > 0 && (point.1 - points[i - 1].1).abs() > 180.0 {
+            runs.push(Vec::new());
+        }
+        if let Some(run) = runs.last_mut() {
+            run.push(*point);
+        }
+    }
+    runs
+}
@@ -0,0 +1,213 @@
+use crate::opengl_helper::USER_AGENT;
+use crate::viewport::Viewport;
+use crate::wake;
+use curl::easy::Easy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Public Nominatim instance, see https://operations.osmfoundation.org/policies/nominatim/
+pub const NOMINATIM_URL: &str = "https://nominatim.openstreetmap.org/search";
+/// Where batch geocoding remembers answers between runs.
+pub const GEOCODE_CACHE_PATH: &str = "geocode_cache.json";
+/// Results asked for per query.
+const RESULT_LIMIT: u32 = 8;
+/// The usage policy allows one request per second at most.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `[geocoder]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeocoderSettings {
+    /// Search endpoint of a Nominatim-compatible geocoder.
+    #[serde(default = "default_url")]
+    pub url: String,
+}
+
+fn default_url() -> String {
+    NOMINATIM_URL.to_string()
+}
+
+impl Default for GeocoderSettings {
+    fn default() -> Self {
+        Self { url: default_url() }
+    }
+}
+
+/// Spaces requests at least `MIN_INTERVAL` apart.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    /// Sleeps until the next request may go out.
+    pub fn wait(&mut self) {
+        if let Some(last) = self.last {
+            thread::sleep(MIN_INTERVAL.saturating_sub(last.elapsed()));
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+/// Best (lat, lon) per query, misses included so they aren't asked again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GeocodeCache {
+    entries: HashMap<String, Option<(f64, f64)>>,
+}
+
+impl GeocodeCache {
+    /// Reads the cache at `path`, starting empty if there is none.
+    pub fn load_or_default(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::default();
+        }
+        std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                warn!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    fn key(query: &str) -> String {
+        query.trim().to_lowercase()
+    }
+
+    /// `None` if the query was never asked, `Some(None)` if it had no match.
+    pub fn get(&self, query: &str) -> Option<Option<(f64, f64)>> {
+        self.entries.get(&Self::key(query)).copied()
+    }
+
+    pub fn insert(&mut self, query: &str, point: Option<(f64, f64)>) {
+        self.entries.insert(Self::key(query), point);
+    }
+}
+
+/// A match returned by the geocoder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// (south, west, north, east) in degrees, when the geocoder has one.
+    pub bounds: Option<[f64; 4]>,
+}
+
+impl Place {
+    /// Viewport framing the match in a `win` sized window.
+    pub fn viewport(&self, win: (u32, u32)) -> Viewport {
+        match self.bounds {
+            Some(bounds) => Viewport::fitting(bounds, win, 18),
+            None => Viewport::centered_on(self.lat, self.lon, 14),
+        }
+    }
+}
+
+/// Runs `query` against the Nominatim-compatible endpoint at `url`.
+pub fn search(url: &str, query: &str) -> Result<Vec<Place>, Box<dyn Error>> {
+    let mut easy = Easy::new();
+    let url = format!(
+        "{}?q={}&format=jsonv2&limit={}",
+        url,
+        easy.url_encode(query.as_bytes()),
+        RESULT_LIMIT
+    );
+    let mut body = Vec::new();
+    easy.url(&url)?;
+    easy.useragent(&USER_AGENT)?;
+    easy.follow_location(true)?;
+    easy.timeout(Duration::from_secs(10))?;
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    let code = easy.response_code()?;
+    if code != 200 {
+        return Err(Box::from(format!("geocoder answered HTTP {}", code)));
+    }
+    parse_places(&serde_json::from_slice(&body)?)
+}
+
+/// Reads the array of matches of a `format=jsonv2` response.
+fn parse_places(value: &Value) -> Result<Vec<Place>, Box<dyn Error>> {
+    let matches = value
+        .as_array()
+        .ok_or_else(|| "expected an array of places".to_string())?;
+    // Nominatim sends coordinates as strings
+    let number = |v: &Value| -> Option<f64> { v.as_str()?.parse().ok() };
+    Ok(matches
+        .iter()
+        .filter_map(|m| {
+            let bounds = m["boundingbox"].as_array().and_then(|b| {
+                let b: Vec<f64> = b.iter().filter_map(number).collect();
+                // [south, north, west, east]
+                (b.len() == 4).then(|| [b[0], b[2], b[1], b[3]])
+            });
+            Some(Place {
+                name: m["display_name"].as_str()?.to_string(),
+                lat: number(&m["lat"])?,
+                lon: number(&m["lon"])?,
+                bounds,
+            })
+        })
+        .collect())
+}
+
+/// Geocodes queries on a background thread, one at a time and no faster
+/// than the public instance allows.
+pub struct Geocoder {
+    query_tx: Sender<String>,
+    result_rx: Receiver<(String, Result<Vec<Place>, String>)>,
+}
+
+impl Geocoder {
+    pub fn spawn(url: String) -> Self {
+        let (query_tx, query_rx) = channel::<String>();
+        let (result_tx, result_rx) = channel();
+        thread::spawn(move || {
+            let mut throttle = Throttle::default();
+            while let Ok(mut query) = query_rx.recv() {
+                // only the latest of queries typed in quick succession matters
+                while let Ok(newer) = query_rx.try_recv() {
+                    query = newer;
+                }
+                throttle.wait();
+                let result = search(&url, &query).map_err(|e| e.to_string());
+                if result_tx.send((query, result)).is_err() {
+                    break;
+                }
+                wake::wake();
+            }
+        });
+        Self {
+            query_tx,
+            result_rx,
+        }
+    }
+
+    pub fn request(&self, query: &str) {
+        let _ = self.query_tx.send(query.to_string());
+    }
+
+    /// The next finished (query, result), if any.
+    pub fn poll(&self) -> Option<(String, Result<Vec<Place>, String>)> {
+        self.result_rx.try_recv().ok()
+    }
+}
@@ -0,0 +1,279 @@
+use crate::sim_clock::days_from_civil;
+use crate::wake;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Wait before opening the receiver again after it went away, doubling up
+/// to the maximum while it stays away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Metres of error per unit of horizontal dilution of precision, for
+/// receivers that don't report their error estimate (GST).
+const UERE: f64 = 5.0;
+/// Knots below which the course over ground is noise.
+const MIN_COURSE_SPEED: f64 = 0.5;
+/// Asks gpsd for the raw NMEA sentences instead of its JSON reports.
+const GPSD_WATCH: &str = "?WATCH={\"enable\":true,\"nmea\":true};\n";
+const GPSD_PREFIX: &str = "gpsd://";
+
+/// The `[gps]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GpsSettings {
+    /// Read positions and show where we are on the map.
+    #[serde(default)]
+    pub enabled: bool,
+    /// `gpsd://host:port` for a gpsd daemon, otherwise the path of a serial
+    /// device (e.g. `/dev/ttyUSB0`) sending NMEA, its baud rate already set.
+    #[serde(default = "default_device")]
+    pub device: String,
+    /// Keep the map centred on the position from the start.
+    #[serde(default)]
+    pub follow: bool,
+}
+
+fn default_device() -> String {
+    format!("{}localhost:2947", GPSD_PREFIX)
+}
+
+impl Default for GpsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: default_device(),
+            follow: false,
+        }
+    }
+}
+
+/// Where the receiver says we are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub lat: f64,
+    pub lon: f64,
+    /// Horizontal error estimate in metres.
+    pub accuracy: f64,
+    /// Course over ground in degrees clockwise from north, None when not moving.
+    pub heading: Option<f64>,
+    /// Metres above mean sea level, once the receiver sent a GGA.
+    pub altitude: Option<f64>,
+    /// Unix time of the fix, by the satellites' clock once the receiver
+    /// sent a date (RMC) and by ours until then.
+    pub time: f64,
+}
+
+/// Builds fixes out of NMEA sentences. GGA and RMC each give a position;
+/// the accuracy comes from GST when the receiver sends it and from the
+/// HDOP otherwise, the heading and the date from RMC.
+#[derive(Debug, Default)]
+struct Nmea {
+    hdop: Option<f64>,
+    /// Metres, from the last GST.
+    error: Option<f64>,
+    heading: Option<f64>,
+    altitude: Option<f64>,
+    /// Days since 1970-01-01 of the last RMC.
+    date: Option<i64>,
+}
+
+impl Nmea {
+    /// Takes one line, returning a fix when it held a valid position.
+    fn feed(&mut self, line: &str) -> Option<Fix> {
+        let fields = checked_fields(line)?;
+        // the talker (GP, GN, GL, ...) doesn't matter, only the sentence
+        let sentence = fields[0].get(2..)?;
+        let field = |i: usize| fields.get(i).copied().unwrap_or("");
+        let number = |i: usize| field(i).parse::<f64>().ok();
+        let (lat, lon) = match sentence {
+            "GGA" => {
+                self.hdop = number(8);
+                self.altitude = number(9);
+                if number(6).unwrap_or(0.0) == 0.0 {
+                    return None;
+                }
+                (
+                    coordinate(field(2), field(3))?,
+                    coordinate(field(4), field(5))?,
+                )
+            }
+            "RMC" => {
+                if field(2) != "A" {
+                    return None;
+                }
+                let moving = number(7).is_some_and(|knots| knots >= MIN_COURSE_SPEED);
+                self.heading = number(8).filter(|_| moving);
+                self.date = date(field(9)).or(self.date);
+                (
+                    coordinate(field(3), field(4))?,
+                    coordinate(field(5), field(6))?,
+                )
+            }
+            "GST" => {
+                if let (Some(lat_error), Some(lon_error)) = (number(6), number(7)) {
+                    self.error = Some(lat_error.hypot(lon_error));
+                }
+                return None;
+            }
+            _ => return None,
+        };
+        let accuracy = self
+            .error
+            .or(self.hdop.map(|hdop| hdop * UERE))
+            .unwrap_or(UERE);
+        let time = match (self.date, time_of_day(field(1))) {
+            (Some(days), Some(secs)) => days as f64 * 86_400.0 + secs,
+            _ => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |d| d.as_secs_f64()),
+        };
+        Some(Fix {
+            lat,
+            lon,
+            accuracy,
+            heading: self.heading,
+            altitude: self.altitude,
+            time,
+        })
+    }
+}
+
+/// The comma separated fields of `$...*hh`, None unless the checksum
+/// (the XOR of everything between `$` and `*`) matches.
+fn checked_fields(line: &str) -> Option<Vec<&str>> {
+    let body = line.trim().strip_prefix('$')?;
+    let (data, checksum) = body.split_once('*')?;
+    let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    let actual = data.bytes().fold(0, |sum, b| sum ^ b);
+    (actual == expected).then(|| data.split(',').collect())
+}
+
+/// Degrees from NMEA's `dddmm.mmmm` and its hemisphere letter.
+fn coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let raw: f64 = value.parse().ok()?;
+    let degrees = (raw / 100.0).trunc() + (raw % 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(degrees),
+        "S" | "W" => Some(-degrees),
+        _ => None,
+    }
+}
+
+/// Seconds since midnight UTC from NMEA's `hhmmss.ss`.
+fn time_of_day(value: &str) -> Option<f64> {
+    let raw: f64 = value.parse().ok()?;
+    let (hours, minutes) = ((raw / 10_000.0).trunc(), (raw / 100.0).trunc() % 100.0);
+    Some(hours * 3600.0 + minutes * 60.0 + raw % 100.0)
+}
+
+/// Days since 1970-01-01 from NMEA's `ddmmyy`.
+fn date(value: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<u32>().ok();
+    let (day, month, year) = (number(0..2)?, number(2..4)?, number(4..6)?);
+    if value.len() != 6 || !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    Some(days_from_civil(2000 + i64::from(year), month, day))
+}
+
+/// Reads positions from a GPS receiver on a thread of its own, which opens
+/// the device again whenever it goes away.
+pub struct Gps {
+    rx: Receiver<Option<Fix>>,
+    fix: Option<Fix>,
+    /// Keep the map centred on the position as it moves.
+    pub follow: bool,
+}
+
+impl Gps {
+    /// Starts reading from the receiver, or None unless GPS is enabled.
+    pub fn start(settings: &GpsSettings) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+        let (tx, rx) = channel();
+        let device = settings.device.clone();
+        thread::spawn(move || run(&device, &tx));
+        Some(Self {
+            rx,
+            fix: None,
+            follow: settings.follow,
+        })
+    }
+
+    /// The latest position, None while the receiver has none.
+    pub fn fix(&self) -> Option<Fix> {
+        self.fix
+    }
+
+    /// Takes in what the receiver sent. Returns true if the position changed.
+    pub fn poll(&mut self) -> bool {
+        let before = self.fix;
+        for fix in self.rx.try_iter() {
+            self.fix = fix;
+        }
+        self.fix != before
+    }
+}
+
+/// Opens `device` and hands on every fix until the map closes, opening it
+/// again whenever it fails. A lost receiver is sent as None.
+fn run(device: &str, tx: &Sender<Option<Fix>>) {
+    let mut wait = RECONNECT_DELAY;
+    loop {
+        match open(device) {
+            Ok(mut reader) => {
+                info!("Reading positions from {}", device);
+                wait = RECONNECT_DELAY;
+                match read_fixes(&mut reader, tx) {
+                    Ok(false) => return,
+                    Ok(true) => warn!("{} closed", device),
+                    Err(e) => warn!("Lost the GPS receiver {}: {}", device, e),
+                }
+                if tx.send(None).is_err() {
+                    return;
+                }
+                wake::wake();
+            }
+            Err(e) => warn!("Failed to open {}: {}", device, e),
+        }
+        thread::sleep(wait);
+        wait = (wait * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+fn open(device: &str) -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    Ok(match device.strip_prefix(GPSD_PREFIX) {
+        Some(address) => {
+            let mut stream = TcpStream::connect(address)?;
+            stream.write_all(GPSD_WATCH.as_bytes())?;
+            Box::new(BufReader::new(stream))
+        }
+        None => Box::new(BufReader::new(File::open(device)?)),
+    })
+}
+
+/// Passes on fixes until the receiver closes (true) or the map stopped
+/// listening (false).
+fn read_fixes(reader: &mut dyn BufRead, tx: &Sender<Option<Fix>>) -> Result<bool, Box<dyn Error>> {
+    let mut nmea = Nmea::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(true);
+        }
+        // serial lines garble the odd byte, the checksum catches those
+        if let Some(fix) = nmea.feed(&String::from_utf8_lossy(&line)) {
+            if tx.send(Some(fix)).is_err() {
+                return Ok(false);
+            }
+            wake::wake();
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use crate::disk_cache::DiskCacheStats;
+use crate::geocoder::Place;
+use crate::opengl_helper::CacheStats;
+use crate::search::SearchBox;
+use crate::tile_layers::TileLayers;
+use crate::tile_source::SourceRegistry;
+use egui::{Modifiers, PointerButton, Pos2, Vec2, ViewportId};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Points a wheel notch scrolls the panels by.
+const WHEEL_STEP: f32 = 40.0;
+
+/// The `[gui]` table of settings.toml.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuiSettings {
+    /// Show the panels at startup; F6 shows and hides them.
+    pub visible: bool,
+    /// Size of the panels relative to the window's own scale.
+    pub scale: f32,
+}
+
+impl Default for GuiSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            scale: 1.0,
+        }
+    }
+}
+
+/// Something done in the panels, carried out by the main loop.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuiAction {
+    SelectSource(u8),
+    AddLayer,
+    RemoveLayer(usize),
+    ShowLayer(usize, bool),
+    LayerOpacity(usize, f32),
+    /// Ask the geocoder for the query.
+    Search(String),
+    GoTo(Place),
+}
+
+/// What the panels show, borrowed for one frame.
+pub struct GuiState<'a> {
+    pub map: u8,
+    pub sources: &'a SourceRegistry,
+    pub tile_layers: &'a TileLayers,
+    pub zoom: u8,
+    pub gpu: CacheStats,
+    /// Only read while the cache panel is open, as it walks the whole cache.
+    pub disk: &'a dyn Fn() -> DiskCacheStats,
+    /// Tiles asked for and not arrived, and downloads waiting or running.
+    pub requested: usize,
+    pub pending: usize,
+}
+
+/// Panels for the layers, the base source, the tile caches and place
+/// search, drawn with egui over the map. SDL events are passed on to egui
+/// first; the ones it takes don't reach the map.
+pub struct Gui {
+    ctx: egui::Context,
+    painter: egui_glow::Painter,
+    /// Events since the last frame.
+    input: egui::RawInput,
+    start: Instant,
+    scale: f32,
+    pub visible: bool,
+    pub search: SearchBox,
+    /// When egui next wants drawing, for tooltips and blinking cursors.
+    repaint_at: Option<Instant>,
+}
+
+impl Gui {
+    /// Sets up egui on the current GL context, whose functions `loader` finds.
+    pub fn new(
+        settings: &GuiSettings,
+        loader: impl FnMut(&str) -> *const std::ffi::c_void,
+    ) -> Result<Self, String> {
+        let gl = unsafe { glow::Context::from_loader_function(loader) };
+        let painter = egui_glow::Painter::new(Arc::new(gl), "", None, false)
+            .map_err(|e| format!("Failed to set up the panels: {}", e))?;
+        Ok(Self {
+            ctx: egui::Context::default(),
+            painter,
+            input: egui::RawInput::default(),
+            start: Instant::now(),
+            scale: settings.scale.clamp(0.5, 4.0),
+            visible: settings.visible,
+            search: SearchBox::default(),
+            repaint_at: None,
+        })
+    }
+
+    /// Hands `event` to the panels. Returns true if they take it, so the
+    /// map should leave it alone.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let wants_pointer = self.ctx.wants_pointer_input();
+        let wants_keyboard = self.ctx.wants_keyboard_input();
+        let modifiers = self.input.modifiers;
+        match event {
+            Event::MouseMotion { x, y, .. } => {
+                self.push(egui::Event::PointerMoved(pos(*x, *y)));
+                wants_pointer
+            }
+            Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            }
+            | Event::MouseButtonUp {
+                mouse_btn, x, y, ..
+            } => {
+                let Some(button) = pointer_button(*mouse_btn) else {
+                    return false;
+                };
+                let pressed = matches!(event, Event::MouseButtonDown { .. });
+                self.push(egui::Event::PointerButton {
+                    pos: pos(*x, *y),
+                    button,
+                    pressed,
+                    modifiers,
+                });
+                // a drag started on the map still ends there
+                pressed && wants_pointer
+            }
+            Event::MouseWheel {
+                precise_x,
+                precise_y,
+                ..
+            } if wants_pointer => {
+                self.push(egui::Event::MouseWheel {
+                    unit: egui::MouseWheelUnit::Point,
+                    delta: Vec2::new(*precise_x, *precise_y) * WHEEL_STEP,
+                    modifiers,
+                });
+                true
+            }
+            Event::TextInput { text, .. } if wants_keyboard => {
+                self.push(egui::Event::Text(text.clone()));
+                true
+            }
+            Event::KeyDown {
+                keycode: Some(key),
+                keymod,
+                repeat,
+                ..
+            }
+            | Event::KeyUp {
+                keycode: Some(key),
+                keymod,
+                repeat,
+                ..
+            } => {
+                let pressed = matches!(event, Event::KeyDown { .. });
+                self.input.modifiers = modifiers_from(*keymod);
+                // F6 hides the panels even while they are typed into
+                if !wants_keyboard || *key == Keycode::F6 {
+                    return false;
+                }
+                if let Some(key) = egui::Key::from_name(&key.name()) {
+                    self.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed,
+                        repeat: *repeat,
+                        modifiers: self.input.modifiers,
+                    });
+                }
+                true
+            }
+            Event::Window {
+                win_event: WindowEvent::Leave,
+                ..
+            } => {
+                self.push(egui::Event::PointerGone);
+                false
+            }
+            Event::Window {
+                win_event: WindowEvent::FocusGained | WindowEvent::FocusLost,
+                ..
+            } => {
+                let focused = matches!(
+                    event,
+                    Event::Window {
+                        win_event: WindowEvent::FocusGained,
+                        ..
+                    }
+                );
+                self.input.focused = focused;
+                self.push(egui::Event::WindowFocused(focused));
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn push(&mut self, event: egui::Event) {
+        self.input.events.push(event);
+    }
+
+    /// Whether a text field has the keyboard, so SDL should send typing.
+    pub fn wants_text(&self) -> bool {
+        self.visible && self.ctx.wants_keyboard_input()
+    }
+
+    /// Whether egui asked to be drawn again by now, e.g. to show a tooltip.
+    pub fn needs_repaint(&self, now: Instant) -> bool {
+        self.visible && self.repaint_at.is_some_and(|at| at <= now)
+    }
+
+    /// Lays out and draws the panels over what is drawn so far. `win` is the
+    /// window size in its own pixels, `drawable` in GL pixels. Returns what
+    /// was done in them.
+    pub fn draw(
+        &mut self,
+        state: &GuiState,
+        win: (u32, u32),
+        drawable: (u32, u32),
+    ) -> Vec<GuiAction> {
+        if !self.visible {
+            return Vec::new();
+        }
+        let native = drawable.0 as f32 / win.0.max(1) as f32;
+        let mut input = std::mem::take(&mut self.input);
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            Pos2::ZERO,
+            Vec2::new(win.0 as f32, win.1 as f32) / self.scale,
+        ));
+        input.time = Some(self.start.elapsed().as_secs_f64());
+        input
+            .viewports
+            .entry(ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(native * self.scale);
+        // pointer positions come in window pixels
+        for event in &mut input.events {
+            match event {
+                egui::Event::PointerMoved(pos) | egui::Event::PointerButton { pos, .. } => {
+                    *pos = Pos2::new(pos.x / self.scale, pos.y / self.scale);
+                }
+                _ => {}
+            }
+        }
+        // kept for the next frame's events
+        self.input.modifiers = input.modifiers;
+        self.input.focused = input.focused;
+
+        let mut actions = Vec::new();
+        let output = self.ctx.clone().run(input, |ctx| {
+            self.panels(ctx, state, &mut actions);
+        });
+        let delay = output
+            .viewport_output
+            .get(&ViewportId::ROOT)
+            .map_or(Duration::MAX, |viewport| viewport.repaint_delay);
+        self.repaint_at = Instant::now().checked_add(delay);
+
+        let primitives = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        self.painter.paint_and_update_textures(
+            [drawable.0, drawable.1],
+            output.pixels_per_point,
+            &primitives,
+            &output.textures_delta,
+        );
+        unsafe {
+            // egui leaves its program and blend function behind
+            gl::UseProgram(0);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        actions
+    }
+
+    fn panels(&mut self, ctx: &egui::Context, state: &GuiState, actions: &mut Vec<GuiAction>) {
+        egui::Window::new("Map")
+            .default_pos([10.0, 10.0])
+            .default_width(240.0)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::CollapsingHeader::new("Search")
+                    .default_open(true)
+                    .show(ui, |ui| self.search_panel(ui, actions));
+                egui::CollapsingHeader::new("Source")
+                    .default_open(true)
+                    .show(ui, |ui| source_panel(ui, state, actions));
+                egui::CollapsingHeader::new("Layers")
+                    .default_open(true)
+                    .show(ui, |ui| layer_panel(ui, state, actions));
+                egui::CollapsingHeader::new("Cache").show(ui, |ui| cache_panel(ui, state));
+            });
+    }
+
+    fn search_panel(&mut self, ui: &mut egui::Ui, actions: &mut Vec<GuiAction>) {
+        let field = ui.add(
+            egui::TextEdit::singleline(&mut self.search.query)
+                .hint_text("Place or address")
+                .desired_width(f32::INFINITY),
+        );
+        if field.lost_focus()
+            && ui.input(|input| input.key_pressed(egui::Key::Enter))
+            && let Some(query) = self.search.submit()
+        {
+            actions.push(GuiAction::Search(query));
+        }
+        if let Some(message) = &self.search.message {
+            ui.weak(message);
+        }
+        for place in &self.search.results {
+            if ui.selectable_label(false, &place.name).clicked() {
+                actions.push(GuiAction::GoTo(place.clone()));
+            }
+        }
+    }
+}
+
+fn source_panel(ui: &mut egui::Ui, state: &GuiState, actions: &mut Vec<GuiAction>) {
+    let current = state
+        .sources
+        .get(state.map)
+        .map_or_else(|| state.map.to_string(), |source| source.name.clone());
+    egui::ComboBox::from_id_salt("base source")
+        .selected_text(current)
+        .width(ui.available_width())
+        .show_ui(ui, |ui| {
+            for source in state.sources.iter() {
+                if ui
+                    .selectable_label(source.id == state.map, &source.name)
+                    .clicked()
+                    && source.id != state.map
+                {
+                    actions.push(GuiAction::SelectSource(source.id));
+                }
+            }
+        });
+}
+
+fn layer_panel(ui: &mut egui::Ui, state: &GuiState, actions: &mut Vec<GuiAction>) {
+    if state.tile_layers.overlays.is_empty() {
+        ui.weak("No overlays");
+    }
+    for (i, layer) in state.tile_layers.overlays.iter().enumerate() {
+        let name = state
+            .sources
+            .get(layer.source)
+            .map_or_else(|| layer.source.to_string(), |source| source.name.clone());
+        ui.horizontal(|ui| {
+            let mut shown = layer.shown();
+            if ui.checkbox(&mut shown, name).changed() {
+                actions.push(GuiAction::ShowLayer(i, shown));
+            }
+            if !layer.zoom.contains(state.zoom) {
+                ui.weak(format!("z{}-{}", layer.zoom.min_zoom, layer.zoom.max_zoom))
+                    .on_hover_text("Not drawn at this zoom");
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button("x").on_hover_text("Remove").clicked() {
+                    actions.push(GuiAction::RemoveLayer(i));
+                }
+            });
+        });
+        let mut opacity = layer.opacity;
+        let slider = egui::Slider::new(&mut opacity, 0.0..=1.0).text("opacity");
+        if ui.add_enabled(layer.shown(), slider).changed() {
+            actions.push(GuiAction::LayerOpacity(i, opacity));
+        }
+    }
+    if ui.button("Add overlay").clicked() {
+        actions.push(GuiAction::AddLayer);
+    }
+}
+
+fn cache_panel(ui: &mut egui::Ui, state: &GuiState) {
+    let gpu = state.gpu;
+    egui::Grid::new("cache stats")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Disk");
+            ui.label((state.disk)().to_string());
+            ui.end_row();
+            ui.label("GPU");
+            ui.label(format!("{} tiles in {} textures", gpu.tiles, gpu.textures));
+            ui.end_row();
+            ui.label("Drawn");
+            ui.label(format!("{} tiles", gpu.drawn));
+            ui.end_row();
+            ui.label("Requested");
+            ui.label(state.requested.to_string());
+            ui.end_row();
+            ui.label("Downloading");
+            ui.label(state.pending.to_string());
+            ui.end_row();
+        });
+}
+
+fn pos(x: i32, y: i32) -> Pos2 {
+    Pos2::new(x as f32, y as f32)
+}
+
+fn pointer_button(button: MouseButton) -> Option<PointerButton> {
+    match button {
+        MouseButton::Left => Some(PointerButton::Primary),
+        MouseButton::Right => Some(PointerButton::Secondary),
+        MouseButton::Middle => Some(PointerButton::Middle),
+        _ => None,
+    }
+}
+
+fn modifiers_from(keymod: Mod) -> Modifiers {
+    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+    let mac_cmd = cfg!(target_os = "macos") && keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD);
+    Modifiers {
+        alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        ctrl,
+        shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+        mac_cmd,
+        command: if cfg!(target_os = "macos") {
+            mac_cmd
+        } else {
+            ctrl
+        },
+    }
+}
@@ -0,0 +1,239 @@
+use crate::overpass::QueryBox;
+use crate::viewport::Viewport;
+use crate::wake;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Dwell time is written to the database this often.
+const FLUSH_EVERY: Duration = Duration::from_secs(10);
+/// Without input for this long the view stops counting as looked at.
+pub const IDLE_AFTER: Duration = Duration::from_secs(60);
+/// Heat cells are this many zoom levels below the view, 64 pixels wide.
+const HEAT_DETAIL: u8 = 2;
+/// Longest gap between two frames that still counts as looking, so a stall
+/// or a suspended laptop doesn't credit hours to one view.
+const MAX_TICK: Duration = Duration::from_secs(1);
+
+/// The `[history]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistorySettings {
+    /// Log which tiles were on screen and for how long.
+    #[serde(default)]
+    pub enabled: bool,
+    /// SQLite file the dwell time is kept in.
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    "history.sqlite".to_string()
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_path(),
+        }
+    }
+}
+
+/// Seconds a tile was on screen, summed over every visit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeatCell {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+    pub seconds: f64,
+}
+
+/// The heat cells covering what `viewport` shows in a `win` sized window.
+pub fn heat_area(viewport: &Viewport, win: (u32, u32)) -> QueryBox {
+    let area = QueryBox::covering(viewport, win);
+    let shift = (area.z + HEAT_DETAIL).min(19) - area.z;
+    QueryBox {
+        z: area.z + shift,
+        x0: area.x0 << shift,
+        y0: area.y0 << shift,
+        x1: ((area.x1 + 1) << shift) - 1,
+        y1: ((area.y1 + 1) << shift) - 1,
+    }
+}
+
+enum Request {
+    Record(Vec<((u8, u32, u32), f64)>),
+    Heat(QueryBox),
+}
+
+fn open(path: &Path) -> Result<Connection, Box<dyn Error>> {
+    let db = Connection::open(path)?;
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS dwell (
+             z INTEGER NOT NULL,
+             x INTEGER NOT NULL,
+             y INTEGER NOT NULL,
+             seconds REAL NOT NULL,
+             PRIMARY KEY (z, x, y)
+         )",
+    )?;
+    Ok(db)
+}
+
+fn record(db: &mut Connection, cells: &[((u8, u32, u32), f64)]) -> Result<(), Box<dyn Error>> {
+    let tx = db.transaction()?;
+    {
+        let mut upsert = tx.prepare_cached(
+            "INSERT INTO dwell (z, x, y, seconds) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (z, x, y) DO UPDATE SET seconds = seconds + excluded.seconds",
+        )?;
+        for ((z, x, y), seconds) in cells {
+            upsert.execute(params![z, x, y, seconds])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Dwell time inside `area`: deeper tiles summed into the cells of
+/// `area.z`, shallower ones as they were recorded.
+fn heat(db: &Connection, area: &QueryBox) -> Result<Vec<HeatCell>, Box<dyn Error>> {
+    let mut cells = Vec::new();
+    let mut deeper = db.prepare_cached(
+        "SELECT x >> (z - ?1) AS cx, y >> (z - ?1) AS cy, SUM(seconds) FROM dwell
+         WHERE z >= ?1 AND cx BETWEEN ?2 AND ?3 AND cy BETWEEN ?4 AND ?5
+         GROUP BY cx, cy",
+    )?;
+    let rows = deeper.query_map(params![area.z, area.x0, area.x1, area.y0, area.y1], |row| {
+        Ok(HeatCell {
+            z: area.z,
+            x: row.get(0)?,
+            y: row.get(1)?,
+            seconds: row.get(2)?,
+        })
+    })?;
+    for row in rows {
+        cells.push(row?);
+    }
+    let mut shallower = db.prepare_cached(
+        "SELECT z, x, y, seconds FROM dwell
+         WHERE z < ?1
+           AND ((x + 1) << (?1 - z)) > ?2 AND (x << (?1 - z)) <= ?3
+           AND ((y + 1) << (?1 - z)) > ?4 AND (y << (?1 - z)) <= ?5",
+    )?;
+    let rows = shallower.query_map(params![area.z, area.x0, area.x1, area.y0, area.y1], |row| {
+        Ok(HeatCell {
+            z: row.get(0)?,
+            x: row.get(1)?,
+            y: row.get(2)?,
+            seconds: row.get(3)?,
+        })
+    })?;
+    for row in rows {
+        cells.push(row?);
+    }
+    Ok(cells)
+}
+
+/// Logs how long each tile was on screen into a SQLite file, on a
+/// background thread, and reads it back for the heatmap.
+pub struct History {
+    request_tx: Option<Sender<Request>>,
+    heat_rx: Receiver<Result<Vec<HeatCell>, String>>,
+    thread: Option<JoinHandle<()>>,
+    /// Seconds per tile not written yet.
+    pending: HashMap<(u8, u32, u32), f64>,
+    last_tick: Instant,
+    last_flush: Instant,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut db = open(path)?;
+        let (request_tx, request_rx) = channel::<Request>();
+        let (heat_tx, heat_rx) = channel();
+        let thread = thread::spawn(move || {
+            while let Ok(request) = request_rx.recv() {
+                match request {
+                    Request::Record(cells) => {
+                        if let Err(e) = record(&mut db, &cells) {
+                            warn!("Failed to log view history: {}", e);
+                        }
+                    }
+                    Request::Heat(area) => {
+                        let cells = heat(&db, &area).map_err(|e| e.to_string());
+                        if heat_tx.send(cells).is_err() {
+                            break;
+                        }
+                        wake::wake();
+                    }
+                }
+            }
+        });
+        let now = Instant::now();
+        Ok(Self {
+            request_tx: Some(request_tx),
+            heat_rx,
+            thread: Some(thread),
+            pending: HashMap::new(),
+            last_tick: now,
+            last_flush: now,
+        })
+    }
+
+    /// Credits the time since the last tick to the tiles `viewport` shows,
+    /// if the user was `active`, and writes them out now and then.
+    pub fn tick(&mut self, viewport: &Viewport, win: (u32, u32), active: bool) {
+        let now = Instant::now();
+        let seconds = (now - self.last_tick).min(MAX_TICK).as_secs_f64();
+        self.last_tick = now;
+        if active {
+            let area = QueryBox::covering(viewport, win);
+            for y in area.y0..=area.y1 {
+                for x in area.x0..=area.x1 {
+                    *self.pending.entry((area.z, x, y)).or_default() += seconds;
+                }
+            }
+        }
+        if now - self.last_flush >= FLUSH_EVERY {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.last_flush = Instant::now();
+        if self.pending.is_empty() {
+            return;
+        }
+        let cells = self.pending.drain().collect();
+        if let Some(tx) = &self.request_tx {
+            let _ = tx.send(Request::Record(cells));
+        }
+    }
+
+    /// Asks for the dwell time inside `area`, answered through `poll`.
+    pub fn request_heat(&self, area: QueryBox) {
+        if let Some(tx) = &self.request_tx {
+            let _ = tx.send(Request::Heat(area));
+        }
+    }
+
+    pub fn poll(&self) -> Option<Result<Vec<HeatCell>, String>> {
+        self.heat_rx.try_recv().ok()
+    }
+
+    /// Writes what is pending and waits for the database to be closed.
+    pub fn close(mut self) {
+        self.flush();
+        self.request_tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
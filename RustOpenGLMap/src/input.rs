@@ -0,0 +1,612 @@
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+
+/// Something the user can trigger from the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    PanNorth,
+    PanSouth,
+    PanWest,
+    PanEast,
+    ZoomIn,
+    ZoomOut,
+    NextSource,
+    SelectSource(u8),
+    TogglePause,
+    TogglePolarView,
+    PrintCacheStats,
+    ToggleHelp,
+    ToggleStatusBar,
+    GoHome,
+    SetHome,
+    ToggleLayerList,
+    AddLayer,
+    RemoveLayer,
+    NextLayer,
+    ToggleLayer,
+    ToggleVectorTiles,
+    LayerOpacityDown,
+    LayerOpacityUp,
+    TimeSlower,
+    TimeFaster,
+    TimeBack,
+    TimeForward,
+    TimeNow,
+    ToggleCompare,
+    NextCompareSource,
+    ClearRangeRings,
+    ToggleCollect,
+    UndoPoint,
+    ClearPoints,
+    ExportPoints,
+    ToggleMeasure,
+    ToggleHeatmap,
+    ToggleCoverage,
+    CoverageDeeper,
+    SaveSession,
+    AddBookmark,
+    ListBookmarks,
+    NextBookmark,
+    Search,
+    ListRecent,
+    GoTo,
+    CopyPermalink,
+    FindAmenity,
+    ClearAmenities,
+    ToggleTilePicker,
+    ClearPickedTiles,
+    RefreshPickedTiles,
+    DeletePickedTiles,
+    ExportPickedTiles,
+    ExportFigure,
+    CycleUnits,
+    ToggleStats,
+    CycleVSync,
+    CycleFrameCap,
+    ToggleGui,
+    FollowPosition,
+    ToggleRecording,
+    ExportTrack,
+    ClearTrack,
+    ClearDirections,
+}
+
+pub struct KeyBinding {
+    pub key: Keycode,
+    /// Only fires with Shift held; without it, only fires without Shift.
+    pub shift: bool,
+    pub action: Action,
+    pub description: &'static str,
+}
+
+/// Every keyboard shortcut. The event loop dispatches through this table and
+/// the F1 overlay lists it, so the two can't disagree.
+pub const KEY_BINDINGS: &[KeyBinding] = &[
+    KeyBinding {
+        key: Keycode::F1,
+        shift: false,
+        action: Action::ToggleHelp,
+        description: "Show or hide this help",
+    },
+    KeyBinding {
+        key: Keycode::W,
+        shift: false,
+        action: Action::PanNorth,
+        description: "Pan north",
+    },
+    KeyBinding {
+        key: Keycode::S,
+        shift: false,
+        action: Action::PanSouth,
+        description: "Pan south",
+    },
+    KeyBinding {
+        key: Keycode::A,
+        shift: false,
+        action: Action::PanWest,
+        description: "Pan west",
+    },
+    KeyBinding {
+        key: Keycode::D,
+        shift: false,
+        action: Action::PanEast,
+        description: "Pan east",
+    },
+    KeyBinding {
+        key: Keycode::Up,
+        shift: false,
+        action: Action::ZoomIn,
+        description: "Zoom in",
+    },
+    KeyBinding {
+        key: Keycode::Down,
+        shift: false,
+        action: Action::ZoomOut,
+        description: "Zoom out",
+    },
+    KeyBinding {
+        key: Keycode::Home,
+        shift: false,
+        action: Action::GoHome,
+        description: "Go to the home view",
+    },
+    KeyBinding {
+        key: Keycode::Home,
+        shift: true,
+        action: Action::SetHome,
+        description: "Make the current view home",
+    },
+    KeyBinding {
+        key: Keycode::M,
+        shift: false,
+        action: Action::NextSource,
+        description: "Next tile source",
+    },
+    KeyBinding {
+        key: Keycode::Kp0,
+        shift: false,
+        action: Action::SelectSource(0),
+        description: "Tile source 0",
+    },
+    KeyBinding {
+        key: Keycode::Kp1,
+        shift: false,
+        action: Action::SelectSource(1),
+        description: "Tile source 1",
+    },
+    KeyBinding {
+        key: Keycode::Kp2,
+        shift: false,
+        action: Action::SelectSource(2),
+        description: "Tile source 2",
+    },
+    KeyBinding {
+        key: Keycode::Kp3,
+        shift: false,
+        action: Action::SelectSource(3),
+        description: "Tile source 3",
+    },
+    KeyBinding {
+        key: Keycode::Kp4,
+        shift: false,
+        action: Action::SelectSource(4),
+        description: "Tile source 4",
+    },
+    KeyBinding {
+        key: Keycode::Kp5,
+        shift: false,
+        action: Action::SelectSource(5),
+        description: "Tile source 5",
+    },
+    KeyBinding {
+        key: Keycode::L,
+        shift: false,
+        action: Action::ToggleLayerList,
+        description: "Show or hide the layer list",
+    },
+    KeyBinding {
+        key: Keycode::N,
+        shift: false,
+        action: Action::AddLayer,
+        description: "Add the next source as an overlay",
+    },
+    KeyBinding {
+        key: Keycode::Delete,
+        shift: false,
+        action: Action::RemoveLayer,
+        description: "Remove the selected overlay",
+    },
+    KeyBinding {
+        key: Keycode::Tab,
+        shift: false,
+        action: Action::NextLayer,
+        description: "Select the next overlay",
+    },
+    KeyBinding {
+        key: Keycode::V,
+        shift: false,
+        action: Action::ToggleLayer,
+        description: "Show or hide the selected overlay",
+    },
+    KeyBinding {
+        key: Keycode::V,
+        shift: true,
+        action: Action::ToggleVectorTiles,
+        description: "Show or hide the vector tiles (needs a url under [vector])",
+    },
+    KeyBinding {
+        key: Keycode::LeftBracket,
+        shift: false,
+        action: Action::LayerOpacityDown,
+        description: "Make the selected overlay more transparent",
+    },
+    KeyBinding {
+        key: Keycode::RightBracket,
+        shift: false,
+        action: Action::LayerOpacityUp,
+        description: "Make the selected overlay more opaque",
+    },
+    KeyBinding {
+        key: Keycode::Comma,
+        shift: false,
+        action: Action::TimeSlower,
+        description: "Slow down or reverse the map clock",
+    },
+    KeyBinding {
+        key: Keycode::Period,
+        shift: false,
+        action: Action::TimeFaster,
+        description: "Speed up the map clock",
+    },
+    KeyBinding {
+        key: Keycode::Comma,
+        shift: true,
+        action: Action::TimeBack,
+        description: "Map clock back 10 minutes",
+    },
+    KeyBinding {
+        key: Keycode::Period,
+        shift: true,
+        action: Action::TimeForward,
+        description: "Map clock ahead 10 minutes",
+    },
+    KeyBinding {
+        key: Keycode::Slash,
+        shift: false,
+        action: Action::TimeNow,
+        description: "Map clock back to the current time",
+    },
+    KeyBinding {
+        key: Keycode::C,
+        shift: false,
+        action: Action::ToggleCompare,
+        description: "Compare two sources side by side, drag the divider",
+    },
+    KeyBinding {
+        key: Keycode::C,
+        shift: true,
+        action: Action::NextCompareSource,
+        description: "Next source right of the divider",
+    },
+    KeyBinding {
+        key: Keycode::R,
+        shift: false,
+        action: Action::ClearRangeRings,
+        description: "Remove all range rings",
+    },
+    KeyBinding {
+        key: Keycode::R,
+        shift: true,
+        action: Action::ClearDirections,
+        description: "Remove the road route",
+    },
+    KeyBinding {
+        key: Keycode::K,
+        shift: false,
+        action: Action::ToggleCollect,
+        description: "Collect clicked points",
+    },
+    KeyBinding {
+        key: Keycode::Backspace,
+        shift: false,
+        action: Action::UndoPoint,
+        description: "Remove the last collected point",
+    },
+    KeyBinding {
+        key: Keycode::Backspace,
+        shift: true,
+        action: Action::ClearPoints,
+        description: "Remove all collected points",
+    },
+    KeyBinding {
+        key: Keycode::K,
+        shift: true,
+        action: Action::ExportPoints,
+        description: "Export collected points to points.csv and points.geojson",
+    },
+    KeyBinding {
+        key: Keycode::M,
+        shift: true,
+        action: Action::ToggleMeasure,
+        description: "Measure distance and area by clicking, Escape ends",
+    },
+    KeyBinding {
+        key: Keycode::F,
+        shift: false,
+        action: Action::Search,
+        description: "Search for a place (Enter searches, Enter again goes there)",
+    },
+    KeyBinding {
+        key: Keycode::F,
+        shift: true,
+        action: Action::ListRecent,
+        description: "Recent places (Enter goes, Tab bookmarks, Delete forgets)",
+    },
+    KeyBinding {
+        key: Keycode::G,
+        shift: false,
+        action: Action::GoTo,
+        description: "Go to coordinates (lat, lon [zoom]), a z/x/y tile or a permalink",
+    },
+    KeyBinding {
+        key: Keycode::G,
+        shift: true,
+        action: Action::CopyPermalink,
+        description: "Copy a permalink to this view",
+    },
+    KeyBinding {
+        key: Keycode::O,
+        shift: false,
+        action: Action::FindAmenity,
+        description: "Find an amenity (cafe, pharmacy, ...) in view with Overpass",
+    },
+    KeyBinding {
+        key: Keycode::O,
+        shift: true,
+        action: Action::ClearAmenities,
+        description: "Remove the amenity markers",
+    },
+    KeyBinding {
+        key: Keycode::T,
+        shift: false,
+        action: Action::ToggleTilePicker,
+        description: "Pick tiles on a grid: click toggles, drag adds a range",
+    },
+    KeyBinding {
+        key: Keycode::T,
+        shift: true,
+        action: Action::ClearPickedTiles,
+        description: "Unpick all tiles",
+    },
+    KeyBinding {
+        key: Keycode::F5,
+        shift: false,
+        action: Action::RefreshPickedTiles,
+        description: "Download the picked tiles again",
+    },
+    KeyBinding {
+        key: Keycode::Delete,
+        shift: true,
+        action: Action::DeletePickedTiles,
+        description: "Delete the picked tiles from the disk cache",
+    },
+    KeyBinding {
+        key: Keycode::E,
+        shift: false,
+        action: Action::ExportPickedTiles,
+        description: "Export the picked tiles to Export/<source>/z/x/y.png",
+    },
+    KeyBinding {
+        key: Keycode::E,
+        shift: true,
+        action: Action::ExportFigure,
+        description: "Export the view with overlays and a scale bar to Export/map-<time>.svg and .pdf",
+    },
+    KeyBinding {
+        key: Keycode::U,
+        shift: false,
+        action: Action::CycleUnits,
+        description: "Switch distances, areas and elevations between metric, imperial and nautical",
+    },
+    KeyBinding {
+        key: Keycode::X,
+        shift: false,
+        action: Action::ToggleCoverage,
+        description: "Show which tiles of the base map are cached (green) or missing (red)",
+    },
+    KeyBinding {
+        key: Keycode::X,
+        shift: true,
+        action: Action::CoverageDeeper,
+        description: "Check coverage up to four zoom levels deeper",
+    },
+    KeyBinding {
+        key: Keycode::S,
+        shift: true,
+        action: Action::SaveSession,
+        description: "Save the view, layers, bookmarks and drawings to a .mapsession file",
+    },
+    KeyBinding {
+        key: Keycode::B,
+        shift: false,
+        action: Action::AddBookmark,
+        description: "Bookmark the current view",
+    },
+    KeyBinding {
+        key: Keycode::B,
+        shift: true,
+        action: Action::ListBookmarks,
+        description: "List bookmarks (type to filter, Enter goes, Tab protects, Delete removes)",
+    },
+    KeyBinding {
+        key: Keycode::J,
+        shift: false,
+        action: Action::NextBookmark,
+        description: "Jump to the next bookmark",
+    },
+    KeyBinding {
+        key: Keycode::H,
+        shift: false,
+        action: Action::ToggleHeatmap,
+        description: "Show or hide where you have looked (needs [history])",
+    },
+    KeyBinding {
+        key: Keycode::P,
+        shift: false,
+        action: Action::TogglePause,
+        description: "Pause or resume downloads",
+    },
+    KeyBinding {
+        key: Keycode::P,
+        shift: true,
+        action: Action::TogglePolarView,
+        description: "Switch between Mercator and a polar view of the nearer pole",
+    },
+    KeyBinding {
+        key: Keycode::I,
+        shift: false,
+        action: Action::PrintCacheStats,
+        description: "Print disk cache usage",
+    },
+    KeyBinding {
+        key: Keycode::F2,
+        shift: false,
+        action: Action::ToggleStatusBar,
+        description: "Show or hide the status bar",
+    },
+    KeyBinding {
+        key: Keycode::F3,
+        shift: false,
+        action: Action::ToggleStats,
+        description: "Show or hide frame timing, tile cache and download statistics",
+    },
+    KeyBinding {
+        key: Keycode::F4,
+        shift: false,
+        action: Action::CycleFrameCap,
+        description: "Cap the frame rate at the display's, 30, 60 or 120 fps, or uncap it",
+    },
+    KeyBinding {
+        key: Keycode::F4,
+        shift: true,
+        action: Action::CycleVSync,
+        description: "Switch vsync on, off or to adaptive",
+    },
+    KeyBinding {
+        key: Keycode::F6,
+        shift: false,
+        action: Action::ToggleGui,
+        description: "Show or hide the layer, source, cache and search panels",
+    },
+    KeyBinding {
+        key: Keycode::Y,
+        shift: false,
+        action: Action::FollowPosition,
+        description: "Keep the map centred on the GPS position, or stop",
+    },
+    KeyBinding {
+        key: Keycode::Y,
+        shift: true,
+        action: Action::ToggleRecording,
+        description: "Start or stop recording the GPS track",
+    },
+    KeyBinding {
+        key: Keycode::Z,
+        shift: false,
+        action: Action::ExportTrack,
+        description: "Export the recorded track to Export/track-<time>.gpx",
+    },
+    KeyBinding {
+        key: Keycode::Z,
+        shift: true,
+        action: Action::ClearTrack,
+        description: "Discard the recorded track",
+    },
+    KeyBinding {
+        key: Keycode::Escape,
+        shift: false,
+        action: Action::Quit,
+        description: "Quit",
+    },
+];
+
+pub fn action_for(key: Keycode, shift: bool) -> Option<Action> {
+    KEY_BINDINGS
+        .iter()
+        .find(|binding| binding.key == key && binding.shift == shift)
+        .map(|binding| binding.action)
+}
+
+/// What a mouse button does on the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// Selects the marker under the pointer, shows the tags of an amenity,
+    /// or centres the map there. Dragging pans the map.
+    /// Adds a measurement vertex instead while measuring, or collects the
+    /// point under the pointer while collecting.
+    Select,
+    ZoomAt,
+    DropMarker,
+    /// First click picks the start, the second draws a great-circle route to
+    /// it, or asks the routing service for a road route when one is set up.
+    Route,
+    /// Range rings and bearing rays around the pointer.
+    RangeRings,
+}
+
+pub struct ToolBinding {
+    pub button: MouseButton,
+    /// Minimum click count, the binding with the most clicks wins.
+    pub clicks: u8,
+    pub tool: Tool,
+    pub label: &'static str,
+    pub description: &'static str,
+}
+
+pub const TOOL_BINDINGS: &[ToolBinding] = &[
+    ToolBinding {
+        button: MouseButton::Left,
+        clicks: 1,
+        tool: Tool::Select,
+        label: "Left click",
+        description: "Select a marker or centre the map, drag to pan",
+    },
+    ToolBinding {
+        button: MouseButton::Left,
+        clicks: 2,
+        tool: Tool::ZoomAt,
+        label: "Double click",
+        description: "Zoom in on the pointer",
+    },
+    ToolBinding {
+        button: MouseButton::Middle,
+        clicks: 1,
+        tool: Tool::DropMarker,
+        label: "Middle click",
+        description: "Drop a marker",
+    },
+    ToolBinding {
+        button: MouseButton::Middle,
+        clicks: 2,
+        tool: Tool::RangeRings,
+        label: "Middle double click",
+        description: "Drop range rings and bearing lines",
+    },
+    ToolBinding {
+        button: MouseButton::Right,
+        clicks: 1,
+        tool: Tool::Route,
+        label: "Right click",
+        description: "Start or finish a route",
+    },
+];
+
+pub fn tool_for(button: MouseButton, clicks: u8) -> Option<Tool> {
+    TOOL_BINDINGS
+        .iter()
+        .filter(|binding| binding.button == button && binding.clicks <= clicks)
+        .max_by_key(|binding| binding.clicks)
+        .map(|binding| binding.tool)
+}
+
+/// (trigger, description) rows for the help overlay, keys first.
+pub fn help_rows() -> Vec<(String, &'static str)> {
+    KEY_BINDINGS
+        .iter()
+        .map(|binding| {
+            let name = binding.key.name();
+            let trigger = if binding.shift {
+                format!("Shift+{}", name)
+            } else {
+                name
+            };
+            (trigger, binding.description)
+        })
+        .chain(
+            TOOL_BINDINGS
+                .iter()
+                .map(|binding| (binding.label.to_string(), binding.description)),
+        )
+        .collect()
+}
@@ -0,0 +1,51 @@
+use crate::viewport::Viewport;
+
+/// Clear space kept around each label, in pixels.
+const MARGIN_PX: f32 = 4.0;
+
+/// A name drawn centred on a point of the map, if there is room for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceLabel {
+    /// Normalised Web Mercator.
+    pub world: (f64, f64),
+    pub text: String,
+    /// Lower goes first, and so wins where labels would overlap.
+    pub priority: u32,
+}
+
+/// Window pixel of the top-left corner of every label that fits, most
+/// important first. A label is skipped if it would stick out of the window
+/// or overlap one placed before it. `size` measures text in pixels.
+pub fn place<'a>(
+    labels: &'a [PlaceLabel],
+    vp: &Viewport,
+    win: (u32, u32),
+    size: impl Fn(&str) -> (f32, f32),
+) -> Vec<(&'a str, (f32, f32))> {
+    let mut order: Vec<&PlaceLabel> = labels.iter().collect();
+    order.sort_by_key(|label| label.priority);
+    let mut taken: Vec<[f32; 4]> = Vec::new();
+    let mut placed = Vec::new();
+    for label in order {
+        let (x, y) = vp.world_to_pixel(label.world, win);
+        let (w, h) = size(&label.text);
+        let (left, top) = (x as f32 - w / 2.0, y as f32 - h / 2.0);
+        let rect = [
+            left - MARGIN_PX,
+            top - MARGIN_PX,
+            left + w + MARGIN_PX,
+            top + h + MARGIN_PX,
+        ];
+        let inside =
+            rect[0] >= 0.0 && rect[1] >= 0.0 && rect[2] <= win.0 as f32 && rect[3] <= win.1 as f32;
+        if inside && !taken.iter().any(|other| overlaps(&rect, other)) {
+            taken.push(rect);
+            placed.push((label.text.as_str(), (left, top)));
+        }
+    }
+    placed
+}
+
+fn overlaps(a: &[f32; 4], b: &[f32; 4]) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
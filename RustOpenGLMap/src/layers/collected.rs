@@ -0,0 +1,130 @@
+use crate::coord_format::CoordFormat;
+use crate::layers::markers::{MarkerIcon, MarkerId, MarkerLayer};
+use crate::layers::{DrawContext, Layer, Shape};
+use serde_json::json;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// File name, without extension, collected points are exported to.
+pub const EXPORT_STEM: &str = "points";
+
+/// Points digitised by clicking while collect mode is on, snapped to the
+/// precision of the coordinate format.
+pub struct CollectedPoints {
+    pub active: bool,
+    pub format: CoordFormat,
+    points: Vec<((f64, f64), MarkerId)>,
+    markers: MarkerLayer,
+    icon: Rc<MarkerIcon>,
+}
+
+impl CollectedPoints {
+    pub fn new(format: CoordFormat) -> Self {
+        Self {
+            active: false,
+            format,
+            points: Vec::new(),
+            markers: MarkerLayer::new(),
+            icon: MarkerIcon::dot([0, 220, 120, 255]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// (lat, lon) of every point, oldest first.
+    pub fn points(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.points.iter().map(|(point, _)| *point)
+    }
+
+    /// Appends (lat, lon) and returns it as stored, after snapping.
+    pub fn push(&mut self, point: (f64, f64)) -> (f64, f64) {
+        let point = self.format.snap(point);
+        let marker = self.markers.add(point.0, point.1, self.icon.clone());
+        self.points.push((point, marker));
+        point
+    }
+
+    /// Drops the most recent point.
+    pub fn undo(&mut self) {
+        if let Some((_, marker)) = self.points.pop() {
+            self.markers.remove(marker);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for (_, marker) in self.points.drain(..) {
+            self.markers.remove(marker);
+        }
+    }
+
+    /// The last `count` points as numbered, formatted lines, oldest first.
+    pub fn rows(&self, count: usize) -> Vec<String> {
+        let skip = self.points.len().saturating_sub(count);
+        self.points
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .map(|(i, (point, _))| format!("{:>3}  {}", i + 1, self.format.format(*point)))
+            .collect()
+    }
+
+    /// `n,lat,lon,position` rows, the position written in the configured format.
+    pub fn to_csv(&self) -> String {
+        let places = self.format.decimal_places();
+        let mut csv = String::from("n,lat,lon,position\n");
+        for (i, (point, _)) in self.points.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{:.*},{:.*},\"{}\"\n",
+                i + 1,
+                places,
+                point.0,
+                places,
+                point.1,
+                self.format.format(*point).replace('"', "\"\"")
+            ));
+        }
+        csv
+    }
+
+    /// A FeatureCollection of numbered points.
+    pub fn to_geojson(&self) -> serde_json::Value {
+        let features: Vec<serde_json::Value> = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, ((lat, lon), _))| {
+                json!({
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [lon, lat] },
+                    "properties": { "n": i + 1, "position": self.format.format((*lat, *lon)) },
+                })
+            })
+            .collect();
+        json!({ "type": "FeatureCollection", "features": features })
+    }
+
+    /// Writes `<stem>.csv` and `<stem>.geojson` into `dir`.
+    pub fn export(&self, dir: &Path) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+        let csv_path = dir.join(format!("{}.csv", EXPORT_STEM));
+        let geojson_path = dir.join(format!("{}.geojson", EXPORT_STEM));
+        std::fs::write(&csv_path, self.to_csv())?;
+        std::fs::write(
+            &geojson_path,
+            serde_json::to_string_pretty(&self.to_geojson())?,
+        )?;
+        Ok((csv_path, geojson_path))
+    }
+}
+
+impl Layer for CollectedPoints {
+    fn draw(&mut self, ctx: &DrawContext) {
+        self.markers.draw(ctx.vp, ctx.win);
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.markers.shapes()
+    }
+}
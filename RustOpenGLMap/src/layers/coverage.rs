@@ -0,0 +1,174 @@
+use crate::disk_cache::DISK_CACHE;
+use crate::geo;
+use crate::layers::{DrawContext, GeometryBuffer, Layer};
+use crate::tile::TilePos;
+use crate::tile_source::SOURCES;
+use crate::viewport::Viewport;
+use tracing::warn;
+
+/// Deepest level below the view coverage can be checked at, 16 cells per tile.
+pub const MAX_DEPTH: u8 = 4;
+
+/// What the grid was last built for; it is rebuilt when any of it changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GridKey {
+    viewport: Viewport,
+    win: (u32, u32),
+    source: u8,
+    depth: u8,
+    /// Tiles in the disk cache, changes whenever one is added or removed.
+    cached: usize,
+}
+
+/// Green/red grid of which tiles of a source are in the disk cache, for
+/// checking a region is seeded before going offline. Cells are the tiles
+/// `depth` levels below the view, so coverage of deeper zooms shows too.
+pub struct CoverageLayer {
+    pub visible: bool,
+    /// Source whose tiles are checked, the base map.
+    pub source: u8,
+    depth: u8,
+    key: Option<GridKey>,
+    /// Vertices of the cached cells, then of the missing ones.
+    counts: (usize, usize),
+    origin: (f64, f64),
+    cached_color: [f32; 4],
+    missing_color: [f32; 4],
+    buffer: Option<GeometryBuffer>,
+}
+
+impl CoverageLayer {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            source: 0,
+            depth: 0,
+            key: None,
+            counts: (0, 0),
+            origin: (0.5, 0.5),
+            cached_color: [0.1, 0.8, 0.2, 0.25],
+            missing_color: [0.9, 0.1, 0.1, 0.3],
+            buffer: None,
+        }
+    }
+
+    /// Checks one level deeper, wrapping back to the view's own zoom.
+    pub fn next_depth(&mut self) {
+        self.depth = (self.depth + 1) % (MAX_DEPTH + 1);
+    }
+
+    /// "Coverage z15: 120 of 144 tiles cached" for the HUD.
+    pub fn summary(&self) -> String {
+        let Some(key) = self.key else {
+            return String::new();
+        };
+        let (cached, missing) = (self.counts.0 / 6, self.counts.1 / 6);
+        format!(
+            "Coverage z{}: {} of {} tiles cached (Shift+X checks deeper)",
+            self.zoom(&key.viewport),
+            cached,
+            cached + missing
+        )
+    }
+
+    fn zoom(&self, viewport: &Viewport) -> u8 {
+        (viewport.z + self.depth).min(19)
+    }
+
+    fn rebuild(&mut self, key: GridKey) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let vp = &key.viewport;
+        let z = self.zoom(vp);
+        let shift = z - vp.z;
+        let (w, h) = (f64::from(key.win.0), f64::from(key.win.1));
+        let (x0, y0) = vp.tile_at_pixel((0.0, 0.0), key.win);
+        let (x1, y1) = vp.tile_at_pixel((w, h), key.win);
+        let (x0, y0) = (x0 << shift, y0 << shift);
+        let (x1, y1) = (((x1 + 1) << shift) - 1, ((y1 + 1) << shift) - 1);
+
+        let n = geo::world_tiles(z);
+        self.origin = (f64::from(x0) / n, f64::from(y0) / n);
+        // a pixel between cells keeps the grid readable
+        let gap = 1.0 / (256.0 * geo::world_tiles(vp.z));
+        let mut cached = Vec::new();
+        let mut missing = Vec::new();
+        {
+            let sources = SOURCES.read().unwrap();
+            let disk = DISK_CACHE.lock().unwrap();
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let tile = TilePos {
+                        z,
+                        x,
+                        y,
+                        m: key.source,
+                    };
+                    let cells = if disk.contains(&sources.file_path(&tile)) {
+                        &mut cached
+                    } else {
+                        &mut missing
+                    };
+                    let (left, top) = (f64::from(x - x0) / n, f64::from(y - y0) / n);
+                    let (right, bottom) = (left + 1.0 / n - gap, top + 1.0 / n - gap);
+                    cells.extend(
+                        [
+                            (left, top),
+                            (right, top),
+                            (right, bottom),
+                            (left, top),
+                            (right, bottom),
+                            (left, bottom),
+                        ]
+                        .map(|(x, y)| [x as f32, y as f32]),
+                    );
+                }
+            }
+        }
+        self.counts = (cached.len(), missing.len());
+        cached.extend(missing);
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&cached);
+        }
+        self.key = Some(key);
+        Ok(())
+    }
+}
+
+impl Layer for CoverageLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if !self.visible {
+            return;
+        }
+        let key = GridKey {
+            viewport: *ctx.vp,
+            win: ctx.win,
+            source: self.source,
+            depth: self.depth,
+            cached: DISK_CACHE.lock().unwrap().stats().tiles,
+        };
+        if self.key != Some(key)
+            && let Err(e) = self.rebuild(key)
+        {
+            warn!("Failed to build the coverage grid: {}", e);
+            self.visible = false;
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        let (cached, missing) = self.counts;
+        ctx.shader
+            .bind(ctx.vp, ctx.win, self.origin, self.cached_color);
+        buffer.draw(gl::TRIANGLES, 0, cached);
+        ctx.shader
+            .bind(ctx.vp, ctx.win, self.origin, self.missing_color);
+        buffer.draw(gl::TRIANGLES, cached, missing);
+        unsafe { gl::Disable(gl::BLEND) };
+    }
+}
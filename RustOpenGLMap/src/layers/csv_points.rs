@@ -0,0 +1,55 @@
+use crate::layers::markers::{MarkerIcon, MarkerLayer};
+use crate::layers::{DrawContext, Layer, Shape};
+use std::error::Error;
+use std::path::Path;
+
+const LAT_COLUMNS: &[&str] = &["lat", "latitude"];
+const LON_COLUMNS: &[&str] = &["lon", "lng", "long", "longitude"];
+
+/// A marker for every row of a CSV file with latitude and longitude
+/// columns, such as the output of `geocode`. Rows without coordinates are
+/// skipped.
+pub struct CsvPointLayer {
+    markers: MarkerLayer,
+}
+
+impl CsvPointLayer {
+    /// Reads the points of `path` as `color` dots, returns them with their count.
+    pub fn from_csv_file(path: &Path, color: [u8; 4]) -> Result<(Self, usize), Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let column = |names: &[&str]| {
+            headers
+                .iter()
+                .position(|h| names.iter().any(|name| h.trim().eq_ignore_ascii_case(name)))
+                .ok_or_else(|| format!("no {} column", names[0]))
+        };
+        let (lat, lon) = (column(LAT_COLUMNS)?, column(LON_COLUMNS)?);
+        let icon = MarkerIcon::dot(color);
+        let mut markers = MarkerLayer::new();
+        let mut count = 0;
+        for record in reader.records() {
+            let record = record?;
+            let number = |i: usize| record.get(i).and_then(|v| v.trim().parse::<f64>().ok());
+            if let (Some(lat), Some(lon)) = (number(lat), number(lon)) {
+                markers.add(lat, lon, icon.clone());
+                count += 1;
+            }
+        }
+        Ok((Self { markers }, count))
+    }
+}
+
+impl Layer for CsvPointLayer {
+    fn bounds(&self) -> Option<[f64; 4]> {
+        self.markers.bounds()
+    }
+
+    fn draw(&mut self, ctx: &DrawContext) {
+        self.markers.draw(ctx.vp, ctx.win);
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.markers.shapes()
+    }
+}
@@ -0,0 +1,144 @@
+use crate::geo;
+use crate::labels::PlaceLabel;
+use crate::layers::markers::{MarkerIcon, MarkerLayer};
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use crate::routing::Directions;
+use crate::units;
+use std::rc::Rc;
+use tracing::warn;
+
+/// Colour of the turn markers.
+const TURN_COLOR: [u8; 4] = [20, 140, 60, 255];
+
+/// The road route last found by the routing service, with a marker at each
+/// turn labelled with its instruction.
+pub struct DirectionsLayer {
+    directions: Option<Directions>,
+    /// Projected runs of the route, split at the antimeridian.
+    runs: Vec<Vec<(f64, f64)>>,
+    markers: MarkerLayer,
+    icon: Rc<MarkerIcon>,
+    color: [f32; 4],
+    /// Set when the route changes, re-uploaded on the next draw.
+    dirty: bool,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl DirectionsLayer {
+    pub fn new() -> Self {
+        Self {
+            directions: None,
+            runs: Vec::new(),
+            markers: MarkerLayer::new(),
+            icon: MarkerIcon::dot_sized(TURN_COLOR, 12),
+            color: [0.1, 0.6, 0.25, 1.0],
+            dirty: false,
+            buffer: None,
+        }
+    }
+
+    /// Shows `directions` in place of the route before.
+    pub fn set(&mut self, directions: Directions) {
+        self.runs = geo::split_at_antimeridian(&directions.points)
+            .into_iter()
+            .map(|run| {
+                run.into_iter()
+                    .map(|(lat, lon)| geo::project(lat, lon))
+                    .collect()
+            })
+            .collect();
+        self.markers = MarkerLayer::new();
+        for turn in &directions.turns {
+            self.markers.add(turn.at.0, turn.at.1, self.icon.clone());
+        }
+        self.directions = Some(directions);
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.directions = None;
+        self.runs.clear();
+        self.markers = MarkerLayer::new();
+        self.dirty = true;
+    }
+
+    /// Length and travel time of the route, e.g. `12.4 km, 1 h 05 min`.
+    pub fn summary(&self) -> Option<String> {
+        let directions = self.directions.as_ref()?;
+        let minutes = (directions.duration / 60.0).round() as u64;
+        let time = if minutes < 60 {
+            format!("{} min", minutes)
+        } else {
+            format!("{} h {:02} min", minutes / 60, minutes % 60)
+        };
+        Some(format!(
+            "{}, {}",
+            units::format_distance(directions.distance),
+            time
+        ))
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let vertices: Vec<[f32; 2]> = self
+            .runs
+            .iter()
+            .flatten()
+            .map(|(x, y)| [(x - 0.5) as f32, (y - 0.5) as f32])
+            .collect();
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Layer for DirectionsLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.dirty
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload the road route: {}", e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        ctx.shader.bind(ctx.vp, ctx.win, (0.5, 0.5), self.color);
+        let mut first = 0;
+        for run in &self.runs {
+            buffer.draw(gl::LINE_STRIP, first, run.len());
+            first += run.len();
+        }
+        self.markers.draw(ctx.vp, ctx.win);
+    }
+
+    /// The turn instructions, the first turns winning where they crowd.
+    fn labels(&self) -> Vec<PlaceLabel> {
+        self.directions
+            .iter()
+            .flat_map(|directions| directions.turns.iter().enumerate())
+            .map(|(i, turn)| PlaceLabel {
+                world: geo::project(turn.at.0, turn.at.1),
+                text: turn.instruction.clone(),
+                priority: i as u32,
+            })
+            .collect()
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        let mut shapes: Vec<Shape> = self
+            .runs
+            .iter()
+            .map(|run| Shape::Line {
+                points: run.clone(),
+                color: self.color,
+            })
+            .collect();
+        shapes.extend(self.markers.shapes());
+        shapes
+    }
+}
@@ -0,0 +1,452 @@
+use crate::geo;
+use crate::labels::PlaceLabel;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use crate::picking;
+use lyon_tessellation::math::point;
+use lyon_tessellation::path::Path as LyonPath;
+use lyon_tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, VertexBuffers};
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+/// Projected coordinates are scaled up before tessellation so lyon's
+/// tolerances don't swallow street-sized polygons.
+const TESSELLATION_SCALE: f64 = (1u64 << 24) as f64;
+
+/// Marker size in pixels, constant at every zoom level.
+const MARKER_PX: f64 = 10.0;
+
+type Ring = Vec<(f64, f64)>;
+
+/// Vertices of one point, line or polygon: the feature it belongs to, and
+/// where they start in their buffer and how many there are.
+type Range = (usize, usize, usize);
+
+/// Features of a GeoJSON file drawn over the basemap: points as markers,
+/// labelled with their `name` property, line strings as polylines and
+/// polygons as translucent fills.
+pub struct GeoJsonLayer {
+    name: String,
+    points: Vec<(f64, f64)>,
+    names: Vec<((f64, f64), String)>,
+    lines: Vec<Ring>,
+    polygons: Vec<Vec<Ring>>,
+    /// Properties of each feature, as text.
+    features: Vec<Vec<(String, String)>>,
+    /// The feature of each point, line and polygon.
+    point_features: Vec<usize>,
+    line_features: Vec<usize>,
+    polygon_features: Vec<usize>,
+    origin: (f64, f64),
+    pub point_color: [f32; 4],
+    pub line_color: [f32; 4],
+    pub fill_color: [f32; 4],
+    gpu: Option<GeoJsonBuffers>,
+}
+
+struct GeoJsonBuffers {
+    markers: GeometryBuffer,
+    marker_vertices: usize,
+    /// Zoom the marker quads were built for.
+    marker_zoom: Option<u8>,
+    lines: GeometryBuffer,
+    line_vertices: usize,
+    line_ranges: Vec<Range>,
+    fills: GeometryBuffer,
+    fill_vertices: usize,
+    fill_ranges: Vec<Range>,
+}
+
+impl GeoJsonLayer {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::parse(&name, &text)
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Self, Box<dyn Error>> {
+        let root: Value = serde_json::from_str(text)?;
+        let mut layer = Self {
+            name: name.to_string(),
+            points: Vec::new(),
+            names: Vec::new(),
+            lines: Vec::new(),
+            polygons: Vec::new(),
+            features: Vec::new(),
+            point_features: Vec::new(),
+            line_features: Vec::new(),
+            polygon_features: Vec::new(),
+            origin: (0.5, 0.5),
+            point_color: [0.85, 0.1, 0.1, 1.0],
+            line_color: [0.1, 0.3, 0.9, 1.0],
+            fill_color: [0.2, 0.45, 0.95, 0.35],
+            gpu: None,
+        };
+        layer.add_object(&root, None)?;
+        if layer.points.is_empty() && layer.lines.is_empty() && layer.polygons.is_empty() {
+            return Err(Box::from(format!("{}: no supported geometries", name)));
+        }
+        layer.origin = layer
+            .points
+            .first()
+            .or(layer.lines.first().and_then(|l| l.first()))
+            .or(layer
+                .polygons
+                .first()
+                .and_then(|p| p.first())
+                .and_then(|r| r.first()))
+            .copied()
+            .unwrap_or((0.5, 0.5));
+        Ok(layer)
+    }
+
+    /// Adds the geometries of `value`, as part of `feature` if it is
+    /// inside one and otherwise as a feature without properties.
+    fn add_object(&mut self, value: &Value, feature: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let kind = value["type"].as_str();
+        let feature = match kind {
+            Some("FeatureCollection" | "Feature") => feature,
+            _ => Some(feature.unwrap_or_else(|| self.add_feature(&Value::Null))),
+        };
+        match kind {
+            Some("FeatureCollection") => {
+                for feature in value["features"].as_array().into_iter().flatten() {
+                    self.add_object(feature, None)?;
+                }
+            }
+            Some("Feature") => {
+                let index = self.add_feature(&value["properties"]);
+                // features without geometry are legal, just not drawable
+                if !value["geometry"].is_null() {
+                    self.add_object(&value["geometry"], Some(index))?;
+                }
+                let geometry = &value["geometry"];
+                if geometry["type"] == "Point"
+                    && let Some(name) = value["properties"]["name"].as_str()
+                {
+                    self.names
+                        .push((position(&geometry["coordinates"])?, name.to_string()));
+                }
+            }
+            Some("GeometryCollection") => {
+                for geometry in value["geometries"].as_array().into_iter().flatten() {
+                    self.add_object(geometry, feature)?;
+                }
+            }
+            _ => self.add_geometry(value, feature.unwrap_or_default())?,
+        }
+        Ok(())
+    }
+
+    fn add_geometry(&mut self, value: &Value, feature: usize) -> Result<(), Box<dyn Error>> {
+        let coordinates = &value["coordinates"];
+        match value["type"].as_str() {
+            Some("Point") => self.add_point(position(coordinates)?, feature),
+            Some("MultiPoint") => {
+                for p in array(coordinates)? {
+                    self.add_point(position(p)?, feature);
+                }
+            }
+            Some("LineString") => self.add_line(ring(coordinates)?, feature),
+            Some("MultiLineString") => {
+                for line in array(coordinates)? {
+                    self.add_line(ring(line)?, feature);
+                }
+            }
+            Some("Polygon") => self.add_polygon(rings(coordinates)?, feature),
+            Some("MultiPolygon") => {
+                for polygon in array(coordinates)? {
+                    self.add_polygon(rings(polygon)?, feature);
+                }
+            }
+            other => return Err(Box::from(format!("unsupported GeoJSON type {:?}", other))),
+        }
+        Ok(())
+    }
+
+    /// Keeps `properties` as text for picking, returning the feature's index.
+    fn add_feature(&mut self, properties: &Value) -> usize {
+        let properties = properties
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| {
+                let text = match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                (key.clone(), text)
+            })
+            .collect();
+        self.features.push(properties);
+        self.features.len() - 1
+    }
+
+    fn add_point(&mut self, p: (f64, f64), feature: usize) {
+        self.points.push(p);
+        self.point_features.push(feature);
+    }
+
+    fn add_line(&mut self, line: Ring, feature: usize) {
+        self.lines.push(line);
+        self.line_features.push(feature);
+    }
+
+    fn add_polygon(&mut self, polygon: Vec<Ring>, feature: usize) {
+        self.polygons.push(polygon);
+        self.polygon_features.push(feature);
+    }
+
+    fn relative(&self, p: (f64, f64)) -> [f32; 2] {
+        [(p.0 - self.origin.0) as f32, (p.1 - self.origin.1) as f32]
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        let mut line_vertices = Vec::new();
+        let mut line_ranges = Vec::new();
+        // every line string and polygon ring becomes GL_LINES pairs so one draw covers all
+        let outlines = self
+            .lines
+            .iter()
+            .map(|l| vec![l])
+            .zip(&self.line_features)
+            .map(|(rings, feature)| (rings, *feature, false))
+            .chain(
+                self.polygons
+                    .iter()
+                    .map(|p| p.iter().collect())
+                    .zip(&self.polygon_features)
+                    .map(|(rings, feature)| (rings, *feature, true)),
+            );
+        for (rings, feature, closed) in outlines {
+            let first = line_vertices.len();
+            for points in rings {
+                for pair in points.windows(2) {
+                    line_vertices.push(self.relative(pair[0]));
+                    line_vertices.push(self.relative(pair[1]));
+                }
+                if closed && points.len() > 2 {
+                    line_vertices.push(self.relative(points[points.len() - 1]));
+                    line_vertices.push(self.relative(points[0]));
+                }
+            }
+            line_ranges.push((feature, first, line_vertices.len() - first));
+        }
+
+        let mut fill_vertices = Vec::new();
+        let mut fill_ranges = Vec::new();
+        for (polygon, feature) in self.polygons.iter().zip(&self.polygon_features) {
+            let first = fill_vertices.len();
+            fill_vertices.extend(tessellate(polygon, self.origin)?);
+            fill_ranges.push((*feature, first, fill_vertices.len() - first));
+        }
+
+        let lines = GeometryBuffer::new()?;
+        lines.upload(&line_vertices);
+        let fills = GeometryBuffer::new()?;
+        fills.upload(&fill_vertices);
+        self.gpu = Some(GeoJsonBuffers {
+            markers: GeometryBuffer::new()?,
+            marker_vertices: 0,
+            marker_zoom: None,
+            lines,
+            line_vertices: line_vertices.len(),
+            line_ranges,
+            fills,
+            fill_vertices: fill_vertices.len(),
+            fill_ranges,
+        });
+        Ok(())
+    }
+
+    /// Uploads the geometry on first use and sizes the markers for zoom `z`.
+    fn prepare(&mut self, z: u8) {
+        if self.gpu.is_none()
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload {}: {}", self.name, e);
+            return;
+        }
+        if self.gpu.as_ref().and_then(|gpu| gpu.marker_zoom) != Some(z) {
+            self.rebuild_markers(z);
+        }
+    }
+
+    /// Rebuilds the marker diamonds so they keep their pixel size at zoom `z`.
+    fn rebuild_markers(&mut self, z: u8) {
+        let half = MARKER_PX / 2.0 / (geo::world_tiles(z) * 256.0);
+        let mut vertices = Vec::with_capacity(self.points.len() * 6);
+        for p in &self.points {
+            let [x, y] = self.relative(*p);
+            let h = half as f32;
+            let (top, right, bottom, left) = ([x, y - h], [x + h, y], [x, y + h], [x - h, y]);
+            vertices.extend_from_slice(&[top, right, bottom, top, bottom, left]);
+        }
+        if let Some(gpu) = &mut self.gpu {
+            gpu.markers.upload(&vertices);
+            gpu.marker_vertices = vertices.len();
+            gpu.marker_zoom = Some(z);
+        }
+    }
+}
+
+impl Layer for GeoJsonLayer {
+    fn bounds(&self) -> Option<[f64; 4]> {
+        let outlines = self.lines.iter().chain(self.polygons.iter().flatten());
+        geo::bounds(self.points.iter().chain(outlines.flatten()).copied())
+    }
+
+    fn labels(&self) -> Vec<PlaceLabel> {
+        self.names
+            .iter()
+            .map(|(world, name)| PlaceLabel {
+                world: *world,
+                text: name.clone(),
+                priority: 0,
+            })
+            .collect()
+    }
+
+    fn draw(&mut self, ctx: &DrawContext) {
+        self.prepare(ctx.vp.z);
+        let Some(gpu) = &self.gpu else {
+            return;
+        };
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        ctx.shader
+            .bind(ctx.vp, ctx.win, self.origin, self.fill_color);
+        gpu.fills.draw(gl::TRIANGLES, 0, gpu.fill_vertices);
+        ctx.shader
+            .bind(ctx.vp, ctx.win, self.origin, self.line_color);
+        gpu.lines.draw(gl::LINES, 0, gpu.line_vertices);
+        ctx.shader
+            .bind(ctx.vp, ctx.win, self.origin, self.point_color);
+        gpu.markers.draw(gl::TRIANGLES, 0, gpu.marker_vertices);
+        unsafe { gl::Disable(gl::BLEND) };
+    }
+
+    fn draw_ids(&mut self, ctx: &DrawContext, first: u32) -> u32 {
+        self.prepare(ctx.vp.z);
+        let Some(gpu) = &self.gpu else {
+            return 0;
+        };
+        // markers last, as they are drawn over the lines and fills
+        let ranges = gpu
+            .fill_ranges
+            .iter()
+            .map(|range| (&gpu.fills, gl::TRIANGLES, *range))
+            .chain(
+                gpu.line_ranges
+                    .iter()
+                    .map(|range| (&gpu.lines, gl::LINES, *range)),
+            )
+            .chain(
+                self.point_features
+                    .iter()
+                    .enumerate()
+                    .map(|(i, feature)| (&gpu.markers, gl::TRIANGLES, (*feature, i * 6, 6))),
+            );
+        for (buffer, mode, (feature, start, count)) in ranges {
+            let color = picking::id_color(first + feature as u32);
+            ctx.shader.bind(ctx.vp, ctx.win, self.origin, color);
+            buffer.draw(mode, start, count);
+        }
+        self.features.len() as u32
+    }
+
+    fn feature(&self, index: usize) -> Vec<(String, String)> {
+        self.features.get(index).cloned().unwrap_or_default()
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        let polygons = self.polygons.iter().map(|rings| Shape::Polygon {
+            rings: rings.clone(),
+            fill: self.fill_color,
+            stroke: self.line_color,
+        });
+        let lines = self.lines.iter().map(|line| Shape::Line {
+            points: line.clone(),
+            color: self.line_color,
+        });
+        let points = self.points.iter().map(|&at| Shape::Dot {
+            at,
+            color: self.point_color,
+            radius: (MARKER_PX / 2.0) as f32,
+        });
+        polygons.chain(lines).chain(points).collect()
+    }
+}
+
+/// Triangulates a polygon (holes included) into a flat triangle list,
+/// relative to `origin`.
+pub fn tessellate(polygon: &[Ring], origin: (f64, f64)) -> Result<Vec<[f32; 2]>, String> {
+    let mut builder = LyonPath::builder();
+    for ring in polygon.iter().filter(|r| r.len() >= 3) {
+        let scaled = |p: &(f64, f64)| {
+            point(
+                ((p.0 - origin.0) * TESSELLATION_SCALE) as f32,
+                ((p.1 - origin.1) * TESSELLATION_SCALE) as f32,
+            )
+        };
+        builder.begin(scaled(&ring[0]));
+        for p in &ring[1..] {
+            builder.line_to(scaled(p));
+        }
+        builder.end(true);
+    }
+    let path = builder.build();
+
+    let mut geometry: VertexBuffers<[f32; 2], u32> = VertexBuffers::new();
+    FillTessellator::new()
+        .tessellate_path(
+            &path,
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(&mut geometry, |v: FillVertex| {
+                let p = v.position();
+                [
+                    (p.x as f64 / TESSELLATION_SCALE) as f32,
+                    (p.y as f64 / TESSELLATION_SCALE) as f32,
+                ]
+            }),
+        )
+        .map_err(|e| format!("polygon tessellation failed: {:?}", e))?;
+    Ok(geometry
+        .indices
+        .iter()
+        .map(|i| geometry.vertices[*i as usize])
+        .collect())
+}
+
+fn array(value: &Value) -> Result<&Vec<Value>, Box<dyn Error>> {
+    value
+        .as_array()
+        .ok_or_else(|| Box::from("expected a coordinate array".to_string()))
+}
+
+/// A GeoJSON `[lon, lat]` position, projected to Web Mercator.
+fn position(value: &Value) -> Result<(f64, f64), Box<dyn Error>> {
+    let coords = array(value)?;
+    match (
+        coords.first().and_then(Value::as_f64),
+        coords.get(1).and_then(Value::as_f64),
+    ) {
+        (Some(lon), Some(lat)) => Ok(geo::project(lat, lon)),
+        _ => Err(Box::from("invalid position".to_string())),
+    }
+}
+
+fn ring(value: &Value) -> Result<Ring, Box<dyn Error>> {
+    array(value)?.iter().map(position).collect()
+}
+
+fn rings(value: &Value) -> Result<Vec<Ring>, Box<dyn Error>> {
+    array(value)?.iter().map(ring).collect()
+}
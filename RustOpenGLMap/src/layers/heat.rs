@@ -0,0 +1,117 @@
+use crate::geo;
+use crate::history::HeatCell;
+use crate::layers::{DrawContext, GeometryBuffer, Layer};
+use tracing::warn;
+
+/// Shades of the heatmap, least looked at first.
+const LEVELS: [[f32; 4]; 5] = [
+    [1.0, 0.85, 0.2, 0.15],
+    [1.0, 0.65, 0.1, 0.25],
+    [1.0, 0.45, 0.0, 0.35],
+    [0.95, 0.25, 0.0, 0.45],
+    [0.85, 0.0, 0.0, 0.55],
+];
+
+/// Where the map has been looked at, from the view history: the longer a
+/// cell was on screen the hotter it is drawn.
+pub struct HeatLayer {
+    pub visible: bool,
+    cells: Vec<HeatCell>,
+    /// Vertex count of each level, in the order they are stored.
+    counts: [usize; LEVELS.len()],
+    /// Set when the cells change, re-uploaded on the next draw.
+    dirty: bool,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl HeatLayer {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            cells: Vec::new(),
+            counts: [0; LEVELS.len()],
+            dirty: false,
+            buffer: None,
+        }
+    }
+
+    pub fn replace(&mut self, cells: Vec<HeatCell>) {
+        self.cells = cells;
+        self.dirty = true;
+    }
+
+    /// Level of every cell on a log scale, so a few long stays don't wash
+    /// out everything else.
+    fn levels(&self) -> Vec<usize> {
+        let max = self
+            .cells
+            .iter()
+            .map(|cell| cell.seconds)
+            .fold(0.0, f64::max);
+        let top = (1.0 + max).ln();
+        self.cells
+            .iter()
+            .map(|cell| {
+                let t = if top > 0.0 {
+                    (1.0 + cell.seconds).ln() / top
+                } else {
+                    0.0
+                };
+                ((t * LEVELS.len() as f64) as usize).min(LEVELS.len() - 1)
+            })
+            .collect()
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let levels = self.levels();
+        let mut vertices: Vec<[f32; 2]> = Vec::new();
+        for level in 0..LEVELS.len() {
+            let start = vertices.len();
+            for (cell, _) in self.cells.iter().zip(&levels).filter(|(_, l)| **l == level) {
+                let n = geo::world_tiles(cell.z);
+                let x0 = (f64::from(cell.x) / n - 0.5) as f32;
+                let y0 = (f64::from(cell.y) / n - 0.5) as f32;
+                let x1 = ((f64::from(cell.x) + 1.0) / n - 0.5) as f32;
+                let y1 = ((f64::from(cell.y) + 1.0) / n - 0.5) as f32;
+                vertices.extend([[x0, y0], [x1, y0], [x1, y1], [x0, y0], [x1, y1], [x0, y1]]);
+            }
+            self.counts[level] = vertices.len() - start;
+        }
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Layer for HeatLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if !self.visible || self.cells.is_empty() {
+            return;
+        }
+        if self.dirty
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload the heatmap: {}", e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        let mut first = 0;
+        for (color, count) in LEVELS.iter().zip(self.counts) {
+            ctx.shader.bind(ctx.vp, ctx.win, (0.5, 0.5), *color);
+            buffer.draw(gl::TRIANGLES, first, count);
+            first += count;
+        }
+        unsafe { gl::Disable(gl::BLEND) };
+    }
+}
@@ -0,0 +1,313 @@
+use crate::geo;
+use crate::labels::PlaceLabel;
+use crate::layers::geojson::tessellate;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape, ZoomRange};
+use crate::tile::MAX_ZOOM;
+use crate::viewport::Viewport;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use tracing::warn;
+
+pub const HOTSPOTS_PATH: &str = "hotspots.toml";
+
+/// What clicking a hotspot does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HotspotAction {
+    /// Opens the page in the default browser.
+    OpenUrl(String),
+    /// Runs a program with its arguments, e.g. `["notify-send", "Harbour"]`.
+    Command(Vec<String>),
+    /// Flies the map there, switching to `source` if one is given.
+    FlyTo {
+        lat: f64,
+        lon: f64,
+        zoom: u8,
+        #[serde(default)]
+        source: Option<u8>,
+    },
+}
+
+impl HotspotAction {
+    /// Starts the browser or the program in the background. Flights are
+    /// left to the caller, which owns the view.
+    pub fn launch(&self) -> Result<(), Box<dyn Error>> {
+        let mut child = match self {
+            HotspotAction::OpenUrl(url) => browser(url).spawn()?,
+            HotspotAction::Command(args) => {
+                let (program, args) = args
+                    .split_first()
+                    .ok_or_else(|| "empty command".to_string())?;
+                Command::new(program).args(args).spawn()?
+            }
+            HotspotAction::FlyTo { .. } => return Ok(()),
+        };
+        // reaped once it exits, so it doesn't linger as a zombie
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    /// Where a `FlyTo` goes.
+    pub fn viewport(&self) -> Option<Viewport> {
+        match *self {
+            HotspotAction::FlyTo { lat, lon, zoom, .. } => {
+                Some(Viewport::centered_on(lat, lon, zoom.min(MAX_ZOOM)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn browser(url: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.args(["/C", "start", "", url]);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn browser(url: &str) -> Command {
+    let mut command = Command::new("open");
+    command.arg(url);
+    command
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn browser(url: &str) -> Command {
+    let mut command = Command::new("xdg-open");
+    command.arg(url);
+    command
+}
+
+fn default_color() -> [u8; 4] {
+    [255, 200, 0, 60]
+}
+
+/// A clickable region of the map and what clicking it does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hotspot {
+    pub name: String,
+    /// Corners as `[lat, lon]`; the last one joins back to the first.
+    pub polygon: Vec<[f64; 2]>,
+    pub action: HotspotAction,
+    /// Fill as RGBA, outlined in the same colour. A transparent hotspot is
+    /// still clickable but neither drawn nor labelled.
+    #[serde(default = "default_color")]
+    pub color: [u8; 4],
+    /// Zooms it is drawn and clickable at.
+    #[serde(flatten)]
+    pub zoom: ZoomRange,
+}
+
+impl Hotspot {
+    fn visible(&self) -> bool {
+        self.color[3] > 0
+    }
+
+    fn fill(&self) -> [f32; 4] {
+        self.color.map(|c| f32::from(c) / 255.0)
+    }
+
+    fn stroke(&self) -> [f32; 4] {
+        let [r, g, b, _] = self.fill();
+        [r, g, b, 1.0]
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HotspotFile {
+    #[serde(default, rename = "hotspot")]
+    hotspots: Vec<Hotspot>,
+}
+
+struct HotspotBuffers {
+    fills: GeometryBuffer,
+    /// Where each hotspot's triangles start and how many vertices they have.
+    fill_ranges: Vec<(usize, usize)>,
+    outlines: GeometryBuffer,
+    outline_ranges: Vec<(usize, usize)>,
+}
+
+/// Regions from `hotspots.toml` that do something when clicked: open a web
+/// page, run a program or fly the map somewhere. With the keys and panels
+/// out of the way this makes a simple kiosk map.
+pub struct HotspotLayer {
+    hotspots: Vec<Hotspot>,
+    /// Each hotspot's outline in Web Mercator.
+    rings: Vec<Vec<(f64, f64)>>,
+    origin: (f64, f64),
+    gpu: Option<HotspotBuffers>,
+}
+
+impl HotspotLayer {
+    pub fn new(hotspots: Vec<Hotspot>) -> Self {
+        let hotspots: Vec<Hotspot> = hotspots
+            .into_iter()
+            .filter(|hotspot| hotspot.polygon.len() >= 3)
+            .collect();
+        let rings: Vec<Vec<(f64, f64)>> = hotspots
+            .iter()
+            .map(|hotspot| {
+                hotspot
+                    .polygon
+                    .iter()
+                    .map(|&[lat, lon]| geo::project(lat, lon))
+                    .collect()
+            })
+            .collect();
+        let origin = rings
+            .first()
+            .and_then(|ring| ring.first())
+            .copied()
+            .unwrap_or_default();
+        Self {
+            hotspots,
+            rings,
+            origin,
+            gpu: None,
+        }
+    }
+
+    /// Reads `path` if it exists, otherwise has no hotspots.
+    pub fn load_or_default(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::new(Vec::new());
+        }
+        let file: HotspotFile = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                warn!("Failed to load {}: {}", path.display(), e);
+                HotspotFile::default()
+            });
+        Self::new(file.hotspots)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hotspots.len()
+    }
+
+    /// The topmost hotspot under window pixel (px, py), if any.
+    pub fn hit(&self, vp: &Viewport, win: (u32, u32), px: i32, py: i32) -> Option<&Hotspot> {
+        let at = vp.pixel_to_world((f64::from(px), f64::from(py)), win);
+        self.hotspots
+            .iter()
+            .zip(&self.rings)
+            .rev()
+            .find(|(hotspot, ring)| hotspot.zoom.contains(vp.z) && contains(ring, at))
+            .map(|(hotspot, _)| hotspot)
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        let mut fill_vertices = Vec::new();
+        let mut fill_ranges = Vec::new();
+        let mut outline_vertices = Vec::new();
+        let mut outline_ranges = Vec::new();
+        for ring in &self.rings {
+            let triangles = tessellate(std::slice::from_ref(ring), self.origin)?;
+            fill_ranges.push((fill_vertices.len(), triangles.len()));
+            fill_vertices.extend(triangles);
+            outline_ranges.push((outline_vertices.len(), ring.len()));
+            outline_vertices.extend(
+                ring.iter()
+                    .map(|p| [(p.0 - self.origin.0) as f32, (p.1 - self.origin.1) as f32]),
+            );
+        }
+        let fills = GeometryBuffer::new()?;
+        fills.upload(&fill_vertices);
+        let outlines = GeometryBuffer::new()?;
+        outlines.upload(&outline_vertices);
+        self.gpu = Some(HotspotBuffers {
+            fills,
+            fill_ranges,
+            outlines,
+            outline_ranges,
+        });
+        Ok(())
+    }
+}
+
+impl Layer for HotspotLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.hotspots.is_empty() {
+            return;
+        }
+        if self.gpu.is_none()
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload the hotspots: {}", e);
+            self.hotspots.clear();
+            return;
+        }
+        let Some(gpu) = &self.gpu else {
+            return;
+        };
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        for (i, hotspot) in self.hotspots.iter().enumerate() {
+            if !hotspot.visible() || !hotspot.zoom.contains(ctx.vp.z) {
+                continue;
+            }
+            let (first, count) = gpu.fill_ranges[i];
+            ctx.shader
+                .bind(ctx.vp, ctx.win, self.origin, hotspot.fill());
+            gpu.fills.draw(gl::TRIANGLES, first, count);
+            let (first, count) = gpu.outline_ranges[i];
+            ctx.shader
+                .bind(ctx.vp, ctx.win, self.origin, hotspot.stroke());
+            gpu.outlines.draw(gl::LINE_LOOP, first, count);
+        }
+    }
+
+    fn labels(&self) -> Vec<PlaceLabel> {
+        self.hotspots
+            .iter()
+            .zip(&self.rings)
+            .filter(|(hotspot, _)| hotspot.visible() && !hotspot.name.is_empty())
+            .map(|(hotspot, ring)| {
+                let n = ring.len() as f64;
+                let (x, y) = ring
+                    .iter()
+                    .fold((0.0, 0.0), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+                PlaceLabel {
+                    world: (x, y),
+                    text: hotspot.name.clone(),
+                    priority: 0,
+                }
+            })
+            .collect()
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.hotspots
+            .iter()
+            .zip(&self.rings)
+            .filter(|(hotspot, _)| hotspot.visible())
+            .map(|(hotspot, ring)| Shape::Polygon {
+                rings: vec![ring.clone()],
+                fill: hotspot.fill(),
+                stroke: hotspot.stroke(),
+            })
+            .collect()
+    }
+}
+
+/// Even-odd test of Web Mercator point `at` against the closed `ring`.
+fn contains(ring: &[(f64, f64)], at: (f64, f64)) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let ((xi, yi), (xj, yj)) = (ring[i], ring[j]);
+        if (yi > at.1) != (yj > at.1) && at.0 < (xj - xi) * (at.1 - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
@@ -0,0 +1,429 @@
+use crate::geo;
+use crate::layers::Shape;
+use crate::opengl_helper::{self, Buffer, BufferType, Sampler, ShaderProgram, VertexArray, c_str};
+use crate::viewport::Viewport;
+use gl::types::*;
+use image::{Rgba, RgbaImage};
+use std::cell::Cell;
+use std::rc::Rc;
+use tracing::warn;
+
+const MARKER_VERT_SHADER: &str = r#"#version 410 core
+layout (location = 0) in vec2 pos;
+layout (location = 1) in vec2 tex;
+
+out vec2 v_tex;
+
+void main() {
+    gl_Position = vec4(pos, 0.0, 1.0);
+    v_tex       = tex;
+}
+"#;
+
+const MARKER_FRAG_SHADER: &str = r#"#version 410 core
+uniform sampler2D u_icon;
+in  vec2 v_tex;
+out vec4 final_color;
+void main() { final_color = texture(u_icon, v_tex); }
+"#;
+
+/// x, y in NDC followed by u, v in the icon.
+type MarkerVertex = [f32; 4];
+
+/// Markers closer than this many pixels count as one stack.
+const STACK_PX: f64 = 4.0;
+/// Least distance of fanned out markers from their stack, in pixels.
+const SPIDER_RADIUS_PX: f64 = 30.0;
+/// Pixels between neighbouring fanned out markers on the circle.
+const SPIDER_SPACING_PX: f64 = 24.0;
+const LEG_COLOR: [u8; 4] = [60, 60, 60, 220];
+const LEG_WIDTH_PX: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarkerId(pub u32);
+
+/// Image drawn for a marker, shared between markers that look alike.
+pub struct MarkerIcon {
+    image: RgbaImage,
+    /// Point of the image placed on the marker position, as fractions of its size.
+    anchor: (f32, f32),
+    // uploaded on first use
+    texture: Cell<GLuint>,
+}
+
+impl MarkerIcon {
+    pub fn new(image: RgbaImage, anchor: (f32, f32)) -> Rc<Self> {
+        Rc::new(Self {
+            image,
+            anchor,
+            texture: Cell::new(0),
+        })
+    }
+
+    /// A round pin in `color`, anchored at its centre.
+    pub fn dot(color: [u8; 4]) -> Rc<Self> {
+        Self::dot_sized(color, 16)
+    }
+
+    /// Like `dot`, `size` pixels across.
+    pub fn dot_sized(color: [u8; 4], size: u32) -> Rc<Self> {
+        let r = size as f32 / 2.0;
+        let image = RgbaImage::from_fn(size, size, |x, y| {
+            let d = ((x as f32 + 0.5 - r).powi(2) + (y as f32 + 0.5 - r).powi(2)).sqrt();
+            if d <= r - 3.0 {
+                Rgba(color)
+            } else if d <= r - 1.0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        Self::new(image, (0.5, 0.5))
+    }
+
+    fn size(&self) -> (f32, f32) {
+        (self.image.width() as f32, self.image.height() as f32)
+    }
+
+    fn texture(&self) -> GLuint {
+        if self.texture.get() == 0 {
+            match opengl_helper::create_texture_from_bitmap(&self.image) {
+                Ok(texture) => self.texture.set(texture),
+                Err(e) => warn!("Failed to upload a marker icon: {}", e),
+            }
+        }
+        self.texture.get()
+    }
+
+    /// The icon as a dot for vector export: the colour at its anchor and
+    /// half its width.
+    fn as_dot(&self) -> ([f32; 4], f32) {
+        let (w, h) = self.size();
+        let x = ((w * self.anchor.0) as u32).min(self.image.width().saturating_sub(1));
+        let y = ((h * self.anchor.1) as u32).min(self.image.height().saturating_sub(1));
+        let color = self.image.get_pixel(x, y).0.map(|c| f32::from(c) / 255.0);
+        (color, w / 2.0)
+    }
+
+    /// Pixel rect [x0, y0, x1, y1] of the icon for a marker at window pixel `at`.
+    fn rect(&self, at: (f64, f64)) -> [f32; 4] {
+        let (w, h) = self.size();
+        let x0 = at.0 as f32 - w * self.anchor.0;
+        let y0 = at.1 as f32 - h * self.anchor.1;
+        [x0, y0, x0 + w, y0 + h]
+    }
+}
+
+pub struct Marker {
+    pub id: MarkerId,
+    pub lat: f64,
+    pub lon: f64,
+    world: (f64, f64),
+    icon: Rc<MarkerIcon>,
+}
+
+/// A stack of markers fanned out in a circle around where they are, so
+/// each can be clicked on its own.
+struct Spider {
+    /// Where the stack is.
+    center: (f64, f64),
+    ids: Vec<MarkerId>,
+    /// Zoom it was fanned out at; zooming collapses it again.
+    z: u8,
+}
+
+impl Spider {
+    /// Pixel offset of the `index`-th marker from the stack.
+    fn offset(&self, index: usize) -> (f64, f64) {
+        let n = self.ids.len() as f64;
+        let radius = SPIDER_RADIUS_PX.max(n * SPIDER_SPACING_PX / std::f64::consts::TAU);
+        let angle = -std::f64::consts::FRAC_PI_2 + index as f64 * std::f64::consts::TAU / n;
+        (radius * angle.cos(), radius * angle.sin())
+    }
+}
+
+/// Icons pinned to geographic positions, drawn at the same pixel size at every zoom.
+pub struct MarkerLayer {
+    markers: Vec<Marker>,
+    next_id: u32,
+    spider: Option<Spider>,
+    /// Drawn stretched into the lines from a fanned out stack to its markers.
+    leg: Rc<MarkerIcon>,
+    gpu: Option<(ShaderProgram, VertexArray, Buffer, Sampler)>,
+}
+
+impl MarkerLayer {
+    pub fn new() -> Self {
+        Self {
+            markers: Vec::new(),
+            next_id: 0,
+            spider: None,
+            leg: MarkerIcon::new(RgbaImage::from_pixel(1, 1, Rgba(LEG_COLOR)), (0.5, 0.5)),
+            gpu: None,
+        }
+    }
+
+    pub fn add(&mut self, lat: f64, lon: f64, icon: Rc<MarkerIcon>) -> MarkerId {
+        let id = MarkerId(self.next_id);
+        self.next_id += 1;
+        self.markers.push(Marker {
+            id,
+            lat,
+            lon,
+            world: geo::project(lat, lon),
+            icon,
+        });
+        id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Marker> {
+        self.markers.iter()
+    }
+
+    /// (south, west, north, east) around all markers.
+    pub fn bounds(&self) -> Option<[f64; 4]> {
+        geo::bounds(self.markers.iter().map(|marker| marker.world))
+    }
+
+    pub fn move_to(&mut self, id: MarkerId, lat: f64, lon: f64) {
+        if let Some(marker) = self.markers.iter_mut().find(|m| m.id == id) {
+            marker.lat = lat;
+            marker.lon = lon;
+            marker.world = geo::project(lat, lon);
+        }
+    }
+
+    pub fn remove(&mut self, id: MarkerId) {
+        self.markers.retain(|m| m.id != id);
+        if let Some(spider) = &mut self.spider {
+            spider.ids.retain(|&other| other != id);
+            if spider.ids.len() < 2 {
+                self.spider = None;
+            }
+        }
+    }
+
+    /// Fans out the stack of markers lying under marker `id`, if there is
+    /// more than one. Returns false when there is no stack or it is already
+    /// fanned out.
+    pub fn spiderfy(&mut self, id: MarkerId, vp: &Viewport, win: (u32, u32)) -> bool {
+        if self
+            .open_spider(vp)
+            .is_some_and(|spider| spider.ids.contains(&id))
+        {
+            return false;
+        }
+        let Some(marker) = self.get(id) else {
+            return false;
+        };
+        let at = vp.world_to_pixel(marker.world, win);
+        let center = marker.world;
+        let ids: Vec<MarkerId> = self
+            .markers
+            .iter()
+            .filter(|other| {
+                let p = vp.world_to_pixel(other.world, win);
+                (p.0 - at.0).hypot(p.1 - at.1) < STACK_PX
+            })
+            .map(|other| other.id)
+            .collect();
+        if ids.len() < 2 {
+            return false;
+        }
+        self.spider = Some(Spider {
+            center,
+            ids,
+            z: vp.z,
+        });
+        true
+    }
+
+    /// Puts a fanned out stack back together. Returns whether there was one.
+    pub fn collapse(&mut self) -> bool {
+        self.spider.take().is_some()
+    }
+
+    pub fn spiderfied(&self) -> bool {
+        self.spider.is_some()
+    }
+
+    /// The fanned out stack, unless the zoom has changed since.
+    fn open_spider(&self, vp: &Viewport) -> Option<&Spider> {
+        self.spider.as_ref().filter(|spider| spider.z == vp.z)
+    }
+
+    /// Window pixel `marker` is drawn at: its place, or its spot around a
+    /// fanned out stack.
+    fn pixel(&self, marker: &Marker, vp: &Viewport, win: (u32, u32)) -> (f64, f64) {
+        let at = vp.world_to_pixel(marker.world, win);
+        let Some(spider) = self.open_spider(vp) else {
+            return at;
+        };
+        match spider.ids.iter().position(|&id| id == marker.id) {
+            Some(index) => {
+                let center = vp.world_to_pixel(spider.center, win);
+                let (dx, dy) = spider.offset(index);
+                (center.0 + dx, center.1 + dy)
+            }
+            None => at,
+        }
+    }
+
+    /// Markers in drawing order: fanned out ones last, on top of the rest.
+    fn drawing_order(&self, vp: &Viewport) -> Vec<&Marker> {
+        let spread = |marker: &Marker| {
+            self.open_spider(vp)
+                .is_some_and(|spider| spider.ids.contains(&marker.id))
+        };
+        let (mut order, fanned): (Vec<&Marker>, Vec<&Marker>) =
+            self.markers.iter().partition(|marker| !spread(marker));
+        order.extend(fanned);
+        order
+    }
+
+    pub fn get(&self, id: MarkerId) -> Option<&Marker> {
+        self.markers.iter().find(|m| m.id == id)
+    }
+
+    /// Every marker as a dot, for vector export.
+    pub fn shapes(&self) -> Vec<Shape> {
+        self.markers
+            .iter()
+            .map(|marker| {
+                let (color, radius) = marker.icon.as_dot();
+                Shape::Dot {
+                    at: marker.world,
+                    color,
+                    radius,
+                }
+            })
+            .collect()
+    }
+
+    /// Topmost marker whose icon covers window pixel (px, py).
+    pub fn hit_test(&self, vp: &Viewport, win: (u32, u32), px: i32, py: i32) -> Option<MarkerId> {
+        let (px, py) = (px as f32, py as f32);
+        // later markers are drawn on top, so test them first
+        self.drawing_order(vp).into_iter().rev().find_map(|marker| {
+            let [x0, y0, x1, y1] = marker.icon.rect(self.pixel(marker, vp, win));
+            (px >= x0 && px < x1 && py >= y0 && py < y1).then_some(marker.id)
+        })
+    }
+
+    fn init_gpu(&mut self) -> Result<(), String> {
+        let program = ShaderProgram::from_vert_frag(MARKER_VERT_SHADER, MARKER_FRAG_SHADER)?;
+        let vao = VertexArray::new().ok_or_else(|| "Couldn't make a marker VAO".to_string())?;
+        let vbo = Buffer::new().ok_or_else(|| "Couldn't make a marker VBO".to_string())?;
+        vao.bind();
+        vbo.bind(BufferType::Array);
+        let stride = size_of::<MarkerVertex>() as GLsizei;
+        unsafe {
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, std::ptr::null());
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(
+                1,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                size_of::<[f32; 2]>() as *const _,
+            );
+            gl::EnableVertexAttribArray(1);
+        }
+        VertexArray::clear_binding();
+        // icons are drawn at their own size, no mipmaps needed
+        let sampler = Sampler::new(gl::LINEAR, gl::LINEAR, gl::CLAMP_TO_EDGE)
+            .ok_or_else(|| "Couldn't make a marker sampler".to_string())?;
+        self.gpu = Some((program, vao, vbo, sampler));
+        Ok(())
+    }
+
+    pub fn draw(&mut self, vp: &Viewport, win: (u32, u32)) {
+        if self.markers.is_empty() {
+            return;
+        }
+        if self.spider.as_ref().is_some_and(|spider| spider.z != vp.z) {
+            self.spider = None;
+        }
+        if self.gpu.is_none()
+            && let Err(e) = self.init_gpu()
+        {
+            warn!("Failed to set up markers: {}", e);
+            return;
+        }
+        let Some((program, vao, vbo, sampler)) = &self.gpu else {
+            return;
+        };
+        unsafe {
+            gl::UseProgram(program.0);
+            gl::Uniform1i(gl::GetUniformLocation(program.0, c_str!("u_icon")), 0);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        sampler.bind(0);
+        vao.bind();
+        vbo.bind(BufferType::Array);
+        let to_ndc = |x: f32, y: f32| [x / win.0 as f32 * 2.0 - 1.0, 1.0 - y / win.1 as f32 * 2.0];
+        if let Some(spider) = &self.spider {
+            let from = vp.world_to_pixel(spider.center, win);
+            unsafe { gl::BindTexture(gl::TEXTURE_2D, self.leg.texture()) };
+            for index in 0..spider.ids.len() {
+                let (dx, dy) = spider.offset(index);
+                // a thin quad along the leg, sampling the one texel
+                let len = dx.hypot(dy) as f32;
+                let (nx, ny) = (
+                    -dy as f32 / len * LEG_WIDTH_PX,
+                    dx as f32 / len * LEG_WIDTH_PX,
+                );
+                let (ax, ay) = (from.0 as f32, from.1 as f32);
+                let (bx, by) = (ax + dx as f32, ay + dy as f32);
+                let corner = |x: f32, y: f32| {
+                    let [x, y] = to_ndc(x, y);
+                    [x, y, 0.5, 0.5]
+                };
+                let quad: [MarkerVertex; 6] = [
+                    corner(ax - nx, ay - ny),
+                    corner(bx - nx, by - ny),
+                    corner(bx + nx, by + ny),
+                    corner(ax - nx, ay - ny),
+                    corner(bx + nx, by + ny),
+                    corner(ax + nx, ay + ny),
+                ];
+                Buffer::data(
+                    BufferType::Array,
+                    bytemuck::cast_slice(&quad),
+                    gl::STREAM_DRAW,
+                );
+                unsafe { gl::DrawArrays(gl::TRIANGLES, 0, 6) };
+            }
+        }
+        for marker in self.drawing_order(vp) {
+            let [x0, y0, x1, y1] = marker.icon.rect(self.pixel(marker, vp, win));
+            if x1 < 0.0 || y1 < 0.0 || x0 > win.0 as f32 || y0 > win.1 as f32 {
+                continue;
+            }
+            let ([ax, ay], [bx, by]) = (to_ndc(x0, y0), to_ndc(x1, y1));
+            let quad: [MarkerVertex; 6] = [
+                [ax, ay, 0.0, 0.0],
+                [bx, ay, 1.0, 0.0],
+                [bx, by, 1.0, 1.0],
+                [ax, ay, 0.0, 0.0],
+                [bx, by, 1.0, 1.0],
+                [ax, by, 0.0, 1.0],
+            ];
+            Buffer::data(
+                BufferType::Array,
+                bytemuck::cast_slice(&quad),
+                gl::STREAM_DRAW,
+            );
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, marker.icon.texture());
+                gl::DrawArrays(gl::TRIANGLES, 0, 6);
+            }
+        }
+        unsafe { gl::Disable(gl::BLEND) };
+        Sampler::clear_binding(0);
+        VertexArray::clear_binding();
+    }
+}
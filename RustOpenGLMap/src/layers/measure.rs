@@ -0,0 +1,286 @@
+use crate::geo;
+use crate::layers::markers::{MarkerIcon, MarkerId, MarkerLayer};
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use crate::touch;
+use crate::units;
+use crate::viewport::Viewport;
+use std::rc::Rc;
+use tracing::warn;
+
+/// Arc length in metres between two points of a densified edge.
+const STEP_METRES: f64 = 10_000.0;
+/// A click this close to the first vertex, in pixels, closes the shape.
+const CLOSE_RADIUS_PX: f64 = 10.0;
+/// Colour of the vertex handles.
+const HANDLE_COLOR: [u8; 4] = [250, 160, 0, 255];
+
+/// A path or polygon measured by clicking its vertices. Edges are great
+/// circles and lengths are haversine distances; once closed the enclosed
+/// area is measured on the sphere as well.
+pub struct MeasureLayer {
+    pub active: bool,
+    vertices: Vec<((f64, f64), MarkerId)>,
+    closed: bool,
+    markers: MarkerLayer,
+    icon: Rc<MarkerIcon>,
+    color: [f32; 4],
+    /// Edited by touch: handles are finger sized and the hints talk about touches.
+    touch: bool,
+    /// Projected runs of every edge, split at the antimeridian.
+    runs: Vec<Vec<(f64, f64)>>,
+    /// Set when the shape changes, re-uploaded on the next draw.
+    dirty: bool,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl MeasureLayer {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            vertices: Vec::new(),
+            closed: false,
+            markers: MarkerLayer::new(),
+            icon: MarkerIcon::dot(HANDLE_COLOR),
+            color: [0.95, 0.55, 0.0, 1.0],
+            touch: false,
+            runs: Vec::new(),
+            dirty: false,
+            buffer: None,
+        }
+    }
+
+    /// Adds the point under window pixel (px, py) as the next vertex. A click
+    /// on the first vertex closes a shape of three or more; the click after
+    /// that starts a new measurement.
+    pub fn click(&mut self, vp: &Viewport, win: (u32, u32), px: i32, py: i32) {
+        if self.closed {
+            self.clear();
+        }
+        let pixel = (f64::from(px), f64::from(py));
+        let close_radius = if self.touch {
+            touch::HANDLE_RADIUS_PX
+        } else {
+            CLOSE_RADIUS_PX
+        };
+        if let Some(&((lat, lon), _)) = self.vertices.first()
+            && self.vertices.len() >= 3
+        {
+            let first = vp.world_to_pixel(geo::project(lat, lon), win);
+            if (first.0 - pixel.0).hypot(first.1 - pixel.1) <= close_radius {
+                self.closed = true;
+                self.rebuild();
+                return;
+            }
+        }
+        let world = vp.pixel_to_world(pixel, win);
+        let (lat, lon) = geo::unproject(world.0, world.1);
+        let marker = self.markers.add(lat, lon, self.icon.clone());
+        self.vertices.push(((lat, lon), marker));
+        self.rebuild();
+    }
+
+    /// Removes the last vertex, or reopens a closed shape.
+    pub fn undo(&mut self) {
+        if self.closed {
+            self.closed = false;
+        } else if let Some((_, marker)) = self.vertices.pop() {
+            self.markers.remove(marker);
+        }
+        self.rebuild();
+    }
+
+    /// Index of the vertex closest to window pixel `at`, if one is within `radius` pixels.
+    pub fn handle_at(
+        &self,
+        vp: &Viewport,
+        win: (u32, u32),
+        at: (f64, f64),
+        radius: f64,
+    ) -> Option<usize> {
+        self.vertices
+            .iter()
+            .enumerate()
+            .map(|(i, &((lat, lon), _))| {
+                let p = vp.world_to_pixel(geo::project(lat, lon), win);
+                (i, (p.0 - at.0).hypot(p.1 - at.1))
+            })
+            .filter(|&(_, d)| d <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    /// Moves vertex `index` under window pixel `at`.
+    pub fn move_vertex(&mut self, index: usize, vp: &Viewport, win: (u32, u32), at: (f64, f64)) {
+        let world = vp.pixel_to_world(at, win);
+        let (lat, lon) = geo::unproject(world.0, world.1);
+        if let Some((point, marker)) = self.vertices.get_mut(index) {
+            *point = (lat, lon);
+            self.markers.move_to(*marker, lat, lon);
+            self.rebuild();
+        }
+    }
+
+    /// Switches to handles big enough to grab with a finger.
+    pub fn use_finger_handles(&mut self) {
+        if self.touch {
+            return;
+        }
+        self.touch = true;
+        self.icon = MarkerIcon::dot_sized(HANDLE_COLOR, 32);
+        for ((lat, lon), marker) in self.vertices.iter_mut() {
+            self.markers.remove(*marker);
+            *marker = self.markers.add(*lat, *lon, self.icon.clone());
+        }
+    }
+
+    /// Throws the shape away and leaves measure mode.
+    pub fn cancel(&mut self) {
+        self.clear();
+        self.active = false;
+    }
+
+    fn clear(&mut self) {
+        for (_, marker) in self.vertices.drain(..) {
+            self.markers.remove(marker);
+        }
+        self.closed = false;
+        self.rebuild();
+    }
+
+    /// Vertices in order, the first repeated at the end once closed.
+    fn path(&self) -> Vec<(f64, f64)> {
+        let mut path: Vec<(f64, f64)> = self.vertices.iter().map(|(point, _)| *point).collect();
+        if self.closed
+            && let Some(&first) = path.first()
+        {
+            path.push(first);
+        }
+        path
+    }
+
+    /// Length of the path in metres, the closing edge included.
+    pub fn distance(&self) -> f64 {
+        self.path()
+            .windows(2)
+            .map(|edge| geo::distance(edge[0], edge[1]))
+            .sum()
+    }
+
+    /// Area in square metres, once the shape is closed.
+    pub fn area(&self) -> Option<f64> {
+        if !self.closed {
+            return None;
+        }
+        let ring: Vec<(f64, f64)> = self.vertices.iter().map(|(point, _)| *point).collect();
+        Some(geo::polygon_area(&ring))
+    }
+
+    /// One line describing the measurement for the HUD.
+    pub fn summary(&self) -> String {
+        let (add, undo) = match self.touch {
+            true => ("long-press", ", two-finger tap undoes"),
+            false => ("click", ""),
+        };
+        match self.area() {
+            Some(area) => format!(
+                "Perimeter {}, area {} ({} to start over{}, Escape ends)",
+                units::format_distance(self.distance()),
+                units::format_area(area),
+                add,
+                undo
+            ),
+            None if self.vertices.is_empty() => {
+                format!("Measuring: {} to add points, Escape ends", add)
+            }
+            None => format!(
+                "Measuring: {} over {} points ({} the first point to close{}, Escape ends)",
+                units::format_distance(self.distance()),
+                self.vertices.len(),
+                add,
+                undo
+            ),
+        }
+    }
+
+    /// Cumulative distance at the last vertex, in Web Mercator.
+    pub fn labels(&self) -> Vec<((f64, f64), String)> {
+        let path = self.path();
+        match path.last() {
+            Some(&(lat, lon)) if path.len() >= 2 => vec![(
+                geo::project(lat, lon),
+                units::format_distance(self.distance()),
+            )],
+            _ => Vec::new(),
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.runs = self
+            .path()
+            .windows(2)
+            .flat_map(|edge| {
+                let steps = (geo::distance(edge[0], edge[1]) / STEP_METRES).ceil() as usize;
+                geo::split_at_antimeridian(&geo::great_circle(edge[0], edge[1], steps.max(1)))
+            })
+            .map(|run| {
+                run.into_iter()
+                    .map(|(lat, lon)| geo::project(lat, lon))
+                    .collect()
+            })
+            .collect();
+        self.dirty = true;
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let vertices: Vec<[f32; 2]> = self
+            .runs
+            .iter()
+            .flatten()
+            .map(|(x, y)| [(x - 0.5) as f32, (y - 0.5) as f32])
+            .collect();
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Layer for MeasureLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        if self.dirty
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload the measurement: {}", e);
+            return;
+        }
+        if let Some(buffer) = &self.buffer {
+            ctx.shader.bind(ctx.vp, ctx.win, (0.5, 0.5), self.color);
+            let mut first = 0;
+            for run in &self.runs {
+                buffer.draw(gl::LINE_STRIP, first, run.len());
+                first += run.len();
+            }
+        }
+        self.markers.draw(ctx.vp, ctx.win);
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        let mut shapes: Vec<Shape> = self
+            .runs
+            .iter()
+            .map(|run| Shape::Line {
+                points: run.clone(),
+                color: self.color,
+            })
+            .collect();
+        shapes.extend(self.markers.shapes());
+        shapes
+    }
+}
@@ -0,0 +1,297 @@
+pub mod collected;
+pub mod coverage;
+pub mod csv_points;
+pub mod directions;
+pub mod geojson;
+pub mod heat;
+pub mod hotspots;
+pub mod markers;
+pub mod measure;
+pub mod poi;
+pub mod position;
+pub mod range_rings;
+pub mod recorder;
+pub mod route;
+pub mod satellite;
+pub mod tile_picker;
+pub mod track;
+
+use crate::geo;
+use crate::labels::PlaceLabel;
+use crate::opengl_helper::{Buffer, BufferType, ShaderProgram, VertexArray, c_str};
+use crate::reproject;
+use crate::tile::MAX_ZOOM;
+use crate::viewport::Viewport;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use tracing::info;
+
+pub use csv_points::CsvPointLayer;
+pub use geojson::GeoJsonLayer;
+pub use satellite::SatelliteLayer;
+pub use track::TrackLayer;
+
+/// Colours `--overlay` files get in turn, so each one can be told apart.
+const OVERLAY_COLORS: &[[u8; 4]] = &[
+    [230, 40, 40, 255],
+    [30, 110, 230, 255],
+    [20, 160, 70, 255],
+    [240, 140, 0, 255],
+    [150, 50, 200, 255],
+    [0, 170, 180, 255],
+];
+
+// A polar view can't be reached by scaling Web Mercator, so there each
+// vertex goes through latitude and longitude onto the view's plane.
+const WORLD_VERT_SHADER: &str = r#"#version 410 core
+#pragma polar
+
+layout (location = 0) in vec2 pos;   // Web Mercator, relative to the layer origin
+
+uniform vec2 u_offset;  // layer origin minus view centre, Web Mercator
+uniform vec2 u_scale;   // view plane units to NDC, y already flipped
+uniform int u_view;     // 0 Web Mercator, 2 Arctic, 3 Antarctic polar stereographic
+uniform vec2 u_origin;  // layer origin, Web Mercator
+uniform vec2 u_center;  // view centre on its plane, in zoom 0 tiles
+
+void main() {
+    if (u_view == 0) {
+        gl_Position = vec4((pos + u_offset) * u_scale, 0.0, 1.0);
+        return;
+    }
+    vec2 world = u_origin + pos;
+    float lat = degrees(atan(sinh(PI * (1.0 - 2.0 * world.y))));
+    float lon = world.x * 360.0 - 180.0;
+    vec2 grid = u_view == 2 ? polar(lat, lon, 70.0, -45.0, 1.0) : polar(lat, lon, -71.0, 0.0, -1.0);
+    gl_Position = vec4((grid / (2.0 * EXTENT) - u_center) * u_scale, 0.0, 1.0);
+}
+"#;
+
+const WORLD_FRAG_SHADER: &str = r#"#version 410 core
+uniform vec4 u_color;
+out vec4 final_color;
+void main() { final_color = u_color; }
+"#;
+
+/// Flat-coloured geometry given in normalised Web Mercator coordinates.
+///
+/// Vertices are stored relative to a per-layer origin so `f32` keeps enough
+/// precision at street zoom levels.
+pub struct WorldShader(ShaderProgram);
+
+impl WorldShader {
+    pub fn new() -> Result<Self, String> {
+        Ok(Self(ShaderProgram::from_vert_frag(
+            &reproject::with_polar_glsl(WORLD_VERT_SHADER),
+            WORLD_FRAG_SHADER,
+        )?))
+    }
+
+    /// Uses the program with uniforms mapping geometry around `origin` into the window.
+    pub fn bind(&self, vp: &Viewport, win: (u32, u32), origin: (f64, f64), color: [f32; 4]) {
+        let (cx, cy) = vp.center_world();
+        let (plane_x, plane_y) = vp.center_plane();
+        let px_per_unit = geo::world_tiles(vp.z) * 256.0;
+        let program = (self.0).0;
+        unsafe {
+            gl::UseProgram(program);
+            gl::Uniform1i(
+                gl::GetUniformLocation(program, c_str!("u_view")),
+                vp.projection.shader_id(),
+            );
+            gl::Uniform2f(
+                gl::GetUniformLocation(program, c_str!("u_origin")),
+                origin.0 as f32,
+                origin.1 as f32,
+            );
+            gl::Uniform2f(
+                gl::GetUniformLocation(program, c_str!("u_center")),
+                plane_x as f32,
+                plane_y as f32,
+            );
+            gl::Uniform2f(
+                gl::GetUniformLocation(program, c_str!("u_offset")),
+                (origin.0 - cx) as f32,
+                (origin.1 - cy) as f32,
+            );
+            gl::Uniform2f(
+                gl::GetUniformLocation(program, c_str!("u_scale")),
+                (px_per_unit * 2.0 / win.0 as f64) as f32,
+                (-px_per_unit * 2.0 / win.1 as f64) as f32,
+            );
+            gl::Uniform4f(
+                gl::GetUniformLocation(program, c_str!("u_color")),
+                color[0],
+                color[1],
+                color[2],
+                color[3],
+            );
+        }
+    }
+}
+
+/// A VAO/VBO pair holding 2D vertices for `WorldShader`.
+pub struct GeometryBuffer {
+    vao: VertexArray,
+    vbo: Buffer,
+}
+
+impl GeometryBuffer {
+    pub fn new() -> Result<Self, String> {
+        let vao = VertexArray::new().ok_or_else(|| "Couldn't make a layer VAO".to_string())?;
+        let vbo = Buffer::new().ok_or_else(|| "Couldn't make a layer VBO".to_string())?;
+        vao.bind();
+        vbo.bind(BufferType::Array);
+        unsafe {
+            gl::VertexAttribPointer(
+                0,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<[f32; 2]>() as gl::types::GLsizei,
+                std::ptr::null(),
+            );
+            gl::EnableVertexAttribArray(0);
+        }
+        VertexArray::clear_binding();
+        Ok(Self { vao, vbo })
+    }
+
+    pub fn upload(&self, vertices: &[[f32; 2]]) {
+        self.vbo.bind(BufferType::Array);
+        Buffer::data(
+            BufferType::Array,
+            bytemuck::cast_slice(vertices),
+            gl::STATIC_DRAW,
+        );
+    }
+
+    /// Draws `count` vertices starting at `first` as primitive `mode`.
+    pub fn draw(&self, mode: gl::types::GLenum, first: usize, count: usize) {
+        self.vao.bind();
+        unsafe { gl::DrawArrays(mode, first as gl::types::GLint, count as gl::types::GLsizei) };
+        VertexArray::clear_binding();
+    }
+}
+
+/// Everything a layer needs to draw itself for the current frame.
+pub struct DrawContext<'a> {
+    pub vp: &'a Viewport,
+    pub win: (u32, u32),
+    pub shader: &'a WorldShader,
+    /// Simulated unix time, for layers that move.
+    pub time: f64,
+}
+
+/// What a layer draws, in normalised Web Mercator, for exporting the map
+/// as a vector figure (see `figure`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Line {
+        points: Vec<(f64, f64)>,
+        color: [f32; 4],
+    },
+    /// The first ring is the outline, any others are holes.
+    Polygon {
+        rings: Vec<Vec<(f64, f64)>>,
+        fill: [f32; 4],
+        stroke: [f32; 4],
+    },
+    /// A marker, `radius` pixels at every zoom.
+    Dot {
+        at: (f64, f64),
+        color: [f32; 4],
+        radius: f32,
+    },
+}
+
+/// Map zooms a layer is shown at, both included, e.g. a heatmap only
+/// zoomed out or labels only zoomed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoomRange {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+}
+
+impl Default for ZoomRange {
+    fn default() -> Self {
+        Self {
+            min_zoom: 0,
+            max_zoom: MAX_ZOOM,
+        }
+    }
+}
+
+impl ZoomRange {
+    pub fn contains(&self, z: u8) -> bool {
+        (self.min_zoom..=self.max_zoom).contains(&z)
+    }
+}
+
+/// Geographic overlay drawn above the tiles.
+pub trait Layer {
+    fn draw(&mut self, ctx: &DrawContext);
+
+    /// (south, west, north, east) around everything drawn, for fitting the
+    /// layer into view. `None` for layers without a fixed extent.
+    fn bounds(&self) -> Option<[f64; 4]> {
+        None
+    }
+
+    /// True if the layer changes with the simulated time and needs redrawing while it runs.
+    fn animated(&self) -> bool {
+        false
+    }
+
+    /// Names to write over the map where they don't collide, see `labels::place`.
+    fn labels(&self) -> Vec<PlaceLabel> {
+        Vec::new()
+    }
+
+    /// The layer as lines, polygons and dots for a vector export. Layers
+    /// without any end up in the exported raster or not at all.
+    fn shapes(&self) -> Vec<Shape> {
+        Vec::new()
+    }
+
+    /// Draws each feature flat in the colour of its id, `first` onwards, for
+    /// the picking pass (see `picking::id_color`), and returns how many ids
+    /// it used. Layers without features to pick draw nothing.
+    fn draw_ids(&mut self, _ctx: &DrawContext, _first: u32) -> u32 {
+        0
+    }
+
+    /// Properties of feature `index`, counted as in `draw_ids`.
+    fn feature(&self, _index: usize) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// Opens a GPX, GeoJSON or CSV file as the `index`-th overlay, drawn in
+/// the next colour of the overlay palette.
+pub fn load_overlay(path: &Path, index: usize) -> Result<Box<dyn Layer>, Box<dyn Error>> {
+    let color = OVERLAY_COLORS[index % OVERLAY_COLORS.len()];
+    let rgba = color.map(|c| f32::from(c) / 255.0);
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    Ok(match extension.as_str() {
+        "gpx" => Box::new(TrackLayer::from_gpx_file(path)?.with_color(rgba)),
+        "geojson" | "json" => {
+            let mut layer = GeoJsonLayer::from_file(path)?;
+            layer.point_color = rgba;
+            layer.line_color = rgba;
+            layer.fill_color = [rgba[0], rgba[1], rgba[2], 0.35];
+            Box::new(layer)
+        }
+        "csv" => {
+            let (points, count) = CsvPointLayer::from_csv_file(path, color)?;
+            info!("Loaded {} points from {}", count, path.display());
+            Box::new(points)
+        }
+        _ => return Err(Box::from("not a GPX, GeoJSON or CSV file".to_string())),
+    })
+}
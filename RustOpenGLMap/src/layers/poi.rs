@@ -0,0 +1,61 @@
+use crate::layers::markers::{MarkerIcon, MarkerId, MarkerLayer};
+use crate::layers::{DrawContext, Layer, Shape};
+use crate::overpass::Poi;
+use crate::viewport::Viewport;
+use std::rc::Rc;
+
+/// Results of the last Overpass query, one marker per element.
+pub struct PoiLayer {
+    /// Amenity the results are for.
+    pub amenity: Option<String>,
+    pois: Vec<(Poi, MarkerId)>,
+    markers: MarkerLayer,
+    icon: Rc<MarkerIcon>,
+}
+
+impl PoiLayer {
+    pub fn new() -> Self {
+        Self {
+            amenity: None,
+            pois: Vec::new(),
+            markers: MarkerLayer::new(),
+            icon: MarkerIcon::dot([150, 60, 220, 255]),
+        }
+    }
+
+    /// Shows `pois` in place of the previous results.
+    pub fn replace(&mut self, amenity: String, pois: Vec<Poi>) {
+        self.clear();
+        for poi in pois {
+            let marker = self.markers.add(poi.lat, poi.lon, self.icon.clone());
+            self.pois.push((poi, marker));
+        }
+        self.amenity = Some(amenity);
+    }
+
+    pub fn clear(&mut self) {
+        for (_, marker) in self.pois.drain(..) {
+            self.markers.remove(marker);
+        }
+        self.amenity = None;
+    }
+
+    /// The element whose marker covers window pixel (px, py).
+    pub fn hit_test(&self, vp: &Viewport, win: (u32, u32), px: i32, py: i32) -> Option<&Poi> {
+        let id = self.markers.hit_test(vp, win, px, py)?;
+        self.pois
+            .iter()
+            .find(|(_, marker)| *marker == id)
+            .map(|(poi, _)| poi)
+    }
+}
+
+impl Layer for PoiLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        self.markers.draw(ctx.vp, ctx.win);
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.markers.shapes()
+    }
+}
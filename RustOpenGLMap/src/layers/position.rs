@@ -0,0 +1,152 @@
+use crate::geo;
+use crate::gps::Fix;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use std::f64::consts::TAU;
+use tracing::warn;
+
+/// Points around the accuracy circle.
+const CIRCLE_SEGMENTS: usize = 72;
+/// Points around the position dot.
+const DOT_SEGMENTS: usize = 16;
+/// Pixels from the position to the tip of the heading arrow, and from it
+/// to the edge of the dot.
+const ARROW_PX: f64 = 14.0;
+const DOT_PX: f64 = 7.0;
+/// White drawn this many pixels wider around the marker.
+const OUTLINE_PX: f64 = 2.5;
+const COLOR: [f32; 4] = [0.1, 0.45, 0.95, 1.0];
+const CIRCLE_FILL: [f32; 4] = [0.1, 0.45, 0.95, 0.15];
+const CIRCLE_STROKE: [f32; 4] = [0.1, 0.45, 0.95, 0.6];
+const OUTLINE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// The live GPS position: a circle as wide as its error estimate, with an
+/// arrow along the heading while moving and a dot otherwise. The marker
+/// keeps its pixel size at every zoom, so it is rebuilt on each draw.
+pub struct PositionLayer {
+    fix: Option<Fix>,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl PositionLayer {
+    pub fn new() -> Self {
+        Self {
+            fix: None,
+            buffer: None,
+        }
+    }
+
+    /// Moves the marker to `fix`, or hides it without one.
+    pub fn set(&mut self, fix: Option<Fix>) {
+        self.fix = fix;
+    }
+
+    /// The accuracy circle in Web Mercator.
+    fn circle(fix: &Fix) -> Vec<(f64, f64)> {
+        (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let bearing = i as f64 * 360.0 / CIRCLE_SEGMENTS as f64;
+                let (lat, lon) = geo::destination((fix.lat, fix.lon), bearing, fix.accuracy);
+                geo::project(lat, lon)
+            })
+            .collect()
+    }
+
+    /// The arrow (a triangle) or dot (a fan) around the origin, `grow`
+    /// pixels larger all round, in Web Mercator units of `unit` per pixel.
+    fn marker(fix: &Fix, unit: f64, grow: f64) -> Vec<[f32; 2]> {
+        let at = |angle: f64, px: f64| {
+            // bearings turn clockwise from north, Web Mercator y grows south
+            let (sin, cos) = angle.sin_cos();
+            [(sin * px * unit) as f32, (-cos * px * unit) as f32]
+        };
+        match fix.heading {
+            Some(heading) => {
+                let heading = heading.to_radians();
+                let back = 0.7 * TAU / 2.0;
+                vec![
+                    at(heading, ARROW_PX + grow),
+                    at(heading + back, ARROW_PX * 0.7 + grow),
+                    at(heading - back, ARROW_PX * 0.7 + grow),
+                ]
+            }
+            None => std::iter::once([0.0, 0.0])
+                .chain(
+                    (0..=DOT_SEGMENTS)
+                        .map(|i| at(i as f64 * TAU / DOT_SEGMENTS as f64, DOT_PX + grow)),
+                )
+                .collect(),
+        }
+    }
+}
+
+impl Layer for PositionLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        let Some(fix) = self.fix else {
+            return;
+        };
+        if self.buffer.is_none() {
+            match GeometryBuffer::new() {
+                Ok(buffer) => self.buffer = Some(buffer),
+                Err(e) => {
+                    warn!("Failed to make the GPS position buffer: {}", e);
+                    self.fix = None;
+                    return;
+                }
+            }
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        let origin = geo::project(fix.lat, fix.lon);
+        let unit = 1.0 / (geo::world_tiles(ctx.vp.z) * 256.0);
+        let ring: Vec<[f32; 2]> = Self::circle(&fix)
+            .iter()
+            .map(|p| [(p.0 - origin.0) as f32, (p.1 - origin.1) as f32])
+            .collect();
+        let outline = Self::marker(&fix, unit, OUTLINE_PX);
+        let marker = Self::marker(&fix, unit, 0.0);
+        let mut vertices = vec![[0.0, 0.0]];
+        vertices.extend(&ring);
+        vertices.push(ring[0]);
+        vertices.extend(&outline);
+        vertices.extend(&marker);
+        buffer.upload(&vertices);
+
+        let mode = if fix.heading.is_some() {
+            gl::TRIANGLES
+        } else {
+            gl::TRIANGLE_FAN
+        };
+        let fan = ring.len() + 2;
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        ctx.shader.bind(ctx.vp, ctx.win, origin, CIRCLE_FILL);
+        buffer.draw(gl::TRIANGLE_FAN, 0, fan);
+        ctx.shader.bind(ctx.vp, ctx.win, origin, CIRCLE_STROKE);
+        buffer.draw(gl::LINE_LOOP, 1, ring.len());
+        ctx.shader.bind(ctx.vp, ctx.win, origin, OUTLINE);
+        buffer.draw(mode, fan, outline.len());
+        ctx.shader.bind(ctx.vp, ctx.win, origin, COLOR);
+        buffer.draw(mode, fan + outline.len(), marker.len());
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        let Some(fix) = self.fix else {
+            return Vec::new();
+        };
+        vec![
+            Shape::Polygon {
+                rings: vec![Self::circle(&fix)],
+                fill: CIRCLE_FILL,
+                stroke: CIRCLE_STROKE,
+            },
+            Shape::Dot {
+                at: geo::project(fix.lat, fix.lon),
+                color: COLOR,
+                radius: DOT_PX as f32,
+            },
+        ]
+    }
+}
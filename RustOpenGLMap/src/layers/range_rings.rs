@@ -0,0 +1,205 @@
+use crate::geo;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use crate::units;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Points per ring, one every two degrees of bearing.
+const RING_SEGMENTS: usize = 180;
+/// Points along each bearing ray.
+const RAY_SEGMENTS: usize = 32;
+/// Bearing labels sit this far beyond the outermost ring.
+const LABEL_MARGIN: f64 = 1.08;
+
+/// Radii and bearing spacing of the rings dropped by the range ring tool,
+/// the `[range_rings]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RangeRingStyle {
+    /// Ring radii in metres.
+    #[serde(default = "default_radii")]
+    pub radii: Vec<f64>,
+    /// Degrees between bearing rays, 0 for none.
+    #[serde(default = "default_bearing_step")]
+    pub bearing_step: f64,
+}
+
+fn default_radii() -> Vec<f64> {
+    vec![1_000.0, 2_000.0, 5_000.0, 10_000.0]
+}
+
+fn default_bearing_step() -> f64 {
+    30.0
+}
+
+impl Default for RangeRingStyle {
+    fn default() -> Self {
+        Self {
+            radii: default_radii(),
+            bearing_step: default_bearing_step(),
+        }
+    }
+}
+
+struct RingSet {
+    /// (lat, lon) the rings are centred on.
+    center: (f64, f64),
+    /// Projected runs of every ring and ray, split at the antimeridian.
+    runs: Vec<Vec<(f64, f64)>>,
+    /// (position in Web Mercator, text) of the radius and bearing labels.
+    labels: Vec<((f64, f64), String)>,
+}
+
+/// Concentric range rings and bearing rays around clicked points. Rings are
+/// true geodesic circles, so they stretch north-south away from the equator
+/// just like the map does.
+pub struct RangeRingLayer {
+    style: RangeRingStyle,
+    sets: Vec<RingSet>,
+    color: [f32; 4],
+    /// Set when rings change, re-uploaded on the next draw.
+    dirty: bool,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl RangeRingLayer {
+    pub fn new(mut style: RangeRingStyle) -> Self {
+        style.radii.retain(|r| r.is_finite() && *r > 0.0);
+        style.radii.sort_by(f64::total_cmp);
+        Self {
+            style,
+            sets: Vec::new(),
+            color: [0.85, 0.1, 0.1, 1.0],
+            dirty: false,
+            buffer: None,
+        }
+    }
+
+    /// Drops a set of rings centred on (lat, lon).
+    pub fn add(&mut self, center: (f64, f64)) {
+        let Some(&outer) = self.style.radii.last() else {
+            return;
+        };
+        let mut lines: Vec<Vec<(f64, f64)>> = Vec::new();
+        let mut labels = Vec::new();
+        for &radius in &self.style.radii {
+            lines.push(
+                (0..=RING_SEGMENTS)
+                    .map(|i| {
+                        let bearing = i as f64 * 360.0 / RING_SEGMENTS as f64;
+                        geo::destination(center, bearing, radius)
+                    })
+                    .collect(),
+            );
+            labels.push((
+                geo::destination(center, 0.0, radius),
+                units::format_distance(radius),
+            ));
+        }
+        if self.style.bearing_step > 0.0 {
+            let rays = (360.0 / self.style.bearing_step).floor() as usize;
+            for k in 0..rays {
+                let bearing = k as f64 * self.style.bearing_step;
+                lines.push(
+                    (0..=RAY_SEGMENTS)
+                        .map(|i| {
+                            let metres = outer * i as f64 / RAY_SEGMENTS as f64;
+                            geo::destination(center, bearing, metres)
+                        })
+                        .collect(),
+                );
+                labels.push((
+                    geo::destination(center, bearing, outer * LABEL_MARGIN),
+                    format!("{:03.0}°", bearing),
+                ));
+            }
+        }
+        let runs = lines
+            .iter()
+            .flat_map(|line| geo::split_at_antimeridian(line))
+            .map(|run| {
+                run.into_iter()
+                    .map(|(lat, lon)| geo::project(lat, lon))
+                    .collect()
+            })
+            .collect();
+        let labels = labels
+            .into_iter()
+            .map(|((lat, lon), text)| (geo::project(lat, lon), text))
+            .collect();
+        self.sets.push(RingSet {
+            center,
+            runs,
+            labels,
+        });
+        self.dirty = true;
+    }
+
+    /// (lat, lon) of every set of rings, oldest first.
+    pub fn centers(&self) -> Vec<(f64, f64)> {
+        self.sets.iter().map(|set| set.center).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.sets.clear();
+        self.dirty = true;
+    }
+
+    /// (label position in Web Mercator, text) of every ring radius and bearing.
+    pub fn labels(&self) -> Vec<((f64, f64), String)> {
+        self.sets
+            .iter()
+            .flat_map(|set| set.labels.iter().cloned())
+            .collect()
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let vertices: Vec<[f32; 2]> = self
+            .sets
+            .iter()
+            .flat_map(|set| set.runs.iter().flatten())
+            .map(|(x, y)| [(x - 0.5) as f32, (y - 0.5) as f32])
+            .collect();
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Layer for RangeRingLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.sets.is_empty() {
+            return;
+        }
+        if self.dirty
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload range rings: {}", e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        ctx.shader.bind(ctx.vp, ctx.win, (0.5, 0.5), self.color);
+        let mut first = 0;
+        for run in self.sets.iter().flat_map(|set| &set.runs) {
+            buffer.draw(gl::LINE_STRIP, first, run.len());
+            first += run.len();
+        }
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.sets
+            .iter()
+            .flat_map(|set| &set.runs)
+            .map(|run| Shape::Line {
+                points: run.clone(),
+                color: self.color,
+            })
+            .collect()
+    }
+}
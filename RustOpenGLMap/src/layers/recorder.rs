@@ -0,0 +1,222 @@
+use crate::geo;
+use crate::gps::Fix;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use crate::sim_clock;
+use std::error::Error;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Metres the position has to move before another point is recorded, so a
+/// receiver standing still doesn't pile up points.
+const MIN_STEP_METRES: f64 = 2.0;
+
+/// One recorded position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub lat: f64,
+    pub lon: f64,
+    /// Metres above mean sea level, if the receiver knew.
+    pub altitude: Option<f64>,
+    /// Unix time.
+    pub time: f64,
+}
+
+/// The GPS track recorded while recording is on, drawn as it grows. Each
+/// stretch of recording, or of fixes between two losses of the receiver,
+/// becomes a segment of its own.
+pub struct TrackRecorder {
+    recording: bool,
+    segments: Vec<Vec<TrackPoint>>,
+    /// The segments in Web Mercator.
+    projected: Vec<Vec<(f64, f64)>>,
+    origin: (f64, f64),
+    color: [f32; 4],
+    /// Set when points were added, re-uploaded on the next draw.
+    dirty: bool,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl TrackRecorder {
+    pub fn new() -> Self {
+        Self {
+            recording: false,
+            segments: Vec::new(),
+            projected: Vec::new(),
+            origin: (0.5, 0.5),
+            color: [0.85, 0.2, 0.6, 1.0],
+            dirty: false,
+            buffer: None,
+        }
+    }
+
+    /// Starts or stops recording and returns whether it is on now.
+    /// Starting again continues the track in a new segment.
+    pub fn toggle(&mut self) -> bool {
+        self.recording = !self.recording;
+        self.break_segment();
+        self.recording
+    }
+
+    /// Points recorded so far.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(Vec::len).sum()
+    }
+
+    /// Adds `fix` to the track while recording, unless it is within a few
+    /// metres of the last point.
+    pub fn push(&mut self, fix: &Fix) {
+        if !self.recording {
+            return;
+        }
+        if self.segments.is_empty() {
+            self.break_segment();
+        }
+        let last = self
+            .segments
+            .iter()
+            .rev()
+            .find_map(|segment| segment.last());
+        if last.is_some_and(|p| geo::distance((p.lat, p.lon), (fix.lat, fix.lon)) < MIN_STEP_METRES)
+        {
+            return;
+        }
+        let world = geo::project(fix.lat, fix.lon);
+        if self.len() == 0 {
+            self.origin = world;
+        }
+        if let (Some(segment), Some(projected)) =
+            (self.segments.last_mut(), self.projected.last_mut())
+        {
+            segment.push(TrackPoint {
+                lat: fix.lat,
+                lon: fix.lon,
+                altitude: fix.altitude,
+                time: fix.time,
+            });
+            projected.push(world);
+        }
+        self.dirty = true;
+    }
+
+    /// Ends the current segment, e.g. when the receiver lost its fix, so
+    /// the line doesn't jump across the gap.
+    pub fn break_segment(&mut self) {
+        if self
+            .segments
+            .last()
+            .is_none_or(|segment| !segment.is_empty())
+        {
+            self.segments.push(Vec::new());
+            self.projected.push(Vec::new());
+        }
+    }
+
+    /// Forgets the recorded track, recording or not.
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.projected.clear();
+        self.dirty = true;
+    }
+
+    /// The track as a GPX 1.1 document, one `<trkseg>` per segment.
+    pub fn to_gpx(&self) -> String {
+        let mut gpx = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <gpx version=\"1.1\" creator=\"RustOpenGLMap\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n\
+             <trk>\n",
+        );
+        if let Some(first) = self.segments.iter().flatten().next() {
+            let _ = writeln!(
+                gpx,
+                "<name>Track {}</name>",
+                sim_clock::format_utc(first.time)
+            );
+        }
+        for segment in self.segments.iter().filter(|segment| !segment.is_empty()) {
+            gpx.push_str("<trkseg>\n");
+            for point in segment {
+                let _ = write!(
+                    gpx,
+                    "<trkpt lat=\"{:.7}\" lon=\"{:.7}\">",
+                    point.lat, point.lon
+                );
+                if let Some(altitude) = point.altitude {
+                    let _ = write!(gpx, "<ele>{:.1}</ele>", altitude);
+                }
+                let _ = writeln!(
+                    gpx,
+                    "<time>{}</time></trkpt>",
+                    sim_clock::format_iso8601(point.time)
+                );
+            }
+            gpx.push_str("</trkseg>\n");
+        }
+        gpx.push_str("</trk>\n</gpx>\n");
+        gpx
+    }
+
+    /// Writes the track to `<dir>/track-<unix time of its first point>.gpx`.
+    pub fn export(&self, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let first = self
+            .segments
+            .iter()
+            .flatten()
+            .next()
+            .ok_or_else(|| "no track recorded".to_string())?;
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("track-{}.gpx", first.time.floor()));
+        std::fs::write(&path, self.to_gpx())?;
+        Ok(path)
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let vertices: Vec<[f32; 2]> = self
+            .projected
+            .iter()
+            .flatten()
+            .map(|(x, y)| [(x - self.origin.0) as f32, (y - self.origin.1) as f32])
+            .collect();
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Layer for TrackRecorder {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.dirty
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload the recorded track: {}", e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        ctx.shader.bind(ctx.vp, ctx.win, self.origin, self.color);
+        let mut first = 0;
+        for segment in &self.projected {
+            if segment.len() >= 2 {
+                buffer.draw(gl::LINE_STRIP, first, segment.len());
+            }
+            first += segment.len();
+        }
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.projected
+            .iter()
+            .filter(|segment| segment.len() >= 2)
+            .map(|segment| Shape::Line {
+                points: segment.clone(),
+                color: self.color,
+            })
+            .collect()
+    }
+}
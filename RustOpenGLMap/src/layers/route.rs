@@ -0,0 +1,125 @@
+use crate::geo;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use crate::units;
+use tracing::warn;
+
+/// Arc length in metres between two points of a densified route.
+const STEP_METRES: f64 = 50_000.0;
+
+struct Route {
+    /// (lat, lon) of both ends.
+    ends: [(f64, f64); 2],
+    distance: f64,
+    /// Midpoint of the arc in normalised Web Mercator, where the label goes.
+    label_at: (f64, f64),
+    /// Projected runs, split where the arc crosses the antimeridian.
+    runs: Vec<Vec<(f64, f64)>>,
+}
+
+/// Great-circle arcs between pairs of points, each labelled with its length.
+pub struct RouteLayer {
+    routes: Vec<Route>,
+    color: [f32; 4],
+    /// Set when routes change, re-uploaded on the next draw.
+    dirty: bool,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl RouteLayer {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            color: [0.1, 0.3, 0.9, 1.0],
+            dirty: false,
+            buffer: None,
+        }
+    }
+
+    /// Adds the shortest route between two (lat, lon) points and returns its length in metres.
+    pub fn add(&mut self, from: (f64, f64), to: (f64, f64)) -> f64 {
+        let distance = geo::distance(from, to);
+        let steps = ((distance / STEP_METRES).ceil() as usize).max(1);
+        let points = geo::great_circle(from, to, steps);
+        let mid = geo::great_circle(from, to, 2)[1];
+        let runs = geo::split_at_antimeridian(&points)
+            .into_iter()
+            .map(|run| {
+                run.into_iter()
+                    .map(|(lat, lon)| geo::project(lat, lon))
+                    .collect()
+            })
+            .collect();
+        self.routes.push(Route {
+            ends: [from, to],
+            distance,
+            label_at: geo::project(mid.0, mid.1),
+            runs,
+        });
+        self.dirty = true;
+        distance
+    }
+
+    /// (lat, lon) of the ends of every route, oldest first.
+    pub fn ends(&self) -> Vec<[(f64, f64); 2]> {
+        self.routes.iter().map(|route| route.ends).collect()
+    }
+
+    /// (label position in Web Mercator, formatted distance) of every route.
+    pub fn labels(&self) -> Vec<((f64, f64), String)> {
+        self.routes
+            .iter()
+            .map(|route| (route.label_at, units::format_distance(route.distance)))
+            .collect()
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let vertices: Vec<[f32; 2]> = self
+            .routes
+            .iter()
+            .flat_map(|route| route.runs.iter().flatten())
+            .map(|(x, y)| [(x - 0.5) as f32, (y - 0.5) as f32])
+            .collect();
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Layer for RouteLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.routes.is_empty() {
+            return;
+        }
+        if self.dirty
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload routes: {}", e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        ctx.shader.bind(ctx.vp, ctx.win, (0.5, 0.5), self.color);
+        let mut first = 0;
+        for run in self.routes.iter().flat_map(|route| &route.runs) {
+            buffer.draw(gl::LINE_STRIP, first, run.len());
+            first += run.len();
+        }
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.routes
+            .iter()
+            .flat_map(|route| &route.runs)
+            .map(|run| Shape::Line {
+                points: run.clone(),
+                color: self.color,
+            })
+            .collect()
+    }
+}
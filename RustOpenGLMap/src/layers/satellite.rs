@@ -0,0 +1,275 @@
+use crate::geo;
+use crate::layers::markers::{MarkerIcon, MarkerId, MarkerLayer};
+use crate::layers::{DrawContext, GeometryBuffer, Layer};
+use crate::sim_clock::days_from_civil;
+use std::error::Error;
+use std::f64::consts::TAU;
+use std::path::Path;
+use tracing::warn;
+
+/// Earth's gravitational parameter, km³/s².
+const MU: f64 = 398_600.441_8;
+/// Equatorial radius in km.
+const EARTH_RADIUS: f64 = 6378.137;
+/// Second zonal harmonic, the oblateness term that makes orbits precess.
+const J2: f64 = 1.082_626_68e-3;
+
+/// Simulated seconds between two points of a drawn ground track.
+const TRACK_STEP: f64 = 20.0;
+
+const COLORS: &[[u8; 4]] = &[
+    [255, 200, 40, 255],
+    [60, 200, 255, 255],
+    [255, 90, 200, 255],
+    [120, 255, 120, 255],
+];
+
+/// Mean orbital elements of one satellite, as read from a two-line element set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tle {
+    pub name: String,
+    /// Unix time the elements are valid for.
+    pub epoch: f64,
+    /// Radians.
+    pub inclination: f64,
+    pub raan: f64,
+    pub eccentricity: f64,
+    pub arg_perigee: f64,
+    pub mean_anomaly: f64,
+    /// Radians per second.
+    pub mean_motion: f64,
+}
+
+impl Tle {
+    /// Parses the two element lines, columns as in the NORAD format.
+    pub fn parse(name: &str, line1: &str, line2: &str) -> Result<Self, Box<dyn Error>> {
+        if !line1.starts_with('1') || !line2.starts_with('2') || line2.len() < 63 {
+            return Err(Box::from(format!("{}: not a two-line element set", name)));
+        }
+        let field = |line: &str, range: std::ops::Range<usize>| -> Result<f64, Box<dyn Error>> {
+            let text = line
+                .get(range)
+                .ok_or_else(|| format!("{}: element line too short", name))?
+                .trim();
+            Ok(text.parse::<f64>()?)
+        };
+        let year = field(line1, 18..20)? as i64;
+        let year = if year < 57 { 2000 + year } else { 1900 + year };
+        let day_of_year = field(line1, 20..32)?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            epoch: (days_from_civil(year, 1, 1) as f64 + day_of_year - 1.0) * 86_400.0,
+            inclination: field(line2, 8..16)?.to_radians(),
+            raan: field(line2, 17..25)?.to_radians(),
+            // the leading "0." is implied
+            eccentricity: field(line2, 26..33)? * 1e-7,
+            arg_perigee: field(line2, 34..42)?.to_radians(),
+            mean_anomaly: field(line2, 43..51)?.to_radians(),
+            mean_motion: field(line2, 52..63)? * TAU / 86_400.0,
+        })
+    }
+
+    /// Reads every element set in a file of optional name lines followed by
+    /// line 1 and line 2, as served by CelesTrak.
+    pub fn parse_file(text: &str) -> Result<Vec<Self>, Box<dyn Error>> {
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty())
+            .collect();
+        let mut sets = Vec::new();
+        let mut i = 0;
+        while i + 1 < lines.len() {
+            if lines[i].starts_with("1 ") && lines[i + 1].starts_with("2 ") {
+                let name = format!("NORAD {}", lines[i].get(2..7).unwrap_or("?").trim());
+                sets.push(Self::parse(&name, lines[i], lines[i + 1])?);
+                i += 2;
+            } else if i + 2 < lines.len() {
+                sets.push(Self::parse(lines[i], lines[i + 1], lines[i + 2])?);
+                i += 3;
+            } else {
+                break;
+            }
+        }
+        Ok(sets)
+    }
+
+    /// Seconds per revolution.
+    pub fn period(&self) -> f64 {
+        TAU / self.mean_motion
+    }
+
+    /// Sub-satellite point (lat, lon) in degrees at unix time `t`.
+    ///
+    /// Keplerian motion plus the secular J2 drift of the node and perigee:
+    /// good to some tens of km within days of the epoch, which is plenty
+    /// for a ground track but no substitute for SGP4.
+    pub fn ground_point(&self, t: f64) -> (f64, f64) {
+        let dt = t - self.epoch;
+        let n = self.mean_motion;
+        let e = self.eccentricity;
+        let a = (MU / (n * n)).cbrt();
+        let p = a * (1.0 - e * e);
+        let (sin_i, cos_i) = self.inclination.sin_cos();
+        let k = 0.75 * n * J2 * (EARTH_RADIUS / p).powi(2);
+        let raan = self.raan - 2.0 * k * cos_i * dt;
+        let arg_perigee = self.arg_perigee + k * (5.0 * cos_i * cos_i - 1.0) * dt;
+        let mean_anomaly =
+            self.mean_anomaly + (n + k * (1.0 - e * e).sqrt() * (3.0 * cos_i * cos_i - 1.0)) * dt;
+
+        // Kepler's equation by Newton's method
+        let m = mean_anomaly.rem_euclid(TAU);
+        let mut ecc_anomaly = if e < 0.8 { m } else { std::f64::consts::PI };
+        for _ in 0..10 {
+            let step = (ecc_anomaly - e * ecc_anomaly.sin() - m) / (1.0 - e * ecc_anomaly.cos());
+            ecc_anomaly -= step;
+            if step.abs() < 1e-10 {
+                break;
+            }
+        }
+        let true_anomaly = 2.0
+            * ((1.0 + e).sqrt() * (ecc_anomaly / 2.0).sin())
+                .atan2((1.0 - e).sqrt() * (ecc_anomaly / 2.0).cos());
+
+        // direction in the inertial frame, the radius doesn't matter for lat/lon
+        let u = arg_perigee + true_anomaly;
+        let (sin_u, cos_u) = u.sin_cos();
+        let (sin_o, cos_o) = raan.sin_cos();
+        let x = cos_o * cos_u - sin_o * sin_u * cos_i;
+        let y = sin_o * cos_u + cos_o * sin_u * cos_i;
+        let z = sin_u * sin_i;
+
+        let lat = z.atan2(x.hypot(y)).to_degrees();
+        let lon = (y.atan2(x).to_degrees() - gmst_degrees(t) + 540.0).rem_euclid(360.0) - 180.0;
+        (lat, lon)
+    }
+}
+
+/// Greenwich mean sidereal time, the Earth's rotation angle at unix time `t`.
+fn gmst_degrees(t: f64) -> f64 {
+    let days_since_j2000 = t / 86_400.0 - 10_957.5;
+    (280.460_618_37 + 360.985_647_366_29 * days_since_j2000).rem_euclid(360.0)
+}
+
+struct Satellite {
+    tle: Tle,
+    marker: MarkerId,
+    color: [f32; 4],
+    /// Ground track around `track_time`, split where it crosses the antimeridian.
+    track: Vec<Vec<(f64, f64)>>,
+}
+
+/// Satellites from TLE files: where each one is at the simulated time, and
+/// its ground track one orbit back and one orbit ahead.
+pub struct SatelliteLayer {
+    satellites: Vec<Satellite>,
+    markers: MarkerLayer,
+    /// Simulated time the tracks were computed for.
+    track_time: f64,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl SatelliteLayer {
+    pub fn new(tles: Vec<Tle>) -> Self {
+        let mut markers = MarkerLayer::new();
+        let satellites = tles
+            .into_iter()
+            .enumerate()
+            .map(|(i, tle)| {
+                let color = COLORS[i % COLORS.len()];
+                Satellite {
+                    marker: markers.add(0.0, 0.0, MarkerIcon::dot(color)),
+                    color: color.map(|c| f32::from(c) / 255.0),
+                    tle,
+                    track: Vec::new(),
+                }
+            })
+            .collect();
+        Self {
+            satellites,
+            markers,
+            track_time: f64::NAN,
+            buffer: None,
+        }
+    }
+
+    pub fn from_tle_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let tles = Tle::parse_file(&std::fs::read_to_string(path)?)?;
+        if tles.is_empty() {
+            return Err(Box::from(format!("{}: no element sets", path.display())));
+        }
+        Ok(Self::new(tles))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.satellites
+            .iter()
+            .map(|satellite| satellite.tle.name.as_str())
+            .collect()
+    }
+
+    fn update_tracks(&mut self, time: f64) -> Result<(), String> {
+        let mut vertices: Vec<[f32; 2]> = Vec::new();
+        for satellite in &mut self.satellites {
+            let period = satellite.tle.period();
+            let steps = (period / TRACK_STEP).ceil() as i64;
+            let points: Vec<(f64, f64)> = (-steps..=steps)
+                .map(|i| satellite.tle.ground_point(time + i as f64 * TRACK_STEP))
+                .collect();
+            satellite.track = geo::split_at_antimeridian(&points)
+                .into_iter()
+                .map(|run| {
+                    run.into_iter()
+                        .map(|(lat, lon)| geo::project(lat, lon))
+                        .collect()
+                })
+                .collect();
+            vertices.extend(
+                satellite
+                    .track
+                    .iter()
+                    .flatten()
+                    .map(|(x, y)| [(x - 0.5) as f32, (y - 0.5) as f32]),
+            );
+        }
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.track_time = time;
+        Ok(())
+    }
+}
+
+impl Layer for SatelliteLayer {
+    fn draw(&mut self, ctx: &DrawContext) {
+        // the tracks only need redoing once the satellites moved a step
+        if (self.track_time.is_nan() || (ctx.time - self.track_time).abs() >= TRACK_STEP)
+            && let Err(e) = self.update_tracks(ctx.time)
+        {
+            warn!("Failed to upload satellite tracks: {}", e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        let mut first = 0;
+        for satellite in &self.satellites {
+            ctx.shader
+                .bind(ctx.vp, ctx.win, (0.5, 0.5), satellite.color);
+            for segment in &satellite.track {
+                buffer.draw(gl::LINE_STRIP, first, segment.len());
+                first += segment.len();
+            }
+            let (lat, lon) = satellite.tle.ground_point(ctx.time);
+            self.markers.move_to(satellite.marker, lat, lon);
+        }
+        self.markers.draw(ctx.vp, ctx.win);
+    }
+
+    fn animated(&self) -> bool {
+        true
+    }
+}
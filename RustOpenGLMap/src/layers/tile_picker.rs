@@ -0,0 +1,183 @@
+use crate::geo;
+use crate::layers::{DrawContext, GeometryBuffer, Layer};
+use crate::tile::TilePos;
+use crate::viewport::Viewport;
+use std::collections::HashSet;
+use tracing::warn;
+
+/// A pick in progress: the tile pressed on and the one under the pointer now.
+#[derive(Debug, Clone, Copy)]
+struct Drag {
+    z: u8,
+    start: (u32, u32),
+    end: (u32, u32),
+}
+
+/// Grid of the tiles in view where clicks pick tiles for cache maintenance.
+/// A click toggles one tile, a drag adds the rectangle of tiles it spans.
+/// Picks are kept per zoom level, so zooming around doesn't lose them.
+pub struct TilePicker {
+    pub active: bool,
+    /// (z, x, y) of every picked tile.
+    selected: HashSet<(u8, u32, u32)>,
+    drag: Option<Drag>,
+    grid_color: [f32; 4],
+    fill_color: [f32; 4],
+    buffer: Option<GeometryBuffer>,
+}
+
+impl TilePicker {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            selected: HashSet::new(),
+            drag: None,
+            grid_color: [0.1, 0.1, 0.1, 0.6],
+            fill_color: [0.15, 0.45, 0.95, 0.35],
+            buffer: None,
+        }
+    }
+
+    /// Starts a pick on the tile under window pixel (px, py).
+    pub fn press(&mut self, vp: &Viewport, win: (u32, u32), px: i32, py: i32) {
+        let tile = vp.tile_at_pixel((f64::from(px), f64::from(py)), win);
+        self.drag = Some(Drag {
+            z: vp.z,
+            start: tile,
+            end: tile,
+        });
+    }
+
+    /// Stretches the pick in progress to the tile under (px, py). True if it grew or shrank.
+    pub fn drag_to(&mut self, vp: &Viewport, win: (u32, u32), px: i32, py: i32) -> bool {
+        let Some(drag) = &mut self.drag else {
+            return false;
+        };
+        if drag.z != vp.z {
+            return false;
+        }
+        let tile = vp.tile_at_pixel((f64::from(px), f64::from(py)), win);
+        let moved = drag.end != tile;
+        drag.end = tile;
+        moved
+    }
+
+    /// Ends the pick: a click toggles its tile, a drag adds every tile it covered.
+    pub fn release(&mut self) -> bool {
+        let Some(Drag { z, start, end }) = self.drag.take() else {
+            return false;
+        };
+        if start == end {
+            let tile = (z, start.0, start.1);
+            if !self.selected.remove(&tile) {
+                self.selected.insert(tile);
+            }
+        } else {
+            self.selected.extend(span(z, start, end));
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.selected.clear();
+        self.drag = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.selected.len()
+    }
+
+    /// The picked tiles of source `m`, lowest zoom first.
+    pub fn tiles(&self, m: u8) -> Vec<TilePos> {
+        let mut tiles: Vec<TilePos> = self
+            .selected
+            .iter()
+            .map(|&(z, x, y)| TilePos { z, x, y, m })
+            .collect();
+        tiles.sort_by_key(|tile| (tile.z, tile.y, tile.x));
+        tiles
+    }
+
+    /// Picked tiles plus the drag in progress.
+    fn highlighted(&self) -> Vec<(u8, u32, u32)> {
+        let mut tiles: Vec<(u8, u32, u32)> = self.selected.iter().copied().collect();
+        if let Some(Drag { z, start, end }) = self.drag {
+            tiles.extend(span(z, start, end));
+        }
+        tiles
+    }
+}
+
+/// Every tile of the rectangle with corners `a` and `b` at zoom `z`.
+fn span(z: u8, a: (u32, u32), b: (u32, u32)) -> impl Iterator<Item = (u8, u32, u32)> {
+    let (x0, x1) = (a.0.min(b.0), a.0.max(b.0));
+    let (y0, y1) = (a.1.min(b.1), a.1.max(b.1));
+    (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| (z, x, y)))
+}
+
+impl Layer for TilePicker {
+    fn draw(&mut self, ctx: &DrawContext) {
+        if !self.active {
+            return;
+        }
+        if self.buffer.is_none() {
+            match GeometryBuffer::new() {
+                Ok(buffer) => self.buffer = Some(buffer),
+                Err(e) => {
+                    warn!("Failed to set up the tile picker: {}", e);
+                    self.active = false;
+                    return;
+                }
+            }
+        }
+        // relative to the view centre, the grid is rebuilt every frame anyway
+        let origin = ctx.vp.center_world();
+        let vertex = |x: f64, y: f64| [(x - origin.0) as f32, (y - origin.1) as f32];
+
+        let mut fills = Vec::new();
+        for (z, x, y) in self.highlighted() {
+            let n = geo::world_tiles(z);
+            let (x0, y0) = (f64::from(x) / n, f64::from(y) / n);
+            let (x1, y1) = ((f64::from(x) + 1.0) / n, (f64::from(y) + 1.0) / n);
+            fills.extend([
+                vertex(x0, y0),
+                vertex(x1, y0),
+                vertex(x1, y1),
+                vertex(x0, y0),
+                vertex(x1, y1),
+                vertex(x0, y1),
+            ]);
+        }
+        let (w, h) = (f64::from(ctx.win.0), f64::from(ctx.win.1));
+        let (tx0, ty0) = ctx.vp.tile_at_pixel((0.0, 0.0), ctx.win);
+        let (tx1, ty1) = ctx.vp.tile_at_pixel((w, h), ctx.win);
+        let n = geo::world_tiles(ctx.vp.z);
+        let (top, bottom) = (f64::from(ty0) / n, f64::from(ty1 + 1) / n);
+        let (left, right) = (f64::from(tx0) / n, f64::from(tx1 + 1) / n);
+        let mut lines = Vec::new();
+        for x in tx0..=tx1 + 1 {
+            let x = f64::from(x) / n;
+            lines.extend([vertex(x, top), vertex(x, bottom)]);
+        }
+        for y in ty0..=ty1 + 1 {
+            let y = f64::from(y) / n;
+            lines.extend([vertex(left, y), vertex(right, y)]);
+        }
+
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        let fill_count = fills.len();
+        fills.extend(lines);
+        buffer.upload(&fills);
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        ctx.shader.bind(ctx.vp, ctx.win, origin, self.fill_color);
+        buffer.draw(gl::TRIANGLES, 0, fill_count);
+        ctx.shader.bind(ctx.vp, ctx.win, origin, self.grid_color);
+        buffer.draw(gl::LINES, fill_count, fills.len() - fill_count);
+        unsafe { gl::Disable(gl::BLEND) };
+    }
+}
@@ -0,0 +1,135 @@
+use crate::geo;
+use crate::layers::{DrawContext, GeometryBuffer, Layer, Shape};
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+/// A run of (lat, lon) or projected points.
+type Segment = Vec<(f64, f64)>;
+
+/// A GPS track drawn as one line strip per GPX segment.
+pub struct TrackLayer {
+    name: String,
+    /// Segments of normalised Web Mercator points.
+    segments: Vec<Segment>,
+    origin: (f64, f64),
+    color: [f32; 4],
+    // created on first draw, once a GL context exists
+    buffer: Option<GeometryBuffer>,
+}
+
+impl TrackLayer {
+    /// Builds a track from segments of (lat, lon) points in degrees.
+    pub fn from_latlon(name: &str, segments: Vec<Segment>) -> Self {
+        let segments: Vec<Segment> = segments
+            .into_iter()
+            .filter(|segment| segment.len() >= 2)
+            .map(|segment| {
+                segment
+                    .into_iter()
+                    .map(|(lat, lon)| geo::project(lat, lon))
+                    .collect()
+            })
+            .collect();
+        let origin = segments
+            .first()
+            .and_then(|segment| segment.first())
+            .copied()
+            .unwrap_or((0.5, 0.5));
+        Self {
+            name: name.to_string(),
+            segments,
+            origin,
+            color: [0.9, 0.1, 0.1, 1.0],
+            buffer: None,
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn from_gpx_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let segments = parse_gpx(&text)?;
+        if segments.iter().all(|segment| segment.len() < 2) {
+            return Err(Box::from(format!("{}: no track points", path.display())));
+        }
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self::from_latlon(&name, segments))
+    }
+
+    fn upload(&mut self) -> Result<(), String> {
+        let buffer = GeometryBuffer::new()?;
+        let vertices: Vec<[f32; 2]> = self
+            .segments
+            .iter()
+            .flatten()
+            .map(|(x, y)| [(x - self.origin.0) as f32, (y - self.origin.1) as f32])
+            .collect();
+        buffer.upload(&vertices);
+        self.buffer = Some(buffer);
+        Ok(())
+    }
+}
+
+impl Layer for TrackLayer {
+    fn bounds(&self) -> Option<[f64; 4]> {
+        geo::bounds(self.segments.iter().flatten().copied())
+    }
+
+    fn draw(&mut self, ctx: &DrawContext) {
+        if self.buffer.is_none()
+            && let Err(e) = self.upload()
+        {
+            warn!("Failed to upload track {}: {}", self.name, e);
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        ctx.shader.bind(ctx.vp, ctx.win, self.origin, self.color);
+        let mut first = 0;
+        for segment in &self.segments {
+            buffer.draw(gl::LINE_STRIP, first, segment.len());
+            first += segment.len();
+        }
+    }
+
+    fn shapes(&self) -> Vec<Shape> {
+        self.segments
+            .iter()
+            .map(|segment| Shape::Line {
+                points: segment.clone(),
+                color: self.color,
+            })
+            .collect()
+    }
+}
+
+/// Reads the `<trkseg>` and `<rte>` point lists of a GPX document as (lat, lon).
+pub fn parse_gpx(text: &str) -> Result<Vec<Segment>, Box<dyn Error>> {
+    let doc = roxmltree::Document::parse(text)?;
+    let mut segments = Vec::new();
+    for node in doc.descendants() {
+        let point_tag = match node.tag_name().name() {
+            "trkseg" => "trkpt",
+            "rte" => "rtept",
+            _ => continue,
+        };
+        let mut points = Vec::new();
+        for point in node.children().filter(|n| n.has_tag_name(point_tag)) {
+            let lat = point.attribute("lat").and_then(|v| v.parse::<f64>().ok());
+            let lon = point.attribute("lon").and_then(|v| v.parse::<f64>().ok());
+            if let (Some(lat), Some(lon)) = (lat, lon) {
+                points.push((lat, lon));
+            }
+        }
+        segments.push(points);
+    }
+    Ok(segments)
+}
@@ -0,0 +1,22 @@
+use tracing_subscriber::EnvFilter;
+
+/// Sends `tracing` events to stderr: info and up by default, debug with
+/// `-v` (tile loads, downloads, uploads, evictions) and trace with `-vv`.
+/// `RUST_LOG` takes over the filter when it is set. With `json` each event
+/// is one JSON object per line, for feeding into other tools.
+pub fn init(verbose: u8, json: bool) {
+    let level = match verbose {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if json {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+}
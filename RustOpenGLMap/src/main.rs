@@ -1,72 +1,182 @@
 extern crate gl;
+mod audit;
+mod basemap;
+mod batch_geocode;
+mod bookmarks;
+mod cache_import;
+mod cache_maintenance;
+mod cache_sync;
+mod calibrate;
+mod cli;
+mod collab;
+mod compare;
+mod coord_format;
+mod crash_report;
+mod disk_cache;
+mod error;
+mod figure;
+mod frame_capture;
+mod frame_pacer;
+mod geo;
+mod geocoder;
+mod gps;
+mod gui;
+mod history;
+mod input;
+mod labels;
+mod layers;
+mod logging;
+mod map_session;
+mod map_view;
+mod missing_tile;
+mod motion;
+mod offscreen;
 mod opengl_helper;
+mod overpass;
+mod permalink;
+mod picking;
+mod presets;
+mod prompt;
+mod rate_limit;
+mod recent;
+mod region_download;
+mod relief;
+mod reproject;
+mod retry;
+mod routing;
+mod search;
+mod session;
+mod settings;
+mod sim_clock;
+mod static_map;
+mod stats_overlay;
+mod text;
+mod texture_upload;
+mod theme;
 mod tile;
+mod tile_atlas;
+mod tile_layers;
+mod tile_pack;
+mod tile_quad;
+mod tile_requests;
+mod tile_source;
+mod touch;
+mod units;
+mod vector_tile;
 mod viewport;
+mod wake;
+mod wms;
+mod wmts;
+mod worker_pool;
 
 use std::sync::mpsc::{Receiver, Sender, channel};
 // Added for channels
-use std::thread;
 
-use lru::LruCache;
+use audit::{AuditEntry, AuditLog, Operation};
+use bookmarks::{BOOKMARKS_PATH, Bookmark, BookmarkPicker, Bookmarks};
+use cache_maintenance::CacheMaintenance;
+use clap::Parser;
+use cli::{Cli, Command};
+use collab::{Collab, Edit};
+use compare::Compare;
+use disk_cache::{DISK_CACHE, TileMeta};
+use frame_pacer::{FrameCap, FramePacer};
+use geocoder::Geocoder;
+use gps::Gps;
+use gui::{Gui, GuiAction, GuiState};
+use history::History;
+use input::{Action, Tool};
+use layers::hotspots::{HOTSPOTS_PATH, HotspotAction, HotspotLayer};
+use layers::markers::MarkerIcon;
+use layers::range_rings::RangeRingLayer;
+use layers::{CsvPointLayer, GeoJsonLayer, SatelliteLayer, TrackLayer, WorldShader};
+use map_session::MapSession;
+use map_view::MapView;
+use missing_tile::MissingTiles;
+use motion::Motion;
+use overpass::{OverpassClient, Poi, QueryBox};
+use picking::PickBuffer;
+use prompt::{LineInput, Prompt};
+use recent::{RECENT_PATH, Recent};
+use relief::ElevationProbe;
+use reproject::Projection;
+use routing::Router;
 use sdl2;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::mouse::MouseButton;
 use sdl2::video::{self, GLContext};
-use std::collections::VecDeque;
+use search::SearchBox;
+use session::{SESSION_PATH, Session};
+use settings::{HomeView, SETTINGS_PATH, Settings};
+use sim_clock::SimClock;
+use stats_overlay::StatsOverlay;
 use std::num::NonZeroUsize;
-use std::sync::mpsc::TryRecvError;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use texture_upload::{PixelBuffers, TextureUploader};
+use theme::{THEME_PATH, Theme};
 use tile::TileLoad;
 use tile::TilePos;
+use tile_layers::{TileLayer, TileLayers};
+use tile_quad::TileQuad;
+use tile_requests::TileRequests;
+use tile_source::{SOURCES, SOURCES_PATH, SourceChange, SourceWatcher};
+use touch::{Gesture, TOUCH_MOUSE_ID, TouchInput};
+use tracing::{debug, info, warn};
+use vector_tile::VectorTileLayer;
 use viewport::Viewport;
+use worker_pool::WorkerPool;
 
-type Vertex = [f32; 3 + 3 + 2];
-type TriIndexes = [u32; 3];
-const VERTICES: [Vertex; 4] = [
-    // top right
-    [0.5, 0.5, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0],
-    // bottom right
-    [0.5, -0.5, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0],
-    // bottom left
-    [-0.5, -0.5, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
-    // top left
-    [-0.5, 0.5, 0.0, 0.2, 0.3, 0.4, 0.0, 1.0],
-];
-
-const INDICES: [TriIndexes; 2] = [[0, 1, 3], [1, 2, 3]];
-
-const VERT_SHADER: &str = r#"#version 410 core
-layout (location = 0) in vec3 pos;
-layout (location = 2) in vec2 tex;
-
-uniform vec2 u_scale;   // tile-size in NDC
-uniform vec2 u_offset;  // per-tile translation in NDC
-
-out vec2 v_tex;
-
-void main() {
-    vec2 scaled     = pos.xy * u_scale;
-    vec2 translated = scaled  + u_offset;
-    gl_Position = vec4(translated, pos.z, 1.0);
-    v_tex       = tex;
-}
+/// Tile images turned into textures per frame when the render thread does
+/// the uploads, so a burst of arrivals can't stall a frame. Each one goes
+/// through a pixel buffer of its own.
+const UPLOADS_PER_FRAME: usize = 8;
+
+/// Smallest window a saved session may reopen with.
+const MIN_WINDOW_SIZE: u32 = 200;
 
-"#;
+/// Most recent collected points listed on screen.
+const POINT_LIST_ROWS: usize = 12;
 
-const FRAG_SHADER: &str = r#"#version 410 core
-uniform sampler2D the_texture;
-in  vec2 v_tex;
-out vec4 final_color;
-void main() { final_color = texture(the_texture, v_tex); }
-"#;
+/// Longest a static view sleeps before looking at timers and background
+/// state nobody wakes it for (circuit breakers, sources.toml, idle work).
+const IDLE_WAIT: Duration = Duration::from_millis(500);
 
 fn main() -> Result<(), String> {
+    crash_report::install();
+    let cli = Cli::parse();
+    logging::init(cli.verbose, cli.log_json);
+    if let Some(dir) = cli.cache_dir.clone() {
+        disk_cache::set_tile_dir(dir)?;
+    }
+    match cli.command {
+        Some(Command::Import(args)) => return cache_import::run_import_command(args),
+        Some(Command::Calibrate { source }) => return calibrate::run_calibrate_command(source),
+        Some(Command::Download(args)) => return region_download::run_download_command(args),
+        Some(Command::ExportPack(args)) => return tile_pack::run_export_pack_command(args),
+        Some(Command::ImportPack(args)) => return tile_pack::run_import_pack_command(args),
+        Some(Command::Presets) => return presets::run_presets_command(),
+        Some(Command::Geocode(args)) => return batch_geocode::run_geocode_command(args),
+        Some(Command::Manifest(args)) => return cache_sync::run_manifest_command(args),
+        Some(Command::Sync(args)) => return cache_sync::run_sync_command(args),
+        Some(Command::Capture(args)) => return frame_capture::run_capture_command(args),
+        Some(Command::Render(args)) => return static_map::run_render_command(args),
+        Some(Command::Wms(args)) => return wms::run_wms_command(args),
+        Some(Command::Audit(args)) => return audit::run_audit_command(args),
+        None => {}
+    }
+    // a slow WMTS server mustn't keep the window from opening
+    wmts::fetch_in_background();
+    opengl_helper::set_downloads_paused(cli.offline);
+
     //let bitmap1 = opengl_helper::load_image("test.png");
     //let bitmap2 = opengl_helper::load_image("test1.png");
     //let mut current_bitmap = &bitmap1;
 
+    // where the last run left off
+    let session = Session::load(SESSION_PATH);
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
@@ -76,77 +186,99 @@ fn main() -> Result<(), String> {
         .gl_attr()
         .set_context_profile(video::GLProfile::Core);
 
-    let window = video_subsystem
-        .window("MapWindow", 800, 600)
-        .position_centered()
-        .build()
-        .map_err(|e| e.to_string())?;
+    let mut window_builder = video_subsystem.window(
+        "MapWindow",
+        cli.width
+            .unwrap_or(session.map_or(800, |s| s.width.max(MIN_WINDOW_SIZE))),
+        cli.height
+            .unwrap_or(session.map_or(600, |s| s.height.max(MIN_WINDOW_SIZE))),
+    );
+    window_builder.position_centered().allow_highdpi();
+    if cli.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().map_err(|e| e.to_string())?;
 
-    let _gl_context: GLContext = window.gl_create_context()?;
+    let gl_context: GLContext = window.gl_create_context()?;
+    opengl_helper::set_hidpi(pixel_scale(&window) >= 1.5);
     gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const _);
+    let uploader = TextureUploader::spawn(&window, &gl_context);
+    // streams tiles into textures when there is no upload thread
+    let mut staging = PixelBuffers::new(UPLOADS_PER_FRAME);
+    let mut elevation_probe = ElevationProbe::default();
 
     let mut event_pump = sdl_context.event_pump()?;
+    wake::init(&sdl_context.event()?)?;
 
     // compile vertex shader
 
     unsafe {
         // Now calls like gl::ClearColor should be recognized
-        gl::ClearColor(0.7, 0.1, 0.5, 1.0);
+        let [r, g, b, a] = tile_quad::CLEAR_COLOR;
+        gl::ClearColor(r, g, b, a);
         // gl::COLOR_BUFFER_BIT comes from gl::types::GLenum
     }
-    let vao = opengl_helper::VertexArray::new().expect("Couldn't make a VAO");
-    vao.bind();
-    let vbo = opengl_helper::Buffer::new().expect("Couldn't make a VBO");
-    vbo.bind(opengl_helper::BufferType::Array);
-    opengl_helper::Buffer::data(
-        opengl_helper::BufferType::Array,
-        bytemuck::cast_slice(&VERTICES),
-        gl::STATIC_DRAW,
-    );
-
-    let ebo = opengl_helper::Buffer::new().expect("Couldn't make the element buffer.");
-    ebo.bind(opengl_helper::BufferType::ElementArray);
-    opengl_helper::Buffer::data(
-        opengl_helper::BufferType::ElementArray,
-        bytemuck::cast_slice(&INDICES),
-        gl::STATIC_DRAW,
-    );
-
-    let shader_program = opengl_helper::ShaderProgram::from_vert_frag(VERT_SHADER, FRAG_SHADER)?;
-    unsafe {
-        // position
-        gl::VertexAttribPointer(
-            0,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            size_of::<Vertex>().try_into().unwrap(),
-            0 as *const _,
-        );
-        gl::EnableVertexAttribArray(0);
-        // colour
-        gl::VertexAttribPointer(
-            1,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            size_of::<Vertex>().try_into().unwrap(),
-            size_of::<[f32; 3]>() as *const _,
-        );
-        gl::EnableVertexAttribArray(1);
-        // tex
-        gl::VertexAttribPointer(
-            2,
-            2,
-            gl::FLOAT,
-            gl::FALSE,
-            size_of::<Vertex>().try_into().unwrap(),
-            size_of::<[f32; 6]>() as *const _,
-        );
-        gl::EnableVertexAttribArray(2);
-        opengl_helper::polygon_mode(opengl_helper::PolygonMode::Fill);
+    let tile_quad = TileQuad::new()?;
+    let reprojector = reproject::Reprojector::new()?;
+    let theme = Theme::load_or_default(THEME_PATH);
+    let text_renderer = text::TextRenderer::new(theme.mono_font()?)?;
+    let world_shader = WorldShader::new()?;
+    let pin_icon = MarkerIcon::dot([220, 40, 40, 255]);
+    let mut settings = Settings::load_or_default(SETTINGS_PATH);
+    let mut gui = Gui::new(&settings.gui, |s| {
+        video_subsystem.gl_get_proc_address(s) as *const _
+    })?;
+    opengl_helper::set_tile_fade(settings.animation.crossfade());
+    basemap::configure(settings.basemap);
+    units::set(settings.units);
+    let mut map = match session {
+        Some(session) => session.source,
+        None => settings.home.map_or(0, |home| home.source),
+    };
+    let mut tile_layers = TileLayers::new(settings.overlays.clone());
+    let combo_flag = cli.combo.as_deref();
+    if let Some(combo) = combo_flag.or(settings.combo.as_deref()) {
+        let (base, overlays) = presets::resolve_combo(combo)?;
+        // the configured combo only picks the base the first time
+        if combo_flag.is_some() || session.is_none() {
+            map = base;
+        }
+        tile_layers = TileLayers::new(overlays);
+    }
+    // a .mapsession given on the command line, or double-clicked
+    let shared = cli
+        .files
+        .iter()
+        .find(|arg| {
+            Path::new(arg)
+                .extension()
+                .is_some_and(|ext| ext == map_session::EXTENSION)
+        })
+        .and_then(|arg| {
+            MapSession::load(Path::new(arg))
+                .map_err(|e| warn!("Failed to open session {}: {}", arg, e))
+                .ok()
+        });
+    if let Some(shared) = &shared {
+        map = shared.source;
+        tile_layers = TileLayers::new(shared.overlays.clone());
+    }
+    if let Some(name) = &cli.source {
+        map = presets::resolve_source(name)?;
     }
-    let mut map = 0;
+    let mut bookmarks = Bookmarks::load_or_default(BOOKMARKS_PATH);
+    let mut audit = AuditLog::open(&settings.audit);
+    if let Some(shared) = &shared {
+        let added = bookmarks.merge(&shared.bookmarks);
+        for bookmark in &bookmarks.bookmarks[bookmarks.bookmarks.len() - added..] {
+            audit.record(AuditEntry::bookmark(Operation::Create, bookmark).detail("session"));
+        }
+        if added > 0 {
+            info!("{} bookmarks from the session, B saves them", added);
+        }
+    }
+    // before the loaders first open the disk cache, which trims it
+    protect_areas(&bookmarks, &settings);
 
     // let mut tile = TilePos::new();
     // let bitmap1 = opengl_helper::fetch_tile(tile.z, tile.x, tile.y, map).unwrap_or_else(|e| {
@@ -157,271 +289,2006 @@ fn main() -> Result<(), String> {
     //     opengl_helper::load_image("test.png") // your own function returning RgbaImage
     // });
 
-    let mut viewport = Viewport {
-        z: 1,
-        center_x: 1.0,
-        center_y: 1.0,
+    let mut viewport = match (session, settings.home) {
+        (Some(session), _) => session.viewport(),
+        (None, Some(home)) => home.viewport(),
+        (None, None) => Viewport::centered_on(0.0, 0.0, 1),
     };
+    if let Some(shared) = &shared {
+        viewport = shared.viewport();
+    }
+    if cli.lat.is_some() || cli.zoom.is_some() {
+        let (lat, lon) = viewport.center_latlon();
+        viewport = Viewport::centered_on(
+            cli.lat.unwrap_or(lat),
+            cli.lon.unwrap_or(lon),
+            cli.zoom.unwrap_or(viewport.z),
+        );
+    }
+    let mut map_view = MapView::new(viewport);
+    map_view.zoom = settings.layer_zoom.clone();
+    // a view from the command line wins over fitting the overlays
+    let mut view_given = cli.lat.is_some() || cli.zoom.is_some() || shared.is_some();
+    let mut files = cli.files.clone();
+    let mut overlay_files = cli.overlay.clone();
+    if let Some(shared) = &shared {
+        files.extend(shared.files.iter().map(|f| f.display().to_string()));
+        overlay_files.extend(shared.overlay_files.iter().cloned());
+    }
+    // what opened, for saving the session
+    let mut open_files: Vec<PathBuf> = Vec::new();
+    let mut open_overlays: Vec<PathBuf> = Vec::new();
+    for arg in &files {
+        let lower = arg.to_ascii_lowercase();
+        if let Some(view) = permalink::parse(arg) {
+            match view {
+                Ok(view) => {
+                    map_view.viewport = view;
+                    view_given = true;
+                }
+                Err(e) => warn!("Failed to open permalink {}: {}", arg, e),
+            }
+        } else if lower.ends_with(".gpx") {
+            match TrackLayer::from_gpx_file(Path::new(arg)) {
+                Ok(track) => {
+                    map_view.layers.push(Box::new(track));
+                    open_files.push(PathBuf::from(arg));
+                }
+                Err(e) => warn!("Failed to load track {}: {}", arg, e),
+            }
+        } else if lower.ends_with(".geojson") || lower.ends_with(".json") {
+            match GeoJsonLayer::from_file(Path::new(arg)) {
+                Ok(overlay) => {
+                    map_view.layers.push(Box::new(overlay));
+                    open_files.push(PathBuf::from(arg));
+                }
+                Err(e) => warn!("Failed to load GeoJSON {}: {}", arg, e),
+            }
+        } else if lower.ends_with(".csv") {
+            match CsvPointLayer::from_csv_file(Path::new(arg), [40, 120, 230, 255]) {
+                Ok((points, count)) => {
+                    info!("Loaded {} points from {}", count, arg);
+                    map_view.layers.push(Box::new(points));
+                    open_files.push(PathBuf::from(arg));
+                }
+                Err(e) => warn!("Failed to load CSV {}: {}", arg, e),
+            }
+        } else if lower.ends_with(".tle") {
+            match SatelliteLayer::from_tle_file(Path::new(arg)) {
+                Ok(satellites) => {
+                    info!("Tracking {}", satellites.names().join(", "));
+                    map_view.layers.push(Box::new(satellites));
+                    open_files.push(PathBuf::from(arg));
+                }
+                Err(e) => warn!("Failed to load TLE {}: {}", arg, e),
+            }
+        }
+    }
 
-    let mut tile_cache: LruCache<TilePos, gl::types::GLuint> =
-        LruCache::new(NonZeroUsize::new(128).unwrap());
-    let tile_cache_buf: Arc<Mutex<LruCache<TilePos, u8>>> =
-        Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())));
+    let mut overlay_bounds = Vec::new();
+    for (i, path) in overlay_files.iter().enumerate() {
+        match layers::load_overlay(path, i) {
+            Ok(overlay) => {
+                overlay_bounds.extend(overlay.bounds());
+                map_view.layers.push(overlay);
+                open_overlays.push(path.clone());
+            }
+            Err(e) => warn!("Failed to load overlay {}: {}", path.display(), e),
+        }
+    }
+    let corners = overlay_bounds
+        .iter()
+        .flat_map(|&[south, west, north, east]| {
+            [geo::project(north, west), geo::project(south, east)]
+        });
+    if !view_given && let Some(bounds) = geo::bounds(corners) {
+        map_view.viewport = Viewport::fitting(bounds, window.size(), 18);
+    }
+
+    map_view.range_rings = RangeRingLayer::new(settings.range_rings.clone());
+    map_view.hotspots = HotspotLayer::load_or_default(HOTSPOTS_PATH);
+    if map_view.hotspots.len() > 0 {
+        info!(
+            "{} hotspots from {}",
+            map_view.hotspots.len(),
+            HOTSPOTS_PATH
+        );
+    }
+    map_view.collected.format = settings.coordinates;
+    map_view.vector = VectorTileLayer::with_settings(&settings.vector);
+    for route in &cli.route {
+        map_view.routes.add(route.from, route.to);
+    }
+    if let Some(shared) = &shared {
+        shared.restore_annotations(&mut map_view, &pin_icon);
+    }
 
-    let (job_tx, job_rx): (Sender<TilePos>, Receiver<TilePos>) = channel();
+    // room for a screenful of tiles on a few layers, plus their placeholders
+    let mut tile_cache = opengl_helper::TileCache::new(NonZeroUsize::new(384).unwrap());
+    let (requests, job_rx) = TileRequests::new();
     let (res_tx, res_rx): (Sender<TileLoad>, Receiver<TileLoad>) = channel();
-    let (server_tx, server_rx): (Sender<TilePos>, Receiver<TilePos>) = channel();
-    let job_rx = Arc::new(Mutex::new(job_rx));
-
-    for _ in 0..4 {
-        let job_rx = job_rx.clone();
-        let res_tx = res_tx.clone();
-        //let tile_map = tile_map.clone();
-        let tile_cache_buf = tile_cache_buf.clone();
-
-        let server_tx = server_tx.clone();
-        thread::spawn(move || {
-            while let Ok(tile_pos) = job_rx.lock().unwrap().recv() {
-                // perform blocking I/O off the main thread
-                let tile_cache_result: Option<(TilePos, u8)> = {
-                    let mut guard = tile_cache_buf.lock().unwrap();
-                    guard
-                        .get_key_value(&tile_pos)
-                        // clone both key & value out of the map
-                        .map(|(k, v)| (k.clone(), v.clone()))
-                };
-                match tile_cache_result {
-                    None => {
-                        {
-                            {
-                                tile_cache_buf.lock().unwrap().put(tile_pos, 0);
-                            }
-                            let tile_load = opengl_helper::fetch_tile(tile_pos);
-                            match tile_load.unwrap() {
-                                TileLoad::Loaded {
-                                    texture,
-                                    source_tile,
-                                } => {
-                                    tile_cache_buf.lock().unwrap().put(tile_pos, 2);
-                                    let _ = res_tx.send(TileLoad::Loaded {
-                                        texture,
-                                        source_tile,
-                                    });
-                                }
-                                TileLoad::Loading {
-                                    texture,
-                                    source_tile,
-                                    target_tile,
-                                } => {
-                                    tile_cache_buf.lock().unwrap().put(tile_pos, 1);
-                                    let _ = res_tx.send(TileLoad::Loading {
-                                        texture,
-                                        source_tile,
-                                        target_tile,
-                                    });
-                                    //let _ = opengl_helper::fetch_tile_from_server(&tile_pos);
-                                    let _ = server_tx.send(target_tile);
+    // tiles the workers gave up downloading
+    let (missing_tx, missing_rx): (Sender<TilePos>, Receiver<TilePos>) = channel();
+    let workers = WorkerPool::spawn(
+        &settings.workers,
+        requests.clone(),
+        job_rx,
+        res_tx.clone(),
+        missing_tx,
+    );
+
+    let mut source_watcher = SourceWatcher::new(SOURCES_PATH);
+    info!("Disk cache: {}", DISK_CACHE.lock().unwrap().stats());
+    let mut scene = 0u64;
+    let mut last_frame: Option<FrameKey> = None;
+    // minimized or hidden: nothing is drawn, loaded or downloaded
+    let mut hidden = false;
+    let mut show_help = false;
+    // set by Shift+E, the next drawn frame is also saved as a figure
+    let mut export_figure = false;
+    let mut show_status_bar = false;
+    let mut show_layer_list = false;
+    let mut stats = StatsOverlay::new();
+    let mut missing_tiles = MissingTiles::new();
+    let mut clock = SimClock::new();
+    let mut compare: Option<Compare> = None;
+    let mut route_start: Option<(f64, f64)> = None;
+    let router = Router::spawn(&settings.routing);
+    // a road route asked for and not found yet
+    let mut routing = false;
+    let mut prompt: Option<Prompt> = None;
+    // window pixel under the mouse, for the coordinate readout
+    let mut cursor: Option<(i32, i32)> = None;
+    let overpass = OverpassClient::spawn(settings.overpass.url.clone());
+    // amenity asked for and not answered yet
+    let mut amenity_query: Option<String> = None;
+    let mut poi_popup: Option<Poi> = None;
+    let mut pick_buffer = PickBuffer::new();
+    let mut recent = Recent::load_or_default(RECENT_PATH);
+    let mut collab = Collab::connect(&settings.collab);
+    let mut gps = Gps::start(&settings.gps);
+    // bookmark J jumps to next
+    let mut next_bookmark = 0;
+    let maintenance = CacheMaintenance::spawn();
+    maintenance.prefetch(&bookmarks.bookmarks);
+    let geocoder = Geocoder::spawn(settings.geocoder.url.clone());
+    let mut history = if settings.history.enabled {
+        History::open(Path::new(&settings.history.path))
+            .map_err(|e| warn!("Failed to open {}: {}", settings.history.path, e))
+            .ok()
+    } else {
+        None
+    };
+    // heat cells last asked for, to ask again only when the view leaves them
+    let mut heat_area: Option<QueryBox> = None;
+    let mut last_input = Instant::now();
+    let mut motion = Motion::new(settings.animation.clone());
+    let mut touch_input = TouchInput::default();
+    // vertex a finger is moving, by index into the measurement
+    let mut dragged_handle: Option<usize> = None;
+    // typing only goes to a prompt while one is open
+    let text_input = video_subsystem.text_input();
+    text_input.stop();
+    let mut vsync = frame_pacer::set_vsync(&video_subsystem, settings.display.vsync);
+    let mut pacer = FramePacer::new(
+        refresh_rate(&video_subsystem, &window),
+        settings.display.cap(),
+    );
+    // input that ended the last idle wait, handled first
+    let mut woke_by: Option<Event> = None;
+
+    'running: loop {
+        let frame_at = pacer.begin();
+        let mut gestures = Vec::new();
+        for event in woke_by.take().into_iter().chain(event_pump.poll_iter()) {
+            if wake::is_wake(&event) {
+                continue;
+            }
+            last_input = Instant::now();
+            if gui.handle_event(&event) {
+                scene += 1;
+                continue;
+            }
+            let drawing = map_view.measure.active || map_view.collected.active;
+            match event {
+                Event::Quit { .. } => break 'running,
+                // while drawing, touches go through the gestures below instead
+                Event::MouseButtonDown {
+                    which: TOUCH_MOUSE_ID,
+                    ..
+                }
+                | Event::MouseMotion {
+                    which: TOUCH_MOUSE_ID,
+                    ..
+                }
+                | Event::MouseButtonUp {
+                    which: TOUCH_MOUSE_ID,
+                    ..
+                } if drawing => {}
+                // double taps zoom through the gestures below, not as a double click
+                Event::MouseButtonDown {
+                    which: TOUCH_MOUSE_ID,
+                    clicks: 2..,
+                    ..
+                } => {}
+                Event::FingerDown {
+                    finger_id, x, y, ..
+                } => {
+                    let at = touch_pixel(&window, x, y);
+                    gestures.extend(touch_input.finger_down(finger_id, at));
+                    // the first finger's mouse neither pans nor clicks during a pinch
+                    if touch_input.multi_touch() {
+                        motion.cancel_press();
+                    }
+                }
+                Event::FingerMotion {
+                    finger_id, x, y, ..
+                } => {
+                    let at = touch_pixel(&window, x, y);
+                    gestures.extend(touch_input.finger_motion(finger_id, at));
+                }
+                Event::FingerUp { finger_id, .. } => {
+                    gestures.extend(touch_input.finger_up(finger_id));
+                }
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } if prompt.is_some() => {
+                    let Some(open) = prompt.as_mut() else {
+                        continue;
+                    };
+                    match (key, open) {
+                        (Keycode::Escape, _) => prompt = None,
+                        (Keycode::Backspace, open) => open.backspace(),
+                        (Keycode::Return | Keycode::KpEnter, Prompt::Search(search_box)) => {
+                            if let Some(place) = search_box.chosen() {
+                                info!("Going to {}", place.name);
+                                let viewport = place.viewport(window.size());
+                                recent.add_place(Bookmark::capture(&place.name, &viewport, map));
+                                save_recent(&recent);
+                                motion.fly_to(&mut map_view.viewport, viewport);
+                                prompt = None;
+                            } else if let Some(query) = search_box.submit() {
+                                recent.add_search(&query);
+                                save_recent(&recent);
+                                geocoder.request(&query);
+                            }
+                        }
+                        (Keycode::Return | Keycode::KpEnter, Prompt::GoTo(go_to)) => {
+                            match prompt::parse_go_to(&go_to.text, map_view.viewport.z) {
+                                Ok(viewport) => {
+                                    recent.add_place(Bookmark::capture("", &viewport, map));
+                                    save_recent(&recent);
+                                    motion.fly_to(&mut map_view.viewport, viewport);
+                                    prompt = None;
                                 }
-                                TileLoad::Failed {} => {
-                                    let _ = opengl_helper::fetch_tile_from_server(&tile_pos);
+                                Err(e) => go_to.error = Some(e),
+                            }
+                        }
+                        (Keycode::Return | Keycode::KpEnter, Prompt::Amenity(line)) => {
+                            let amenity = line.text.trim().to_lowercase();
+                            if amenity.is_empty() {
+                                line.error = Some("type an amenity, e.g. cafe".to_string());
+                            } else if map_view.viewport.z < overpass::MIN_QUERY_ZOOM {
+                                line.error = Some(format!(
+                                    "zoom in to at least z{} first",
+                                    overpass::MIN_QUERY_ZOOM
+                                ));
+                            } else {
+                                overpass.request(
+                                    &amenity,
+                                    QueryBox::covering(&map_view.viewport, window.size()),
+                                );
+                                amenity_query = Some(amenity);
+                                prompt = None;
+                            }
+                        }
+                        (Keycode::Return | Keycode::KpEnter, Prompt::Bookmark(line)) => {
+                            let bookmark = Bookmark::capture(&line.text, &map_view.viewport, map);
+                            info!("Bookmarked {}", bookmark.name);
+                            audit.record(AuditEntry::bookmark(Operation::Create, &bookmark));
+                            bookmarks.bookmarks.push(bookmark);
+                            if let Err(e) = bookmarks.save(BOOKMARKS_PATH) {
+                                warn!("Failed to save {}: {}", BOOKMARKS_PATH, e);
+                            }
+                            maintenance.prefetch(&bookmarks.bookmarks);
+                            prompt = None;
+                        }
+                        (Keycode::Return | Keycode::KpEnter, Prompt::SaveSession(line)) => {
+                            if line.text.trim().is_empty() {
+                                line.error = Some("type a file name".to_string());
+                            } else {
+                                let path = map_session::file_name(&line.text);
+                                let session = MapSession::capture(
+                                    &map_view,
+                                    map,
+                                    &tile_layers,
+                                    &bookmarks,
+                                    &open_files,
+                                    &open_overlays,
+                                );
+                                match session.save(&path) {
+                                    Ok(()) => {
+                                        info!("Saved the session to {}", path.display());
+                                        prompt = None;
+                                    }
+                                    Err(e) => line.error = Some(e.to_string()),
                                 }
                             }
                         }
-                    }
-                    Some(entry) => {
-                        //let map_val = ;
-                        if entry.1 == 3 {
-                            let tile_load = opengl_helper::fetch_tile(tile_pos);
-                            match tile_load.unwrap() {
-                                TileLoad::Loaded {
-                                    texture,
-                                    source_tile,
-                                } => {
-                                    let _ = res_tx.send(TileLoad::Loaded {
-                                        texture,
-                                        source_tile,
-                                    });
+                        (Keycode::Return | Keycode::KpEnter, Prompt::Bookmarks(picker)) => {
+                            if let Some(i) = picker.chosen(&bookmarks.bookmarks) {
+                                let bookmark = &bookmarks.bookmarks[i];
+                                motion.fly_to(&mut map_view.viewport, bookmark.viewport());
+                                map = bookmark.source;
+                                recent.add_place(bookmark.clone());
+                                save_recent(&recent);
+                                next_bookmark = i + 1;
+                                prompt = None;
+                            }
+                        }
+                        (Keycode::Return | Keycode::KpEnter, Prompt::Recent(picker)) => {
+                            if let Some(i) = picker.chosen(&recent.places) {
+                                let place = recent.places[i].clone();
+                                motion.fly_to(&mut map_view.viewport, place.viewport());
+                                map = place.source;
+                                recent.add_place(place);
+                                save_recent(&recent);
+                                prompt = None;
+                            }
+                        }
+                        (Keycode::Tab, Prompt::Recent(picker)) => {
+                            if let Some(i) = picker.chosen(&recent.places) {
+                                let place = &recent.places[i];
+                                if bookmarks.merge(std::slice::from_ref(place)) > 0 {
+                                    info!("Bookmarked {}", place.name);
+                                    audit.record(
+                                        AuditEntry::bookmark(Operation::Create, place)
+                                            .detail("recent"),
+                                    );
+                                    if let Err(e) = bookmarks.save(BOOKMARKS_PATH) {
+                                        warn!("Failed to save {}: {}", BOOKMARKS_PATH, e);
+                                    }
+                                    maintenance.prefetch(&bookmarks.bookmarks);
+                                } else {
+                                    info!("{} is already bookmarked", place.name);
                                 }
-                                TileLoad::Loading {
-                                    texture: _texture,
-                                    source_tile: _source_tile,
-                                    target_tile: _target_tile,
-                                } => {}
-                                TileLoad::Failed {} => {}
                             }
-                        } else {
                         }
-                    }
-                }
-            }
-        });
-    }
-
-    {
-        let res_tx = res_tx.clone();
-        //let tile_cache_buf =  tile_cache_buf.clone();
-        thread::spawn(move || {
-            let mut buffer = VecDeque::new();
-
-            loop {
-                // Try to get as many messages as are pending
-                match server_rx.try_recv() {
-                    Ok(tile_pos) => {
-                        buffer.push_back(tile_pos); // stack-like
-                        if buffer.len() > 64 {
-                            buffer.pop_front();
+                        (Keycode::Delete, Prompt::Recent(picker)) => {
+                            if let Some(i) = picker.chosen(&recent.places) {
+                                recent.places.remove(i);
+                                save_recent(&recent);
+                                let count =
+                                    bookmarks::matching(&recent.places, &picker.filter).len();
+                                picker.move_selection(0, count);
+                            }
                         }
-                    }
-                    Err(TryRecvError::Empty) => {
-                        // Nothing new; process last-in item
-                        if let Some(tile_pos) = buffer.pop_back() {
-                            let tile_load = opengl_helper::fetch_tile_from_server(&tile_pos);
-                            if let Ok(load) = tile_load {
-                                let _ = res_tx.send(load);
-                                println!(
-                                    "Loaded Tile from web {}_{}_{}: {}",
-                                    tile_pos.z, tile_pos.x, tile_pos.y, tile_pos.m
+                        (Keycode::Up, Prompt::Recent(picker)) => picker.move_selection(
+                            -1,
+                            bookmarks::matching(&recent.places, &picker.filter).len(),
+                        ),
+                        (Keycode::Down, Prompt::Recent(picker)) => picker.move_selection(
+                            1,
+                            bookmarks::matching(&recent.places, &picker.filter).len(),
+                        ),
+                        (Keycode::Delete, Prompt::Bookmarks(picker)) => {
+                            if let Some(i) = picker.chosen(&bookmarks.bookmarks) {
+                                let removed = bookmarks.bookmarks.remove(i);
+                                info!("Removed bookmark {}", removed.name);
+                                audit.record(AuditEntry::bookmark(Operation::Delete, &removed));
+                                if let Err(e) = bookmarks.save(BOOKMARKS_PATH) {
+                                    warn!("Failed to save {}: {}", BOOKMARKS_PATH, e);
+                                }
+                                maintenance.prefetch(&bookmarks.bookmarks);
+                                if removed.protected {
+                                    protect_areas(&bookmarks, &settings);
+                                }
+                                let count = bookmarks.matching(&picker.filter).len();
+                                picker.move_selection(0, count);
+                            }
+                        }
+                        (Keycode::Tab, Prompt::Bookmarks(picker)) => {
+                            if let Some(i) = picker.chosen(&bookmarks.bookmarks) {
+                                let bookmark = &mut bookmarks.bookmarks[i];
+                                bookmark.protected = !bookmark.protected;
+                                if bookmark.protected {
+                                    info!("Keeping the tiles around {} cached", bookmark.name);
+                                } else {
+                                    info!("{} is no longer protected", bookmark.name);
+                                }
+                                audit.record(
+                                    AuditEntry::bookmark(Operation::Edit, bookmark)
+                                        .detail(format!("protected = {}", bookmark.protected)),
                                 );
+                                if let Err(e) = bookmarks.save(BOOKMARKS_PATH) {
+                                    warn!("Failed to save {}: {}", BOOKMARKS_PATH, e);
+                                }
+                                protect_areas(&bookmarks, &settings);
                             }
-                        } else {
-                            // Sleep briefly if there's no work to avoid busy spinning
-                            thread::sleep(Duration::from_millis(12));
                         }
+                        (Keycode::Up, Prompt::Bookmarks(picker)) => {
+                            picker.move_selection(-1, bookmarks.matching(&picker.filter).len())
+                        }
+                        (Keycode::Down, Prompt::Bookmarks(picker)) => {
+                            picker.move_selection(1, bookmarks.matching(&picker.filter).len())
+                        }
+                        (Keycode::Up, Prompt::Search(search_box)) => search_box.move_selection(-1),
+                        (Keycode::Down, Prompt::Search(search_box)) => search_box.move_selection(1),
+                        _ => {}
                     }
-                    Err(TryRecvError::Disconnected) => break,
+                    if prompt.is_none() {
+                        text_input.stop();
+                    }
+                    scene += 1;
                 }
-            }
-        });
-    }
-
-    'running: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
+                Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => {
-                    break 'running;
+                } if map_view.measure.active => {
+                    map_view.measure.cancel();
+                    scene += 1;
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::W),
-                    ..
-                } => viewport.pan(0.0, -0.25),
-                Event::KeyDown {
-                    keycode: Some(Keycode::S),
-                    ..
-                } => viewport.pan(0.0, 0.25),
-                Event::KeyDown {
-                    keycode: Some(Keycode::A),
-                    ..
-                } => viewport.pan(-0.25, 0.0),
-                Event::KeyDown {
-                    keycode: Some(Keycode::D),
-                    ..
-                } => viewport.pan(0.25, 0.0),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => viewport.zoom_in(),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
+                    keycode: Some(Keycode::Escape),
                     ..
-                } => {
-                    viewport.zoom_out();
-                    //tile_map.clear();
+                } if map_view.markers.spiderfied() => {
+                    map_view.markers.collapse();
+                    scene += 1;
+                }
+                Event::TextInput { text, .. } => {
+                    if let Some(open) = &mut prompt {
+                        open.type_text(&text);
+                        scene += 1;
+                    }
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::M),
+                    keycode: Some(key),
+                    keymod,
                     ..
                 } => {
-                    if map == 0 {
-                        map = 1;
-                    } else {
-                        map = 0;
+                    let action =
+                        input::action_for(key, keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD));
+                    if matches!(
+                        action,
+                        Some(
+                            Action::PanNorth
+                                | Action::PanSouth
+                                | Action::PanWest
+                                | Action::PanEast
+                                | Action::ZoomIn
+                                | Action::ZoomOut
+                        )
+                    ) {
+                        // a key move lands where the view is now, not where a jump was headed
+                        motion.stop();
+                    }
+                    match action {
+                        Some(Action::Quit) => break 'running,
+                        Some(Action::PanNorth) => map_view.viewport.pan(0.0, -0.25),
+                        Some(Action::PanSouth) => map_view.viewport.pan(0.0, 0.25),
+                        Some(Action::PanWest) => map_view.viewport.pan(-0.25, 0.0),
+                        Some(Action::PanEast) => map_view.viewport.pan(0.25, 0.0),
+                        Some(Action::ZoomIn) => map_view.viewport.zoom_in(),
+                        Some(Action::ZoomOut) => map_view.viewport.zoom_out(),
+                        Some(Action::NextSource) => map = SOURCES.read().unwrap().next_id(map),
+                        Some(Action::SelectSource(id)) => map = id,
+                        Some(Action::TogglePause) => {
+                            let paused = opengl_helper::toggle_downloads_paused();
+                            info!("Downloads {}", if paused { "paused" } else { "resumed" });
+                        }
+                        Some(Action::TogglePolarView) => {
+                            let vp = map_view.viewport;
+                            let projection = if vp.projection.is_polar() {
+                                Projection::WebMercator
+                            } else {
+                                // a polar source decides the pole, otherwise the view's hemisphere
+                                SOURCES
+                                    .read()
+                                    .unwrap()
+                                    .get(map)
+                                    .map(|source| source.projection)
+                                    .filter(|projection| projection.is_polar())
+                                    .unwrap_or_else(|| Projection::polar_for(vp.center_latlon().0))
+                            };
+                            motion.stop();
+                            map_view.viewport = vp.with_projection(projection);
+                        }
+                        Some(Action::PrintCacheStats) => {
+                            info!("Disk cache: {}", DISK_CACHE.lock().unwrap().stats())
+                        }
+                        Some(Action::ToggleHelp) => show_help = !show_help,
+                        Some(Action::ToggleStatusBar) => show_status_bar = !show_status_bar,
+                        Some(Action::GoHome) => {
+                            if let Some(home) = settings.home {
+                                motion.fly_to(&mut map_view.viewport, home.viewport());
+                                map = home.source;
+                            }
+                        }
+                        Some(Action::SetHome) => {
+                            let (lat, lon) = map_view.viewport.center_latlon();
+                            let home = HomeView {
+                                lat,
+                                lon,
+                                zoom: map_view.viewport.z,
+                                source: map,
+                            };
+                            settings.home = Some(home);
+                            protect_areas(&bookmarks, &settings);
+                            match Settings::save_home(SETTINGS_PATH, home) {
+                                Ok(()) => {
+                                    info!("Home set to {:.5}, {:.5} z{}", lat, lon, home.zoom)
+                                }
+                                Err(e) => warn!("Failed to save {}: {}", SETTINGS_PATH, e),
+                            }
+                        }
+                        Some(Action::ToggleLayerList) => show_layer_list = !show_layer_list,
+                        Some(Action::AddLayer) => {
+                            if !tile_layers.add_next(
+                                map,
+                                &SOURCES.read().unwrap(),
+                                settings.animation.layer_fade(),
+                            ) {
+                                info!("Every tile source is already shown");
+                            }
+                            scene += 1;
+                        }
+                        Some(Action::RemoveLayer) => {
+                            tile_layers.remove_selected();
+                            scene += 1;
+                        }
+                        Some(Action::NextLayer) => {
+                            tile_layers.select_next();
+                            scene += 1;
+                        }
+                        Some(Action::ToggleLayer) => {
+                            tile_layers.toggle_selected(settings.animation.layer_fade());
+                            scene += 1;
+                        }
+                        Some(Action::LayerOpacityDown) => {
+                            tile_layers.step_opacity(-1, settings.animation.layer_fade());
+                            scene += 1;
+                        }
+                        Some(Action::LayerOpacityUp) => {
+                            tile_layers.step_opacity(1, settings.animation.layer_fade());
+                            scene += 1;
+                        }
+                        Some(Action::TimeSlower) => clock.step_rate(-1),
+                        Some(Action::TimeFaster) => clock.step_rate(1),
+                        Some(Action::TimeBack) => clock.jump(-600.0),
+                        Some(Action::TimeForward) => clock.jump(600.0),
+                        Some(Action::TimeNow) => clock.reset(),
+                        Some(Action::ToggleCompare) => {
+                            compare = match compare {
+                                Some(_) => None,
+                                None => Some(Compare::new(SOURCES.read().unwrap().next_id(map))),
+                            };
+                            scene += 1;
+                        }
+                        Some(Action::NextCompareSource) => {
+                            if let Some(compare) = &mut compare {
+                                compare.right = SOURCES.read().unwrap().next_id(compare.right);
+                                scene += 1;
+                            }
+                        }
+                        Some(Action::ClearRangeRings) => {
+                            map_view.range_rings.clear();
+                            share_edit(&collab, &mut audit, Edit::ClearRangeRings);
+                            scene += 1;
+                        }
+                        Some(Action::Search) => {
+                            prompt = Some(Prompt::Search(SearchBox::with_history(
+                                recent.searches.clone(),
+                            )));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::ListRecent) => {
+                            prompt = Some(Prompt::Recent(BookmarkPicker::default()));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::GoTo) => {
+                            prompt = Some(Prompt::GoTo(LineInput::default()));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::CopyPermalink) => {
+                            let link = permalink::format(&map_view.viewport);
+                            match video_subsystem.clipboard().set_clipboard_text(&link) {
+                                Ok(()) => info!("Copied {}", link),
+                                Err(e) => warn!("Failed to copy {}: {}", link, e),
+                            }
+                        }
+                        Some(Action::FindAmenity) => {
+                            let last = map_view.pois.amenity.clone().unwrap_or_default();
+                            prompt = Some(Prompt::Amenity(LineInput {
+                                text: last,
+                                error: None,
+                            }));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::ClearAmenities) => {
+                            map_view.pois.clear();
+                            poi_popup = None;
+                            scene += 1;
+                        }
+                        Some(Action::ToggleTilePicker) => {
+                            map_view.tile_picker.active = !map_view.tile_picker.active;
+                            scene += 1;
+                        }
+                        Some(Action::ClearPickedTiles) => {
+                            map_view.tile_picker.clear();
+                            scene += 1;
+                        }
+                        Some(Action::RefreshPickedTiles) => {
+                            let tiles = map_view.tile_picker.tiles(map);
+                            for tile in &tiles {
+                                // without validators the server sends the whole tile again
+                                let disk = opengl_helper::get_file_path(*tile);
+                                if let Err(e) = TileMeta::default().save(&disk) {
+                                    warn!("Failed to reset {}: {}", disk.display(), e);
+                                }
+                                workers.download(*tile);
+                            }
+                            info!("Refreshing {} tiles", tiles.len());
+                        }
+                        Some(Action::DeletePickedTiles) => {
+                            let mut deleted = 0;
+                            for tile in map_view.tile_picker.tiles(map) {
+                                let disk = opengl_helper::get_file_path(tile);
+                                match disk_cache::delete_tile(&disk) {
+                                    Ok(true) => deleted += 1,
+                                    Ok(false) => {}
+                                    Err(e) => {
+                                        warn!("Failed to delete {}: {}", disk.display(), e)
+                                    }
+                                }
+                                opengl_helper::evict_tile(&mut tile_cache, tile);
+                                requests.done(&tile);
+                            }
+                            info!("Deleted {} tiles from the disk cache", deleted);
+                            scene += 1;
+                        }
+                        Some(Action::ExportPickedTiles) => {
+                            let prefix = SOURCES
+                                .read()
+                                .unwrap()
+                                .get(map)
+                                .map_or_else(|| map.to_string(), |s| s.file_prefix.clone());
+                            let dir = Path::new(cache_import::EXPORT_DIR).join(prefix);
+                            let tiles = map_view.tile_picker.tiles(map);
+                            match cache_import::export_tiles(&tiles, &dir) {
+                                Ok(exported) => info!(
+                                    "Exported {} of {} tiles to {}",
+                                    exported,
+                                    tiles.len(),
+                                    dir.display()
+                                ),
+                                Err(e) => warn!("Failed to export tiles: {}", e),
+                            }
+                        }
+                        Some(Action::ExportFigure) => {
+                            export_figure = true;
+                            scene += 1;
+                        }
+                        Some(Action::CycleUnits) => {
+                            let next = units::current().next();
+                            units::set(next);
+                            info!("Showing {} units", next.name());
+                            scene += 1;
+                        }
+                        Some(Action::ToggleStats) => {
+                            stats.visible = !stats.visible;
+                            scene += 1;
+                        }
+                        Some(Action::FollowPosition) => match &mut gps {
+                            Some(gps) => {
+                                gps.follow = !gps.follow;
+                                if gps.follow {
+                                    info!("Following the GPS position");
+                                    if let Some(fix) = gps.fix() {
+                                        let vp = map_view.viewport;
+                                        let to = Viewport::centered_on(fix.lat, fix.lon, vp.z)
+                                            .with_projection(vp.projection);
+                                        motion.fly_to(&mut map_view.viewport, to);
+                                    }
+                                } else {
+                                    info!("Stopped following the GPS position");
+                                }
+                                scene += 1;
+                            }
+                            None => info!("GPS is off, enable it in the [gps] settings"),
+                        },
+                        Some(Action::ToggleRecording) => {
+                            if gps.is_none() {
+                                info!("GPS is off, enable it in the [gps] settings");
+                            } else if map_view.recorder.toggle() {
+                                info!("Recording the GPS track");
+                            } else {
+                                info!(
+                                    "Stopped recording, {} points so far",
+                                    map_view.recorder.len()
+                                );
+                            }
+                        }
+                        Some(Action::ExportTrack) => {
+                            match map_view
+                                .recorder
+                                .export(Path::new(cache_import::EXPORT_DIR))
+                            {
+                                Ok(path) => info!(
+                                    "Exported {} track points to {}",
+                                    map_view.recorder.len(),
+                                    path.display()
+                                ),
+                                Err(e) => warn!("Failed to export the track: {}", e),
+                            }
+                        }
+                        Some(Action::ClearDirections) => {
+                            map_view.directions.clear();
+                            scene += 1;
+                        }
+                        Some(Action::ClearTrack) => {
+                            map_view.recorder.clear();
+                            info!("Discarded the recorded track");
+                            scene += 1;
+                        }
+                        Some(Action::ToggleGui) => {
+                            gui.visible = !gui.visible;
+                            scene += 1;
+                        }
+                        Some(Action::CycleVSync) => {
+                            vsync = frame_pacer::set_vsync(&video_subsystem, vsync.next());
+                            info!("Vsync {}", vsync.name());
+                        }
+                        Some(Action::CycleFrameCap) => {
+                            pacer.set_cap(pacer.cap().next());
+                            info!("Frame rate: {}", pacer.cap().describe());
+                            scene += 1;
+                        }
+                        Some(Action::ToggleMeasure) => {
+                            if map_view.measure.active {
+                                map_view.measure.cancel();
+                            } else {
+                                map_view.measure.active = true;
+                            }
+                            scene += 1;
+                        }
+                        Some(Action::ToggleHeatmap) => {
+                            if history.is_some() {
+                                map_view.heat.visible = !map_view.heat.visible;
+                                heat_area = None;
+                                scene += 1;
+                            } else {
+                                info!("Set enabled = true under [history] in settings.toml");
+                            }
+                        }
+                        Some(Action::ToggleVectorTiles) => {
+                            if map_view.vector.available() {
+                                map_view.vector.visible = !map_view.vector.visible;
+                                scene += 1;
+                            } else {
+                                info!("Set url under [vector] in settings.toml");
+                            }
+                        }
+                        Some(Action::ToggleCoverage) => {
+                            map_view.coverage.visible = !map_view.coverage.visible;
+                            scene += 1;
+                        }
+                        Some(Action::CoverageDeeper) => {
+                            map_view.coverage.next_depth();
+                            scene += 1;
+                        }
+                        Some(Action::SaveSession) => {
+                            prompt = Some(Prompt::SaveSession(LineInput::default()));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::AddBookmark) => {
+                            prompt = Some(Prompt::Bookmark(LineInput::default()));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::ListBookmarks) => {
+                            prompt = Some(Prompt::Bookmarks(BookmarkPicker::default()));
+                            text_input.start();
+                            scene += 1;
+                        }
+                        Some(Action::NextBookmark) => {
+                            next_bookmark %= bookmarks.len().max(1);
+                            if let Some(bookmark) = bookmarks.bookmarks.get(next_bookmark) {
+                                info!("Bookmark: {}", bookmark.name);
+                                motion.fly_to(&mut map_view.viewport, bookmark.viewport());
+                                map = bookmark.source;
+                                next_bookmark += 1;
+                            }
+                        }
+                        Some(Action::ToggleCollect) => {
+                            map_view.collected.active = !map_view.collected.active;
+                            scene += 1;
+                        }
+                        Some(Action::UndoPoint) => {
+                            map_view.collected.undo();
+                            scene += 1;
+                        }
+                        Some(Action::ClearPoints) => {
+                            map_view.collected.clear();
+                            scene += 1;
+                        }
+                        Some(Action::ExportPoints) => {
+                            match map_view.collected.export(Path::new(".")) {
+                                Ok((csv, geojson)) => info!(
+                                    "Exported {} points to {} and {}",
+                                    map_view.collected.len(),
+                                    csv.display(),
+                                    geojson.display()
+                                ),
+                                Err(e) => warn!("Failed to export points: {}", e),
+                            }
+                        }
+                        None => {}
                     }
                 }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Kp0),
-                    ..
-                } => map = 0,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Kp1),
-                    ..
-                } => map = 1,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Kp2),
-                    ..
-                } => map = 2,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Kp3),
-                    ..
-                } => map = 3,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Kp4),
-                    ..
-                } => map = 4,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Kp5),
-                    ..
-                } => map = 5,
-
                 Event::MouseButtonDown {
-                    mouse_btn: MouseButton::Left,
-                    clicks: clicks_in_event,
+                    mouse_btn,
+                    clicks,
                     x,
                     y,
                     ..
                 } => {
                     let (w, h) = window.size();
-                    if clicks_in_event >= 2 {
-                        viewport.zoom_in_at_pixel(w, h, x, y);
-                    } else {
-                        // clicks == 1
-                        viewport.center_on_pixel(w, h, x, y);
+                    if let Some(compare) = &mut compare
+                        && compare.grab(x, w)
+                    {
+                        continue;
+                    }
+                    if map_view.tile_picker.active && mouse_btn == MouseButton::Left {
+                        map_view.tile_picker.press(&map_view.viewport, (w, h), x, y);
+                        scene += 1;
+                        continue;
+                    }
+                    let marker = map_view.marker_at_pixel((w, h), x, y);
+                    let poi = map_view
+                        .pois
+                        .hit_test(&map_view.viewport, (w, h), x, y)
+                        .cloned();
+                    match (input::tool_for(mouse_btn, clicks), marker) {
+                        (Some(Tool::Select), _) if map_view.measure.active => {
+                            map_view.measure.click(&map_view.viewport, (w, h), x, y);
+                            scene += 1;
+                        }
+                        (Some(Tool::Select), _) if map_view.collected.active => {
+                            collect_point(&mut map_view, (w, h), (x as f64, y as f64));
+                            scene += 1;
+                        }
+                        (Some(Tool::Select | Tool::ZoomAt), Some(id)) => {
+                            // a stack fans out first so its markers can be told apart
+                            if map_view.markers.spiderfy(id, &map_view.viewport, (w, h)) {
+                                scene += 1;
+                            } else if let Some(marker) = map_view.markers.get(id) {
+                                info!(
+                                    "Selected marker {} at {:.5}, {:.5}",
+                                    id.0, marker.lat, marker.lon
+                                );
+                            }
+                        }
+                        (Some(Tool::Select), None) if poi.is_some() => {
+                            poi_popup = poi;
+                            scene += 1;
+                        }
+                        (Some(Tool::Select), None) if map_view.markers.spiderfied() => {
+                            map_view.markers.collapse();
+                            scene += 1;
+                        }
+                        // the hotspot acts on release, so the map can still be dragged
+                        (Some(Tool::Select), None)
+                            if map_view
+                                .hotspots
+                                .hit(&map_view.viewport, (w, h), x, y)
+                                .is_some() =>
+                        {
+                            poi_popup = None;
+                            motion.press(x, y);
+                            scene += 1;
+                        }
+                        (Some(Tool::Select), None) => {
+                            // an overlay feature's properties show like a POI's
+                            let feature = pick_buffer.pick(
+                                &mut map_view,
+                                &world_shader,
+                                (w, h),
+                                window.drawable_size(),
+                                (x, y),
+                            );
+                            poi_popup = feature.map(|tags| {
+                                let (lat, lon) = map_view
+                                    .viewport
+                                    .pixel_to_latlon((x as f64, y as f64), (w, h));
+                                Poi { lat, lon, tags }
+                            });
+                            scene += 1;
+                            if poi_popup.is_none() {
+                                // a click centres on release, a drag pans instead
+                                motion.press(x, y);
+                            }
+                        }
+                        (Some(Tool::ZoomAt), None) => {
+                            map_view.viewport.zoom_in_at_pixel(w, h, x, y)
+                        }
+                        (Some(Tool::DropMarker), _) => {
+                            let world = map_view
+                                .viewport
+                                .pixel_to_world((x as f64, y as f64), (w, h));
+                            let (lat, lon) = geo::unproject(world.0, world.1);
+                            map_view.add_marker(lat, lon, pin_icon.clone());
+                            share_edit(&collab, &mut audit, Edit::Marker { lat, lon });
+                            scene += 1;
+                        }
+                        (Some(Tool::Route), _) => {
+                            let world = map_view
+                                .viewport
+                                .pixel_to_world((x as f64, y as f64), (w, h));
+                            let point = geo::unproject(world.0, world.1);
+                            match (route_start.take(), &router) {
+                                (Some(start), Some(router)) => {
+                                    router.request(start, point);
+                                    routing = true;
+                                }
+                                (Some(start), None) => {
+                                    let metres = map_view.routes.add(start, point);
+                                    info!("Route: {}", units::format_distance(metres));
+                                    let route = Edit::Route {
+                                        from: [start.0, start.1],
+                                        to: [point.0, point.1],
+                                    };
+                                    share_edit(&collab, &mut audit, route);
+                                }
+                                (None, _) => route_start = Some(point),
+                            }
+                            scene += 1;
+                        }
+                        (Some(Tool::RangeRings), _) => {
+                            let world = map_view
+                                .viewport
+                                .pixel_to_world((x as f64, y as f64), (w, h));
+                            let (lat, lon) = geo::unproject(world.0, world.1);
+                            map_view.range_rings.add((lat, lon));
+                            info!("Range rings at {:.5}, {:.5}", lat, lon);
+                            share_edit(&collab, &mut audit, Edit::RangeRings { lat, lon });
+                            scene += 1;
+                        }
+                        (None, _) => {}
+                    }
+                }
+                Event::MouseMotion { x, y, .. } => {
+                    cursor = Some((x, y));
+                    if let Some(collab) = &mut collab {
+                        let (lat, lon) = map_view
+                            .viewport
+                            .pixel_to_latlon((x as f64, y as f64), window.size());
+                        collab.send_cursor(lat, lon);
+                    }
+                    motion.drag_to(&mut map_view.viewport, x, y);
+                    // dragging the map away lets go of the position
+                    if motion.dragging()
+                        && let Some(gps) = &mut gps
+                        && gps.follow
+                    {
+                        gps.follow = false;
+                        info!("Stopped following the GPS position");
+                    }
+                    if let Some(compare) = &mut compare
+                        && compare.drag(x, window.size().0)
+                    {
+                        scene += 1;
+                    }
+                    if map_view
+                        .tile_picker
+                        .drag_to(&map_view.viewport, window.size(), x, y)
+                    {
+                        scene += 1;
+                    }
+                }
+                Event::MouseButtonUp { mouse_btn, .. } => {
+                    if mouse_btn == MouseButton::Left
+                        && let Some((x, y)) = motion.release()
+                    {
+                        let (w, h) = window.size();
+                        match map_view.hotspots.hit(&map_view.viewport, (w, h), x, y) {
+                            Some(hotspot) => {
+                                info!("Hotspot {}", hotspot.name);
+                                let action = hotspot.action.clone();
+                                if let Err(e) = action.launch() {
+                                    warn!("Failed to open hotspot {}: {}", hotspot.name, e);
+                                }
+                                if let Some(viewport) = action.viewport() {
+                                    motion.fly_to(&mut map_view.viewport, viewport);
+                                }
+                                if let HotspotAction::FlyTo {
+                                    source: Some(source),
+                                    ..
+                                } = action
+                                {
+                                    map = source;
+                                }
+                            }
+                            None => map_view.viewport.center_on_pixel(w, h, x, y),
+                        }
+                    }
+                    if let Some(compare) = &mut compare {
+                        compare.release();
+                    }
+                    if map_view.tile_picker.release() {
+                        scene += 1;
                     }
                 }
+                // exposed, resized, restored... the old frame may be gone
+                Event::Window {
+                    win_event: WindowEvent::Leave,
+                    ..
+                } => cursor = None,
+                Event::Window {
+                    win_event: WindowEvent::Minimized | WindowEvent::Hidden,
+                    ..
+                } => {
+                    debug!("Window hidden, pausing the tile pipeline");
+                    hidden = true;
+                    workers.suspend(true);
+                }
+                Event::Window {
+                    win_event: WindowEvent::Restored | WindowEvent::Shown | WindowEvent::Maximized,
+                    ..
+                } if hidden => {
+                    debug!("Window back, resuming the tile pipeline");
+                    hidden = false;
+                    workers.suspend(false);
+                    scene += 1;
+                }
+                Event::Window { .. } => {
+                    // may have moved to a display with another pixel density or refresh rate
+                    opengl_helper::set_hidpi(pixel_scale(&window) >= 1.5);
+                    pacer.set_refresh_rate(refresh_rate(&video_subsystem, &window));
+                    scene += 1;
+                }
                 _ => {}
             }
         }
 
-        unsafe {
-            gl::Clear(gl::COLOR_BUFFER_BIT);
+        if let Some(collab) = &mut collab {
+            if let Some(edits) = collab.poll() {
+                for (author, edit) in edits {
+                    apply_edit(&mut map_view, &pin_icon, edit);
+                    audit.record(AuditEntry::annotation(&edit).by(author).detail("relay"));
+                }
+                scene += 1;
+            }
+            let win = window.size();
+            let (north, west) = map_view.viewport.pixel_to_latlon((0.0, 0.0), win);
+            let (south, east) = map_view
+                .viewport
+                .pixel_to_latlon((f64::from(win.0), f64::from(win.1)), win);
+            collab.send_view([south, west, north, east]);
+        }
+        if let Some(gps) = &mut gps
+            && gps.poll()
+        {
+            let fix = gps.fix();
+            map_view.position.set(fix);
+            match &fix {
+                Some(fix) => map_view.recorder.push(fix),
+                // don't join the points either side of the gap
+                None => map_view.recorder.break_segment(),
+            }
+            if gps.follow
+                && let Some(fix) = fix
+            {
+                let to = Viewport::centered_on(fix.lat, fix.lon, map_view.viewport.z);
+                motion.stop();
+                map_view.viewport = to.with_projection(map_view.viewport.projection);
+            }
+            scene += 1;
+        }
+        for change in source_watcher.poll() {
+            crash_report::log_event(format!("sources reloaded: {:?}", change));
+            match change {
+                SourceChange::Removed(id) | SourceChange::Changed(id) => {
+                    opengl_helper::evict_source_textures(&mut tile_cache, id);
+                    requests.forget_source(id);
+                    missing_tiles.forget_source(id);
+                }
+                SourceChange::Added(_) => {}
+            }
+            scene += 1;
+        }
+        {
+            let sources = SOURCES.read().unwrap();
+            if !sources.contains(map) {
+                map = sources.first_id().unwrap_or(0);
+            }
+            tile_layers.retain_sources(&sources);
+            if let Some(c) = compare
+                && !sources.contains(c.right)
+            {
+                compare = None;
+            }
+        }
+        while let Some((query, result)) = geocoder.poll() {
+            if let Some(Prompt::Search(search_box)) = &mut prompt {
+                search_box.receive(query, result);
+            } else {
+                gui.search.receive(query, result);
+            }
+            scene += 1;
+        }
+        while let Some((amenity, result)) = overpass.poll() {
+            match result {
+                Ok(pois) => {
+                    info!("Found {} x {}", pois.len(), amenity);
+                    map_view.pois.replace(amenity, pois);
+                }
+                Err(e) => warn!("Overpass query for {} failed: {}", amenity, e),
+            }
+            amenity_query = None;
+            poi_popup = None;
+            scene += 1;
+        }
+        if let Some(router) = &router {
+            while let Some(result) = router.poll() {
+                match result {
+                    Ok(directions) => {
+                        map_view.directions.set(directions);
+                        if let Some(summary) = map_view.directions.summary() {
+                            info!("Road route: {}", summary);
+                        }
+                    }
+                    Err(e) => warn!("Routing failed: {}", e),
+                }
+                routing = false;
+                scene += 1;
+            }
+        }
+        // a minimized map does no background work either
+        maintenance.set_idle(!hidden && last_input.elapsed() >= cache_maintenance::IDLE_AFTER);
+        if let Some(history) = &mut history {
+            history.tick(
+                &map_view.viewport,
+                window.size(),
+                last_input.elapsed() < history::IDLE_AFTER,
+            );
+            let area = history::heat_area(&map_view.viewport, window.size());
+            if map_view.heat.visible && heat_area != Some(area) {
+                history.request_heat(area);
+                heat_area = Some(area);
+            }
+            while let Some(cells) = history.poll() {
+                match cells {
+                    Ok(cells) => map_view.heat.replace(cells),
+                    Err(e) => warn!("Failed to read the view history: {}", e),
+                }
+                scene += 1;
+            }
+        }
+        gestures.extend(touch_input.tick());
+        for gesture in &gestures {
+            let win = window.size();
+            match *gesture {
+                Gesture::Pinch {
+                    center,
+                    delta,
+                    zoom,
+                } => {
+                    map_view.viewport.pan(-delta.0 / 256.0, -delta.1 / 256.0);
+                    for _ in 0..zoom.unsigned_abs() {
+                        map_view.viewport.zoom_around_pixel(win, center, zoom > 0);
+                    }
+                }
+                Gesture::DoubleTap(at) => map_view.viewport.zoom_around_pixel(win, at, true),
+                _ => continue,
+            }
+            scene += 1;
+        }
+        // elsewhere SDL's mouse emulation already does what a single finger should
+        if map_view.measure.active || map_view.collected.active {
+            for gesture in gestures {
+                let win = window.size();
+                map_view.measure.use_finger_handles();
+                match gesture {
+                    Gesture::Press(at) => {
+                        dragged_handle = map_view.measure.handle_at(
+                            &map_view.viewport,
+                            win,
+                            at,
+                            touch::HANDLE_RADIUS_PX,
+                        );
+                    }
+                    Gesture::Drag { at, delta } => match dragged_handle {
+                        Some(index) => {
+                            map_view
+                                .measure
+                                .move_vertex(index, &map_view.viewport, win, at)
+                        }
+                        None => map_view.viewport.pan(-delta.0 / 256.0, -delta.1 / 256.0),
+                    },
+                    // holding the first vertex closes the shape, holding another one doesn't add a point
+                    Gesture::LongPress(at) if dragged_handle.is_none_or(|index| index == 0) => {
+                        if map_view.measure.active {
+                            let (x, y) = (at.0 as i32, at.1 as i32);
+                            map_view.measure.click(&map_view.viewport, win, x, y);
+                        } else {
+                            collect_point(&mut map_view, win, at);
+                        }
+                    }
+                    Gesture::LongPress(_) => {}
+                    Gesture::TwoFingerTap if map_view.measure.active => map_view.measure.undo(),
+                    Gesture::TwoFingerTap => map_view.collected.undo(),
+                    Gesture::Release | Gesture::DoubleTap(_) => dragged_handle = None,
+                    Gesture::Pinch { .. } => dragged_handle = None,
+                }
+                scene += 1;
+            }
+        }
+        motion.tick(&mut map_view.viewport, frame_at);
+        motion.keep_in_world(&mut map_view.viewport, window.size(), frame_at);
+        if map_view.vector.poll() {
+            scene += 1;
+        }
+        if tile_layers.tick(frame_at) {
+            scene += 1;
+        }
+        if gui.needs_repaint(frame_at) {
+            scene += 1;
+        }
+        let drawn_layers = tile_layers.drawn(map, map_view.viewport.z, frame_at);
+        map_view.coverage.source = map;
+        if map_view.animated()
+            || !clock.is_live()
+            || pacer.cap() == FrameCap::Uncapped
+            || stats.visible
+        {
+            // moving layers, the clock readout, an uncapped benchmark and the
+            // statistics need a fresh frame every tick
+            scene += 1;
         }
 
-        opengl_helper::draw_visible_tiles(
-            &mut viewport,
-            window.size().0,
-            window.size().1,
-            shader_program.0,
-            vao.0,
-            &mut tile_cache,
+        crash_report::set_state(map_view.viewport, map);
+        let frame = FrameKey {
+            viewport: map_view.viewport,
+            win: window.size(),
             map,
-            job_tx.clone(),
-        );
-        window.gl_swap_window();
-        while let Ok(tile_load) = res_rx.try_recv() {
+            paused: opengl_helper::downloads_paused(),
+            help: show_help,
+            status_bar: show_status_bar,
+            layer_list: show_layer_list,
+            cursor,
+            pending: opengl_helper::pending_downloads(),
+            source_blocked: drawn_layers
+                .iter()
+                .any(|layer| retry::source_blocked_for(layer.source).is_some()),
+            offline: retry::offline(),
+            scene,
+        };
+        // nothing changed since the last swap: keep the frame on screen
+        let drawn = !hidden && last_frame != Some(frame);
+        let mut gui_actions = Vec::new();
+        if drawn {
+            let draw_start = Instant::now();
+            tile_cache.start_frame();
+            let (drawable_w, drawable_h) = window.drawable_size();
+            unsafe {
+                // render at the display's native resolution
+                gl::Viewport(0, 0, drawable_w as i32, drawable_h as i32);
+                gl::Clear(gl::COLOR_BUFFER_BIT);
+            }
+            if export_figure {
+                export_figure = false;
+                // tiles and backdrop layers only; the rest goes in as vectors
+                opengl_helper::draw_visible_tiles(
+                    &mut map_view.viewport,
+                    window.size().0,
+                    window.size().1,
+                    &tile_quad,
+                    &reprojector,
+                    &mut tile_cache,
+                    &drawn_layers,
+                    &requests,
+                );
+                map_view.draw_backdrop(window.size(), &world_shader, clock.now());
+                let backdrop = figure::read_back_buffer((drawable_w, drawable_h));
+                let attribution = SOURCES.read().unwrap().attribution(&drawn_layers);
+                let figure = map_view.figure(backdrop, window.size(), &attribution);
+                let stamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                for extension in ["svg", "pdf"] {
+                    let path = Path::new(cache_import::EXPORT_DIR)
+                        .join(format!("map-{}.{}", stamp, extension));
+                    match figure.save(&path) {
+                        Ok(()) => info!("Exported the view to {}", path.display()),
+                        Err(e) => warn!("Failed to export {}: {}", path.display(), e),
+                    }
+                }
+                unsafe { gl::Clear(gl::COLOR_BUFFER_BIT) };
+            }
+
+            // in compare mode each half gets its own base under the same overlays
+            let passes = match compare {
+                Some(compare) => {
+                    let mut right_layers = drawn_layers.clone();
+                    right_layers[0] = TileLayer::base(compare.right);
+                    vec![
+                        (
+                            Some(compare.half(window.drawable_size(), true)),
+                            drawn_layers.clone(),
+                        ),
+                        (
+                            Some(compare.half(window.drawable_size(), false)),
+                            right_layers,
+                        ),
+                    ]
+                }
+                None => vec![(None, drawn_layers.clone())],
+            };
+            for (rect, layers) in &passes {
+                opengl_helper::scissor(*rect);
+                let fading = opengl_helper::draw_visible_tiles(
+                    &mut map_view.viewport,
+                    window.size().0,
+                    window.size().1,
+                    &tile_quad,
+                    &reprojector,
+                    &mut tile_cache,
+                    layers,
+                    &requests,
+                );
+                if fading {
+                    scene += 1;
+                }
+            }
+            opengl_helper::scissor(None);
+            map_view.draw_overlays(window.size(), &world_shader, clock.now());
+            let place_labels = map_view.place_labels();
+            let name_scale = theme.panel_text_scale();
+            for (name, at) in
+                labels::place(&place_labels, &map_view.viewport, window.size(), |name| {
+                    text_renderer.measure(name, name_scale)
+                })
+            {
+                text::draw_place_name(&text_renderer, &theme, name, at, window.size());
+            }
+            let mut labels = map_view.routes.labels();
+            labels.extend(map_view.range_rings.labels());
+            labels.extend(map_view.measure.labels());
+            for (world, label) in labels {
+                let (x, y) = map_view.viewport.world_to_pixel(world, window.size());
+                text::draw_label(
+                    &text_renderer,
+                    &theme,
+                    &label,
+                    (x as f32, y as f32),
+                    window.size(),
+                );
+            }
+            if let Some(poi) = &poi_popup {
+                let at = map_view
+                    .viewport
+                    .world_to_pixel(geo::project(poi.lat, poi.lon), window.size());
+                let rows: Vec<String> = poi
+                    .tags
+                    .iter()
+                    .map(|(key, value)| format!("{} = {}", key, value))
+                    .collect();
+                text::draw_popup(
+                    &text_renderer,
+                    &theme,
+                    poi.title(),
+                    &rows,
+                    (at.0 as f32, at.1 as f32),
+                    window.size(),
+                );
+            }
+            if let Some(collab) = &collab {
+                collab::draw_peers(
+                    &text_renderer,
+                    &theme,
+                    collab,
+                    &map_view.viewport,
+                    window.size(),
+                );
+            }
+            let mut status = Vec::new();
+            let clock_line;
+            if !clock.is_live() {
+                clock_line = format!("{}  x{}", sim_clock::format_utc(clock.now()), clock.rate());
+                status.push((clock_line.as_str(), theme.accent));
+            }
+            if map_view.collected.active {
+                status.push((
+                    "Collecting points: Backspace undoes, Shift+K exports",
+                    theme.accent,
+                ));
+            }
+            let coverage_line;
+            if map_view.coverage.visible {
+                coverage_line = map_view.coverage.summary();
+                status.push((coverage_line.as_str(), theme.accent));
+            }
+            let measure_line;
+            if map_view.measure.active {
+                measure_line = map_view.measure.summary();
+                status.push((measure_line.as_str(), theme.accent));
+            }
+            let picker_line;
+            if map_view.tile_picker.active {
+                picker_line = format!(
+                    "Picking tiles: {} picked, F5 refreshes, Shift+Delete deletes, E exports",
+                    map_view.tile_picker.len()
+                );
+                status.push((picker_line.as_str(), theme.accent));
+            }
+            let amenity_line;
+            if let Some(amenity) = &amenity_query {
+                amenity_line = format!("Asking Overpass for {}...", amenity);
+                status.push((amenity_line.as_str(), theme.accent));
+            }
+            if route_start.is_some() {
+                status.push(("Right click the end of the route", theme.accent));
+            }
+            if routing {
+                status.push(("Asking the router for a route...", theme.accent));
+            }
+            let directions_line;
+            if let Some(summary) = map_view.directions.summary() {
+                directions_line = format!("Road route {}, Shift+R removes it", summary);
+                status.push((directions_line.as_str(), theme.accent));
+            }
+            let projection_line;
+            if frame.viewport.projection.is_polar() {
+                projection_line = format!(
+                    "{} view, Shift+P goes back to Mercator",
+                    frame.viewport.projection.name()
+                );
+                status.push((projection_line.as_str(), theme.accent));
+            }
+            let fps_line;
+            if pacer.cap() == FrameCap::Uncapped {
+                fps_line = format!("{:.0} fps, vsync {}, F4 caps", pacer.fps(), vsync.name());
+                status.push((fps_line.as_str(), theme.accent));
+            }
+            if frame.paused {
+                status.push(("Downloads paused (P)", theme.warning));
+            }
+            if frame.offline {
+                status.push(("Offline, showing cached tiles only", theme.error));
+            } else if frame.source_blocked {
+                status.push(("Tile server failing, backing off", theme.error));
+            }
+            text::draw_hud(&text_renderer, &theme, &status, window.size());
+            let sources = SOURCES.read().unwrap();
+            if let Some(source) = sources.get(map) {
+                let shown: Vec<TileLayer> = passes.into_iter().flat_map(|(_, l)| l).collect();
+                let attribution = sources.attribution(&shown);
+                if let Some(compare) = compare {
+                    let right_name = sources.get(compare.right).map_or("", |s| s.name.as_str());
+                    text::draw_divider(
+                        &text_renderer,
+                        &theme,
+                        compare.divider_x(window.size().0) as f32,
+                        (&source.name, right_name),
+                        window.size(),
+                    );
+                }
+                let readout = cursor.map(|pixel| {
+                    let mut readout = cursor_readout(
+                        &map_view.viewport,
+                        pixel,
+                        window.size(),
+                        &settings.coordinates,
+                    );
+                    let world = map_view
+                        .viewport
+                        .pixel_to_world((f64::from(pixel.0), f64::from(pixel.1)), window.size());
+                    // the height under the cursor, when a relief layer is drawn
+                    let elevation = shown
+                        .iter()
+                        .filter_map(|layer| sources.get(layer.source))
+                        .find_map(|source| {
+                            elevation_probe.sample(source, world, map_view.viewport.z)
+                        });
+                    if let Some(metres) = elevation {
+                        readout.push_str("  ");
+                        readout.push_str(&units::format_elevation(f64::from(metres)));
+                    }
+                    readout
+                });
+                if show_status_bar {
+                    let mut info = status_line(&frame, &source.name);
+                    if let Some(readout) = &readout {
+                        info.push_str("  cursor ");
+                        info.push_str(readout);
+                    }
+                    text::draw_status_bar(
+                        &text_renderer,
+                        &theme,
+                        &info,
+                        &attribution,
+                        window.size(),
+                    );
+                } else {
+                    text::draw_attribution(&text_renderer, &theme, &attribution, window.size());
+                    if let Some(readout) = &readout {
+                        text::draw_cursor_readout(&text_renderer, &theme, readout, window.size());
+                    }
+                }
+                if show_layer_list {
+                    let overlays: Vec<(String, f32, bool)> = tile_layers
+                        .overlays
+                        .iter()
+                        .map(|layer| {
+                            let mut name = sources
+                                .get(layer.source)
+                                .map_or_else(|| layer.source.to_string(), |s| s.name.clone());
+                            if !layer.zoom.contains(map_view.viewport.z) {
+                                name += &format!(
+                                    "  (z{}-{})",
+                                    layer.zoom.min_zoom, layer.zoom.max_zoom
+                                );
+                            }
+                            (name, layer.opacity, layer.shown())
+                        })
+                        .collect();
+                    text::draw_layer_list(
+                        &text_renderer,
+                        &theme,
+                        &source.name,
+                        &overlays,
+                        tile_layers.selected,
+                        window.size(),
+                    );
+                }
+                if map_view.collected.active {
+                    text::draw_point_list(
+                        &text_renderer,
+                        &theme,
+                        &map_view.collected.rows(POINT_LIST_ROWS),
+                        map_view.collected.len(),
+                        window.size(),
+                    );
+                }
+                match &prompt {
+                    Some(Prompt::Search(search_box)) => {
+                        let suggestions = search_box.suggestions();
+                        let (names, selected, message) = if suggestions.is_empty() {
+                            let names: Vec<&str> = search_box
+                                .results
+                                .iter()
+                                .map(|place| place.name.as_str())
+                                .collect();
+                            (names, search_box.selected, search_box.message.as_deref())
+                        } else {
+                            // nothing highlighted until Down picks an earlier search
+                            let selected = search_box.suggested().unwrap_or(suggestions.len());
+                            let message = search_box
+                                .message
+                                .as_deref()
+                                .unwrap_or("Earlier searches, Down picks one");
+                            (suggestions, selected, Some(message))
+                        };
+                        text::draw_prompt(
+                            &text_renderer,
+                            &theme,
+                            &format!("Search: {}_", search_box.query),
+                            message,
+                            &names,
+                            selected,
+                            window.size(),
+                        );
+                    }
+                    Some(Prompt::Amenity(line)) => text::draw_prompt(
+                        &text_renderer,
+                        &theme,
+                        &format!("Amenity: {}_", line.text),
+                        Some(
+                            line.error
+                                .as_deref()
+                                .unwrap_or("e.g. cafe, pharmacy, toilets"),
+                        ),
+                        &[],
+                        0,
+                        window.size(),
+                    ),
+                    Some(Prompt::GoTo(go_to)) => text::draw_prompt(
+                        &text_renderer,
+                        &theme,
+                        &format!("Go to: {}_", go_to.text),
+                        Some(go_to.error.as_deref().unwrap_or("lat, lon [zoom] or z/x/y")),
+                        &[],
+                        0,
+                        window.size(),
+                    ),
+                    Some(Prompt::Bookmark(line)) => text::draw_prompt(
+                        &text_renderer,
+                        &theme,
+                        &format!("Bookmark as: {}_", line.text),
+                        Some("Enter saves, a blank name uses the coordinates"),
+                        &[],
+                        0,
+                        window.size(),
+                    ),
+                    Some(Prompt::SaveSession(line)) => text::draw_prompt(
+                        &text_renderer,
+                        &theme,
+                        &format!("Save session as: {}_", line.text),
+                        Some(line.error.as_deref().unwrap_or(
+                            "Enter saves, open it again with RustOpenGLMap <file>.mapsession",
+                        )),
+                        &[],
+                        0,
+                        window.size(),
+                    ),
+                    Some(Prompt::Bookmarks(picker)) => {
+                        let rows: Vec<String> = bookmarks
+                            .matching(&picker.filter)
+                            .into_iter()
+                            .map(|i| {
+                                let bookmark = &bookmarks.bookmarks[i];
+                                match bookmark.protected {
+                                    true => format!("{} (protected)", bookmark.name),
+                                    false => bookmark.name.clone(),
+                                }
+                            })
+                            .collect();
+                        let names: Vec<&str> = rows.iter().map(String::as_str).collect();
+                        let message = if bookmarks.len() == 0 {
+                            "No bookmarks yet, B adds one"
+                        } else {
+                            "Enter goes there, Tab protects its tiles, Delete removes"
+                        };
+                        text::draw_prompt(
+                            &text_renderer,
+                            &theme,
+                            &format!("Bookmarks: {}_", picker.filter),
+                            Some(message),
+                            &names,
+                            picker.selected,
+                            window.size(),
+                        );
+                    }
+                    Some(Prompt::Recent(picker)) => {
+                        let names: Vec<&str> = bookmarks::matching(&recent.places, &picker.filter)
+                            .into_iter()
+                            .map(|i| recent.places[i].name.as_str())
+                            .collect();
+                        let message = if recent.places.is_empty() {
+                            "Nowhere yet, places searched for or gone to show up here"
+                        } else {
+                            "Enter goes there, Tab bookmarks, Delete forgets"
+                        };
+                        text::draw_prompt(
+                            &text_renderer,
+                            &theme,
+                            &format!("Recent: {}_", picker.filter),
+                            Some(message),
+                            &names,
+                            picker.selected,
+                            window.size(),
+                        );
+                    }
+                    None => {}
+                }
+                if show_help {
+                    text::draw_help(
+                        &text_renderer,
+                        &theme,
+                        &input::help_rows(),
+                        &attribution,
+                        window.size(),
+                    );
+                }
+            }
+            if stats.visible {
+                let lines = stats.lines(&pacer, tile_cache.stats(), requests.in_flight());
+                text::draw_stats(&text_renderer, &theme, &lines, window.size());
+            }
+            gui_actions = gui.draw(
+                &GuiState {
+                    map,
+                    sources: &SOURCES.read().unwrap(),
+                    tile_layers: &tile_layers,
+                    zoom: map_view.viewport.z,
+                    gpu: tile_cache.stats(),
+                    disk: &|| DISK_CACHE.lock().unwrap().stats(),
+                    requested: requests.in_flight(),
+                    pending: frame.pending,
+                },
+                window.size(),
+                window.drawable_size(),
+            );
+            stats.record_draw(draw_start.elapsed());
+            window.gl_swap_window();
+            // drawing may have clamped the viewport, compare against what was shown
+            last_frame = Some(FrameKey {
+                viewport: map_view.viewport,
+                ..frame
+            });
+        }
+        for action in gui_actions {
+            match action {
+                GuiAction::SelectSource(id) => map = id,
+                GuiAction::AddLayer => {
+                    if !tile_layers.add_next(
+                        map,
+                        &SOURCES.read().unwrap(),
+                        settings.animation.layer_fade(),
+                    ) {
+                        info!("Every tile source is already shown");
+                    }
+                }
+                GuiAction::RemoveLayer(index) => tile_layers.remove(index),
+                GuiAction::ShowLayer(index, shown) => {
+                    tile_layers.set_shown(index, shown, settings.animation.layer_fade())
+                }
+                // the slider is dragged, so follow it without a fade
+                GuiAction::LayerOpacity(index, opacity) => {
+                    tile_layers.fade_to(index, opacity, Duration::ZERO)
+                }
+                GuiAction::Search(query) => {
+                    recent.add_search(&query);
+                    save_recent(&recent);
+                    geocoder.request(&query);
+                }
+                GuiAction::GoTo(place) => {
+                    info!("Going to {}", place.name);
+                    let viewport = place.viewport(window.size());
+                    recent.add_place(Bookmark::capture(&place.name, &viewport, map));
+                    save_recent(&recent);
+                    motion.fly_to(&mut map_view.viewport, viewport);
+                }
+            }
+            scene += 1;
+        }
+        // typing goes to a focused text field in the panels too
+        if prompt.is_none() && gui.wants_text() != text_input.is_active() {
+            if gui.wants_text() {
+                text_input.start();
+            } else {
+                text_input.stop();
+            }
+        }
+        if let Some(uploader) = &uploader {
+            for tile_load in res_rx.try_iter() {
+                stats.record_load();
+                uploader.upload(tile_load);
+            }
+            while let Some(uploaded) = uploader.poll() {
+                requests.done(&uploaded.pos);
+                let Some(texture) = uploaded.texture else {
+                    continue;
+                };
+                if uploaded.placeholder {
+                    opengl_helper::store_placeholder_texture(
+                        &mut tile_cache,
+                        uploaded.pos,
+                        texture,
+                    );
+                } else {
+                    // drop the checkerboard so the tile fades in
+                    if missing_tiles.forget(&uploaded.pos) {
+                        opengl_helper::evict_tile(&mut tile_cache, uploaded.pos);
+                    }
+                    opengl_helper::store_texture(&mut tile_cache, uploaded.pos, texture);
+                }
+                scene += 1;
+            }
+        }
+        // without an upload thread, the rest waits for the next frames
+        let budget = if uploader.is_some() {
+            0
+        } else {
+            UPLOADS_PER_FRAME
+        };
+        for tile_load in res_rx.try_iter().take(budget) {
+            stats.record_load();
             match tile_load {
                 TileLoad::Loaded {
                     texture,
                     source_tile,
                 } => {
-                    let tex_id = opengl_helper::create_texture_from_bitmap(&texture);
-                    tile_cache.put(source_tile, tex_id);
+                    if missing_tiles.forget(&source_tile) {
+                        opengl_helper::evict_tile(&mut tile_cache, source_tile);
+                    }
+                    opengl_helper::store_tile(&mut tile_cache, &mut staging, source_tile, &texture);
+                    requests.done(&source_tile);
+                    scene += 1;
                 }
                 TileLoad::Loading {
                     texture,
-                    source_tile: _source_tile,
-                    target_tile,
+                    source_tile,
+                    target_tile: _target_tile,
                 } => {
-                    let tex_id = opengl_helper::create_texture_from_bitmap(&texture);
-                    tile_cache.put(target_tile, tex_id);
+                    opengl_helper::store_placeholder(
+                        &mut tile_cache,
+                        &mut staging,
+                        source_tile,
+                        &texture,
+                    );
+                    requests.done(&source_tile);
+                    scene += 1;
                 }
                 TileLoad::Failed {} => {}
             }
         }
-        ::std::thread::sleep(std::time::Duration::new(0, (1_000_000_000 / 60) as u32));
+        for tile in missing_rx.try_iter() {
+            let wait = missing_tiles.mark(tile);
+            debug!(
+                "Showing {}/{}/{} as missing, trying again in {}s",
+                tile.z,
+                tile.x,
+                tile.y,
+                wait.as_secs()
+            );
+            opengl_helper::store_tile(&mut tile_cache, &mut staging, tile, missing_tile::image());
+            requests.done(&tile);
+            scene += 1;
+        }
+        for tile in missing_tiles.due() {
+            // out of view it'll be asked for again when it's needed
+            if !tile_cache.contains(&tile) {
+                missing_tiles.forget(&tile);
+                continue;
+            }
+            requests.request(tile);
+        }
+        // a static view sleeps until something happens instead of redrawing
+        if !hidden && (drawn || scene != frame.scene || touch_input.pending_long_press()) {
+            pacer.wait();
+        } else {
+            woke_by = wake::wait(&mut event_pump, IDLE_WAIT);
+        }
     }
 
+    if let Some(history) = history {
+        history.close();
+    }
+    let session = Session::capture(&map_view.viewport, map, window.size());
+    if let Err(e) = session.save(SESSION_PATH) {
+        warn!("Failed to save {}: {}", SESSION_PATH, e);
+    }
     Ok(())
 }
+
+/// Everything a frame depends on. While it matches the last presented frame
+/// the main loop neither draws nor swaps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameKey {
+    viewport: Viewport,
+    win: (u32, u32),
+    map: u8,
+    paused: bool,
+    help: bool,
+    status_bar: bool,
+    layer_list: bool,
+    cursor: Option<(i32, i32)>,
+    pending: usize,
+    source_blocked: bool,
+    offline: bool,
+    /// Bumped whenever textures, sources, layers, markers or the window change.
+    scene: u64,
+}
+
+/// Adds the point under window pixel `pixel` to the collected points.
+fn collect_point(map_view: &mut MapView, win: (u32, u32), pixel: (f64, f64)) {
+    let world = map_view.viewport.pixel_to_world(pixel, win);
+    let point = map_view.collected.push(geo::unproject(world.0, world.1));
+    info!(
+        "Point {}: {}",
+        map_view.collected.len(),
+        map_view.collected.format.format(point)
+    );
+}
+
+/// Keeps the disk cache from evicting the tiles around home and protected
+/// bookmarks.
+fn protect_areas(bookmarks: &Bookmarks, settings: &Settings) {
+    disk_cache::protect(cache_maintenance::protected_tiles(
+        &bookmarks.bookmarks,
+        settings.home,
+        &settings.cache,
+    ));
+}
+
+/// Window pixel of a finger, which SDL reports as a fraction of the window.
+fn touch_pixel(window: &sdl2::video::Window, x: f32, y: f32) -> (f64, f64) {
+    let (w, h) = window.size();
+    (f64::from(x) * f64::from(w), f64::from(y) * f64::from(h))
+}
+
+fn save_recent(recent: &Recent) {
+    if let Err(e) = recent.save(RECENT_PATH) {
+        warn!("Failed to save {}: {}", RECENT_PATH, e);
+    }
+}
+
+/// Position and tile address under window pixel `pixel`.
+fn cursor_readout(
+    viewport: &Viewport,
+    pixel: (i32, i32),
+    win: (u32, u32),
+    format: &coord_format::CoordFormat,
+) -> String {
+    let pixel = (f64::from(pixel.0), f64::from(pixel.1));
+    let world = viewport.pixel_to_world(pixel, win);
+    let (x, y) = viewport.tile_at_pixel(pixel, win);
+    format!(
+        "{}  z{}/{}/{}",
+        format.format(geo::unproject(world.0, world.1)),
+        viewport.z,
+        x,
+        y
+    )
+}
+
+/// Zoom, centre, source, queue length and network state for the status bar.
+fn status_line(frame: &FrameKey, source_name: &str) -> String {
+    let (lat, lon) = frame.viewport.center_latlon();
+    let network = if frame.paused {
+        "paused"
+    } else if frame.offline {
+        "offline"
+    } else if frame.source_blocked {
+        "backing off"
+    } else if frame.pending > 0 {
+        "downloading"
+    } else {
+        "idle"
+    };
+    format!(
+        "z{}  {:.5}, {:.5}  {}  {} pending  {}",
+        frame.viewport.z, lat, lon, source_name, frame.pending, network
+    )
+}
+
+/// Physical pixels per window pixel, 2 on a typical Retina display.
+fn pixel_scale(window: &sdl2::video::Window) -> f32 {
+    let (w, _) = window.size();
+    let (drawable_w, _) = window.drawable_size();
+    if w == 0 {
+        1.0
+    } else {
+        drawable_w as f32 / w as f32
+    }
+}
+
+/// Refresh rate of the display the window is on, 0 if SDL can't tell.
+fn refresh_rate(video: &sdl2::VideoSubsystem, window: &sdl2::video::Window) -> i32 {
+    window
+        .display_index()
+        .and_then(|display| video.current_display_mode(display))
+        .map_or(0, |mode| mode.refresh_rate)
+}
+
+/// Logs an annotation change made here and repeats it on the other
+/// instances in the collaboration room.
+fn share_edit(collab: &Option<Collab>, audit: &mut AuditLog, edit: Edit) {
+    audit.record(AuditEntry::annotation(&edit));
+    if let Some(collab) = collab {
+        collab.send_edit(edit);
+    }
+}
+
+/// Repeats an annotation another instance added.
+fn apply_edit(map_view: &mut MapView, pin_icon: &Rc<MarkerIcon>, edit: Edit) {
+    match edit {
+        Edit::Marker { lat, lon } => {
+            map_view.add_marker(lat, lon, pin_icon.clone());
+        }
+        Edit::RangeRings { lat, lon } => map_view.range_rings.add((lat, lon)),
+        Edit::ClearRangeRings => map_view.range_rings.clear(),
+        Edit::Route {
+            from: [from_lat, from_lon],
+            to: [to_lat, to_lon],
+        } => {
+            map_view.routes.add((from_lat, from_lon), (to_lat, to_lon));
+        }
+    }
+}
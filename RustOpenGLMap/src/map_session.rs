@@ -0,0 +1,158 @@
+use crate::bookmarks::{Bookmark, Bookmarks};
+use crate::layers::markers::MarkerIcon;
+use crate::map_view::MapView;
+use crate::tile_layers::{TileLayer, TileLayers};
+use crate::viewport::Viewport;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Extension of session files, which open like any other file argument.
+pub const EXTENSION: &str = "mapsession";
+
+/// Everything needed to pick up someone else's work: the view, the tile
+/// layers, the files that were open, their bookmarks and what they drew.
+/// Saved with Shift+S as TOML.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapSession {
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: u8,
+    pub source: u8,
+    #[serde(default)]
+    pub vector_tiles: bool,
+    /// Tracks, GeoJSON, CSV and TLE files, relative to the session file
+    /// when they are next to it.
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    /// Files given with --overlay, each in a colour of its own.
+    #[serde(default)]
+    pub overlay_files: Vec<PathBuf>,
+    /// (lat, lon) of dropped markers.
+    #[serde(default)]
+    pub markers: Vec<[f64; 2]>,
+    /// (lat, lon) of both ends of each route.
+    #[serde(default)]
+    pub routes: Vec<[[f64; 2]; 2]>,
+    /// (lat, lon) range rings are centred on.
+    #[serde(default)]
+    pub range_rings: Vec<[f64; 2]>,
+    /// (lat, lon) of collected points.
+    #[serde(default)]
+    pub points: Vec<[f64; 2]>,
+    #[serde(default, rename = "overlay")]
+    pub overlays: Vec<TileLayer>,
+    #[serde(default, rename = "bookmark")]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+impl MapSession {
+    pub fn capture(
+        map_view: &MapView,
+        source: u8,
+        tile_layers: &TileLayers,
+        bookmarks: &Bookmarks,
+        files: &[PathBuf],
+        overlay_files: &[PathBuf],
+    ) -> Self {
+        let (lat, lon) = map_view.viewport.center_latlon();
+        let pair = |(lat, lon): (f64, f64)| [lat, lon];
+        Self {
+            lat,
+            lon,
+            zoom: map_view.viewport.z,
+            source,
+            vector_tiles: map_view.vector.visible,
+            files: files.to_vec(),
+            overlay_files: overlay_files.to_vec(),
+            markers: map_view
+                .markers
+                .iter()
+                .map(|marker| [marker.lat, marker.lon])
+                .collect(),
+            routes: map_view
+                .routes
+                .ends()
+                .into_iter()
+                .map(|[from, to]| [pair(from), pair(to)])
+                .collect(),
+            range_rings: map_view
+                .range_rings
+                .centers()
+                .into_iter()
+                .map(pair)
+                .collect(),
+            points: map_view.collected.points().map(pair).collect(),
+            overlays: tile_layers.overlays.clone(),
+            bookmarks: bookmarks.bookmarks.clone(),
+        }
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        Viewport::centered_on(self.lat, self.lon, self.zoom.min(19))
+    }
+
+    /// Reads `path`, with the file paths in it resolved against its directory.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut session: Self = toml::from_str(&std::fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for file in session.files.iter_mut().chain(&mut session.overlay_files) {
+            *file = dir.join(&*file);
+        }
+        Ok(session)
+    }
+
+    /// Writes the session to `path`. Files under its directory are stored
+    /// relative to it, so the folder can be sent along as a whole.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.canonicalize()?,
+            _ => std::env::current_dir()?,
+        };
+        let relative = |file: &PathBuf| match file.canonicalize() {
+            Ok(full) => full
+                .strip_prefix(&dir)
+                .map(Path::to_path_buf)
+                .unwrap_or(full),
+            Err(_) => file.clone(),
+        };
+        let shared = Self {
+            files: self.files.iter().map(relative).collect(),
+            overlay_files: self.overlay_files.iter().map(relative).collect(),
+            ..self.clone()
+        };
+        std::fs::write(path, toml::to_string(&shared)?)?;
+        Ok(())
+    }
+
+    /// Puts the markers, routes, range rings and collected points back on the map.
+    pub fn restore_annotations(&self, map_view: &mut MapView, pin_icon: &Rc<MarkerIcon>) {
+        for &[lat, lon] in &self.markers {
+            map_view.add_marker(lat, lon, pin_icon.clone());
+        }
+        for &[[from_lat, from_lon], [to_lat, to_lon]] in &self.routes {
+            map_view.routes.add((from_lat, from_lon), (to_lat, to_lon));
+        }
+        for &[lat, lon] in &self.range_rings {
+            map_view.range_rings.add((lat, lon));
+        }
+        for &[lat, lon] in &self.points {
+            map_view.collected.push((lat, lon));
+        }
+        map_view.vector.visible |= self.vector_tiles;
+    }
+}
+
+/// `name` with the session extension added unless it already has it.
+pub fn file_name(name: &str) -> PathBuf {
+    let name = name.trim();
+    if Path::new(name)
+        .extension()
+        .is_some_and(|ext| ext == EXTENSION)
+    {
+        PathBuf::from(name)
+    } else {
+        PathBuf::from(format!("{}.{}", name, EXTENSION))
+    }
+}
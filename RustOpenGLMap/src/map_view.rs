@@ -0,0 +1,205 @@
+use crate::coord_format::CoordFormat;
+use crate::figure::Figure;
+use crate::labels::PlaceLabel;
+use crate::layers::collected::CollectedPoints;
+use crate::layers::coverage::CoverageLayer;
+use crate::layers::directions::DirectionsLayer;
+use crate::layers::heat::HeatLayer;
+use crate::layers::hotspots::HotspotLayer;
+use crate::layers::markers::{MarkerIcon, MarkerId, MarkerLayer};
+use crate::layers::measure::MeasureLayer;
+use crate::layers::poi::PoiLayer;
+use crate::layers::position::PositionLayer;
+use crate::layers::range_rings::{RangeRingLayer, RangeRingStyle};
+use crate::layers::recorder::TrackRecorder;
+use crate::layers::route::RouteLayer;
+use crate::layers::tile_picker::TilePicker;
+use crate::layers::{DrawContext, Layer, Shape, WorldShader, ZoomRange};
+use crate::vector_tile::VectorTileLayer;
+use crate::viewport::Viewport;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// The `[layer_zoom]` table of settings.toml: the zooms each of the
+/// built-in layers is drawn at. Tile overlays carry their own.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerZoom {
+    pub vector: ZoomRange,
+    pub coverage: ZoomRange,
+    pub heat: ZoomRange,
+    /// Every GPX, GeoJSON, CSV and satellite overlay.
+    pub overlays: ZoomRange,
+    /// Place names from the vector tiles and overlays.
+    pub labels: ZoomRange,
+}
+
+/// The viewport together with everything drawn on top of the tiles.
+pub struct MapView {
+    pub viewport: Viewport,
+    pub zoom: LayerZoom,
+    pub layers: Vec<Box<dyn Layer>>,
+    pub vector: VectorTileLayer,
+    pub heat: HeatLayer,
+    pub hotspots: HotspotLayer,
+    pub coverage: CoverageLayer,
+    pub markers: MarkerLayer,
+    pub routes: RouteLayer,
+    pub directions: DirectionsLayer,
+    pub range_rings: RangeRingLayer,
+    pub collected: CollectedPoints,
+    pub measure: MeasureLayer,
+    pub pois: PoiLayer,
+    pub recorder: TrackRecorder,
+    pub position: PositionLayer,
+    pub tile_picker: TilePicker,
+}
+
+impl MapView {
+    pub fn new(viewport: Viewport) -> Self {
+        Self {
+            viewport,
+            zoom: LayerZoom::default(),
+            layers: Vec::new(),
+            vector: VectorTileLayer::new(),
+            heat: HeatLayer::new(),
+            hotspots: HotspotLayer::new(Vec::new()),
+            coverage: CoverageLayer::new(),
+            markers: MarkerLayer::new(),
+            routes: RouteLayer::new(),
+            directions: DirectionsLayer::new(),
+            range_rings: RangeRingLayer::new(RangeRingStyle::default()),
+            collected: CollectedPoints::new(CoordFormat::default()),
+            measure: MeasureLayer::new(),
+            pois: PoiLayer::new(),
+            recorder: TrackRecorder::new(),
+            position: PositionLayer::new(),
+            tile_picker: TilePicker::new(),
+        }
+    }
+
+    /// Pins `icon` to (lat, lon); it keeps its pixel size at every zoom level.
+    pub fn add_marker(&mut self, lat: f64, lon: f64, icon: Rc<MarkerIcon>) -> MarkerId {
+        self.markers.add(lat, lon, icon)
+    }
+
+    /// The marker under window pixel (px, py), if any.
+    pub fn marker_at_pixel(&self, win: (u32, u32), px: i32, py: i32) -> Option<MarkerId> {
+        self.markers.hit_test(&self.viewport, win, px, py)
+    }
+
+    /// Names to write over the map from the vector tiles and the overlay
+    /// layers, none outside the zooms of the labels or their layer.
+    pub fn place_labels(&self) -> Vec<PlaceLabel> {
+        let z = self.viewport.z;
+        if !self.zoom.labels.contains(z) {
+            return Vec::new();
+        }
+        let mut labels = Vec::new();
+        if self.zoom.vector.contains(z) {
+            labels.extend(self.vector.labels());
+        }
+        if self.zoom.overlays.contains(z) {
+            for layer in &self.layers {
+                labels.extend(layer.labels());
+            }
+        }
+        labels.extend(self.hotspots.labels());
+        labels.extend(self.directions.labels());
+        labels
+    }
+
+    /// True if any layer moves with the simulated time.
+    pub fn animated(&self) -> bool {
+        self.layers.iter().any(|layer| layer.animated())
+    }
+
+    /// Everything over the tiles as vector shapes, in drawing order, for a
+    /// figure export. The vector tiles, coverage grid and heatmap have none
+    /// and are exported with the tiles as a picture.
+    pub fn shapes(&self) -> Vec<Shape> {
+        let mut shapes: Vec<Shape> = Vec::new();
+        if self.zoom.overlays.contains(self.viewport.z) {
+            shapes.extend(self.layers.iter().flat_map(|l| l.shapes()));
+        }
+        shapes.extend(self.hotspots.shapes());
+        shapes.extend(self.routes.shapes());
+        shapes.extend(self.directions.shapes());
+        shapes.extend(self.range_rings.shapes());
+        shapes.extend(self.collected.shapes());
+        shapes.extend(self.measure.shapes());
+        shapes.extend(self.pois.shapes());
+        shapes.extend(self.recorder.shapes());
+        shapes.extend(self.position.shapes());
+        shapes.extend(self.markers.shapes());
+        shapes
+    }
+
+    /// The view as a print figure over `backdrop`, the tiles and backdrop
+    /// layers as read from a window of `win` pixels.
+    pub fn figure(&self, backdrop: RgbaImage, win: (u32, u32), attribution: &str) -> Figure {
+        let mut figure = Figure::new(backdrop, win);
+        figure.add_graticule(&self.viewport);
+        figure.add_shapes(&self.shapes(), &self.viewport);
+        let mut tags = self.routes.labels();
+        tags.extend(self.range_rings.labels());
+        tags.extend(self.measure.labels());
+        figure.add_labels(&self.place_labels(), &tags, &self.viewport);
+        figure.add_scale_bar(&self.viewport);
+        figure.add_attribution(attribution);
+        figure
+    }
+
+    /// Draws the vector tiles, the disk cache coverage grid and the view
+    /// history heatmap: what a figure export keeps as a picture. Each is
+    /// skipped outside its zoom range.
+    pub fn draw_backdrop(&mut self, win: (u32, u32), shader: &WorldShader, time: f64) {
+        let ctx = DrawContext {
+            vp: &self.viewport,
+            win,
+            shader,
+            time,
+        };
+        let z = self.viewport.z;
+        if self.zoom.vector.contains(z) {
+            self.vector.draw(&ctx);
+        }
+        if self.zoom.coverage.contains(z) {
+            self.coverage.draw(&ctx);
+        }
+        if self.zoom.heat.contains(z) {
+            self.heat.draw(&ctx);
+        }
+    }
+
+    /// Draws the backdrop and the overlay layers at simulated unix time
+    /// `time`, then the hotspots, routes, road route, range rings,
+    /// collected points, the measurement, POIs, the recorded track and GPS
+    /// position, the tile picker grid and markers above them.
+    pub fn draw_overlays(&mut self, win: (u32, u32), shader: &WorldShader, time: f64) {
+        self.draw_backdrop(win, shader, time);
+        let ctx = DrawContext {
+            vp: &self.viewport,
+            win,
+            shader,
+            time,
+        };
+        if self.zoom.overlays.contains(self.viewport.z) {
+            for layer in self.layers.iter_mut() {
+                layer.draw(&ctx);
+            }
+        }
+        self.hotspots.draw(&ctx);
+        self.routes.draw(&ctx);
+        self.directions.draw(&ctx);
+        self.range_rings.draw(&ctx);
+        self.collected.draw(&ctx);
+        self.measure.draw(&ctx);
+        self.pois.draw(&ctx);
+        self.recorder.draw(&ctx);
+        self.position.draw(&ctx);
+        self.tile_picker.draw(&ctx);
+        self.markers.draw(&self.viewport, win);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::tile::TilePos;
+use image::{Rgba, RgbaImage};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Side of the checkerboard squares in pixels.
+const SQUARE: u32 = 32;
+const LIGHT: Rgba<u8> = Rgba([208, 208, 208, 255]);
+const DARK: Rgba<u8> = Rgba([176, 176, 176, 255]);
+/// Wait before a missing tile is tried again, doubling each time it fails
+/// again up to the maximum.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+static IMAGE: Lazy<RgbaImage> = Lazy::new(|| {
+    RgbaImage::from_fn(256, 256, |x, y| {
+        if (x / SQUARE + y / SQUARE).is_multiple_of(2) {
+            LIGHT
+        } else {
+            DARK
+        }
+    })
+});
+
+/// The checkerboard drawn where a tile couldn't be downloaded or decoded.
+pub fn image() -> &'static RgbaImage {
+    &IMAGE
+}
+
+/// Tiles the tile workers gave up downloading. They are drawn as the
+/// checkerboard instead of being requested every frame, and tried again
+/// once their wait is over.
+#[derive(Debug, Default)]
+pub struct MissingTiles {
+    /// Failures in a row, and when to try again; none while a retry is out.
+    tiles: HashMap<TilePos, (u32, Option<Instant>)>,
+}
+
+impl MissingTiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records another failure of `tile`, returning how long until it is
+    /// tried again.
+    pub fn mark(&mut self, tile: TilePos) -> Duration {
+        let (failures, due) = self.tiles.entry(tile).or_insert((0, None));
+        *failures += 1;
+        let wait = RETRY_DELAY
+            .saturating_mul(1 << (*failures - 1).min(16))
+            .min(MAX_RETRY_DELAY);
+        *due = Some(Instant::now() + wait);
+        wait
+    }
+
+    /// Takes the tiles whose wait is over. They stay known, so failing
+    /// again waits longer, until they arrive or are forgotten.
+    pub fn due(&mut self) -> Vec<TilePos> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (tile, (_, retry)) in self.tiles.iter_mut() {
+            if retry.is_some_and(|at| at <= now) {
+                *retry = None;
+                due.push(*tile);
+            }
+        }
+        due
+    }
+
+    /// Forgets `tile`, returning whether it was missing.
+    pub fn forget(&mut self, tile: &TilePos) -> bool {
+        self.tiles.remove(tile).is_some()
+    }
+
+    /// Forgets the tiles of source `m`, e.g. after its definition changed.
+    pub fn forget_source(&mut self, m: u8) {
+        self.tiles.retain(|tile, _| tile.m != m);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::geo;
+use crate::reproject::Projection;
+use crate::viewport::Viewport;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Pixels the mouse has to move with the button down before a click becomes a drag.
+const DRAG_THRESHOLD: i32 = 4;
+
+/// A release this long after the last motion lets the map stop where it is.
+const FLICK_WINDOW: Duration = Duration::from_millis(100);
+
+/// Speed in pixels per second below which a glide ends.
+const MIN_GLIDE_SPEED: f64 = 20.0;
+
+/// Tiles the map can be dragged past the top or bottom of the world.
+const MAX_OVERSCROLL: f64 = 0.5;
+/// Seconds for the map to spring most of the way back after over-scrolling.
+const SPRING_BACK: f64 = 0.08;
+
+/// How an animation spreads its progress over its duration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    Linear,
+    /// Starts fast and settles on the target.
+    EaseOut,
+    /// Speeds up, then slows down again.
+    #[default]
+    EaseInOut,
+}
+
+impl Easing {
+    /// Progress at `t` of the way through, both in 0..=1.
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4.0 * t * t * t,
+            Easing::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+        }
+    }
+}
+
+/// The `[animation]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationSettings {
+    /// Turns every animation off: jumps land at once, tiles appear without
+    /// fading and the map stops as soon as it is let go.
+    #[serde(default)]
+    pub reduced_motion: bool,
+    /// Milliseconds a jump to a bookmark, search result, go-to or home takes.
+    #[serde(default = "default_fly_to_ms")]
+    pub fly_to_ms: u64,
+    /// Curve the position and zoom of a jump follow.
+    #[serde(default)]
+    pub easing: Easing,
+    /// Milliseconds a newly arrived tile takes to fade in over its placeholder.
+    #[serde(default = "default_crossfade_ms")]
+    pub crossfade_ms: u64,
+    /// Milliseconds an overlay takes to fade in or out, or to a new opacity.
+    #[serde(default = "default_layer_fade_ms")]
+    pub layer_fade_ms: u64,
+    /// How quickly the map slows down after being flicked, per second. Higher
+    /// stops sooner; 0 turns gliding off.
+    #[serde(default = "default_kinetic_friction")]
+    pub kinetic_friction: f64,
+    /// Lets the map be dragged a little past the top or bottom of the world
+    /// and spring back, rather than stop dead at the edge.
+    #[serde(default = "default_rubber_band")]
+    pub rubber_band: bool,
+}
+
+fn default_fly_to_ms() -> u64 {
+    600
+}
+
+fn default_crossfade_ms() -> u64 {
+    250
+}
+
+fn default_layer_fade_ms() -> u64 {
+    300
+}
+
+fn default_kinetic_friction() -> f64 {
+    4.0
+}
+
+fn default_rubber_band() -> bool {
+    true
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            reduced_motion: false,
+            fly_to_ms: default_fly_to_ms(),
+            easing: Easing::default(),
+            crossfade_ms: default_crossfade_ms(),
+            layer_fade_ms: default_layer_fade_ms(),
+            kinetic_friction: default_kinetic_friction(),
+            rubber_band: default_rubber_band(),
+        }
+    }
+}
+
+impl AnimationSettings {
+    pub fn fly_to(&self) -> Duration {
+        match self.reduced_motion {
+            true => Duration::ZERO,
+            false => Duration::from_millis(self.fly_to_ms),
+        }
+    }
+
+    pub fn crossfade(&self) -> Duration {
+        match self.reduced_motion {
+            true => Duration::ZERO,
+            false => Duration::from_millis(self.crossfade_ms),
+        }
+    }
+
+    pub fn layer_fade(&self) -> Duration {
+        match self.reduced_motion {
+            true => Duration::ZERO,
+            false => Duration::from_millis(self.layer_fade_ms),
+        }
+    }
+
+    fn rubber_band(&self) -> bool {
+        self.rubber_band && !self.reduced_motion
+    }
+
+    /// Friction of a glide, `None` when the map shouldn't glide at all.
+    fn friction(&self) -> Option<f64> {
+        Some(self.kinetic_friction).filter(|f| !self.reduced_motion && *f > 0.0)
+    }
+}
+
+/// A jump from one view to another, drawn over several frames.
+struct Flight {
+    /// Centres on the view's plane, see `Viewport::center_plane`.
+    from: (f64, f64),
+    to: (f64, f64),
+    zoom: (u8, u8),
+    projection: Projection,
+    start: Instant,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Flight {
+    /// Viewport at `now`, and whether the flight has arrived.
+    fn at(&self, now: Instant) -> (Viewport, bool) {
+        let t = now.duration_since(self.start).as_secs_f64() / self.duration.as_secs_f64();
+        let e = self.easing.apply(t);
+        let lerp = |a: f64, b: f64| a + (b - a) * e;
+        let z = lerp(f64::from(self.zoom.0), f64::from(self.zoom.1)).round() as u8;
+        let n = geo::world_tiles(z);
+        let mut view = Viewport {
+            z,
+            center_x: lerp(self.from.0, self.to.0) * n - 0.5,
+            center_y: lerp(self.from.1, self.to.1) * n - 0.5,
+            projection: self.projection,
+        };
+        view.wrap();
+        (view, t >= 1.0)
+    }
+}
+
+/// The left button held down on the map, a click until it moves far enough.
+struct Drag {
+    pressed: (i32, i32),
+    last: (i32, i32),
+    last_at: Instant,
+    moved: bool,
+    /// Smoothed pixels per second, for the glide after letting go.
+    velocity: (f64, f64),
+}
+
+/// Animated view changes: jumps, dragging and the glide after a flick.
+pub struct Motion {
+    settings: AnimationSettings,
+    flight: Option<Flight>,
+    drag: Option<Drag>,
+    /// Pixels per second the map still moves at after a flick.
+    glide: Option<((f64, f64), Instant)>,
+    /// How far past the top or bottom of the world the view was left on
+    /// the last frame, and when.
+    overscroll: Option<(f64, Instant)>,
+}
+
+impl Motion {
+    pub fn new(settings: AnimationSettings) -> Self {
+        Self {
+            settings,
+            flight: None,
+            drag: None,
+            glide: None,
+            overscroll: None,
+        }
+    }
+
+    /// Moves `viewport` to `to`, at once when animations are off.
+    pub fn fly_to(&mut self, viewport: &mut Viewport, to: Viewport) {
+        self.stop();
+        // stay in a polar view when going to a place
+        let to = if to.projection == viewport.projection {
+            to
+        } else {
+            to.with_projection(viewport.projection)
+        };
+        let duration = self.settings.fly_to();
+        if duration.is_zero() || *viewport == to {
+            *viewport = to;
+            return;
+        }
+        let from = viewport.center_plane();
+        let mut target = to.center_plane();
+        if viewport.projection == Projection::WebMercator {
+            // across the antimeridian when that way is shorter
+            target.0 -= (target.0 - from.0).round();
+        }
+        self.flight = Some(Flight {
+            from,
+            to: target,
+            zoom: (viewport.z, to.z),
+            projection: viewport.projection,
+            start: Instant::now(),
+            duration,
+            easing: self.settings.easing,
+        });
+    }
+
+    /// Ends any flight or glide where the view is now, e.g. on a key press.
+    pub fn stop(&mut self) {
+        self.flight = None;
+        self.glide = None;
+    }
+
+    pub fn press(&mut self, x: i32, y: i32) {
+        self.stop();
+        self.drag = Some(Drag {
+            pressed: (x, y),
+            last: (x, y),
+            last_at: Instant::now(),
+            moved: false,
+            velocity: (0.0, 0.0),
+        });
+    }
+
+    /// True once a press has moved far enough to pan the map.
+    pub fn dragging(&self) -> bool {
+        self.drag.as_ref().is_some_and(|drag| drag.moved)
+    }
+
+    /// Forgets the press without a click or a glide, e.g. once a second
+    /// finger turns it into a pinch.
+    pub fn cancel_press(&mut self) {
+        self.drag = None;
+    }
+
+    /// Pans `viewport` along with a drag, once the mouse has left the
+    /// click threshold.
+    pub fn drag_to(&mut self, viewport: &mut Viewport, x: i32, y: i32) {
+        let Some(drag) = &mut self.drag else {
+            return;
+        };
+        if !drag.moved {
+            let (dx, dy) = (x - drag.pressed.0, y - drag.pressed.1);
+            if dx.abs().max(dy.abs()) < DRAG_THRESHOLD {
+                return;
+            }
+            drag.moved = true;
+        }
+        let now = Instant::now();
+        let (dx, dy) = (f64::from(x - drag.last.0), f64::from(y - drag.last.1));
+        let dt = now.duration_since(drag.last_at).as_secs_f64().max(0.001);
+        // weigh in the latest motion without letting one jittery event dominate
+        drag.velocity = (
+            drag.velocity.0 * 0.5 + dx / dt * 0.5,
+            drag.velocity.1 * 0.5 + dy / dt * 0.5,
+        );
+        drag.last = (x, y);
+        drag.last_at = now;
+        viewport.pan(-dx / 256.0, -dy / 256.0);
+    }
+
+    /// Ends a press. Returns where it was pressed if it was a click rather
+    /// than a drag; a drag that was still moving glides on.
+    pub fn release(&mut self) -> Option<(i32, i32)> {
+        let drag = self.drag.take()?;
+        if !drag.moved {
+            return Some(drag.pressed);
+        }
+        if self.settings.friction().is_some() && drag.last_at.elapsed() < FLICK_WINDOW {
+            self.glide = Some((drag.velocity, Instant::now()));
+        }
+        None
+    }
+
+    /// Advances a flight or glide to `now`, the timestamp of the frame
+    /// being drawn.
+    pub fn tick(&mut self, viewport: &mut Viewport, now: Instant) {
+        if let Some(flight) = &self.flight {
+            let (view, arrived) = flight.at(now);
+            *viewport = view;
+            if arrived {
+                self.flight = None;
+            }
+            return;
+        }
+        let (Some(((vx, vy), last)), Some(friction)) = (self.glide, self.settings.friction())
+        else {
+            self.glide = None;
+            return;
+        };
+        let dt = now.duration_since(last).as_secs_f64();
+        viewport.pan(-vx * dt / 256.0, -vy * dt / 256.0);
+        let decay = (-friction * dt).exp();
+        let velocity = (vx * decay, vy * decay);
+        self.glide =
+            Some((velocity, now)).filter(|_| velocity.0.hypot(velocity.1) > MIN_GLIDE_SPEED);
+    }
+
+    /// Keeps `viewport` from leaving the top or bottom of the world in a
+    /// `win` sized window, at the end of each frame. Dragged past the edge
+    /// the map follows with growing resistance and springs back once let
+    /// go; without the rubber band it stops at the edge.
+    pub fn keep_in_world(&mut self, viewport: &mut Viewport, win: (u32, u32), now: Instant) {
+        let mut over = viewport.overscroll(win);
+        if !self.settings.rubber_band() {
+            viewport.center_y -= over;
+            return;
+        }
+        // a flight lands where it was sent, then springs back
+        if self.flight.is_some() {
+            return;
+        }
+        let (last, at) = self.overscroll.unwrap_or((0.0, now));
+        if self.drag.is_some() {
+            if over.abs() > last.abs() && over * last >= 0.0 {
+                // only part of the way further out is followed
+                let room = (1.0 - last.abs() / MAX_OVERSCROLL).max(0.0);
+                let damped = last + (over - last) * room * 0.5;
+                viewport.center_y -= over - damped;
+                over = damped;
+            }
+        } else if over != 0.0 {
+            let dt = now.saturating_duration_since(at).as_secs_f64();
+            let back = if over.abs() < 0.001 {
+                over
+            } else {
+                over * (1.0 - (-dt / SPRING_BACK).exp())
+            };
+            viewport.center_y -= back;
+            over -= back;
+            // a flick into the edge stops there instead of pushing on
+            if let Some(((_, vy), _)) = &mut self.glide {
+                *vy = 0.0;
+            }
+        }
+        self.overscroll = (over != 0.0).then_some((over, now));
+    }
+}
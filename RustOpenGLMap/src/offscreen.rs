@@ -0,0 +1,246 @@
+use crate::basemap;
+use crate::error::MapError;
+use crate::opengl_helper::{self, TileCache};
+use crate::reproject::Reprojector;
+use crate::texture_upload::PixelBuffers;
+use crate::tile::{TileLoad, TilePos};
+use crate::tile_layers::TileLayer;
+use crate::tile_quad::{self, TileQuad};
+use crate::tile_requests::TileRequests;
+use crate::viewport::Viewport;
+use gl::types::GLuint;
+use image::RgbaImage;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use tracing::warn;
+
+/// Draw passes per frame. The first asks for every tile, the second for the
+/// ones that only got a placeholder; more never turn up anything new.
+const MAX_PASSES: usize = 4;
+
+/// A GL context without a visible window, for commands that render frames
+/// to images. Fields drop in order, so the framebuffer goes before the context.
+pub struct Offscreen {
+    target: Framebuffer,
+    quad: TileQuad,
+    reprojector: Reprojector,
+    _gl_context: sdl2::video::GLContext,
+    _window: sdl2::video::Window,
+    _sdl: sdl2::Sdl,
+}
+
+impl Offscreen {
+    /// Sets up GL and a `size` framebuffer to draw into.
+    pub fn new(size: (u32, u32)) -> Result<Self, String> {
+        let sdl = sdl2::init()?;
+        let video_subsystem = sdl.video()?;
+        video_subsystem.gl_attr().set_context_major_version(4);
+        video_subsystem.gl_attr().set_context_minor_version(1);
+        video_subsystem
+            .gl_attr()
+            .set_context_profile(sdl2::video::GLProfile::Core);
+        // only there for its GL context, frames go to the framebuffer
+        let window = video_subsystem
+            .window("offscreen", 64, 64)
+            .opengl()
+            .hidden()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let gl_context = window.gl_create_context()?;
+        gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as *const _);
+        let quad = TileQuad::new()?;
+        let reprojector = Reprojector::new()?;
+        let target = Framebuffer::new(size)?;
+        unsafe {
+            let [r, g, b, a] = tile_quad::CLEAR_COLOR;
+            gl::ClearColor(r, g, b, a);
+            gl::Viewport(0, 0, size.0 as i32, size.1 as i32);
+        }
+        Ok(Self {
+            target,
+            quad,
+            reprojector,
+            _gl_context: gl_context,
+            _window: window,
+            _sdl: sdl,
+        })
+    }
+
+    /// Draws `layers` as `viewport` shows them once every tile is loaded,
+    /// from disk and, with `download`, from the servers for tiles not on
+    /// disk yet. Also returns how many tiles are still missing, drawn from
+    /// a lower zoom or not at all.
+    pub fn render(
+        &self,
+        mut viewport: Viewport,
+        layers: &[TileLayer],
+        download: bool,
+    ) -> (RgbaImage, usize) {
+        // a cache per frame, so what earlier frames loaded can't show up as placeholders
+        let mut tile_cache = TileCache::new(NonZeroUsize::new(4096).unwrap());
+        // no frames to keep smooth, tiles go straight into textures
+        let mut staging = PixelBuffers::new(0);
+        let mut looked_up = HashSet::new();
+        let mut missing = HashSet::new();
+        let (requests, job_rx) = TileRequests::new();
+        for _ in 0..MAX_PASSES {
+            unsafe { gl::Clear(gl::COLOR_BUFFER_BIT) };
+            opengl_helper::draw_visible_tiles(
+                &mut viewport,
+                self.target.size.0,
+                self.target.size.1,
+                &self.quad,
+                &self.reprojector,
+                &mut tile_cache,
+                layers,
+                &requests,
+            );
+            let wanted: Vec<_> = job_rx
+                .try_iter()
+                .filter(|pos| looked_up.insert(*pos))
+                .collect();
+            if wanted.is_empty() {
+                break;
+            }
+            for pos in wanted {
+                requests.done(&pos);
+                match load_tile(pos, download) {
+                    Ok(TileLoad::Loaded {
+                        texture,
+                        source_tile,
+                    }) => {
+                        missing.remove(&pos);
+                        opengl_helper::store_tile(
+                            &mut tile_cache,
+                            &mut staging,
+                            source_tile,
+                            &texture,
+                        );
+                    }
+                    Ok(TileLoad::Loading {
+                        texture,
+                        source_tile,
+                        ..
+                    }) => {
+                        missing.insert(pos);
+                        opengl_helper::store_placeholder(
+                            &mut tile_cache,
+                            &mut staging,
+                            source_tile,
+                            &texture,
+                        );
+                    }
+                    Ok(TileLoad::Failed) => {
+                        missing.insert(pos);
+                    }
+                    Err(e) => {
+                        missing.insert(pos);
+                        warn!("Failed to load tile {:?}: {}", pos, e);
+                    }
+                }
+            }
+            opengl_helper::skip_fades(&mut tile_cache);
+        }
+        let frame = self.target.read();
+        (frame, missing.len())
+    }
+}
+
+/// Reads `pos` from disk, downloading it first if it isn't there and
+/// `download` is set. A failed download falls back to a placeholder from disk.
+fn load_tile(pos: TilePos, download: bool) -> Result<TileLoad, MapError> {
+    if download && pos.m != basemap::SOURCE_ID && !opengl_helper::get_file_path(pos).exists() {
+        match opengl_helper::fetch_tile_from_server(&pos) {
+            Ok(tile) => return Ok(tile),
+            Err(e) => warn!("Failed to download tile {:?}: {}", pos, e),
+        }
+    }
+    opengl_helper::fetch_tile(pos)
+}
+
+/// A renderbuffer frames are drawn into and read back from.
+pub struct Framebuffer {
+    fbo: GLuint,
+    color: GLuint,
+    size: (u32, u32),
+}
+
+impl Framebuffer {
+    /// A `size` target, left bound for drawing.
+    pub fn new(size: (u32, u32)) -> Result<Self, String> {
+        let mut target = Self {
+            fbo: 0,
+            color: 0,
+            size,
+        };
+        let status = unsafe {
+            gl::GenFramebuffers(1, &mut target.fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
+            gl::GenRenderbuffers(1, &mut target.color);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, target.color);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, size.0 as i32, size.1 as i32);
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::RENDERBUFFER,
+                target.color,
+            );
+            gl::CheckFramebufferStatus(gl::FRAMEBUFFER)
+        };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("Offscreen framebuffer incomplete: {:#x}", status));
+        }
+        Ok(target)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Draws into this target instead of the window until `unbind`.
+    pub fn bind(&self) {
+        unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo) };
+    }
+
+    /// Draws into the window again.
+    pub fn unbind() {
+        unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, 0) };
+    }
+
+    /// RGBA of the `w` by `h` pixels from (`x`, `y`), bottom row first as
+    /// GL counts them. The target has to be bound.
+    pub fn read_pixels(&self, x: i32, y: i32, w: u32, h: u32) -> Vec<u8> {
+        let mut pixels = vec![0u8; (w * h * 4) as usize];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                x,
+                y,
+                w as i32,
+                h as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr().cast(),
+            );
+        }
+        pixels
+    }
+
+    /// The drawn frame, top row first.
+    fn read(&self) -> RgbaImage {
+        let (w, h) = self.size;
+        let pixels = self.read_pixels(0, 0, w, h);
+        let mut image = RgbaImage::from_raw(w, h, pixels).expect("frame buffer size");
+        image::imageops::flip_vertical_in_place(&mut image);
+        image
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteRenderbuffers(1, &self.color);
+            gl::DeleteFramebuffers(1, &self.fbo);
+        }
+    }
+}
@@ -1,28 +1,42 @@
 extern crate gl;
 
+use crate::basemap;
+use crate::disk_cache::{self, DISK_CACHE, TileMeta};
+use crate::error::MapError;
 use crate::opengl_helper;
+use crate::reproject::{Projection, Reprojector};
+use crate::texture_upload::PixelBuffers;
+use crate::tile::MAX_ZOOM;
 use crate::tile::TileLoad;
 use crate::tile::TilePos;
+use crate::tile_atlas::{AtlasSlot, TileAtlas};
+use crate::tile_layers::TileLayer;
+use crate::tile_quad::{Instance, TileQuad};
+use crate::tile_requests::TileRequests;
+use crate::tile_source::{Filter, SOURCES, TileSource};
 use crate::viewport::Viewport;
-use curl::easy::Easy;
+use curl::easy::{Easy, List};
 use gl::types::*;
 use image::ImageReader;
 use image::RgbaImage;
 use lru::LruCache;
-use std::error::Error;
+use std::cell::Cell;
 // curl = "0.4"
-use std::io::Write;
+use std::io::Cursor;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use once_cell::sync::Lazy;
-use tokio;
+use tracing::{debug, debug_span, warn};
 
 macro_rules! c_str {
     ($s:expr) => {
         concat!($s, "\0").as_ptr() as *const gl::types::GLchar
     };
 }
+pub(crate) use c_str;
 
 pub static USER_AGENT: Lazy<String> = Lazy::new(|| {
     format!(
@@ -118,6 +132,8 @@ pub enum BufferType {
     Array = gl::ARRAY_BUFFER as isize,
     /// Element Array Buffers hold indexes of what vertexes to use for drawing.
     ElementArray = gl::ELEMENT_ARRAY_BUFFER as isize,
+    /// Pixel Unpack Buffers hold pixels on their way into a texture.
+    PixelUnpack = gl::PIXEL_UNPACK_BUFFER as isize,
 }
 pub struct Buffer(pub gl::types::GLuint);
 impl Buffer {
@@ -151,6 +167,37 @@ impl Buffer {
     }
 }
 
+/// Filtering and edge handling for a texture unit. A bound sampler
+/// overrides the parameters of whatever texture is on the unit, so the
+/// same tile texture can be drawn smooth or sharp.
+pub struct Sampler(pub gl::types::GLuint);
+impl Sampler {
+    pub fn new(min_filter: GLenum, mag_filter: GLenum, wrap: GLenum) -> Option<Self> {
+        let mut sampler = 0;
+        unsafe { gl::GenSamplers(1, &mut sampler) };
+        if sampler == 0 {
+            return None;
+        }
+        unsafe {
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, mag_filter as GLint);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_S, wrap as GLint);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_T, wrap as GLint);
+        }
+        Some(Self(sampler))
+    }
+
+    /// Samples texture unit `unit` with this sampler until `clear_binding`.
+    pub fn bind(&self, unit: GLuint) {
+        unsafe { gl::BindSampler(unit, self.0) };
+    }
+
+    /// Back to the parameters of the texture itself.
+    pub fn clear_binding(unit: GLuint) {
+        unsafe { gl::BindSampler(unit, 0) };
+    }
+}
+
 /// The types of shader object.
 pub enum ShaderType {
     /// Vertex shaders determine the position of geometry within the screen.
@@ -163,15 +210,22 @@ pub enum ShaderType {
 
 pub struct Shader;
 impl Shader {
-    pub fn compile_shader(shader_type: ShaderType, shader_code: &str) -> gl::types::GLenum {
+    pub fn compile_shader(
+        shader_type: ShaderType,
+        shader_code: &str,
+    ) -> Result<gl::types::GLenum, MapError> {
+        let length = GLint::try_from(shader_code.len())
+            .map_err(|_| MapError::Gl("Shader source too long".to_string()))?;
         unsafe {
             let shader = gl::CreateShader(shader_type as gl::types::GLenum);
-            assert_ne!(shader, 0);
+            if shader == 0 {
+                return Err(MapError::Gl("Couldn't allocate a shader".to_string()));
+            }
             gl::ShaderSource(
                 shader,
                 1,
                 &(shader_code.as_bytes().as_ptr().cast()),
-                &(shader_code.len().try_into().unwrap()),
+                &length,
             );
             gl::CompileShader(shader);
 
@@ -181,12 +235,15 @@ impl Shader {
                 let mut v: Vec<u8> = Vec::with_capacity(1024);
                 let mut log_len = 0_i32;
                 gl::GetShaderInfoLog(shader, 1024, &mut log_len, v.as_mut_ptr().cast());
-                v.set_len(log_len.try_into().unwrap());
-                panic!("Compile Error: {}", String::from_utf8_lossy(&v));
-            } else {
-                println!("Shader Compiled Succccesfully");
+                v.set_len(log_len.clamp(0, 1024) as usize);
+                gl::DeleteShader(shader);
+                return Err(MapError::Gl(format!(
+                    "Compile Error: {}",
+                    String::from_utf8_lossy(&v)
+                )));
             }
-            shader
+            debug!("Shader compiled");
+            Ok(shader)
         }
     }
 }
@@ -197,14 +254,27 @@ impl ShaderProgram {
         let prog = unsafe { gl::CreateProgram() };
         if prog != 0 { Some(Self(prog)) } else { None }
     }
-    pub fn from_vert_frag(vert_str: &str, frag_str: &str) -> Result<Self, String> {
+    pub fn from_vert_frag(vert_str: &str, frag_str: &str) -> Result<Self, MapError> {
         // Vertex Shader
         let shader_program =
-            Self::new().ok_or_else(|| "Couldn't allocate a program".to_string())?;
-        let vertex_shader =
-            opengl_helper::Shader::compile_shader(opengl_helper::ShaderType::Vertex, vert_str);
-        let frag_shader =
-            opengl_helper::Shader::compile_shader(opengl_helper::ShaderType::Fragment, frag_str);
+            Self::new().ok_or_else(|| MapError::Gl("Couldn't allocate a program".to_string()))?;
+        let compiled =
+            opengl_helper::Shader::compile_shader(opengl_helper::ShaderType::Vertex, vert_str)
+                .and_then(|vertex_shader| {
+                    opengl_helper::Shader::compile_shader(
+                        opengl_helper::ShaderType::Fragment,
+                        frag_str,
+                    )
+                    .map(|frag_shader| (vertex_shader, frag_shader))
+                    .inspect_err(|_| unsafe { gl::DeleteShader(vertex_shader) })
+                });
+        let (vertex_shader, frag_shader) = match compiled {
+            Ok(shaders) => shaders,
+            Err(e) => {
+                shader_program.delete();
+                return Err(e);
+            }
+        };
 
         unsafe {
             gl::AttachShader(shader_program.0, vertex_shader);
@@ -216,12 +286,14 @@ impl ShaderProgram {
                 let mut v: Vec<u8> = Vec::with_capacity(1024);
                 let mut log_len = 0_i32;
                 gl::GetProgramInfoLog(shader_program.0, 1024, &mut log_len, v.as_mut_ptr().cast());
-                v.set_len(log_len.try_into().unwrap());
+                v.set_len(log_len.clamp(0, 1024) as usize);
                 let out = format!("Program Link Error: {}", String::from_utf8_lossy(&v));
+                gl::DeleteShader(vertex_shader);
+                gl::DeleteShader(frag_shader);
                 shader_program.delete();
-                Err(out)
+                Err(MapError::Gl(out))
             } else {
-                println!("Shader's Linked Successfully");
+                debug!("Shader program linked");
                 // clean up
                 gl::DeleteShader(vertex_shader);
                 gl::DeleteShader(frag_shader);
@@ -247,7 +319,111 @@ pub fn load_image(path: &str) -> image::RgbaImage {
     image::imageops::flip_vertical_in_place(&mut rgba_image);
     rgba_image
 }
-pub fn fetch_tile_from_server(tile: &TilePos) -> Result<TileLoad, Box<dyn Error>> {
+/// When set, nothing is downloaded; disk and GPU caches keep serving tiles.
+static DOWNLOADS_PAUSED: AtomicBool = AtomicBool::new(false);
+
+pub fn downloads_paused() -> bool {
+    DOWNLOADS_PAUSED.load(Ordering::Relaxed)
+}
+
+pub fn set_downloads_paused(paused: bool) {
+    DOWNLOADS_PAUSED.store(paused, Ordering::Relaxed);
+}
+
+/// Tiles queued, waiting for a retry or downloading in the tile workers.
+static PENDING_DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn pending_downloads() -> usize {
+    PENDING_DOWNLOADS.load(Ordering::Relaxed)
+}
+
+pub fn set_pending_downloads(count: usize) {
+    PENDING_DOWNLOADS.store(count, Ordering::Relaxed);
+}
+
+/// Set while the window is on a HiDPI display, so sources with a `url_2x`
+/// serve double-resolution tiles.
+static HIDPI: AtomicBool = AtomicBool::new(false);
+
+pub fn hidpi() -> bool {
+    HIDPI.load(Ordering::Relaxed)
+}
+
+pub fn set_hidpi(hidpi: bool) {
+    HIDPI.store(hidpi, Ordering::Relaxed);
+}
+
+/// Flips the pause state and returns the new value.
+pub fn toggle_downloads_paused() -> bool {
+    let paused = !downloads_paused();
+    set_downloads_paused(paused);
+    paused
+}
+
+pub fn fetch_tile_from_server(tile: &TilePos) -> Result<TileLoad, MapError> {
+    if downloads_paused() {
+        return Err(MapError::Source("Downloads are paused".to_string()));
+    }
+    let source = SOURCES
+        .read()
+        .unwrap()
+        .get(tile.m)
+        .cloned()
+        .ok_or_else(|| MapError::Source(format!("Unknown tile source {}", tile.m)))?;
+    if !source.has_zoom(tile.z) {
+        return Err(MapError::Source(format!(
+            "{} has no tiles at zoom {}",
+            source.name, tile.z
+        )));
+    }
+
+    let disk = get_file_path(*tile);
+    // a cached copy is revalidated instead of fetched again
+    let cached = if disk.exists() {
+        TileMeta::load(&disk)
+    } else {
+        None
+    };
+    let img_rgba = match download_tile(&source, tile, cached.as_ref())? {
+        Some((img, meta)) => {
+            debug!("Downloaded");
+            img.save(&disk)?;
+            meta.save(&disk)?;
+            DISK_CACHE.lock().unwrap().record(&disk);
+            img
+        }
+        None => {
+            debug!("Unchanged on the server");
+            disk_cache::mark_fresh(&disk)?;
+            ImageReader::open(&disk)?
+                .with_guessed_format()?
+                .decode()?
+                .to_rgba8()
+        }
+    };
+    let img_rgba = prepare_texture(tile, img_rgba);
+    let tile_state = TileLoad::Loaded {
+        texture: img_rgba,
+        source_tile: *tile,
+    };
+    Ok(tile_state)
+}
+
+/// Downloads and decodes one tile of `source`, applying its orientation flags
+/// so the result is always north-up with rows in XYZ order.
+pub fn download_tile_image(source: &TileSource, tile: &TilePos) -> Result<RgbaImage, MapError> {
+    download_tile(source, tile, None)?
+        .map(|(img, _)| img)
+        .ok_or_else(|| MapError::BadTile("Server sent no tile".to_string()))
+}
+
+/// Like `download_tile_image`, but sends the validators of a `cached` copy
+/// and returns `None` when the server answers 304 Not Modified.
+pub fn download_tile(
+    source: &TileSource,
+    tile: &TilePos,
+    cached: Option<&TileMeta>,
+) -> Result<Option<(RgbaImage, TileMeta)>, MapError> {
     // Pre‑allocate ~8KiB to avoid repeated reallocations for small tiles.
     let mut data: Vec<u8> = Vec::with_capacity(8 * 1024);
 
@@ -259,165 +435,438 @@ pub fn fetch_tile_from_server(tile: &TilePos) -> Result<TileLoad, Box<dyn Error>
 
     let mut count = 0;
 
-    while response_code != 200 && (tile.m == 1 && count == 0) || (tile.m == 0 && count == 0) {
-        let url;
-        if tile.m == 0 {
-            url = format!(
-                "https://tile.openstreetmap.org/{}/{}/{}.png",
-                tile.z, tile.x, tile.y
-            );
-        } else {
-            url = format!(
-                "https://services.arcgisonline.com/ArcGIS/rest/services/World_Imagery/MapServer/tile/{}/{}/{}",
-                tile.z, tile.y, tile.x
-            );
-        }
+    let key = source.api_key().map_err(MapError::Source)?;
+    let with_key = |text: &str| match &key {
+        Some(key) => text.replace("{key}", key),
+        None => text.to_string(),
+    };
+    let mut url = with_key(&source.url_for(tile));
+    for (name, value) in &source.query {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let value = easy.url_encode(with_key(value).as_bytes());
+        url = format!("{}{}{}={}", url, separator, name, value);
+    }
+    let too_large = Cell::new(false);
+    let mut meta = TileMeta::default();
+
+    while response_code != 200 && count == 0 {
         easy.url(&url)?;
         easy.follow_location(true)?;
-        easy.useragent(&USER_AGENT)?; // <- sets the HTTP User‑Agent header
+        easy.useragent(source.user_agent())?; // <- sets the HTTP User‑Agent header
+        if let Some(referer) = &source.referer {
+            easy.referer(referer)?;
+        }
+        if let Some(proxy) = source.proxy() {
+            easy.proxy(&proxy)?;
+            if let Ok(no_proxy) = std::env::var("NO_PROXY").or_else(|_| std::env::var("no_proxy")) {
+                easy.noproxy(&no_proxy)?;
+            }
+        }
+        if let Some(ca_bundle) = &source.ca_bundle {
+            easy.cainfo(ca_bundle)?;
+        }
+        if source.insecure {
+            easy.ssl_verify_peer(false)?;
+            easy.ssl_verify_host(false)?;
+        }
+        let mut headers = List::new();
+        for (name, value) in &source.headers {
+            headers.append(&format!("{}: {}", name, with_key(value)))?;
+        }
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                headers.append(&format!("If-None-Match: {}", etag))?;
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                headers.append(&format!("If-Modified-Since: {}", last_modified))?;
+            }
+        }
+        easy.http_headers(headers)?;
         // --- Perform the HTTP GET ---------------------------------------------
         {
             let mut transfer = easy.transfer();
 
             transfer.header_function(|header| {
                 let header_str = String::from_utf8_lossy(header);
-                if header_str.to_ascii_lowercase().starts_with("content-type:") {
+                let lower = header_str.to_ascii_lowercase();
+                if lower.starts_with("content-type:") {
                     content_type = header_str["content-type:".len()..].trim().to_string();
                 }
+                if lower.starts_with("etag:") {
+                    meta.etag = Some(header_str["etag:".len()..].trim().to_string());
+                }
+                if lower.starts_with("last-modified:") {
+                    meta.last_modified =
+                        Some(header_str["last-modified:".len()..].trim().to_string());
+                }
+                if let Some(length) = lower.strip_prefix("content-length:") {
+                    let length = length.trim().parse::<usize>();
+                    if length.is_ok_and(|length| length > source.max_bytes) {
+                        too_large.set(true);
+                        return false; // abort before the body arrives
+                    }
+                }
                 true
             })?;
 
             transfer.write_function(|chunk| {
-                data.write_all(chunk).unwrap();
+                // servers may omit or lie about Content-Length
+                if data.len() + chunk.len() > source.max_bytes {
+                    too_large.set(true);
+                    return Ok(0);
+                }
+                data.extend_from_slice(chunk);
                 Ok(chunk.len())
             })?;
-            transfer.perform()?; // propagate any HTTP/network error
+            let result = transfer.perform();
+            if too_large.get() {
+                return Err(MapError::BadTile(format!(
+                    "Tile larger than {} bytes",
+                    source.max_bytes
+                )));
+            }
+            result?; // propagate any HTTP/network error
         }
         response_code = easy.response_code().unwrap_or(0);
 
+        if response_code == 304 && cached.is_some() {
+            return Ok(None);
+        }
         if response_code != 200 {
-            return Err(Box::from(format!("HTTP error: {}", response_code)));
+            return Err(MapError::Http(response_code));
         }
 
         if data.len() < 4 {
-            return Err(Box::from(
+            return Err(MapError::BadTile(
                 "Downloaded data too small to be valid image".to_string(),
             ));
         }
 
         // must be a png or jpg
         if &data[0..4] != b"\x89PNG" && &data[0..2] != b"\xFF\xD8" {
-            return Err(Box::from("Not a PNG or JPEG".to_string()));
+            return Err(MapError::BadTile("Not a PNG or JPEG".to_string()));
         }
 
         count = count + 1;
     }
-    // --- Decode PNG into RGBA8 --------------------------------------------
+    // --- Check the size before decoding, then decode into RGBA8 -------------
+    let (width, height) = ImageReader::new(Cursor::new(&data))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    let expected = source.expected_tile_size();
+    if width != expected || height != expected {
+        return Err(MapError::BadTile(format!(
+            "Tile is {}x{}, expected {}x{}",
+            width, height, expected, expected
+        )));
+    }
     let img = image::load_from_memory(&data)?;
     let mut img_rgba = img.to_rgba8();
-    let disk: PathBuf;
-    if tile.m == 0 {
-        disk = format!("Tiles/OSMTile_{}_{}_{}.png", tile.z, tile.x, tile.y).into();
-    } else {
-        disk = format!("Tiles/ESRITile_{}_{}_{}.png", tile.z, tile.x, tile.y).into();
+    if source.flip_image {
+        image::imageops::flip_vertical_in_place(&mut img_rgba);
     }
-    img_rgba.save(disk)?;
-    image::imageops::flip_vertical_in_place(&mut img_rgba); // GL wants origin‑bottom‑left
-    let tile_state = TileLoad::Loaded {
-        texture: img_rgba,
-        source_tile: *tile,
+    Ok(Some((img_rgba, meta)))
+}
+
+/// True when the cached copy of `tile` is older than its source's TTL and
+/// should be revalidated with the server.
+pub fn tile_is_stale(tile: &TilePos) -> bool {
+    let ttl = match SOURCES.read().unwrap().get(tile.m) {
+        Some(source) if source.ttl_hours > 0 => source.ttl_hours * 3600,
+        _ => return false,
     };
-    Ok(tile_state)
+    disk_cache::is_older_than(&get_file_path(*tile), Duration::from_secs(ttl))
 }
-pub fn get_file_path(loaded_tile: TilePos) -> PathBuf {
-    if loaded_tile.m == 0 {
-        format!(
-            "Tiles/OSMTile_{}_{}_{}.png",
-            loaded_tile.z, loaded_tile.x, loaded_tile.y
-        )
-        .into()
-    } else {
-        format!(
-            "Tiles/ESRITile_{}_{}_{}.png",
-            loaded_tile.z, loaded_tile.x, loaded_tile.y
-        )
-        .into()
+/// Turns a cached tile image into texture pixels: DEM sources are rendered
+/// as relief, then rows are flipped because GL wants origin‑bottom‑left.
+/// Colours are premultiplied by alpha, so filtering and mipmaps of
+/// transparent tiles (labels, seamarks) don't pull dark fringes in from
+/// the invisible black around the text.
+fn prepare_texture(tile: &TilePos, image: RgbaImage) -> RgbaImage {
+    let relief = SOURCES
+        .read()
+        .unwrap()
+        .get(tile.m)
+        .and_then(|source| source.relief.clone());
+    let mut image = match relief {
+        Some(relief) => relief.render(&image, tile),
+        None => image,
+    };
+    image::imageops::flip_vertical_in_place(&mut image);
+    for pixel in image.pixels_mut().filter(|pixel| pixel[3] < 255) {
+        let alpha = u16::from(pixel[3]);
+        for channel in &mut pixel.0[..3] {
+            *channel = ((u16::from(*channel) * alpha + 127) / 255) as u8;
+        }
     }
+    image
 }
 
-pub fn fetch_tile(tile: TilePos) -> Result<TileLoad, Box<dyn Error>> {
-    let mut disk: PathBuf;
-    let mut tile_state = TileLoad::Failed;
-    let mut first_load = true;
+pub fn get_file_path(loaded_tile: TilePos) -> PathBuf {
+    SOURCES.read().unwrap().file_path(&loaded_tile)
+}
+
+/// Loads `tile` from disk. If it isn't cached, the closest cached ancestor
+/// (at most `MAX_PLACEHOLDER_LEVELS` up) comes back as `Loading` so the GPU
+/// has something to stretch over the gap while the tile downloads.
+pub fn fetch_tile(tile: TilePos) -> Result<TileLoad, MapError> {
+    if tile.m == basemap::SOURCE_ID {
+        return Ok(TileLoad::Loaded {
+            texture: prepare_texture(&tile, basemap::render(&tile)),
+            source_tile: tile,
+        });
+    }
     let mut loaded_tile = tile;
-    while tile_state == TileLoad::Failed && (first_load || tile.z > 0) {
-        disk = get_file_path(loaded_tile);
-        // if loaded_tile.m == 0
-        // {
-        //     disk = format!("Tiles/OSMTile_{}_{}_{}.png", loaded_tile.z, loaded_tile.x, loaded_tile.y).into();
-        // } else {
-        //     disk = format!("Tiles/ESRITile_{}_{}_{}.png", loaded_tile.z, loaded_tile.x, loaded_tile.y).into();
-        // }
+    loop {
+        let disk = get_file_path(loaded_tile);
         if disk.exists() {
-            println!("load from disk");
-            let image_open = ImageReader::open(&disk)
-                .unwrap()
-                .with_guessed_format()
-                .unwrap() // detect by magic bytes
+            let _span = debug_span!("decode", z = loaded_tile.z).entered();
+            let image_open = ImageReader::open(&disk)?
+                .with_guessed_format()? // detect by magic bytes
                 .decode(); // dynamic image
             match image_open {
                 Ok(img) => {
-                    if first_load {
-                        let mut img_rgba = img.to_rgba8(); // hard‑convert to RGBA8
-                        image::imageops::flip_vertical_in_place(&mut img_rgba); // GL wants origin‑bottom‑left
-                        //let id = create_texture_from_bitmap(&img_rgba);
-                        tile_state = TileLoad::Loaded {
+                    debug!("Loaded from disk");
+                    DISK_CACHE.lock().unwrap().touch(&disk);
+                    let img_rgba = prepare_texture(&loaded_tile, img.to_rgba8()); // hard‑convert to RGBA8
+                    return Ok(if loaded_tile == tile {
+                        TileLoad::Loaded {
                             texture: img_rgba,
                             source_tile: loaded_tile,
-                        };
+                        }
                     } else {
-                        let (x, y, width, height) = loaded_tile.get_crop(&tile);
-                        let cropped = img.crop_imm(x as u32, y as u32, width as u32, height as u32);
-                        //let resized = cropped.resize_exact(256, 256, image::imageops::FilterType::Nearest);
-                        let mut img_rgba = cropped.to_rgba8(); // hard‑convert to RGBA8
-                        image::imageops::flip_vertical_in_place(&mut img_rgba); // GL wants origin‑bottom‑left
-                        //let id = create_texture_from_bitmap(&img_rgba);
-                        tile_state = TileLoad::Loading {
+                        TileLoad::Loading {
                             texture: img_rgba,
                             source_tile: loaded_tile,
                             target_tile: tile,
-                        };
-                    }
+                        }
+                    });
                 }
                 Err(e) => {
-                    eprintln!(
-                        "Failed to open tile, loading from web {}_{}_{}: {}",
+                    warn!(
+                        "Failed to open tile {}_{}_{}, loading it from the web: {}",
                         loaded_tile.z, loaded_tile.x, loaded_tile.y, e
                     );
-                    delete_file(disk);
+                    DISK_CACHE.lock().unwrap().forget(&disk);
+                    if let Err(e) = std::fs::remove_file(&disk) {
+                        warn!("Failed to remove {}: {}", disk.display(), e);
+                    }
                 }
             }
         }
-        first_load = false;
+        if loaded_tile.z == 0 || tile.z - loaded_tile.z >= MAX_PLACEHOLDER_LEVELS {
+            return Ok(TileLoad::Failed);
+        }
         loaded_tile.zoom_out();
     }
-    Ok(tile_state)
 }
 
-pub fn create_texture_from_bitmap(bitmap: &RgbaImage) -> GLuint {
-    let mut texture: GLuint = 0;
+/// Where a tile is in the atlas and when it arrived, so it can fade in.
+#[derive(Debug, Clone, Copy)]
+pub struct GpuTile {
+    pub slot: AtlasSlot,
+    pub arrived: Instant,
+}
+
+/// What the tile cache holds and did, for the statistics overlay (F3).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Tiles and placeholders drawn since `start_frame`.
+    pub drawn: usize,
+    /// Visible tiles that were on the GPU when drawn, and ones that weren't.
+    pub hits: u64,
+    pub misses: u64,
+    /// Tiles on the GPU, and the array textures holding them.
+    pub tiles: usize,
+    pub textures: usize,
+}
 
-    let (width, height) = (bitmap.width(), bitmap.height());
+/// The tiles on the GPU, least recently drawn evicted first, and the atlas
+/// their textures live in.
+pub struct TileCache {
+    tiles: LruCache<TilePos, GpuTile>,
+    atlas: TileAtlas,
+    drawn: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl TileCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            tiles: LruCache::new(capacity),
+            atlas: TileAtlas::new(),
+            drawn: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The tile at `pos`, marked as recently used.
+    pub fn get(&mut self, pos: &TilePos) -> Option<GpuTile> {
+        self.tiles.get(pos).copied()
+    }
+
+    /// `get` for a tile the view needs, counted as a cache hit or miss.
+    pub fn lookup(&mut self, pos: &TilePos) -> Option<GpuTile> {
+        let tile = self.get(pos);
+        match tile {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        tile
+    }
+
+    /// Counts `tiles` more drawn this frame.
+    pub fn count_drawn(&mut self, tiles: usize) {
+        self.drawn += tiles;
+    }
+
+    /// Starts counting the tiles drawn in a new frame.
+    pub fn start_frame(&mut self) {
+        self.drawn = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            drawn: self.drawn,
+            hits: self.hits,
+            misses: self.misses,
+            tiles: self.tiles.len(),
+            textures: self.atlas.page_count(),
+        }
+    }
+
+    pub fn peek(&self, pos: &TilePos) -> Option<GpuTile> {
+        self.tiles.peek(pos).copied()
+    }
+
+    pub fn contains(&self, pos: &TilePos) -> bool {
+        self.tiles.contains(pos)
+    }
+
+    /// The array texture to bind for drawing `tile`.
+    pub fn texture(&self, tile: &GpuTile) -> GLuint {
+        self.atlas.texture(tile.slot)
+    }
+
+    /// Drops the tile at `pos`, freeing its layer of the atlas.
+    fn remove(&mut self, pos: &TilePos) {
+        if let Some(tile) = self.tiles.pop(pos) {
+            self.atlas.release(tile.slot);
+        }
+    }
+}
+
+/// How far up the pyramid a missing tile looks for a placeholder. Beyond
+/// eight levels a parent pixel would cover the whole tile.
+pub const MAX_PLACEHOLDER_LEVELS: u8 = 8;
+
+/// Milliseconds a freshly arrived tile takes to fade in over its placeholder.
+static TILE_FADE_MS: AtomicU64 = AtomicU64::new(250);
+
+pub fn tile_fade() -> Duration {
+    Duration::from_millis(TILE_FADE_MS.load(Ordering::Relaxed))
+}
+
+/// Sets how long tiles fade in, zero for none (`animation.crossfade_ms`).
+pub fn set_tile_fade(fade: Duration) {
+    TILE_FADE_MS.store(fade.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Marks every tile as fully faded in, for frames that can't wait for it.
+pub fn skip_fades(tile_cache: &mut TileCache) {
+    for (_, tile) in tile_cache.tiles.iter_mut() {
+        if let Some(arrived) = tile.arrived.checked_sub(tile_fade()) {
+            tile.arrived = arrived;
+        }
+    }
+}
+
+/// Uploads `image` through `staging` as the texture of `pos`.
+pub fn store_tile(
+    tile_cache: &mut TileCache,
+    staging: &mut PixelBuffers,
+    pos: TilePos,
+    image: &RgbaImage,
+) {
+    match staging.create_texture(image) {
+        Ok(texture) => store_texture(tile_cache, pos, texture),
+        Err(e) => warn!("Failed to upload tile {:?}: {}", pos, e),
+    }
+}
+
+/// Like `store_tile`, for an ancestor read from disk as a placeholder: if it
+/// is already on the GPU nothing changes, so a late placeholder can't clobber it.
+pub fn store_placeholder(
+    tile_cache: &mut TileCache,
+    staging: &mut PixelBuffers,
+    pos: TilePos,
+    image: &RgbaImage,
+) {
+    if !tile_cache.contains(&pos) {
+        store_tile(tile_cache, staging, pos, image);
+    }
+}
+
+/// Moves the already uploaded `texture` into the atlas as the texture of
+/// `pos`. A tile that replaces an older copy of itself keeps the old
+/// arrival time, so it doesn't fade in again.
+pub fn store_texture(tile_cache: &mut TileCache, pos: TilePos, texture: GLuint) {
+    let arrived = tile_cache
+        .peek(&pos)
+        .map_or_else(Instant::now, |old| old.arrived);
+    let slot = tile_cache.atlas.insert(texture);
+    // push hands back either the replaced copy or the evicted LRU tile
+    if let Some((_, old)) = tile_cache.tiles.push(pos, GpuTile { slot, arrived }) {
+        tile_cache.atlas.release(old.slot);
+    }
+}
+
+/// `store_placeholder` for an already uploaded `texture`, which is deleted
+/// if `pos` is on the GPU already.
+pub fn store_placeholder_texture(tile_cache: &mut TileCache, pos: TilePos, texture: GLuint) {
+    if tile_cache.contains(&pos) {
+        unsafe { gl::DeleteTextures(1, &texture) };
+    } else {
+        store_texture(tile_cache, pos, texture);
+    }
+}
+
+pub fn create_texture_from_bitmap(bitmap: &RgbaImage) -> Result<GLuint, MapError> {
     let pixels = bitmap.as_raw(); // Gets &[u8] of pixel data
+    create_texture(
+        bitmap.width(),
+        bitmap.height(),
+        pixels.as_ptr() as *const GLvoid,
+    )
+}
+
+/// Makes a mipmapped RGBA texture of `pixels`. While a pixel unpack buffer
+/// is bound, `pixels` is an offset into it and the copy happens on the GPU.
+/// Fails, with nothing left behind, if the driver can't store it.
+pub fn create_texture(width: u32, height: u32, pixels: *const GLvoid) -> Result<GLuint, MapError> {
+    let mut texture: GLuint = 0;
 
     unsafe {
+        // errors left over from elsewhere aren't this texture's
+        while gl::GetError() != gl::NO_ERROR {}
         gl::GenTextures(1, &mut texture);
         gl::BindTexture(gl::TEXTURE_2D, texture);
         gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1); // <- makes any width safe
 
-        // Texture wrapping
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+        // Texture wrapping; repeating would blend the opposite edge into
+        // the border texels and show up as seams between tiles
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_WRAP_S,
+            gl::CLAMP_TO_EDGE as GLint,
+        );
+        gl::TexParameteri(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_WRAP_T,
+            gl::CLAMP_TO_EDGE as GLint,
+        );
 
         // Texture filtering
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
@@ -433,8 +882,16 @@ pub fn create_texture_from_bitmap(bitmap: &RgbaImage) -> GLuint {
             0,                 // border
             gl::RGBA,          // input format
             gl::UNSIGNED_BYTE, // input type
-            pixels.as_ptr() as *const GLvoid,
+            pixels,
         );
+        let error = gl::GetError();
+        if error != gl::NO_ERROR {
+            gl::DeleteTextures(1, &texture);
+            return Err(MapError::Gl(format!(
+                "Failed to create a {}x{} texture: 0x{:04X}",
+                width, height, error
+            )));
+        }
 
         gl::TexParameteri(
             gl::TEXTURE_2D,
@@ -445,7 +902,25 @@ pub fn create_texture_from_bitmap(bitmap: &RgbaImage) -> GLuint {
 
         gl::GenerateMipmap(gl::TEXTURE_2D);
     }
-    texture
+    Ok(texture)
+}
+
+/// Drops every cached texture belonging to tile source `m`.
+pub fn evict_source_textures(tile_cache: &mut TileCache, m: u8) {
+    let stale: Vec<TilePos> = tile_cache
+        .tiles
+        .iter()
+        .filter(|(pos, _)| pos.m == m)
+        .map(|(pos, _)| *pos)
+        .collect();
+    for pos in stale {
+        tile_cache.remove(&pos);
+    }
+}
+
+/// Drops the texture of `pos`, e.g. after its cached file was deleted.
+pub fn evict_tile(tile_cache: &mut TileCache, pos: TilePos) {
+    tile_cache.remove(&pos);
 }
 
 /// The polygon display modes you can set.
@@ -459,6 +934,19 @@ pub enum PolygonMode {
     Fill = gl::FILL as isize,
 }
 
+/// Limits drawing to window rect [x, y, w, h] (origin bottom-left), or lifts the limit.
+pub fn scissor(rect: Option<[i32; 4]>) {
+    unsafe {
+        match rect {
+            Some([x, y, w, h]) => {
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Scissor(x, y, w, h);
+            }
+            None => gl::Disable(gl::SCISSOR_TEST),
+        }
+    }
+}
+
 /// Sets the font and back polygon mode to the mode given.
 pub fn polygon_mode(mode: PolygonMode) {
     unsafe { gl::PolygonMode(gl::FRONT_AND_BACK, mode as GLenum) };
@@ -475,16 +963,32 @@ pub fn polygon_mode(mode: PolygonMode) {
 //     create_texture_from_bitmap(&bitmap.0)
 // }
 
+/// The part of `ancestor` covering `tile` as (u, v, size), however many
+/// levels apart they are. Texture rows are flipped, v = 1 is the top.
+pub(crate) fn crop_uv(ancestor: &TilePos, tile: &TilePos) -> (f32, f32, f32) {
+    let p = 1u32 << (tile.z - ancestor.z);
+    let size = 1.0 / p as f32;
+    let u = (tile.x % p) as f32 * size;
+    let v = 1.0 - (tile.y % p) as f32 * size - size;
+    (u, v, size)
+}
+
+/// Draws the tiles of every layer covering the window. Missing tiles are requested through
+/// `requests` and drawn as a stretched crop of their closest ancestor on the
+/// GPU; new tiles fade in over that placeholder. Sources in other
+/// projections go through `reprojector`. Returns true while a fade is
+/// still running, so the caller keeps redrawing.
 pub fn draw_visible_tiles(
     vp: &mut Viewport,
     win_w: u32,
     win_h: u32,
-    shader: u32, // program id
-    vao: u32,
-    tile_cache: &mut LruCache<TilePos, gl::types::GLuint>,
-    map: u8,
-    job_tx: Sender<TilePos>,
-) {
+    quad: &TileQuad,
+    reprojector: &Reprojector,
+    tile_cache: &mut TileCache,
+    layers: &[TileLayer],
+    requests: &TileRequests,
+) -> bool {
+    let (shader, vao) = (quad.program.0, quad.vao.0);
     unsafe {
         gl::UseProgram(shader);
     }
@@ -494,85 +998,171 @@ pub fn draw_visible_tiles(
     let scale_y = (256.0 / win_h as f64) * 2.0;
 
     let scale_loc = unsafe { gl::GetUniformLocation(shader, c_str!("u_scale")) };
-    let offset_loc = unsafe { gl::GetUniformLocation(shader, c_str!("u_offset")) };
     let texture_loc = unsafe { gl::GetUniformLocation(shader, c_str!("the_texture")) }; // Get location
+    let halo_loc = unsafe { gl::GetUniformLocation(shader, c_str!("u_halo")) };
 
     unsafe {
         gl::Uniform2f(scale_loc, scale_x as f32, scale_y as f32);
         gl::Uniform1i(texture_loc, 0); // Tell "the_texture" to use texture unit 0
+        gl::Enable(gl::BLEND);
     }
 
     // how many tiles we need around the centre
     let tiles_x = (win_w as f64 / 256.0).ceil() as i32 + 2;
     let tiles_y = (win_h as f64 / 256.0).ceil() as i32 + 2;
+    let fade_secs = tile_fade().as_secs_f32();
 
     unsafe {
         gl::ActiveTexture(gl::TEXTURE0);
         gl::BindVertexArray(vao);
     }
+    // `tile` over the slot at `offset`, showing the (u, v, size) part of it
+    let instance = |offset: (f64, f64), tile: &GpuTile, uv: (f32, f32, f32), alpha: f32| {
+        let layer = tile.slot.layer as f32;
+        [
+            offset.0 as f32,
+            offset.1 as f32,
+            uv.0,
+            uv.1,
+            uv.2,
+            alpha,
+            layer,
+        ]
+    };
+    let mut fading = false;
     let z_max = (1 << vp.z) - 1;
+    // Web Mercator repeats east and west of the world, polar planes don't
+    let wraps = vp.projection == Projection::WebMercator;
     let m_y = vp.center_y.floor() - tiles_y as f64 / 2.0;
     let ma_y = vp.center_y.ceil() + tiles_y as f64 / 2.0;
     let m_x = vp.center_x.floor() - tiles_x as f64 / 2.0;
     let ma_x = vp.center_x.ceil() + tiles_x as f64 / 2.0;
-    for ty in m_y as i32..=ma_y as i32 {
-        for tx in m_x as i32..=ma_x as i32 {
-            if tx < 0 || ty < 0 {
-                continue;
-            }
-            if tx > z_max || ty > z_max {
-                continue;
+    // bottom layer first, each one blended over what is already drawn
+    for (index, layer) in layers.iter().enumerate() {
+        let bottom = index == 0;
+        let (filter, zooms, reprojected) = {
+            let sources = SOURCES.read().unwrap();
+            let source = sources.get(layer.source);
+            (
+                source.map_or(Filter::default(), |source| source.filter),
+                source.map_or((0, MAX_ZOOM), |source| (source.min_zoom, source.max_zoom)),
+                source
+                    .filter(|source| source.projection != vp.projection)
+                    .cloned(),
+            )
+        };
+        quad.sampler(filter).bind(0);
+        layer.blend.apply();
+        if let Some(source) = reprojected {
+            fading |= reprojector.draw(vp, (win_w, win_h), tile_cache, layer, &source, requests);
+            // back to the tile quad for the next layer
+            unsafe {
+                gl::UseProgram(shader);
+                gl::BindVertexArray(vao);
             }
+            continue;
+        }
+        unsafe { gl::Uniform1f(halo_loc, layer.halo) };
+        // placeholders go under the tiles fading in over them; neither
+        // list overlaps itself, so each is drawn a page at a time
+        let mut under: Vec<(GLuint, Instance)> = Vec::new();
+        let mut over: Vec<(GLuint, Instance)> = Vec::new();
+        for ty in m_y as i32..=ma_y as i32 {
+            for tx in m_x as i32..=ma_x as i32 {
+                // the copy of the world this column is in shows the same tiles
+                let x = if wraps { tx.rem_euclid(z_max + 1) } else { tx };
+                if x < 0 || ty < 0 {
+                    continue;
+                }
+                if x > z_max || ty > z_max {
+                    continue;
+                }
+
+                let pos = TilePos {
+                    z: vp.z,
+                    x: x as u32,
+                    y: ty as u32,
+                    m: layer.source,
+                };
+                // left and top edges on whole window pixels, so neighbouring
+                // tiles share their edges exactly instead of leaving hairlines
+                let snap = |offset: f64, win: u32| {
+                    let edge = (f64::from(win) / 2.0 + (offset - 0.5) * 256.0).round();
+                    (edge - f64::from(win) / 2.0) / 256.0 + 0.5
+                };
+                let dx = snap(tx as f64 - vp.center_x, win_w);
+                let dy = snap(ty as f64 - vp.center_y, win_h);
+                // per-tile translation in NDC -----------------------
+                let ofs_x = (dx) * scale_x;
+                let ofs_y = -(dy) * scale_y; // window Y is flipped
+                let offset = (ofs_x, ofs_y);
 
-            let pos = TilePos {
-                z: vp.z,
-                x: tx as u32,
-                y: ty as u32,
-                m: map,
-            };
-            // get or download the texture for this tile -------------
-            let state = tile_cache.get_key_value(&pos);
-            match state {
-                Some(tile_state) => {
-                    let dx = tx as f64 - vp.center_x;
-                    let dy = ty as f64 - vp.center_y;
-                    // set per-tile translation in NDC -----------------------
-                    let ofs_x = (dx) * scale_x;
-                    let ofs_y = -(dy) * scale_y; // window Y is flipped
-                    unsafe {
-                        gl::Uniform2f(offset_loc, ofs_x as f32, ofs_y as f32);
-                        gl::BindTexture(gl::TEXTURE_2D, *tile_state.1);
-                        gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
+                // past the source's deepest level its deepest tile is scaled
+                // up instead of asking the server for tiles it doesn't have
+                let mut fetch = pos;
+                while fetch.z > zooms.1 {
+                    fetch.zoom_out();
+                }
+                let tile = tile_cache.lookup(&fetch);
+                let fade = tile.map_or(0.0, |tile| {
+                    if fade_secs > 0.0 {
+                        (tile.arrived.elapsed().as_secs_f32() / fade_secs).min(1.0)
+                    } else {
+                        1.0
+                    }
+                });
+                if fade < 1.0 {
+                    // placeholder underneath: the closest ancestor already on the GPU
+                    let mut ancestor = fetch;
+                    let mut covered = false;
+                    while ancestor.z > 0 && fetch.z - ancestor.z < MAX_PLACEHOLDER_LEVELS {
+                        ancestor.zoom_out();
+                        if let Some(parent) = tile_cache.get(&ancestor) {
+                            let uv = crop_uv(&ancestor, &pos);
+                            let texture = tile_cache.texture(&parent);
+                            under.push((texture, instance(offset, &parent, uv, layer.opacity)));
+                            covered = true;
+                            break;
+                        }
+                    }
+                    // nothing cached at all: the built-in basemap under the bottom layer
+                    if !covered && bottom && basemap::enabled() {
+                        let (base, uv) = basemap::covering(&pos);
+                        match tile_cache.get(&base) {
+                            Some(base) => under.push((
+                                tile_cache.texture(&base),
+                                instance(offset, &base, uv, layer.opacity),
+                            )),
+                            None => requests.request(base),
+                        }
                     }
-                    // if *tile_state.0 != pos
-                    // {
-                    //     let _ = job_tx.send(pos);
-                    //     tile_cache.pop(&pos);
-                    // }
                 }
-                None => {
-                    let _ = job_tx.send(pos);
+                match tile {
+                    Some(tile) => {
+                        over.push((
+                            tile_cache.texture(&tile),
+                            instance(offset, &tile, crop_uv(&fetch, &pos), fade * layer.opacity),
+                        ));
+                        fading |= fade < 1.0;
+                    }
+                    // above the source's levels there is nothing to fetch
+                    None if fetch.z < zooms.0 => {}
+                    None => requests.request(fetch),
                 }
             }
-
-            // match tile_state {
-            //     TileState::Loaded{texture_id, source_tile} => {
-            //         unsafe {
-            //             gl::BindTexture(gl::TEXTURE_2D, texture_id);
-            //             gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
-            //         }
-            //     },
-            //     TileState::Loading{texture_id, source_tile} => {
-            //         unsafe {
-            //             gl::BindTexture(gl::TEXTURE_2D, texture_id);
-            //             gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
-            //         }
-            //     },
-            //     TileState::Failed{} => {
-            //     }
-            // }
+        }
+        tile_cache.count_drawn(under.len() + over.len());
+        for mut list in [under, over] {
+            list.sort_by_key(|(texture, _)| *texture);
+            for page in list.chunk_by(|a, b| a.0 == b.0) {
+                let instances: Vec<Instance> = page.iter().map(|(_, instance)| *instance).collect();
+                quad.draw_instances(page[0].0, &instances);
+            }
         }
     }
+    Sampler::clear_binding(0);
+    unsafe { gl::Disable(gl::BLEND) };
+    fading
 }
 // This new function initiates an asynchronous tile load.
 // It's called by draw_visible_tiles.
@@ -598,13 +1188,3 @@ pub fn draw_visible_tiles(
 //         }
 //     });
 // }
-
-pub async fn delete_file_async(path: &str) -> Result<(), std::io::Error> {
-    tokio::fs::remove_file(path).await
-}
-
-#[tokio::main]
-async fn delete_file(s: PathBuf) {
-    // Delete file.txt asynchronously
-    delete_file_async(s.to_str().unwrap()).await.unwrap();
-}
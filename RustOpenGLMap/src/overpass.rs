@@ -0,0 +1,238 @@
+use crate::geo;
+use crate::geocoder::Throttle;
+use crate::opengl_helper::USER_AGENT;
+use crate::viewport::Viewport;
+use crate::wake;
+use curl::easy::Easy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// Public Overpass instance, see https://wiki.openstreetmap.org/wiki/Overpass_API
+pub const OVERPASS_URL: &str = "https://overpass-api.de/api/interpreter";
+/// Answers are kept here, one file per amenity and tile-aligned box.
+pub const OVERPASS_CACHE_DIR: &str = "Overpass";
+/// Elements asked for per query, keeps big boxes cheap.
+const RESULT_LIMIT: u32 = 500;
+/// Smallest zoom a query may cover, wider views would ask for whole countries.
+pub const MIN_QUERY_ZOOM: u8 = 10;
+
+/// The `[overpass]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverpassSettings {
+    /// Interpreter endpoint of an Overpass API instance.
+    #[serde(default = "default_url")]
+    pub url: String,
+}
+
+fn default_url() -> String {
+    OVERPASS_URL.to_string()
+}
+
+impl Default for OverpassSettings {
+    fn default() -> Self {
+        Self { url: default_url() }
+    }
+}
+
+/// An OSM node, way or relation with its tags, placed at its centre.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Poi {
+    pub lat: f64,
+    pub lon: f64,
+    pub tags: Vec<(String, String)>,
+}
+
+impl Poi {
+    /// The `name` tag, or the amenity if it has none.
+    pub fn title(&self) -> &str {
+        let tag = |key: &str| {
+            self.tags
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        tag("name").or_else(|| tag("amenity")).unwrap_or("?")
+    }
+}
+
+/// Tiles at zoom `z` covering a view, the unit answers are cached per.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QueryBox {
+    pub z: u8,
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl QueryBox {
+    /// Tile-aligned box around what `viewport` shows in a `win` sized window.
+    pub fn covering(viewport: &Viewport, win: (u32, u32)) -> Self {
+        let n = geo::world_tiles(viewport.z);
+        let tile = |v: f64| (v * n).floor().clamp(0.0, n - 1.0) as u32;
+        let (x0, y0) = viewport.pixel_to_world((0.0, 0.0), win);
+        let (x1, y1) = viewport.pixel_to_world((f64::from(win.0), f64::from(win.1)), win);
+        Self {
+            z: viewport.z,
+            x0: tile(x0),
+            y0: tile(y0),
+            x1: tile(x1),
+            y1: tile(y1),
+        }
+    }
+
+    /// (south, west, north, east) in degrees.
+    pub fn bounds(&self) -> [f64; 4] {
+        let n = geo::world_tiles(self.z);
+        let (north, west) = geo::unproject(f64::from(self.x0) / n, f64::from(self.y0) / n);
+        let (south, east) = geo::unproject(f64::from(self.x1 + 1) / n, f64::from(self.y1 + 1) / n);
+        [south, west, north, east]
+    }
+
+    fn cache_path(&self, amenity: &str) -> PathBuf {
+        let amenity: String = amenity
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        Path::new(OVERPASS_CACHE_DIR).join(format!(
+            "{}_{}_{}_{}_{}_{}.json",
+            amenity, self.z, self.x0, self.y0, self.x1, self.y1
+        ))
+    }
+}
+
+/// Asks the Overpass API at `url` for everything tagged `amenity=<amenity>`
+/// inside `area`.
+pub fn query(url: &str, amenity: &str, area: &QueryBox) -> Result<Vec<Poi>, Box<dyn Error>> {
+    let [south, west, north, east] = area.bounds();
+    let ql = format!(
+        "[out:json][timeout:25];nwr[\"amenity\"=\"{}\"]({},{},{},{});out center {};",
+        amenity.replace('"', ""),
+        south,
+        west,
+        north,
+        east,
+        RESULT_LIMIT
+    );
+    let mut easy = Easy::new();
+    let form = format!("data={}", easy.url_encode(ql.as_bytes()));
+    let mut body = Vec::new();
+    easy.url(url)?;
+    easy.useragent(&USER_AGENT)?;
+    easy.post(true)?;
+    easy.post_fields_copy(form.as_bytes())?;
+    easy.timeout(Duration::from_secs(30))?;
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    let code = easy.response_code()?;
+    if code != 200 {
+        return Err(Box::from(format!("Overpass answered HTTP {}", code)));
+    }
+    parse_elements(&serde_json::from_slice(&body)?)
+}
+
+/// Reads the `elements` of an `[out:json]` answer; ways and relations
+/// carry their position in `center`.
+fn parse_elements(value: &Value) -> Result<Vec<Poi>, Box<dyn Error>> {
+    let elements = value["elements"]
+        .as_array()
+        .ok_or_else(|| "expected an elements array".to_string())?;
+    Ok(elements
+        .iter()
+        .filter_map(|element| {
+            let at = if element["lat"].is_number() {
+                element
+            } else {
+                &element["center"]
+            };
+            let tags = element["tags"]
+                .as_object()
+                .map(|tags| {
+                    tags.iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(Poi {
+                lat: at["lat"].as_f64()?,
+                lon: at["lon"].as_f64()?,
+                tags,
+            })
+        })
+        .collect())
+}
+
+/// A cached answer for (`amenity`, `area`), if one was saved before.
+fn cached(amenity: &str, area: &QueryBox) -> Option<Vec<Poi>> {
+    let bytes = std::fs::read(area.cache_path(amenity)).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+fn store(amenity: &str, area: &QueryBox, pois: &[Poi]) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(OVERPASS_CACHE_DIR)?;
+    std::fs::write(area.cache_path(amenity), serde_json::to_vec(pois)?)?;
+    Ok(())
+}
+
+type Answer = (String, Result<Vec<Poi>, String>);
+
+/// Runs queries on a background thread, answering repeats from the disk
+/// cache so the public instance only sees each box once.
+pub struct OverpassClient {
+    query_tx: Sender<(String, QueryBox)>,
+    result_rx: Receiver<Answer>,
+}
+
+impl OverpassClient {
+    pub fn spawn(url: String) -> Self {
+        let (query_tx, query_rx) = channel::<(String, QueryBox)>();
+        let (result_tx, result_rx) = channel();
+        thread::spawn(move || {
+            let mut throttle = Throttle::default();
+            while let Ok((amenity, area)) = query_rx.recv() {
+                let result = match cached(&amenity, &area) {
+                    Some(pois) => Ok(pois),
+                    None => {
+                        throttle.wait();
+                        let result = query(&url, &amenity, &area).map_err(|e| e.to_string());
+                        if let Ok(pois) = &result
+                            && let Err(e) = store(&amenity, &area, pois)
+                        {
+                            warn!("Failed to cache Overpass answer: {}", e);
+                        }
+                        result
+                    }
+                };
+                if result_tx.send((amenity, result)).is_err() {
+                    break;
+                }
+                wake::wake();
+            }
+        });
+        Self {
+            query_tx,
+            result_rx,
+        }
+    }
+
+    pub fn request(&self, amenity: &str, area: QueryBox) {
+        let _ = self.query_tx.send((amenity.to_string(), area));
+    }
+
+    /// The next finished (amenity, result), if any.
+    pub fn poll(&self) -> Option<Answer> {
+        self.result_rx.try_recv().ok()
+    }
+}
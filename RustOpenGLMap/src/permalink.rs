@@ -0,0 +1,39 @@
+use crate::viewport::Viewport;
+
+/// Base of the links `format` writes, openstreetmap.org reads the same fragment.
+const PERMALINK_BASE: &str = "https://www.openstreetmap.org/";
+
+/// OSM-style link to what `viewport` shows: `…/#map=zoom/lat/lon`.
+pub fn format(viewport: &Viewport) -> String {
+    let (lat, lon) = viewport.center_latlon();
+    format!(
+        "{}#map={}/{:.5}/{:.5}",
+        PERMALINK_BASE, viewport.z, lat, lon
+    )
+}
+
+/// The view a permalink points at. Takes a whole URL or just its
+/// `map=zoom/lat/lon` part; `None` if `text` has no `map=`.
+pub fn parse(text: &str) -> Option<Result<Viewport, String>> {
+    let (_, value) = text.split_once("map=")?;
+    // anything after the three numbers, like &layers=, belongs to other tools
+    let value = value.split(['&', '#', '?']).next().unwrap_or_default();
+    let parts: Vec<&str> = value.split('/').collect();
+    let [zoom, lat, lon] = parts[..] else {
+        return Some(Err(format!("{} is not zoom/lat/lon", value)));
+    };
+    let number = |v: &str, limit: f64| {
+        v.parse::<f64>()
+            .ok()
+            .filter(|n| n.abs() <= limit)
+            .ok_or_else(|| format!("invalid permalink value {}", v))
+    };
+    Some((|| {
+        let z = number(zoom, 19.0)?.round() as u8;
+        Ok(Viewport::centered_on(
+            number(lat, 90.0)?,
+            number(lon, 180.0)?,
+            z,
+        ))
+    })())
+}
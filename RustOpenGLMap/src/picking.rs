@@ -0,0 +1,111 @@
+use crate::layers::{DrawContext, WorldShader};
+use crate::map_view::MapView;
+use crate::offscreen::Framebuffer;
+use crate::tile_quad;
+use tracing::warn;
+
+/// Pixels around the click searched for a feature, so lines a pixel wide
+/// can still be hit.
+const PICK_RADIUS: i32 = 4;
+
+/// The colour feature id `id` is drawn in for picking. Ids start at 1, 0
+/// being the cleared background; 24 bits leave room for 16 million.
+pub fn id_color(id: u32) -> [f32; 4] {
+    let [r, g, b, _] = id.to_le_bytes();
+    [r, g, b, 255].map(|c| f32::from(c) / 255.0)
+}
+
+fn pixel_id(rgba: &[u8]) -> u32 {
+    u32::from_le_bytes([rgba[0], rgba[1], rgba[2], 0])
+}
+
+/// An offscreen ID buffer for finding the overlay feature under a pixel.
+/// The overlays draw every feature flat in a colour of its own, and the
+/// colour read back under the click names it: exact for overlapping and
+/// oddly shaped features however many there are, where testing each one
+/// on the CPU gets slow.
+#[derive(Default)]
+pub struct PickBuffer {
+    /// Sized to the window's drawable; made on the first pick.
+    target: Option<Framebuffer>,
+}
+
+impl PickBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Properties of the overlay feature nearest window pixel `at`, if
+    /// there is one within a few pixels. `drawable` is the window size in
+    /// GL pixels, `win` in the window's own.
+    pub fn pick(
+        &mut self,
+        map_view: &mut MapView,
+        shader: &WorldShader,
+        win: (u32, u32),
+        drawable: (u32, u32),
+        at: (i32, i32),
+    ) -> Option<Vec<(String, String)>> {
+        if map_view.layers.is_empty() || !map_view.zoom.overlays.contains(map_view.viewport.z) {
+            return None;
+        }
+        if self.target.as_ref().map(Framebuffer::size) != Some(drawable) {
+            self.target = None;
+            self.target = Framebuffer::new(drawable)
+                .map_err(|e| warn!("Failed to make the pick buffer: {}", e))
+                .ok();
+            // made bound, or left bound if incomplete
+            Framebuffer::unbind();
+        }
+        let target = self.target.as_ref()?;
+        target.bind();
+        unsafe {
+            gl::Viewport(0, 0, drawable.0 as i32, drawable.1 as i32);
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::Disable(gl::BLEND);
+        }
+        let ctx = DrawContext {
+            vp: &map_view.viewport,
+            win,
+            shader,
+            time: 0.0,
+        };
+        // the ids each layer used, from 1
+        let mut ranges = Vec::with_capacity(map_view.layers.len());
+        let mut next = 1;
+        for layer in map_view.layers.iter_mut() {
+            let used = layer.draw_ids(&ctx, next);
+            ranges.push(next..next + used);
+            next += used;
+        }
+
+        // GL pixels, counted from the bottom
+        let scale = f64::from(drawable.0) / f64::from(win.0.max(1));
+        let x = (f64::from(at.0) * scale) as i32;
+        let y = drawable.1 as i32 - 1 - (f64::from(at.1) * scale) as i32;
+        let x0 = (x - PICK_RADIUS).clamp(0, drawable.0 as i32 - 1);
+        let y0 = (y - PICK_RADIUS).clamp(0, drawable.1 as i32 - 1);
+        let x1 = (x + PICK_RADIUS).clamp(0, drawable.0 as i32 - 1);
+        let y1 = (y + PICK_RADIUS).clamp(0, drawable.1 as i32 - 1);
+        let (w, h) = ((x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32);
+        let pixels = target.read_pixels(x0, y0, w, h);
+        Framebuffer::unbind();
+        unsafe {
+            let [r, g, b, a] = tile_quad::CLEAR_COLOR;
+            gl::ClearColor(r, g, b, a);
+        }
+
+        let id = pixels
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, rgba)| pixel_id(rgba) != 0)
+            .min_by_key(|(i, _)| {
+                let (px, py) = (x0 + (*i as u32 % w) as i32, y0 + (*i as u32 / w) as i32);
+                (px - x).pow(2) + (py - y).pow(2)
+            })
+            .map(|(_, rgba)| pixel_id(rgba))?;
+        let layer = ranges.iter().position(|ids| ids.contains(&id))?;
+        Some(map_view.layers[layer].feature((id - ranges[layer].start) as usize))
+    }
+}
@@ -0,0 +1,145 @@
+use crate::tile_layers::{Blend, TileLayer};
+use crate::tile_source::{SOURCES, TileSource};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::error::Error;
+
+/// The preset pack compiled into the binary.
+pub static PRESETS: Lazy<Presets> = Lazy::new(|| {
+    toml::from_str(include_str!("../presets.toml")).expect("bundled presets.toml is invalid")
+});
+
+/// Ready-made source definitions, referenced by their `preset` key, and
+/// suggested ways of stacking them.
+#[derive(Debug, Deserialize)]
+pub struct Presets {
+    /// Source tables without an id, each with a unique `preset` key.
+    #[serde(rename = "source")]
+    sources: Vec<toml::Table>,
+    #[serde(rename = "combo")]
+    pub combos: Vec<Combo>,
+}
+
+/// A base source and overlays that look good together.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Combo {
+    pub name: String,
+    pub description: String,
+    /// Preset key of the base map.
+    pub base: String,
+    #[serde(default)]
+    pub overlays: Vec<ComboLayer>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComboLayer {
+    pub preset: String,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend: Blend,
+    #[serde(default)]
+    pub halo: f32,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+impl Presets {
+    fn table(&self, key: &str) -> Option<&toml::Table> {
+        self.sources
+            .iter()
+            .find(|table| table.get("preset").and_then(|v| v.as_str()) == Some(key))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.sources
+            .iter()
+            .filter_map(|table| table.get("preset").and_then(|v| v.as_str()))
+    }
+
+    /// Fills a `[[source]]` table that names a `preset` with the preset's
+    /// keys; keys given in `table` win. Tables without one pass through.
+    pub fn expand(&self, table: toml::Table) -> Result<toml::Table, String> {
+        let Some(key) = table.get("preset") else {
+            return Ok(table);
+        };
+        let key = key
+            .as_str()
+            .ok_or_else(|| format!("preset must be a string, not {}", key))?;
+        let mut merged = self
+            .table(key)
+            .cloned()
+            .ok_or_else(|| format!("unknown preset {}", key))?;
+        merged.extend(table);
+        Ok(merged)
+    }
+
+    /// The preset `key` as a source with id `id`.
+    pub fn source(&self, key: &str, id: u8) -> Result<TileSource, Box<dyn Error>> {
+        let mut table = toml::Table::new();
+        table.insert("preset".to_string(), key.into());
+        table.insert("id".to_string(), i64::from(id).into());
+        Ok(toml::Value::Table(self.expand(table)?).try_into()?)
+    }
+
+    pub fn combo(&self, name: &str) -> Option<&Combo> {
+        self.combos.iter().find(|combo| combo.name == name)
+    }
+}
+
+/// Id of the source called `name` (its name or preset key), adding the
+/// preset of that name to `SOURCES` if no source matches yet.
+pub fn resolve_source(name: &str) -> Result<u8, String> {
+    let mut sources = SOURCES.write().unwrap();
+    if let Some(id) = sources.find(name) {
+        return Ok(id);
+    }
+    let id = sources
+        .free_id()
+        .ok_or("every source id is taken".to_string())?;
+    let source = PRESETS
+        .source(name, id)
+        .map_err(|e| format!("no source or preset called {}: {}", name, e))?;
+    sources.pin(source);
+    Ok(id)
+}
+
+/// Base source id and overlays of the combo called `name`.
+pub fn resolve_combo(name: &str) -> Result<(u8, Vec<TileLayer>), String> {
+    let combo = PRESETS.combo(name).ok_or_else(|| {
+        let names: Vec<&str> = PRESETS.combos.iter().map(|c| c.name.as_str()).collect();
+        format!("unknown combo {}, try one of {}", name, names.join(", "))
+    })?;
+    let base = resolve_source(&combo.base)?;
+    let overlays = combo
+        .overlays
+        .iter()
+        .map(|layer| {
+            let mut overlay = TileLayer::base(resolve_source(&layer.preset)?);
+            overlay.opacity = layer.opacity;
+            overlay.blend = layer.blend;
+            overlay.halo = layer.halo;
+            Ok(overlay)
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((base, overlays))
+}
+
+/// `RustOpenGLMap presets`: lists the bundled sources and combos.
+pub fn run_presets_command() -> Result<(), String> {
+    println!("Sources (use with `preset = \"<key>\"` in sources.toml or --source <key>):");
+    for key in PRESETS.keys() {
+        let source = PRESETS.source(key, 0).map_err(|e| e.to_string())?;
+        println!(
+            "  {:<16} {:<28} z{}-{}",
+            key, source.name, source.min_zoom, source.max_zoom
+        );
+    }
+    println!("Combos (use with --combo <name> or `combo = \"<name>\"` in settings.toml):");
+    for combo in &PRESETS.combos {
+        println!("  {:<16} {}", combo.name, combo.description);
+    }
+    Ok(())
+}
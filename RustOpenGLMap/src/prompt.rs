@@ -0,0 +1,118 @@
+use crate::bookmarks::BookmarkPicker;
+use crate::permalink;
+use crate::reproject::Projection;
+use crate::search::SearchBox;
+use crate::viewport::Viewport;
+
+/// A one-line text input shown over the map. Keys go to it, not to the
+/// shortcuts, while it is open.
+#[derive(Debug, Clone)]
+pub enum Prompt {
+    Search(SearchBox),
+    GoTo(LineInput),
+    /// Amenity to look up with Overpass.
+    Amenity(LineInput),
+    /// Name for a bookmark of the current view.
+    Bookmark(LineInput),
+    /// The bookmark list.
+    Bookmarks(BookmarkPicker),
+    /// Places recently gone to.
+    Recent(BookmarkPicker),
+    /// File to save a `.mapsession` to.
+    SaveSession(LineInput),
+}
+
+impl Prompt {
+    pub fn type_text(&mut self, text: &str) {
+        match self {
+            Self::Search(search) => search.type_text(text),
+            Self::Bookmarks(picker) | Self::Recent(picker) => picker.type_text(text),
+            Self::GoTo(line)
+            | Self::Amenity(line)
+            | Self::Bookmark(line)
+            | Self::SaveSession(line) => {
+                line.text.push_str(text);
+                line.error = None;
+            }
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        match self {
+            Self::Search(search) => search.backspace(),
+            Self::Bookmarks(picker) | Self::Recent(picker) => picker.backspace(),
+            Self::GoTo(line)
+            | Self::Amenity(line)
+            | Self::Bookmark(line)
+            | Self::SaveSession(line) => {
+                line.text.pop();
+                line.error = None;
+            }
+        }
+    }
+}
+
+/// Text typed into a plain prompt and why it was last rejected.
+#[derive(Debug, Clone, Default)]
+pub struct LineInput {
+    pub text: String,
+    pub error: Option<String>,
+}
+
+/// Where `text` points: `lat, lon`, `lat, lon zoom`, a `z/x/y` tile
+/// address or a `#map=zoom/lat/lon` permalink. Without a zoom the current zoom `z` is kept.
+pub fn parse_go_to(text: &str, z: u8) -> Result<Viewport, String> {
+    let text = text.trim();
+    if let Some(view) = permalink::parse(text) {
+        return view;
+    }
+    if text.contains('/') {
+        let parts: Vec<&str> = text.split('/').map(str::trim).collect();
+        let [tile_z, x, y] = parts[..] else {
+            return Err("a tile is z/x/y".to_string());
+        };
+        let tile_z: u8 = tile_z
+            .parse()
+            .ok()
+            .filter(|z| *z <= 19)
+            .ok_or_else(|| format!("invalid zoom {}", tile_z))?;
+        let n = 1u32 << tile_z;
+        let index = |v: &str| {
+            v.parse::<u32>()
+                .ok()
+                .filter(|i| *i < n)
+                .ok_or_else(|| format!("{} is not a tile index at zoom {}", v, tile_z))
+        };
+        return Ok(Viewport {
+            z: tile_z,
+            center_x: f64::from(index(x)?),
+            center_y: f64::from(index(y)?),
+            projection: Projection::WebMercator,
+        });
+    }
+    let parts: Vec<&str> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .collect();
+    let (lat, lon, zoom) = match parts[..] {
+        [lat, lon] => (lat, lon, None),
+        [lat, lon, zoom] => (lat, lon, Some(zoom)),
+        _ => return Err("type lat, lon [zoom] or z/x/y".to_string()),
+    };
+    let degrees = |v: &str, limit: f64| {
+        v.parse::<f64>()
+            .ok()
+            .filter(|d| d.abs() <= limit)
+            .ok_or_else(|| format!("invalid coordinate {}", v))
+    };
+    let (lat, lon) = (degrees(lat, 90.0)?, degrees(lon, 180.0)?);
+    let z = match zoom {
+        Some(zoom) => zoom
+            .parse::<u8>()
+            .ok()
+            .filter(|z| *z <= 19)
+            .ok_or_else(|| format!("invalid zoom {}", zoom))?,
+        None => z,
+    };
+    Ok(Viewport::centered_on(lat, lon, z))
+}
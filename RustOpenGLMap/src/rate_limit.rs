@@ -0,0 +1,67 @@
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static BUCKETS: Lazy<Mutex<HashMap<u8, TokenBucket>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The `rate_limit` table of a source: how many tile requests it is sent
+/// per second, with `burst` allowed back to back after a quiet spell.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub per_second: f64,
+    /// Requests saved up while idle, at least 1. Defaults to a second's worth.
+    #[serde(default)]
+    pub burst: Option<f64>,
+}
+
+impl RateLimit {
+    fn capacity(&self) -> f64 {
+        self.burst.unwrap_or(self.per_second).max(1.0)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.capacity(),
+            refilled: now,
+        }
+    }
+
+    /// Takes a token if there is one, else returns how long until there is.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.capacity());
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.per_second,
+            ))
+        }
+    }
+}
+
+/// Takes one request from source `m`'s allowance. Returns how long to hold
+/// the request instead if the source is at its `limit`; sources without a
+/// limit (or a zero one) are never held.
+pub fn source_throttled_for(m: u8, limit: Option<RateLimit>) -> Option<Duration> {
+    let limit = limit.filter(|limit| limit.per_second > 0.0)?;
+    let now = Instant::now();
+    BUCKETS
+        .lock()
+        .unwrap()
+        .entry(m)
+        .or_insert_with(|| TokenBucket::new(&limit, now))
+        .take(&limit, now)
+}
@@ -0,0 +1,64 @@
+use crate::bookmarks::Bookmark;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+pub const RECENT_PATH: &str = "recent.toml";
+/// Most searches and places kept; older ones drop off the end.
+const MAX_ENTRIES: usize = 30;
+/// Places closer than this in degrees at the same zoom count as one visit.
+const SAME_PLACE: f64 = 1e-4;
+
+/// Queries sent to the geocoder and places gone to, newest first, kept in
+/// `recent.toml` so they outlive the session. Places are bookmarks in all but
+/// name, so pinning one just copies it into `bookmarks.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recent {
+    #[serde(default)]
+    pub searches: Vec<String>,
+    #[serde(default, rename = "place")]
+    pub places: Vec<Bookmark>,
+}
+
+impl Recent {
+    /// Reads `path` if it exists, otherwise starts empty.
+    pub fn load_or_default(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::default();
+        }
+        std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                warn!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Puts `query` first, dropping an earlier copy that differs only in case.
+    pub fn add_search(&mut self, query: &str) {
+        let query = query.trim();
+        self.searches
+            .retain(|earlier| !earlier.eq_ignore_ascii_case(query));
+        self.searches.insert(0, query.to_string());
+        self.searches.truncate(MAX_ENTRIES);
+    }
+
+    /// Puts `place` first, dropping an earlier visit to the same view.
+    pub fn add_place(&mut self, place: Bookmark) {
+        self.places.retain(|earlier| {
+            earlier.zoom != place.zoom
+                || (earlier.lat - place.lat).abs() > SAME_PLACE
+                || (earlier.lon - place.lon).abs() > SAME_PLACE
+        });
+        self.places.insert(0, place);
+        self.places.truncate(MAX_ENTRIES);
+    }
+}
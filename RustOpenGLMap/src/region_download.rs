@@ -0,0 +1,234 @@
+use crate::disk_cache::{DISK_CACHE, TileMeta, tile_dir};
+use crate::error::MapError;
+use crate::geo;
+use crate::opengl_helper::download_tile;
+use crate::tile::TilePos;
+use crate::tile_source::SOURCES;
+use clap::Args;
+use image::RgbaImage;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Tiles requested per second unless `--rate` says otherwise. Public tile
+/// servers (OSM in particular) ban clients that bulk download much faster.
+const DEFAULT_RATE: f64 = 2.0;
+
+/// A lat/lon bounding box and the zoom levels to cache inside it.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+    pub min_z: u8,
+    pub max_z: u8,
+}
+
+impl Region {
+    /// Inclusive tile ranges (x0, y0, x1, y1) covering the box at zoom `z`.
+    fn tile_range(&self, z: u8) -> (u32, u32, u32, u32) {
+        let n = geo::world_tiles(z);
+        let last = n as u32 - 1;
+        // north-west corner has the smallest tile y
+        let (x0, y0) = geo::project(self.max_lat, self.min_lon);
+        let (x1, y1) = geo::project(self.min_lat, self.max_lon);
+        let clamp = |v: f64| ((v * n) as u32).min(last);
+        (clamp(x0), clamp(y0), clamp(x1), clamp(y1))
+    }
+
+    pub fn tile_count(&self) -> u64 {
+        (self.min_z..=self.max_z)
+            .map(|z| {
+                let (x0, y0, x1, y1) = self.tile_range(z);
+                (x1 - x0 + 1) as u64 * (y1 - y0 + 1) as u64
+            })
+            .sum()
+    }
+
+    /// Every tile of source `m` in the region, lowest zoom first.
+    pub fn tiles(&self, m: u8) -> impl Iterator<Item = TilePos> + '_ {
+        (self.min_z..=self.max_z).flat_map(move |z| {
+            let (x0, y0, x1, y1) = self.tile_range(z);
+            (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| TilePos { z, x, y, m }))
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DownloadStats {
+    pub downloaded: u64,
+    pub skipped_existing: u64,
+    pub failed: u64,
+}
+
+/// Downloads every tile of `region` from source `m` into the tile directory, at most
+/// `rate` requests per second or the source's `rate_limit`. Tiles already on disk
+/// are not fetched again.
+pub fn download_region(region: &Region, m: u8, rate: f64) -> Result<DownloadStats, Box<dyn Error>> {
+    let source = SOURCES
+        .read()
+        .unwrap()
+        .get(m)
+        .cloned()
+        .ok_or_else(|| format!("Unknown tile source {}", m))?;
+    std::fs::create_dir_all(tile_dir())?;
+
+    // the source's own limit wins over a faster --rate
+    let rate = source
+        .rate_limit
+        .filter(|limit| limit.per_second > 0.0)
+        .map_or(rate, |limit| rate.min(limit.per_second));
+    let total = region.tile_count();
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    let mut stats = DownloadStats::default();
+    let mut next_request = Instant::now();
+    for (i, tile) in region.tiles(m).enumerate() {
+        let target = source.file_path(&tile);
+        if target.exists() {
+            stats.skipped_existing += 1;
+        } else {
+            let now = Instant::now();
+            if next_request > now {
+                std::thread::sleep(next_request - now);
+            }
+            next_request = Instant::now() + interval;
+            match download_tile(&source, &tile, None)
+                .and_then(|downloaded| save(downloaded, &target))
+            {
+                Ok(()) => {
+                    DISK_CACHE.lock().unwrap().record(&target);
+                    stats.downloaded += 1;
+                }
+                Err(e) => {
+                    // end the progress line first
+                    println!();
+                    warn!("Failed to download {:?}: {}", tile, e);
+                    stats.failed += 1;
+                }
+            }
+        }
+        print!(
+            "\r{}/{} tiles (z={}), {} downloaded, {} cached, {} failed",
+            i + 1,
+            total,
+            tile.z,
+            stats.downloaded,
+            stats.skipped_existing,
+            stats.failed
+        );
+        std::io::stdout().flush().ok();
+    }
+    println!();
+    Ok(stats)
+}
+
+fn save(tile: Option<(RgbaImage, TileMeta)>, target: &Path) -> Result<(), MapError> {
+    let (img, meta) = tile.ok_or_else(|| MapError::BadTile("Server sent no tile".to_string()))?;
+    img.save(target)?;
+    Ok(meta.save(target)?)
+}
+
+/// `RustOpenGLMap download <min_lat> <min_lon> <max_lat> <max_lon> <min_z> <max_z> [--source <id>] [--rate <tiles/s>] [--yes]`
+/// `RustOpenGLMap download <min_lat> <min_lon> <max_lat> <max_lon> <min_z> <max_z>`
+#[derive(Debug, Args)]
+pub struct DownloadArgs {
+    #[arg(allow_negative_numbers = true)]
+    min_lat: f64,
+    #[arg(allow_negative_numbers = true)]
+    min_lon: f64,
+    #[arg(allow_negative_numbers = true)]
+    max_lat: f64,
+    #[arg(allow_negative_numbers = true)]
+    max_lon: f64,
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=19))]
+    min_z: u8,
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=19))]
+    max_z: u8,
+    /// Source id to download from.
+    #[arg(long, default_value_t = 0)]
+    source: u8,
+    /// Tiles requested per second.
+    #[arg(long, default_value_t = DEFAULT_RATE, value_parser = positive_rate)]
+    rate: f64,
+    /// Download even when the region has more than 10000 tiles.
+    #[arg(long)]
+    yes: bool,
+}
+
+fn positive_rate(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|r: &f64| *r > 0.0)
+        .ok_or_else(|| "needs a positive number of tiles per second".to_string())
+}
+
+/// The box between two corners given in either order, and the zoom levels
+/// from `zooms` source `m` has tiles for.
+pub fn source_region(corners: [f64; 4], zooms: (u8, u8), m: u8) -> Result<Region, String> {
+    let [lat_a, lon_a, lat_b, lon_b] = corners;
+    let (source_min_z, source_max_z) = SOURCES
+        .read()
+        .unwrap()
+        .get(m)
+        .map(|s| (s.min_zoom, s.max_zoom))
+        .ok_or_else(|| format!("Unknown tile source {}", m))?;
+    let region = Region {
+        min_lat: lat_a.min(lat_b),
+        min_lon: lon_a.min(lon_b),
+        max_lat: lat_a.max(lat_b),
+        max_lon: lon_a.max(lon_b),
+        // the server has nothing outside its zoom range
+        min_z: zooms.0.min(zooms.1).max(source_min_z),
+        max_z: zooms.0.max(zooms.1).min(source_max_z),
+    };
+    if region.min_z > region.max_z {
+        return Err(format!(
+            "Source {} only has zoom levels {} to {}",
+            m, source_min_z, source_max_z
+        ));
+    }
+    Ok(region)
+}
+
+pub fn run_download_command(args: DownloadArgs) -> Result<(), String> {
+    let DownloadArgs { source, rate, .. } = args;
+    let region = source_region(
+        [args.min_lat, args.min_lon, args.max_lat, args.max_lon],
+        (args.min_z, args.max_z),
+        source,
+    )?;
+
+    let total = region.tile_count();
+    println!(
+        "{} tiles from z={} to z={}, about {} at {} tiles/s",
+        total,
+        region.min_z,
+        region.max_z,
+        format_duration(total as f64 / rate),
+        rate
+    );
+    if total > 10_000 && !args.yes {
+        return Err("That's a lot of tiles, pass --yes if you really want them".to_string());
+    }
+
+    let stats = download_region(&region, source, rate).map_err(|e| e.to_string())?;
+    println!(
+        "Downloaded {} tiles, {} already cached, {} failed",
+        stats.downloaded, stats.skipped_existing, stats.failed
+    );
+    Ok(())
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    format!(
+        "{}h{:02}m{:02}s",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,215 @@
+use crate::geo;
+use crate::tile::TilePos;
+use crate::tile_source::TileSource;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+/// Earth's circumference at the equator, in metres.
+const EQUATOR: f64 = 40_075_016.686;
+
+/// How a DEM tile packs the elevation into its RGB channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DemEncoding {
+    /// `r * 256 + g + b / 256 - 32768`, used by the AWS/Mapzen terrain tiles.
+    #[default]
+    Terrarium,
+    /// `(r * 65536 + g * 256 + b) / 10 - 10000`, Mapbox Terrain-RGB.
+    Mapbox,
+}
+
+impl DemEncoding {
+    pub fn elevation(self, pixel: &Rgba<u8>) -> f32 {
+        let [r, g, b, _] = pixel.0.map(f32::from);
+        match self {
+            Self::Terrarium => r * 256.0 + g + b / 256.0 - 32768.0,
+            Self::Mapbox => (r * 65536.0 + g * 256.0 + b) / 10.0 - 10000.0,
+        }
+    }
+}
+
+/// Colour of the ramp at one elevation, the ramp interpolates between stops.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ColorStop {
+    /// Metres above sea level.
+    pub elevation: f32,
+    pub color: [u8; 3],
+}
+
+/// Turns a source's DEM tiles into a shaded relief: a hypsometric tint by
+/// elevation, darkened by a hillshade lit from `azimuth`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Relief {
+    #[serde(default)]
+    pub encoding: DemEncoding,
+    /// Stops sorted by elevation. Elevations outside take the closest end.
+    #[serde(default = "default_ramp")]
+    pub ramp: Vec<ColorStop>,
+    /// 0 is a flat tint, 1 lets the shade go all the way to black.
+    #[serde(default = "default_hillshade")]
+    pub hillshade: f32,
+    /// Direction the light comes from, degrees clockwise from north.
+    #[serde(default = "default_azimuth")]
+    pub azimuth: f32,
+    /// Height of the light above the horizon, in degrees.
+    #[serde(default = "default_altitude")]
+    pub altitude: f32,
+    /// Vertical exaggeration of the hillshade, the tint always uses real heights.
+    #[serde(default = "default_exaggeration")]
+    pub exaggeration: f32,
+}
+
+fn default_ramp() -> Vec<ColorStop> {
+    let stop = |elevation, color| ColorStop { elevation, color };
+    vec![
+        stop(-6000.0, [40, 80, 150]),
+        stop(-200.0, [110, 160, 215]),
+        stop(0.0, [170, 205, 235]),
+        stop(0.1, [100, 150, 95]),
+        stop(300.0, [160, 195, 120]),
+        stop(800.0, [225, 215, 150]),
+        stop(1500.0, [205, 165, 110]),
+        stop(2500.0, [160, 120, 90]),
+        stop(3500.0, [170, 160, 155]),
+        stop(4500.0, [250, 250, 250]),
+    ]
+}
+
+fn default_hillshade() -> f32 {
+    0.6
+}
+
+fn default_azimuth() -> f32 {
+    315.0
+}
+
+fn default_altitude() -> f32 {
+    45.0
+}
+
+fn default_exaggeration() -> f32 {
+    1.0
+}
+
+impl Relief {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ramp.is_empty() {
+            return Err("relief ramp has no stops".to_string());
+        }
+        if self
+            .ramp
+            .windows(2)
+            .any(|pair| pair[0].elevation > pair[1].elevation)
+        {
+            return Err("relief ramp stops must be sorted by elevation".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.hillshade) {
+            return Err(format!(
+                "hillshade must be within 0..1, not {}",
+                self.hillshade
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn tint(&self, elevation: f32) -> [f32; 3] {
+        let above = self
+            .ramp
+            .iter()
+            .position(|stop| stop.elevation >= elevation);
+        let (low, high) = match above {
+            Some(0) => (self.ramp[0], self.ramp[0]),
+            Some(i) => (self.ramp[i - 1], self.ramp[i]),
+            None => {
+                let last = self.ramp[self.ramp.len() - 1];
+                (last, last)
+            }
+        };
+        let span = high.elevation - low.elevation;
+        let t = if span > 0.0 {
+            (elevation - low.elevation) / span
+        } else {
+            0.0
+        };
+        [0, 1, 2].map(|c| {
+            f32::from(low.color[c]) + (f32::from(high.color[c]) - f32::from(low.color[c])) * t
+        })
+    }
+
+    /// Renders the DEM image of `tile` as relief. Slopes at the tile edge
+    /// repeat the outermost pixels, which is invisible at normal zooms.
+    pub fn render(&self, dem: &RgbaImage, tile: &TilePos) -> RgbaImage {
+        let (w, h) = dem.dimensions();
+        let heights: Vec<f32> = dem.pixels().map(|p| self.encoding.elevation(p)).collect();
+        let at = |x: i64, y: i64| {
+            let x = x.clamp(0, w as i64 - 1) as usize;
+            let y = y.clamp(0, h as i64 - 1) as usize;
+            heights[y * w as usize + x]
+        };
+
+        // ground size of a pixel, taken at the tile's middle latitude
+        let (lat, _) = geo::unproject(0.0, (tile.y as f64 + 0.5) / geo::world_tiles(tile.z));
+        let pixel_size =
+            (EQUATOR * lat.to_radians().cos() / geo::world_tiles(tile.z) / w as f64) as f32;
+
+        let zenith = (90.0 - self.altitude).to_radians();
+        // the light direction in maths convention, counter-clockwise from east
+        let azimuth = (450.0 - self.azimuth).rem_euclid(360.0).to_radians();
+
+        RgbaImage::from_fn(w, h, |x, y| {
+            let (x, y) = (x as i64, y as i64);
+            let scale = self.exaggeration / (2.0 * pixel_size);
+            let dz_dx = (at(x + 1, y) - at(x - 1, y)) * scale;
+            // rows grow southwards
+            let dz_dy = (at(x, y + 1) - at(x, y - 1)) * scale;
+            let slope = dz_dx.hypot(dz_dy).atan();
+            let aspect = dz_dy.atan2(-dz_dx);
+            let shade = (zenith.cos() * slope.cos()
+                + zenith.sin() * slope.sin() * (azimuth - aspect).cos())
+            .max(0.0);
+            let light = 1.0 - self.hillshade + self.hillshade * shade;
+            let [r, g, b] = self
+                .tint(at(x, y))
+                .map(|c| (c * light).clamp(0.0, 255.0) as u8);
+            Rgba([r, g, b, 255])
+        })
+    }
+}
+
+/// Reads heights out of cached DEM tiles for the cursor readout. Keeps the
+/// last tile it decoded, as the cursor mostly moves within one.
+#[derive(Debug, Default)]
+pub struct ElevationProbe {
+    last: Option<(TilePos, RgbaImage)>,
+}
+
+impl ElevationProbe {
+    /// Metres above sea level at normalised Web Mercator `world`, from the
+    /// cached tile of relief `source` at zoom `z` (or its deepest zoom).
+    /// `None` if `source` isn't a relief source or the tile isn't cached.
+    pub fn sample(&mut self, source: &TileSource, world: (f64, f64), z: u8) -> Option<f32> {
+        let encoding = source.relief.as_ref()?.encoding;
+        let z = z.clamp(source.min_zoom, source.max_zoom);
+        let n = geo::world_tiles(z);
+        let (x, y) = (world.0.rem_euclid(1.0) * n, world.1 * n);
+        if !(0.0..n).contains(&y) {
+            return None;
+        }
+        let tile = TilePos {
+            z,
+            x: x as u32,
+            y: y as u32,
+            m: source.id,
+        };
+        if self.last.as_ref().is_none_or(|(last, _)| *last != tile) {
+            let dem = image::open(source.file_path(&tile)).ok()?.to_rgba8();
+            self.last = Some((tile, dem));
+        }
+        let (_, dem) = self.last.as_ref()?;
+        let (w, h) = dem.dimensions();
+        let px = ((x.fract() * f64::from(w)) as u32).min(w - 1);
+        let py = ((y.fract() * f64::from(h)) as u32).min(h - 1);
+        Some(encoding.elevation(dem.get_pixel(px, py)))
+    }
+}
@@ -0,0 +1,504 @@
+use crate::geo;
+use crate::layers::GeometryBuffer;
+use crate::opengl_helper::{self, GpuTile, ShaderProgram, TileCache, c_str};
+use crate::tile::TilePos;
+use crate::tile_layers::TileLayer;
+use crate::tile_quad;
+use crate::tile_requests::TileRequests;
+use crate::tile_source::TileSource;
+use crate::viewport::Viewport;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_E: f64 = 0.081_819_190_842_622;
+
+/// Half the side of the square the polar grids split into tiles, as NASA
+/// GIBS and GDAL-made polar pyramids use.
+const POLAR_EXTENT_M: f64 = 4_194_304.0;
+
+/// Ellipsoidal polar stereographic for shaders, as in Snyder's "Map
+/// Projections: A Working Manual". Keep it in step with `polar` and
+/// `polar_inverse`; `with_polar_glsl` pastes it in.
+const POLAR_GLSL: &str = r#"
+const float PI = 3.14159265358979;
+const float A = 6378137.0;
+const float E = 0.0818191908426;
+const float EXTENT = 4194304.0;
+
+float t(float phi) {
+    float s = E * sin(phi);
+    return tan(PI / 4.0 - phi / 2.0) / pow((1.0 - s) / (1.0 + s), E / 2.0);
+}
+
+float m(float phi) {
+    float s = E * sin(phi);
+    return cos(phi) / sqrt(1.0 - s * s);
+}
+
+// x right and y down from the top-left corner of the grid
+vec2 polar(float lat, float lon, float lat_c, float lon_0, float hemisphere) {
+    float phi = radians(lat * hemisphere);
+    float phi_c = radians(lat_c * hemisphere);
+    float lambda = radians(lon - lon_0);
+    float rho = A * m(phi_c) * t(phi) / t(phi_c);
+    vec2 xy = vec2(rho * sin(lambda), -hemisphere * rho * cos(lambda));
+    return vec2(xy.x + EXTENT, EXTENT - xy.y);
+}
+
+// (lat, lon) of a point given like `polar` returns it
+vec2 polar_inverse(vec2 grid, float lat_c, float lon_0, float hemisphere) {
+    vec2 xy = vec2(grid.x - EXTENT, EXTENT - grid.y);
+    float phi_c = radians(lat_c * hemisphere);
+    float ts = length(xy) * t(phi_c) / (A * m(phi_c));
+    float phi = PI / 2.0 - 2.0 * atan(ts);
+    for (int i = 0; i < 5; i++) {
+        float s = E * sin(phi);
+        phi = PI / 2.0 - 2.0 * atan(ts * pow((1.0 - s) / (1.0 + s), E / 2.0));
+    }
+    float lambda = atan(xy.x, -hemisphere * xy.y);
+    return vec2(degrees(phi) * hemisphere, degrees(lambda) + lon_0);
+}
+"#;
+
+/// `shader` with the polar stereographic functions in place of its
+/// `#pragma polar` line.
+pub fn with_polar_glsl(shader: &str) -> String {
+    shader.replace("#pragma polar", POLAR_GLSL)
+}
+
+/// Spacing in window pixels of the points sampled to find the tiles in view.
+const SAMPLE_PX: u32 = 32;
+
+/// More tiles than this in view means the zoom is too deep for the window.
+const MAX_TILES: usize = 96;
+
+const VERT_SHADER: &str = r#"#version 410 core
+layout (location = 0) in vec2 pos;  // window pixels
+
+uniform vec2 u_win;
+
+out vec2 v_pixel;
+
+void main() {
+    gl_Position = vec4(pos.x / u_win.x * 2.0 - 1.0, 1.0 - pos.y / u_win.y * 2.0, 0.0, 1.0);
+    v_pixel = pos;
+}
+"#;
+
+// Every fragment goes from the view's plane back to latitude and longitude
+// and on into the source's projection, then samples the tile there. Keep
+// the projections in step with `Projection::forward` and `inverse`.
+const FRAG_SHADER: &str = r#"#version 410 core
+#pragma polar
+
+uniform sampler2DArray the_texture;
+uniform float u_layer;        // of the tile in the atlas
+uniform vec2 u_win;
+uniform int u_view;           // projection of the view, numbered like u_projection
+uniform vec2 u_center;        // view centre in its plane, in zoom 0 tiles
+uniform float u_px_per_unit;  // window pixels per zoom 0 tile
+uniform int u_projection;     // 0 EPSG:3857, 1 EPSG:4326, 2 EPSG:3413, 3 EPSG:3031
+uniform vec4 u_tile;          // left, top, width, height in source units
+uniform vec2 u_uv_offset;     // part of the texture to show, for ancestor placeholders
+uniform float u_uv_scale;
+uniform float u_alpha;
+#pragma halo
+
+in vec2 v_pixel;
+out vec4 final_color;
+
+void main() {
+    vec2 plane = u_center + (v_pixel - u_win / 2.0) / u_px_per_unit;
+    vec2 latlon;
+    if (u_view == 1) {
+        latlon = vec2(90.0 - plane.y * 180.0, plane.x * 180.0 - 180.0);
+    } else if (u_view == 2) {
+        latlon = polar_inverse(plane * 2.0 * EXTENT, 70.0, -45.0, 1.0);
+    } else if (u_view == 3) {
+        latlon = polar_inverse(plane * 2.0 * EXTENT, -71.0, 0.0, -1.0);
+    } else {
+        latlon = vec2(degrees(atan(sinh(PI * (1.0 - 2.0 * plane.y)))), plane.x * 360.0 - 180.0);
+    }
+    float lat = latlon.x;
+    float lon = mod(latlon.y + 180.0, 360.0) - 180.0;
+    vec2 source;
+    if (u_projection == 0) {
+        // nothing of Mercator beyond its square world
+        if (abs(lat) > 85.0511) {
+            discard;
+        }
+        float phi = radians(lat);
+        source = vec2((lon + 180.0) / 360.0, (1.0 - log(tan(phi) + 1.0 / cos(phi)) / PI) / 2.0);
+    } else if (u_projection == 1) {
+        source = vec2(lon + 180.0, 90.0 - lat);
+    } else if (u_projection == 2) {
+        source = polar(lat, lon, 70.0, -45.0, 1.0);
+    } else if (u_projection == 3) {
+        source = polar(lat, lon, -71.0, 0.0, -1.0);
+    }
+    vec2 uv = (source - u_tile.xy) / u_tile.zw;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThanEqual(uv, vec2(1.0)))) {
+        discard;
+    }
+    // texture rows are flipped, v = 1 is the top of the image; stay half a
+    // texel inside the tile so filtering doesn't seam it against the next
+    vec2 half_texel = 0.5 / vec2(textureSize(the_texture, 0).xy);
+    vec2 lo = u_uv_offset + half_texel;
+    vec2 hi = u_uv_offset + u_uv_scale - half_texel;
+    vec2 texel = clamp(u_uv_offset + vec2(uv.x, 1.0 - uv.y) * u_uv_scale, lo, hi);
+    vec4 color = texture(the_texture, vec3(texel, u_layer));
+    // premultiplied, like the tiles drawn straight
+    final_color = with_halo(color, texel, u_layer, lo, hi) * u_alpha;
+}
+"#;
+
+/// How a source's tiles are laid out on the globe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Projection {
+    /// The usual slippy map tiles, drawn as they are.
+    #[default]
+    #[serde(rename = "EPSG:3857")]
+    WebMercator,
+    /// Plate carrée: two tiles side by side at zoom 0, each 180° square.
+    #[serde(rename = "EPSG:4326")]
+    Geographic,
+    /// NSIDC sea ice polar stereographic north, the Arctic.
+    #[serde(rename = "EPSG:3413")]
+    ArcticStereographic,
+    /// Antarctic polar stereographic.
+    #[serde(rename = "EPSG:3031")]
+    AntarcticStereographic,
+}
+
+impl Projection {
+    pub fn shader_id(self) -> i32 {
+        match self {
+            Self::WebMercator => 0,
+            Self::Geographic => 1,
+            Self::ArcticStereographic => 2,
+            Self::AntarcticStereographic => 3,
+        }
+    }
+
+    /// Side of a tile at zoom `z`, in degrees or metres.
+    fn tile_span(self, z: u8) -> f64 {
+        let tiles = f64::from(1u32 << z);
+        match self {
+            Self::WebMercator => 1.0 / tiles,
+            Self::Geographic => 180.0 / tiles,
+            Self::ArcticStereographic | Self::AntarcticStereographic => {
+                2.0 * POLAR_EXTENT_M / tiles
+            }
+        }
+    }
+
+    /// Columns and rows of tiles at zoom `z`.
+    fn grid(self, z: u8) -> (u32, u32) {
+        match self {
+            Self::Geographic => (2u32 << z, 1u32 << z),
+            _ => (1u32 << z, 1u32 << z),
+        }
+    }
+
+    /// Where (lat, lon) falls in the source, measured right and down from
+    /// the top-left corner of its tile grid.
+    fn forward(self, lat: f64, lon: f64) -> (f64, f64) {
+        match self {
+            Self::WebMercator => geo::project(lat, lon),
+            Self::Geographic => (lon + 180.0, 90.0 - lat),
+            Self::ArcticStereographic => polar(lat, lon, 70.0, -45.0, 1.0),
+            Self::AntarcticStereographic => polar(lat, lon, -71.0, 0.0, -1.0),
+        }
+    }
+
+    /// Inverse of `forward`, returns (lat, lon) in degrees.
+    fn inverse(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            Self::WebMercator => geo::unproject(x, y),
+            Self::Geographic => (90.0 - y, x - 180.0),
+            Self::ArcticStereographic => polar_inverse((x, y), 70.0, -45.0, 1.0),
+            Self::AntarcticStereographic => polar_inverse((x, y), -71.0, 0.0, -1.0),
+        }
+    }
+
+    /// Where (lat, lon) falls on the plane a view in this projection is
+    /// laid out on, in zoom 0 tiles from its top-left corner. For Web
+    /// Mercator that is normalised Web Mercator itself.
+    pub fn to_plane(self, lat: f64, lon: f64) -> (f64, f64) {
+        let (x, y) = self.forward(lat, lon);
+        let span = self.tile_span(0);
+        (x / span, y / span)
+    }
+
+    /// Inverse of `to_plane`.
+    pub fn plane_to_latlon(self, x: f64, y: f64) -> (f64, f64) {
+        let span = self.tile_span(0);
+        self.inverse(x * span, y * span)
+    }
+
+    pub fn is_polar(self) -> bool {
+        matches!(
+            self,
+            Self::ArcticStereographic | Self::AntarcticStereographic
+        )
+    }
+
+    /// The polar projection for the hemisphere `lat` is in.
+    pub fn polar_for(lat: f64) -> Self {
+        if lat < 0.0 {
+            Self::AntarcticStereographic
+        } else {
+            Self::ArcticStereographic
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::WebMercator => "Web Mercator",
+            Self::Geographic => "plate carrée",
+            Self::ArcticStereographic => "Arctic polar stereographic",
+            Self::AntarcticStereographic => "Antarctic polar stereographic",
+        }
+    }
+}
+
+fn polar(lat: f64, lon: f64, lat_c: f64, lon_0: f64, hemisphere: f64) -> (f64, f64) {
+    let t = |phi: f64| {
+        let s = WGS84_E * phi.sin();
+        (std::f64::consts::FRAC_PI_4 - phi / 2.0).tan()
+            / ((1.0 - s) / (1.0 + s)).powf(WGS84_E / 2.0)
+    };
+    let m = |phi: f64| {
+        let s = WGS84_E * phi.sin();
+        phi.cos() / (1.0 - s * s).sqrt()
+    };
+    let phi = (lat * hemisphere).to_radians();
+    let phi_c = (lat_c * hemisphere).to_radians();
+    let lambda = (lon - lon_0).to_radians();
+    let rho = WGS84_A * m(phi_c) * t(phi) / t(phi_c);
+    let (x, y) = (rho * lambda.sin(), -hemisphere * rho * lambda.cos());
+    (x + POLAR_EXTENT_M, POLAR_EXTENT_M - y)
+}
+
+/// Inverse of `polar`, iterating Snyder's (7-9) for the latitude.
+fn polar_inverse(grid: (f64, f64), lat_c: f64, lon_0: f64, hemisphere: f64) -> (f64, f64) {
+    let t = |phi: f64| {
+        let s = WGS84_E * phi.sin();
+        (std::f64::consts::FRAC_PI_4 - phi / 2.0).tan()
+            / ((1.0 - s) / (1.0 + s)).powf(WGS84_E / 2.0)
+    };
+    let m = |phi: f64| {
+        let s = WGS84_E * phi.sin();
+        phi.cos() / (1.0 - s * s).sqrt()
+    };
+    let (x, y) = (grid.0 - POLAR_EXTENT_M, POLAR_EXTENT_M - grid.1);
+    let phi_c = (lat_c * hemisphere).to_radians();
+    let ts = x.hypot(y) * t(phi_c) / (WGS84_A * m(phi_c));
+    let mut phi = std::f64::consts::FRAC_PI_2 - 2.0 * ts.atan();
+    for _ in 0..5 {
+        let s = WGS84_E * phi.sin();
+        phi = std::f64::consts::FRAC_PI_2
+            - 2.0 * (ts * ((1.0 - s) / (1.0 + s)).powf(WGS84_E / 2.0)).atan();
+    }
+    let lambda = x.atan2(-hemisphere * y);
+    let lon = (lambda.to_degrees() + lon_0 + 540.0).rem_euclid(360.0) - 180.0;
+    (phi.to_degrees() * hemisphere, lon)
+}
+
+/// Draws sources that aren't in the view's projection, warping their tiles into the
+/// view in a fragment shader.
+pub struct Reprojector {
+    program: ShaderProgram,
+    quads: GeometryBuffer,
+}
+
+impl Reprojector {
+    pub fn new() -> Result<Self, String> {
+        Ok(Self {
+            program: ShaderProgram::from_vert_frag(
+                VERT_SHADER,
+                &tile_quad::with_halo_glsl(&with_polar_glsl(FRAG_SHADER)),
+            )?,
+            quads: GeometryBuffer::new()?,
+        })
+    }
+
+    /// The tiles of `source` under the view at the zoom closest to the
+    /// window's resolution, and the window pixels each one covers.
+    fn tiles_in_view(
+        source: &TileSource,
+        vp: &Viewport,
+        win: (u32, u32),
+    ) -> (u8, BTreeMap<(u32, u32), [f64; 4]>) {
+        let projection = source.projection;
+        // source units one window pixel spans at the centre
+        let (cx, cy) = (f64::from(win.0) / 2.0, f64::from(win.1) / 2.0);
+        let here = |x: f64, y: f64| {
+            let (lat, lon) = vp.pixel_to_latlon((x, y), win);
+            projection.forward(lat, lon)
+        };
+        let (a, b) = (here(cx, cy), here(cx + 1.0, cy));
+        let per_pixel = (b.0 - a.0).hypot(b.1 - a.1).max(f64::MIN_POSITIVE);
+        let ideal = (projection.tile_span(0) / (f64::from(source.tile_size) * per_pixel)).log2();
+        let mut z = (ideal.round().max(0.0) as u8).clamp(source.min_zoom, source.max_zoom);
+        loop {
+            let span = projection.tile_span(z);
+            let (columns, rows) = projection.grid(z);
+            let step = f64::from(SAMPLE_PX);
+            let mut tiles: BTreeMap<(u32, u32), [f64; 4]> = BTreeMap::new();
+            for py in (0..=win.1 + SAMPLE_PX).step_by(SAMPLE_PX as usize) {
+                for px in (0..=win.0 + SAMPLE_PX).step_by(SAMPLE_PX as usize) {
+                    let (x, y) = (f64::from(px), f64::from(py));
+                    let (sx, sy) = here(x, y);
+                    let (tx, ty) = ((sx / span).floor(), (sy / span).floor());
+                    if tx < 0.0 || ty < 0.0 || tx >= f64::from(columns) || ty >= f64::from(rows) {
+                        continue;
+                    }
+                    let rect = [x - step, y - step, x + step, y + step];
+                    tiles
+                        .entry((tx as u32, ty as u32))
+                        .and_modify(|r| {
+                            *r = [
+                                r[0].min(rect[0]),
+                                r[1].min(rect[1]),
+                                r[2].max(rect[2]),
+                                r[3].max(rect[3]),
+                            ]
+                        })
+                        .or_insert(rect);
+                }
+            }
+            if tiles.len() <= MAX_TILES || z <= source.min_zoom {
+                return (z, tiles);
+            }
+            z -= 1;
+        }
+    }
+
+    /// Draws `layer` of `source` over the view, requesting missing tiles
+    /// through `requests` and showing their closest ancestors meanwhile, like
+    /// `draw_visible_tiles`. Returns true while a tile is still fading in.
+    pub fn draw(
+        &self,
+        vp: &Viewport,
+        win: (u32, u32),
+        tile_cache: &mut TileCache,
+        layer: &TileLayer,
+        source: &TileSource,
+        requests: &TileRequests,
+    ) -> bool {
+        let (z, tiles) = Self::tiles_in_view(source, vp, win);
+        let span = source.projection.tile_span(z) as f32;
+        let (w, h) = (win.0 as f32, win.1 as f32);
+        let mut vertices = Vec::new();
+        for rect in tiles.values() {
+            let [left, top, right, bottom] = [
+                (rect[0] as f32).max(0.0),
+                (rect[1] as f32).max(0.0),
+                (rect[2] as f32).min(w),
+                (rect[3] as f32).min(h),
+            ];
+            vertices.extend([
+                [left, top],
+                [right, top],
+                [right, bottom],
+                [left, top],
+                [right, bottom],
+                [left, bottom],
+            ]);
+        }
+        self.quads.upload(&vertices);
+
+        let program = self.program.0;
+        let location =
+            |name: *const gl::types::GLchar| unsafe { gl::GetUniformLocation(program, name) };
+        let (center_x, center_y) = vp.center_plane();
+        unsafe {
+            gl::UseProgram(program);
+            gl::Uniform1i(location(c_str!("the_texture")), 0);
+            gl::Uniform1f(location(c_str!("u_halo")), layer.halo);
+            gl::Uniform1i(location(c_str!("u_view")), vp.projection.shader_id());
+            gl::Uniform2f(location(c_str!("u_win")), w, h);
+            gl::Uniform2f(
+                location(c_str!("u_center")),
+                center_x as f32,
+                center_y as f32,
+            );
+            gl::Uniform1f(
+                location(c_str!("u_px_per_unit")),
+                (geo::world_tiles(vp.z) * 256.0) as f32,
+            );
+            gl::Uniform1i(
+                location(c_str!("u_projection")),
+                source.projection.shader_id(),
+            );
+        }
+        let tile_loc = location(c_str!("u_tile"));
+        let uv_offset_loc = location(c_str!("u_uv_offset"));
+        let uv_scale_loc = location(c_str!("u_uv_scale"));
+        let alpha_loc = location(c_str!("u_alpha"));
+        let layer_loc = location(c_str!("u_layer"));
+        let draw = |quad: usize,
+                    texture: gl::types::GLuint,
+                    tile: &GpuTile,
+                    uv: (f32, f32, f32),
+                    alpha: f32| {
+            unsafe {
+                gl::Uniform2f(uv_offset_loc, uv.0, uv.1);
+                gl::Uniform1f(uv_scale_loc, uv.2);
+                gl::Uniform1f(alpha_loc, alpha);
+                gl::Uniform1f(layer_loc, tile.slot.layer as f32);
+                gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+            }
+            self.quads.draw(gl::TRIANGLES, quad * 6, 6);
+        };
+
+        let fade_secs = opengl_helper::tile_fade().as_secs_f32();
+        let mut fading = false;
+        for (quad, &(x, y)) in tiles.keys().enumerate() {
+            let pos = TilePos {
+                z,
+                x,
+                y,
+                m: layer.source,
+            };
+            unsafe { gl::Uniform4f(tile_loc, x as f32 * span, y as f32 * span, span, span) };
+            let tile = tile_cache.lookup(&pos);
+            let fade = tile.map_or(0.0, |tile| {
+                if fade_secs > 0.0 {
+                    (tile.arrived.elapsed().as_secs_f32() / fade_secs).min(1.0)
+                } else {
+                    1.0
+                }
+            });
+            if fade < 1.0 {
+                let mut ancestor = pos;
+                while ancestor.z > 0 && pos.z - ancestor.z < opengl_helper::MAX_PLACEHOLDER_LEVELS {
+                    ancestor.zoom_out();
+                    if let Some(parent) = tile_cache.get(&ancestor) {
+                        draw(
+                            quad,
+                            tile_cache.texture(&parent),
+                            &parent,
+                            opengl_helper::crop_uv(&ancestor, &pos),
+                            layer.opacity,
+                        );
+                        tile_cache.count_drawn(1);
+                        break;
+                    }
+                }
+            }
+            match tile {
+                Some(tile) => {
+                    let texture = tile_cache.texture(&tile);
+                    draw(quad, texture, &tile, (0.0, 0.0, 1.0), fade * layer.opacity);
+                    tile_cache.count_drawn(1);
+                    fading |= fade < 1.0;
+                }
+                None => requests.request(pos),
+            }
+        }
+        fading
+    }
+}
@@ -0,0 +1,282 @@
+use crate::error::MapError;
+use crate::tile::TilePos;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Consecutive 5xx/429 answers after which a source is left alone for a while.
+const BREAKER_THRESHOLD: u32 = 5;
+const BREAKER_COOL_DOWN: Duration = Duration::from_secs(30);
+const BREAKER_MAX_COOL_DOWN: Duration = Duration::from_secs(300);
+
+/// Downloads in a row, over all sources, that couldn't reach a server
+/// before the map goes offline and only serves the disk cache.
+const OFFLINE_THRESHOLD: u32 = 6;
+/// While offline one download is let through this often to check the
+/// network, backing off to the maximum as the probes keep failing.
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_PROBE_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Most retries waiting at once, older ones are dropped like the download queue does.
+const MAX_PENDING: usize = 64;
+
+static BREAKERS: Lazy<Mutex<HashMap<u8, CircuitBreaker>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static CONNECTIVITY: Lazy<Mutex<Connectivity>> = Lazy::new(|| Mutex::new(Connectivity::new()));
+/// Mirrors `CONNECTIVITY` for the main loop, which checks it every frame.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// How often and how patiently a failed download is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before attempt `attempt` (1 = first retry): doubling per attempt,
+    /// then jittered so a burst of failures doesn't retry in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        backoff.mul_f64(0.5 + fastrand::f64() * 0.5)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Requests go through.
+    Closed,
+    /// The source failed too often; nothing is sent until the instant passes.
+    Open(Instant),
+    /// Cool-down over, one trial request decides whether to close or reopen.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    state: BreakerState,
+    failures: u32,
+    cool_down: Duration,
+}
+
+impl CircuitBreaker {
+    fn new() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            failures: 0,
+            cool_down: BREAKER_COOL_DOWN,
+        }
+    }
+
+    fn blocked_for(&mut self, now: Instant) -> Option<Duration> {
+        match self.state {
+            BreakerState::Open(until) if until > now => Some(until - now),
+            BreakerState::Open(_) => {
+                self.state = BreakerState::HalfOpen;
+                None
+            }
+            BreakerState::Closed | BreakerState::HalfOpen => None,
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.state = BreakerState::Closed;
+        self.failures = 0;
+        self.cool_down = BREAKER_COOL_DOWN;
+    }
+
+    fn record_server_error(&mut self, now: Instant) {
+        self.failures += 1;
+        if self.state == BreakerState::HalfOpen {
+            // still broken, wait longer before the next trial
+            self.cool_down = (self.cool_down * 2).min(BREAKER_MAX_COOL_DOWN);
+            self.state = BreakerState::Open(now + self.cool_down);
+        } else if self.failures >= BREAKER_THRESHOLD {
+            self.state = BreakerState::Open(now + self.cool_down);
+        }
+    }
+}
+
+/// Whether the network as a whole is reachable, as opposed to the
+/// per-source breakers which track servers that answer with errors.
+#[derive(Debug)]
+struct Connectivity {
+    failures: u32,
+    /// Set while offline: when the next probe may go out and the wait after it.
+    probe: Option<(Instant, Duration)>,
+}
+
+impl Connectivity {
+    fn new() -> Self {
+        Self {
+            failures: 0,
+            probe: None,
+        }
+    }
+
+    fn blocked_for(&mut self, now: Instant) -> Option<Duration> {
+        match self.probe {
+            None => None,
+            Some((due, interval)) if due <= now => {
+                // let this one through, hold the rest until it comes back
+                self.probe = Some((now + interval, (interval * 2).min(MAX_PROBE_INTERVAL)));
+                None
+            }
+            Some((due, _)) => Some(due - now),
+        }
+    }
+
+    fn record_success(&mut self) {
+        if self.probe.take().is_some() {
+            info!("Network is back, downloading again");
+        }
+        self.failures = 0;
+    }
+
+    fn record_network_error(&mut self, now: Instant) {
+        self.failures += 1;
+        if self.probe.is_none() && self.failures >= OFFLINE_THRESHOLD {
+            warn!(
+                "{} downloads in a row failed to connect, going offline",
+                self.failures
+            );
+            self.probe = Some((now + PROBE_INTERVAL, PROBE_INTERVAL * 2));
+        }
+    }
+}
+
+/// True while the network is considered down and only cached tiles are shown.
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// How long downloads are held because the network is down, `None` if a
+/// request may go out. Offline, one request per probe interval is let
+/// through to find out whether the network is back.
+pub fn network_blocked_for() -> Option<Duration> {
+    CONNECTIVITY.lock().unwrap().blocked_for(Instant::now())
+}
+
+/// How long source `m` is still being left alone, `None` if requests may go out.
+pub fn source_blocked_for(m: u8) -> Option<Duration> {
+    BREAKERS
+        .lock()
+        .unwrap()
+        .get_mut(&m)
+        .and_then(|breaker| breaker.blocked_for(Instant::now()))
+}
+
+/// Feeds the outcome of a download of source `m` into its circuit breaker.
+pub fn record_result<T>(m: u8, result: &Result<T, MapError>) {
+    {
+        let mut connectivity = CONNECTIVITY.lock().unwrap();
+        match result {
+            // any answer, even an error page, means the network is up
+            Ok(_) => connectivity.record_success(),
+            Err(MapError::Network(_)) => connectivity.record_network_error(Instant::now()),
+            Err(MapError::Http(_)) => connectivity.record_success(),
+            Err(_) => {}
+        }
+        OFFLINE.store(connectivity.probe.is_some(), Ordering::Relaxed);
+    }
+    let mut breakers = BREAKERS.lock().unwrap();
+    let breaker = breakers.entry(m).or_insert_with(CircuitBreaker::new);
+    match result {
+        Ok(_) => breaker.record_success(),
+        Err(e) if e.is_server_error() => {
+            breaker.record_server_error(Instant::now());
+            if let BreakerState::Open(until) = breaker.state {
+                warn!(
+                    "Source {} keeps failing, pausing it for {}s",
+                    m,
+                    until.saturating_duration_since(Instant::now()).as_secs()
+                );
+            }
+        }
+        // a 404 or a broken image says nothing about the server's health
+        Err(_) => {}
+    }
+}
+
+/// Network failures and server errors may go away; anything else won't.
+fn is_retryable(e: &MapError) -> bool {
+    matches!(e, MapError::Network(_)) || e.is_server_error()
+}
+
+/// Failed downloads waiting for their next attempt.
+pub struct RetryQueue {
+    policy: RetryPolicy,
+    /// (due, tile, attempts made so far)
+    pending: Vec<(Instant, TilePos, u32)>,
+    /// Tiles pushed out by newer ones since `take_dropped`.
+    dropped: Vec<TilePos>,
+}
+
+impl RetryQueue {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            pending: Vec::new(),
+            dropped: Vec::new(),
+        }
+    }
+
+    /// Number of tiles waiting for another attempt.
+    pub fn waiting(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// A tile whose wait is over, with the number of attempts it already had.
+    pub fn pop_due(&mut self) -> Option<(TilePos, u32)> {
+        let now = Instant::now();
+        let i = self.pending.iter().position(|(due, _, _)| *due <= now)?;
+        let (_, tile, attempts) = self.pending.remove(i);
+        Some((tile, attempts))
+    }
+
+    /// Schedules another attempt after `error`. Returns false once the tile is given up on.
+    pub fn retry(&mut self, tile: TilePos, attempts: u32, error: &MapError) -> bool {
+        if !is_retryable(error) || attempts >= self.policy.max_attempts {
+            return false;
+        }
+        self.push(Instant::now() + self.policy.delay(attempts), tile, attempts);
+        true
+    }
+
+    /// Tiles dropped to make room since the last call, which won't be tried again.
+    pub fn take_dropped(&mut self) -> Vec<TilePos> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Puts a tile aside without counting an attempt, e.g. while its source is blocked.
+    pub fn defer(&mut self, tile: TilePos, attempts: u32, wait: Duration) {
+        self.push(Instant::now() + wait, tile, attempts);
+    }
+
+    fn push(&mut self, due: Instant, tile: TilePos, attempts: u32) {
+        if self.pending.len() >= MAX_PENDING {
+            let (_, tile, _) = self.pending.remove(0);
+            self.dropped.push(tile);
+        }
+        self.pending.push((due, tile, attempts));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::geocoder::Throttle;
+use crate::opengl_helper::USER_AGENT;
+use crate::wake;
+use curl::easy::{Easy, List};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::error::Error;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+/// Public OSRM demo server, see https://project-osrm.org/
+pub const OSRM_URL: &str = "https://router.project-osrm.org";
+
+/// Routing service the `[routing]` table talks to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    /// `/route/v1/<profile>/...`, geometry as a polyline of 5 decimals.
+    #[default]
+    Osrm,
+    /// `POST /route` with a JSON request, geometry as a polyline of 6 decimals.
+    Valhalla,
+}
+
+impl Engine {
+    fn default_profile(self) -> &'static str {
+        match self {
+            Engine::Osrm => "driving",
+            Engine::Valhalla => "auto",
+        }
+    }
+}
+
+/// The `[routing]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoutingSettings {
+    /// Right clicks ask the routing service for a road route instead of
+    /// drawing a great circle.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub engine: Engine,
+    /// Base URL of the service, without the `/route` part.
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// OSRM profile or Valhalla costing, e.g. `bicycle`; empty for driving.
+    #[serde(default)]
+    pub profile: String,
+}
+
+fn default_url() -> String {
+    OSRM_URL.to_string()
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            engine: Engine::default(),
+            url: default_url(),
+            profile: String::new(),
+        }
+    }
+}
+
+impl RoutingSettings {
+    fn profile(&self) -> &str {
+        if self.profile.trim().is_empty() {
+            self.engine.default_profile()
+        } else {
+            self.profile.trim()
+        }
+    }
+}
+
+/// A manoeuvre along a route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turn {
+    /// (lat, lon) where it happens.
+    pub at: (f64, f64),
+    pub instruction: String,
+}
+
+/// A route along the roads as the routing service found it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directions {
+    /// (lat, lon) of the whole route.
+    pub points: Vec<(f64, f64)>,
+    pub turns: Vec<Turn>,
+    /// Metres.
+    pub distance: f64,
+    /// Seconds.
+    pub duration: f64,
+}
+
+/// Asks the service in `settings` for a route from `from` to `to`, both (lat, lon).
+pub fn route(
+    settings: &RoutingSettings,
+    from: (f64, f64),
+    to: (f64, f64),
+) -> Result<Directions, Box<dyn Error>> {
+    let base = settings.url.trim_end_matches('/');
+    let mut easy = Easy::new();
+    match settings.engine {
+        Engine::Osrm => {
+            easy.url(&format!(
+                "{}/route/v1/{}/{:.6},{:.6};{:.6},{:.6}?overview=full&geometries=polyline&steps=true",
+                base,
+                settings.profile(),
+                from.1,
+                from.0,
+                to.1,
+                to.0
+            ))?;
+        }
+        Engine::Valhalla => {
+            let request = json!({
+                "locations": [
+                    { "lat": from.0, "lon": from.1 },
+                    { "lat": to.0, "lon": to.1 },
+                ],
+                "costing": settings.profile(),
+            });
+            let mut headers = List::new();
+            headers.append("Content-Type: application/json")?;
+            easy.url(&format!("{}/route", base))?;
+            easy.http_headers(headers)?;
+            easy.post(true)?;
+            easy.post_fields_copy(request.to_string().as_bytes())?;
+        }
+    }
+    easy.useragent(&USER_AGENT)?;
+    easy.timeout(Duration::from_secs(30))?;
+    let mut body = Vec::new();
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    let code = easy.response_code()?;
+    let answer: Value =
+        serde_json::from_slice(&body).map_err(|e| format!("HTTP {}, not JSON: {}", code, e))?;
+    if code != 200 {
+        // OSRM explains in `message`, Valhalla in `error`
+        let reason = answer["message"]
+            .as_str()
+            .or(answer["error"].as_str())
+            .unwrap_or("no reason given");
+        return Err(Box::from(format!("HTTP {}: {}", code, reason)));
+    }
+    match settings.engine {
+        Engine::Osrm => parse_osrm(&answer),
+        Engine::Valhalla => parse_valhalla(&answer),
+    }
+}
+
+fn parse_osrm(answer: &Value) -> Result<Directions, Box<dyn Error>> {
+    if answer["code"].as_str() != Some("Ok") {
+        return Err(Box::from(format!(
+            "{}: {}",
+            answer["code"].as_str().unwrap_or("?"),
+            answer["message"].as_str().unwrap_or("no route")
+        )));
+    }
+    let route = &answer["routes"][0];
+    let geometry = route["geometry"]
+        .as_str()
+        .ok_or_else(|| "expected a polyline geometry".to_string())?;
+    let turns = route["legs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|leg| leg["steps"].as_array().into_iter().flatten())
+        .filter_map(|step| {
+            let location = &step["maneuver"]["location"];
+            Some(Turn {
+                at: (location[1].as_f64()?, location[0].as_f64()?),
+                instruction: osrm_instruction(step),
+            })
+        })
+        .collect();
+    Ok(Directions {
+        points: decode_polyline(geometry, 5)?,
+        turns,
+        distance: route["distance"].as_f64().unwrap_or(0.0),
+        duration: route["duration"].as_f64().unwrap_or(0.0),
+    })
+}
+
+/// "Turn left onto Main Street" out of an OSRM step, which only has the
+/// kind of manoeuvre and the road.
+fn osrm_instruction(step: &Value) -> String {
+    let maneuver = &step["maneuver"];
+    let kind = maneuver["type"].as_str().unwrap_or("continue");
+    let modifier = maneuver["modifier"].as_str();
+    let mut text = match (kind, modifier) {
+        ("depart", _) => "Depart".to_string(),
+        ("arrive", _) => return "Arrive".to_string(),
+        ("new name", _) => "Continue".to_string(),
+        ("roundabout" | "rotary", _) => match maneuver["exit"].as_u64() {
+            Some(exit) => format!("Take exit {} at the roundabout", exit),
+            None => "Enter the roundabout".to_string(),
+        },
+        (kind, Some(modifier)) => format!("{} {}", capitalised(kind), modifier),
+        (kind, None) => capitalised(kind),
+    };
+    if let Some(name) = step["name"].as_str().filter(|name| !name.is_empty()) {
+        text.push_str(if kind == "depart" { " on " } else { " onto " });
+        text.push_str(name);
+    }
+    text
+}
+
+fn capitalised(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+fn parse_valhalla(answer: &Value) -> Result<Directions, Box<dyn Error>> {
+    let trip = &answer["trip"];
+    let legs = trip["legs"]
+        .as_array()
+        .ok_or_else(|| "expected trip legs".to_string())?;
+    let mut points = Vec::new();
+    let mut turns = Vec::new();
+    for leg in legs {
+        let shape = decode_polyline(
+            leg["shape"]
+                .as_str()
+                .ok_or_else(|| "expected a leg shape".to_string())?,
+            6,
+        )?;
+        for maneuver in leg["maneuvers"].as_array().into_iter().flatten() {
+            let at = maneuver["begin_shape_index"]
+                .as_u64()
+                .and_then(|i| shape.get(i as usize));
+            if let (Some(&at), Some(instruction)) = (at, maneuver["instruction"].as_str()) {
+                turns.push(Turn {
+                    at,
+                    instruction: instruction.to_string(),
+                });
+            }
+        }
+        points.extend(shape);
+    }
+    let summary = &trip["summary"];
+    Ok(Directions {
+        points,
+        turns,
+        // asked for in the default kilometres
+        distance: summary["length"].as_f64().unwrap_or(0.0) * 1000.0,
+        duration: summary["time"].as_f64().unwrap_or(0.0),
+    })
+}
+
+/// (lat, lon) points of an encoded polyline, as used by Google, OSRM (5
+/// decimals) and Valhalla (6 decimals): each coordinate the zigzag-encoded
+/// difference to the previous one, in 5-bit chunks offset by 63.
+pub fn decode_polyline(text: &str, precision: i32) -> Result<Vec<(f64, f64)>, String> {
+    let factor = 10f64.powi(precision);
+    let bytes = text.as_bytes();
+    let mut points = Vec::new();
+    let mut at = 0;
+    let mut coords = [0i64; 2];
+    while at < bytes.len() {
+        for coord in &mut coords {
+            let mut value = 0i64;
+            let mut shift = 0;
+            loop {
+                let chunk = i64::from(
+                    *bytes
+                        .get(at)
+                        .ok_or_else(|| "polyline ends inside a point".to_string())?,
+                ) - 63;
+                at += 1;
+                if !(0..64).contains(&chunk) || shift > 60 {
+                    return Err("not an encoded polyline".to_string());
+                }
+                value |= (chunk & 0x1f) << shift;
+                shift += 5;
+                if chunk < 0x20 {
+                    break;
+                }
+            }
+            *coord += if value & 1 != 0 {
+                !(value >> 1)
+            } else {
+                value >> 1
+            };
+        }
+        points.push((coords[0] as f64 / factor, coords[1] as f64 / factor));
+    }
+    Ok(points)
+}
+
+type Answer = Result<Directions, String>;
+
+/// Asks for routes on a background thread, one at a time and no faster
+/// than the public servers allow.
+pub struct Router {
+    query_tx: Sender<((f64, f64), (f64, f64))>,
+    result_rx: Receiver<Answer>,
+}
+
+impl Router {
+    /// Starts the thread, or None unless routing is enabled.
+    pub fn spawn(settings: &RoutingSettings) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+        let settings = settings.clone();
+        let (query_tx, query_rx) = channel::<((f64, f64), (f64, f64))>();
+        let (result_tx, result_rx) = channel();
+        thread::spawn(move || {
+            let mut throttle = Throttle::default();
+            while let Ok((from, to)) = query_rx.recv() {
+                throttle.wait();
+                let result = route(&settings, from, to).map_err(|e| e.to_string());
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+                wake::wake();
+            }
+        });
+        Some(Self {
+            query_tx,
+            result_rx,
+        })
+    }
+
+    /// Asks for the route from `from` to `to`, both (lat, lon).
+    pub fn request(&self, from: (f64, f64), to: (f64, f64)) {
+        let _ = self.query_tx.send((from, to));
+    }
+
+    /// The next finished route, if any.
+    pub fn poll(&self) -> Option<Answer> {
+        self.result_rx.try_recv().ok()
+    }
+}
@@ -0,0 +1,120 @@
+use crate::geocoder::Place;
+
+/// The search prompt: the query being typed, the matches of the last
+/// query sent to the geocoder and earlier queries to pick from until then.
+#[derive(Debug, Clone, Default)]
+pub struct SearchBox {
+    pub query: String,
+    /// Query the results belong to, `None` while it is still running.
+    searched: Option<String>,
+    pub results: Vec<Place>,
+    pub selected: usize,
+    /// "Searching...", "No matches" or an error, shown under the query.
+    pub message: Option<String>,
+    /// Earlier queries, newest first.
+    history: Vec<String>,
+    /// Highlighted earlier query, once Down has moved into them.
+    suggested: Option<usize>,
+}
+
+impl SearchBox {
+    /// A search box offering `history`, newest first, while nothing is found.
+    pub fn with_history(history: Vec<String>) -> Self {
+        Self {
+            history,
+            ..Self::default()
+        }
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        self.query.push_str(text);
+        self.suggested = None;
+    }
+
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.suggested = None;
+    }
+
+    /// Earlier queries containing what is typed, ignoring case, listed while
+    /// there are no results.
+    pub fn suggestions(&self) -> Vec<&str> {
+        if !self.results.is_empty() {
+            return Vec::new();
+        }
+        let typed = self.query.trim().to_lowercase();
+        self.history
+            .iter()
+            .filter(|query| query.to_lowercase().contains(&typed))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Highlighted row of `suggestions`; none until Down is pressed, so Enter
+    /// searches for what is typed.
+    pub fn suggested(&self) -> Option<usize> {
+        self.suggested
+    }
+
+    /// Moves the highlight `steps` rows down (negative: up), through the
+    /// results or else the suggestions, leaving those above the first.
+    pub fn move_selection(&mut self, steps: i32) {
+        if !self.results.is_empty() {
+            let last = self.results.len() as i32 - 1;
+            self.selected = (self.selected as i32 + steps).clamp(0, last) as usize;
+            return;
+        }
+        let count = self.suggestions().len() as i32;
+        if count > 0 {
+            let row = self.suggested.map_or(-1, |i| i as i32) + steps;
+            self.suggested = (row >= 0).then(|| row.min(count - 1) as usize);
+        }
+    }
+
+    /// The highlighted match, if the results are for the query as typed.
+    pub fn chosen(&self) -> Option<&Place> {
+        if self.searched.as_deref() == Some(self.query.trim()) {
+            self.results.get(self.selected)
+        } else {
+            None
+        }
+    }
+
+    /// Marks the current query, or the highlighted earlier one, as sent and
+    /// returns it, or `None` if it is blank.
+    pub fn submit(&mut self) -> Option<String> {
+        if let Some(earlier) = self
+            .suggested
+            .and_then(|i| self.suggestions().get(i).copied())
+        {
+            self.query = earlier.to_string();
+        }
+        self.suggested = None;
+        let query = self.query.trim().to_string();
+        if query.is_empty() {
+            return None;
+        }
+        self.searched = None;
+        self.message = Some("Searching...".to_string());
+        Some(query)
+    }
+
+    /// Takes an answer from the geocoder, ignoring answers to older queries.
+    pub fn receive(&mut self, query: String, result: Result<Vec<Place>, String>) {
+        if query != self.query.trim() {
+            return;
+        }
+        match result {
+            Ok(places) => {
+                self.message = places.is_empty().then(|| "No matches".to_string());
+                self.results = places;
+            }
+            Err(e) => {
+                self.message = Some(e);
+                self.results.clear();
+            }
+        }
+        self.selected = 0;
+        self.searched = Some(query);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::viewport::Viewport;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+/// Written on exit, read on launch.
+pub const SESSION_PATH: &str = "session.toml";
+
+/// Where the map was left: view, source and window size.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: u8,
+    pub source: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Session {
+    pub fn capture(viewport: &Viewport, source: u8, win: (u32, u32)) -> Self {
+        let (lat, lon) = viewport.center_latlon();
+        Self {
+            lat,
+            lon,
+            zoom: viewport.z,
+            source,
+            width: win.0,
+            height: win.1,
+        }
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        Viewport::centered_on(self.lat, self.lon, self.zoom.min(19))
+    }
+
+    /// The session saved at `path`, if there is a readable one.
+    pub fn load(path: &str) -> Option<Self> {
+        let path = Path::new(path);
+        if !path.exists() {
+            return None;
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| warn!("Failed to read {}: {}", path.display(), e))
+            .ok()?;
+        toml::from_str(&text)
+            .map_err(|e| warn!("Failed to load {}: {}", path.display(), e))
+            .ok()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
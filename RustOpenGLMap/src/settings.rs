@@ -0,0 +1,145 @@
+use crate::audit::AuditSettings;
+use crate::basemap::BasemapSettings;
+use crate::collab::CollabSettings;
+use crate::coord_format::CoordFormat;
+use crate::disk_cache::CacheSettings;
+use crate::frame_pacer::DisplaySettings;
+use crate::geocoder::GeocoderSettings;
+use crate::gps::GpsSettings;
+use crate::gui::GuiSettings;
+use crate::history::HistorySettings;
+use crate::layers::range_rings::RangeRingStyle;
+use crate::map_view::LayerZoom;
+use crate::motion::AnimationSettings;
+use crate::overpass::OverpassSettings;
+use crate::routing::RoutingSettings;
+use crate::tile_layers::TileLayer;
+use crate::units::Units;
+use crate::vector_tile::VectorSettings;
+use crate::viewport::Viewport;
+use crate::worker_pool::WorkerSettings;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+pub const SETTINGS_PATH: &str = "settings.toml";
+
+/// Where the map first opens and where the Home key returns to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HomeView {
+    pub lat: f64,
+    pub lon: f64,
+    pub zoom: u8,
+    /// Tile source id shown at home.
+    #[serde(default)]
+    pub source: u8,
+}
+
+impl HomeView {
+    pub fn viewport(&self) -> Viewport {
+        Viewport::centered_on(self.lat, self.lon, self.zoom.min(19))
+    }
+}
+
+/// User preferences read from `settings.toml`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub home: Option<HomeView>,
+    /// Sources drawn over the base map at startup, bottom first.
+    #[serde(default, rename = "overlay")]
+    pub overlays: Vec<TileLayer>,
+    /// Preset combo to open with instead of `home.source` and the overlays.
+    #[serde(default)]
+    pub combo: Option<String>,
+    /// Radii and bearing spacing for the range ring tool.
+    #[serde(default)]
+    pub range_rings: RangeRingStyle,
+    /// How coordinates are shown and exported.
+    #[serde(default)]
+    pub coordinates: CoordFormat,
+    /// Units of distances, areas and elevations until switched with U.
+    #[serde(default)]
+    pub units: Units,
+    /// Geocoder used by the search box and `geocode`.
+    #[serde(default)]
+    pub geocoder: GeocoderSettings,
+    /// Overpass API used for amenity lookups.
+    #[serde(default)]
+    pub overpass: OverpassSettings,
+    /// Logging of where the map was looked at, for the heatmap.
+    #[serde(default)]
+    pub history: HistorySettings,
+    /// Durations and curves of view changes, or none at all.
+    #[serde(default)]
+    pub animation: AnimationSettings,
+    /// Vector tile server and the style its tiles are drawn in.
+    #[serde(default)]
+    pub vector: VectorSettings,
+    /// Zooms the vector tiles, coverage grid, heatmap, overlays and labels show at.
+    #[serde(default)]
+    pub layer_zoom: LayerZoom,
+    /// Colours of the built-in world map shown before tiles are cached.
+    #[serde(default)]
+    pub basemap: BasemapSettings,
+    /// Vsync and the frame rate cap until changed with Shift+F4 and F4.
+    #[serde(default)]
+    pub display: DisplaySettings,
+    /// Areas whose tiles the disk cache keeps.
+    #[serde(default)]
+    pub cache: CacheSettings,
+    /// Threads loading and downloading tiles.
+    #[serde(default)]
+    pub workers: WorkerSettings,
+    /// Relay and room for sharing cursors, views and annotations.
+    #[serde(default)]
+    pub collab: CollabSettings,
+    /// Log of bookmark and annotation changes.
+    #[serde(default)]
+    pub audit: AuditSettings,
+    /// Panels for layers, sources, cache statistics and search.
+    #[serde(default)]
+    pub gui: GuiSettings,
+    /// Receiver for the live position.
+    #[serde(default)]
+    pub gps: GpsSettings,
+    /// Service that finds routes along the roads.
+    #[serde(default)]
+    pub routing: RoutingSettings,
+}
+
+impl Settings {
+    pub fn from_toml(text: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads `path` if it exists, falling back to the defaults.
+    pub fn load_or_default(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("Failed to load {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    /// Stores `home` in the file at `path`, leaving its other keys alone.
+    pub fn save_home(path: &str, home: HomeView) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(path);
+        let mut table: toml::Table = if path.exists() {
+            toml::from_str(&std::fs::read_to_string(path)?)?
+        } else {
+            toml::Table::new()
+        };
+        table.insert("home".to_string(), toml::Value::try_from(home)?);
+        std::fs::write(path, toml::to_string(&table)?)?;
+        Ok(())
+    }
+}
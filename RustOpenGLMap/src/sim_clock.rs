@@ -0,0 +1,127 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Speeds the `,` and `.` keys step through, in simulated seconds per second.
+const RATES: &[f64] = &[
+    -3600.0, -600.0, -60.0, -10.0, -1.0, 1.0, 10.0, 60.0, 600.0, 3600.0,
+];
+
+/// Time shown by animated layers. Runs with the wall clock until it is
+/// sped up, reversed or moved.
+#[derive(Debug, Clone, Copy)]
+pub struct SimClock {
+    /// Simulated unix time at `since`.
+    base: f64,
+    since: Instant,
+    rate: f64,
+}
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self {
+            base: unix_now(),
+            since: Instant::now(),
+            rate: 1.0,
+        }
+    }
+
+    /// Simulated unix time in seconds.
+    pub fn now(&self) -> f64 {
+        self.base + self.since.elapsed().as_secs_f64() * self.rate
+    }
+
+    /// True while the clock shows the actual time.
+    pub fn is_live(&self) -> bool {
+        self.rate == 1.0 && (self.now() - unix_now()).abs() < 1.0
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    fn rebase(&mut self) {
+        self.base = self.now();
+        self.since = Instant::now();
+    }
+
+    /// Moves to the next faster (`steps` > 0) or slower rate.
+    pub fn step_rate(&mut self, steps: i32) {
+        self.rebase();
+        let current = RATES.iter().position(|r| *r == self.rate).unwrap_or(5) as i32;
+        let next = (current + steps).clamp(0, RATES.len() as i32 - 1);
+        self.rate = RATES[next as usize];
+    }
+
+    pub fn jump(&mut self, seconds: f64) {
+        self.rebase();
+        self.base += seconds;
+    }
+
+    /// Back to the wall clock at normal speed.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// (year, month, day) of a unix time and the seconds into that day.
+fn civil_from_unix(unix: f64) -> ((i64, i64, i64), i64) {
+    let secs = unix.floor() as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // inverse of days_from_civil
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    ((year, month, day), rem)
+}
+
+/// `YYYY-MM-DD hh:mm:ss UTC` for a unix time.
+pub fn format_utc(unix: f64) -> String {
+    let ((year, month, day), rem) = civil_from_unix(unix);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// `YYYY-MM-DDThh:mm:ssZ` for a unix time, as GPX and other ISO 8601
+/// readers expect.
+pub fn format_iso8601(unix: f64) -> String {
+    let ((year, month, day), rem) = civil_from_unix(unix);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
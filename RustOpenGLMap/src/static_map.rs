@@ -0,0 +1,194 @@
+use crate::figure::Figure;
+use crate::geo;
+use crate::offscreen::Offscreen;
+use crate::presets;
+use crate::settings::{SETTINGS_PATH, Settings};
+use crate::tile_layers::TileLayer;
+use crate::tile_source::SOURCES;
+use crate::units::{self, Units};
+use crate::viewport::Viewport;
+use clap::Args;
+use std::path::PathBuf;
+
+/// Largest image `render` draws without an explicit `--size`.
+const MAX_SIDE: u32 = 8192;
+
+/// `RustOpenGLMap render --bbox S,W,N,E [--zoom <z>] [--size WxH] --out <png|svg|pdf>`
+/// `RustOpenGLMap render --center LAT,LON --zoom <z> [--size WxH] --out <png|svg|pdf>`
+///
+/// Downloads whatever tiles the image needs and aren't cached yet, so a
+/// script can turn any area into a PNG without opening the map.
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Area to show, south,west,north,east in degrees.
+    #[arg(
+        long,
+        value_name = "S,W,N,E",
+        allow_hyphen_values = true,
+        value_parser = bbox,
+        required_unless_present = "center",
+        conflicts_with = "center"
+    )]
+    bbox: Option<[f64; 4]>,
+    /// Point to centre the image on, instead of a bbox.
+    #[arg(
+        long,
+        value_name = "LAT,LON",
+        allow_hyphen_values = true,
+        value_parser = point,
+        requires = "zoom"
+    )]
+    center: Option<(f64, f64)>,
+    /// Zoom level; with a bbox, the deepest one the bbox fits at by default.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=19))]
+    zoom: Option<u8>,
+    /// Image size, by default the bbox at --zoom or 1024x768.
+    #[arg(long, value_name = "WxH", value_parser = size)]
+    size: Option<(u32, u32)>,
+    /// Base map, a preset key or source name; the first source by default.
+    #[arg(long)]
+    source: Option<String>,
+    /// Preset base map and overlays, instead of --source.
+    #[arg(long, conflicts_with = "source")]
+    combo: Option<String>,
+    /// Only use cached tiles, drawing missing ones from lower zooms.
+    #[arg(long)]
+    offline: bool,
+    /// Units of the scale bar in .svg and .pdf figures, by default the
+    /// `units` of settings.toml.
+    #[arg(long, value_enum)]
+    units: Option<Units>,
+    /// PNG file to write, or an .svg or .pdf figure with a graticule, a
+    /// scale bar and the attribution over the map.
+    #[arg(long)]
+    out: PathBuf,
+}
+
+fn bbox(value: &str) -> Result<[f64; 4], String> {
+    let parts: Vec<f64> = value
+        .split(',')
+        .map(|p| p.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    let [south, west, north, east] = parts[..] else {
+        return Err("needs south,west,north,east".to_string());
+    };
+    if south.abs() > 90.0 || north.abs() > 90.0 || west.abs() > 180.0 || east.abs() > 180.0 {
+        return Err("needs degrees within -90..90 and -180..180".to_string());
+    }
+    Ok([
+        south.min(north),
+        west.min(east),
+        south.max(north),
+        west.max(east),
+    ])
+}
+
+fn point(value: &str) -> Result<(f64, f64), String> {
+    value
+        .split_once(',')
+        .and_then(|(lat, lon)| Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?)))
+        .filter(|(lat, lon): &(f64, f64)| lat.abs() <= 90.0 && lon.abs() <= 180.0)
+        .ok_or_else(|| "needs lat,lon in degrees".to_string())
+}
+
+fn size(value: &str) -> Result<(u32, u32), String> {
+    value
+        .split_once(['x', 'X'])
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+        .filter(|&(w, h): &(u32, u32)| (1..=MAX_SIDE).contains(&w) && (1..=MAX_SIDE).contains(&h))
+        .ok_or_else(|| format!("needs WIDTHxHEIGHT, each 1 to {}", MAX_SIDE))
+}
+
+/// Pixel size of `bounds` at zoom `z`, at least one pixel each way.
+fn extent(bounds: [f64; 4], z: u8) -> (u32, u32) {
+    let [south, west, north, east] = bounds;
+    let (x0, y0) = geo::project(north, west);
+    let (x1, y1) = geo::project(south, east);
+    let px_per_unit = geo::world_tiles(z) * 256.0;
+    let side = |d: f64| ((d * px_per_unit).ceil() as u32).max(1);
+    (side(x1 - x0), side(y1 - y0))
+}
+
+pub fn run_render_command(args: RenderArgs) -> Result<(), String> {
+    let (viewport, size) = match (args.bbox, args.center, args.zoom) {
+        (Some(bounds), _, Some(z)) => {
+            let size = args.size.unwrap_or_else(|| extent(bounds, z));
+            if size.0 > MAX_SIDE || size.1 > MAX_SIDE {
+                return Err(format!(
+                    "The bbox is {}x{} pixels at zoom {}, pass a lower --zoom or a --size",
+                    size.0, size.1, z
+                ));
+            }
+            let [south, west, north, east] = bounds;
+            let (x0, y0) = geo::project(north, west);
+            let (x1, y1) = geo::project(south, east);
+            let (lat, lon) = geo::unproject((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+            (Viewport::centered_on(lat, lon, z), size)
+        }
+        (Some(bounds), _, None) => {
+            let size = args.size.unwrap_or((1024, 768));
+            (Viewport::fitting(bounds, size, 19), size)
+        }
+        (None, Some((lat, lon)), Some(z)) => (
+            Viewport::centered_on(lat, lon, z),
+            args.size.unwrap_or((1024, 768)),
+        ),
+        // clap requires a bbox or a centre and a zoom
+        _ => return Err("Pass --bbox or --center and --zoom".to_string()),
+    };
+    let layers = match &args.combo {
+        Some(combo) => {
+            let (base, overlays) = presets::resolve_combo(combo)?;
+            std::iter::once(TileLayer::base(base))
+                .chain(overlays.into_iter().filter(|layer| layer.visible))
+                .collect()
+        }
+        None => {
+            let base = match &args.source {
+                Some(name) => presets::resolve_source(name)?,
+                None => 0,
+            };
+            vec![TileLayer::base(base)]
+        }
+    };
+
+    let offscreen = Offscreen::new(size)?;
+    let (frame, missing) = offscreen.render(viewport, &layers, !args.offline);
+    let extension = args
+        .out
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let saved = if matches!(extension.as_str(), "svg" | "pdf") {
+        units::set(
+            args.units
+                .unwrap_or_else(|| Settings::load_or_default(SETTINGS_PATH).units),
+        );
+        let mut figure = Figure::new(frame, size);
+        figure.add_graticule(&viewport);
+        figure.add_scale_bar(&viewport);
+        figure.add_attribution(&SOURCES.read().unwrap().attribution(&layers));
+        figure.save(&args.out).map_err(|e| e.to_string())
+    } else {
+        frame.save(&args.out).map_err(|e| e.to_string())
+    };
+    saved.map_err(|e| format!("Failed to write {}: {}", args.out.display(), e))?;
+    let (lat, lon) = viewport.center_latlon();
+    println!(
+        "Wrote {}x{} at {:.5}, {:.5} z{} to {}",
+        size.0,
+        size.1,
+        lat,
+        lon,
+        viewport.z,
+        args.out.display()
+    );
+    if missing > 0 {
+        eprintln!(
+            "{} tiles were missing and are drawn from lower zooms or left blank",
+            missing
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::frame_pacer::FramePacer;
+use crate::opengl_helper::{self, CacheStats};
+use std::time::{Duration, Instant};
+
+/// How often the rates are worked out again.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Weight of the newest frame in the smoothed draw time.
+const SMOOTHING: f64 = 0.1;
+
+/// Frame timing, tile and download counts drawn over the map with F3, to
+/// see where a slow or stuttering view spends its time.
+#[derive(Debug)]
+pub struct StatsOverlay {
+    pub visible: bool,
+    /// Smoothed seconds the render thread spends drawing a frame.
+    draw_time: f64,
+    /// Tiles that came back from the disk loaders, all time.
+    loads: u64,
+    /// Start of the current rate window and the counters then.
+    window: (Instant, u64, u64, u64),
+    /// Share of visible tiles found on the GPU over the last window.
+    hit_rate: Option<f64>,
+    loads_per_second: f64,
+}
+
+impl StatsOverlay {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            draw_time: 0.0,
+            loads: 0,
+            window: (Instant::now(), 0, 0, 0),
+            hit_rate: None,
+            loads_per_second: 0.0,
+        }
+    }
+
+    /// Counts a frame that took `took` to draw, swap excluded.
+    pub fn record_draw(&mut self, took: Duration) {
+        let took = took.as_secs_f64();
+        self.draw_time = if self.draw_time > 0.0 {
+            self.draw_time + (took - self.draw_time) * SMOOTHING
+        } else {
+            took
+        };
+    }
+
+    /// Counts a tile or placeholder handed over by the disk loaders.
+    pub fn record_load(&mut self) {
+        self.loads += 1;
+    }
+
+    /// The overlay's lines, with the rates brought up to date once a second.
+    /// `requested` is the number of tiles asked for and not arrived yet.
+    pub fn lines(
+        &mut self,
+        pacer: &FramePacer,
+        cache: CacheStats,
+        requested: usize,
+    ) -> Vec<String> {
+        let (since, hits, misses, loads) = self.window;
+        let elapsed = since.elapsed();
+        if elapsed >= RATE_WINDOW {
+            let (hits, misses) = (cache.hits - hits, cache.misses - misses);
+            self.hit_rate = match hits + misses {
+                0 => None,
+                lookups => Some(hits as f64 / lookups as f64),
+            };
+            self.loads_per_second = (self.loads - loads) as f64 / elapsed.as_secs_f64();
+            self.window = (Instant::now(), cache.hits, cache.misses, self.loads);
+        }
+        let fps = pacer.fps();
+        let frame_ms = if fps > 0.0 { 1000.0 / fps } else { 0.0 };
+        let hit_rate = self
+            .hit_rate
+            .map_or_else(|| "-".to_string(), |rate| format!("{:.0}%", rate * 100.0));
+        vec![
+            format!(
+                "frame {:.1} ms ({:.0} fps), drawing {:.1} ms",
+                frame_ms,
+                fps,
+                self.draw_time * 1000.0
+            ),
+            format!("tiles drawn {}, GPU cache hits {}", cache.drawn, hit_rate),
+            format!("GPU tiles {} in {} textures", cache.tiles, cache.textures),
+            format!(
+                "tiles requested {}, downloads pending {}, disk loads {:.0}/s",
+                requested,
+                opengl_helper::pending_downloads(),
+                self.loads_per_second
+            ),
+        ]
+    }
+}
@@ -0,0 +1,634 @@
+extern crate gl;
+
+use crate::opengl_helper::{Buffer, BufferType, Sampler, ShaderProgram, VertexArray, c_str};
+use crate::theme::{Corner, Theme};
+use embedded_graphics::image::GetPixel;
+use embedded_graphics::mono_font::MonoFont;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use gl::types::*;
+
+const TEXT_VERT_SHADER: &str = r#"#version 410 core
+layout (location = 0) in vec2 pos;
+layout (location = 1) in vec2 tex;
+
+out vec2 v_tex;
+
+void main() {
+    gl_Position = vec4(pos, 0.0, 1.0);
+    v_tex       = tex;
+}
+"#;
+
+const TEXT_FRAG_SHADER: &str = r#"#version 410 core
+uniform sampler2D u_atlas;
+uniform vec4 u_color;
+uniform bool u_solid;   // fill the quad instead of sampling a glyph
+in  vec2 v_tex;
+out vec4 final_color;
+void main() {
+    float coverage = u_solid ? 1.0 : texture(u_atlas, v_tex).r;
+    final_color = vec4(u_color.rgb, u_color.a * coverage);
+}
+"#;
+
+/// x, y in NDC followed by u, v in the glyph atlas.
+type TextVertex = [f32; 4];
+
+/// Draws screen-space text from a baked bitmap font.
+///
+/// The font image itself is uploaded as the glyph atlas, so every Latin-1
+/// character the font covers (including `©`) can be drawn.
+pub struct TextRenderer {
+    program: ShaderProgram,
+    vao: VertexArray,
+    vbo: Buffer,
+    atlas: GLuint,
+    /// Nearest texels, the pixel font stays crisp.
+    sampler: Sampler,
+    font: &'static MonoFont<'static>,
+    atlas_w: f32,
+    atlas_h: f32,
+}
+
+impl TextRenderer {
+    pub fn new(font: &'static MonoFont<'static>) -> Result<Self, String> {
+        let program = ShaderProgram::from_vert_frag(TEXT_VERT_SHADER, TEXT_FRAG_SHADER)?;
+        let vao = VertexArray::new().ok_or_else(|| "Couldn't make a text VAO".to_string())?;
+        let vbo = Buffer::new().ok_or_else(|| "Couldn't make a text VBO".to_string())?;
+
+        vao.bind();
+        vbo.bind(BufferType::Array);
+        let stride = size_of::<TextVertex>() as GLsizei;
+        unsafe {
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, std::ptr::null());
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(
+                1,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                size_of::<[f32; 2]>() as *const _,
+            );
+            gl::EnableVertexAttribArray(1);
+        }
+        VertexArray::clear_binding();
+
+        let size = font.image.size();
+        let mut pixels = vec![0u8; (size.width * size.height) as usize];
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let on = font.image.pixel(Point::new(x as i32, y as i32)) == Some(BinaryColor::On);
+                if on {
+                    pixels[(y * size.width + x) as usize] = 255;
+                }
+            }
+        }
+
+        let mut atlas: GLuint = 0;
+        unsafe {
+            gl::GenTextures(1, &mut atlas);
+            gl::BindTexture(gl::TEXTURE_2D, atlas);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_WRAP_S,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_WRAP_T,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            // pixel font, keep the edges crisp
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::R8 as GLint,
+                size.width as GLsizei,
+                size.height as GLsizei,
+                0,
+                gl::RED,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const GLvoid,
+            );
+        }
+
+        let sampler = Sampler::new(gl::NEAREST, gl::NEAREST, gl::CLAMP_TO_EDGE)
+            .ok_or_else(|| "Couldn't make a text sampler".to_string())?;
+
+        Ok(Self {
+            program,
+            vao,
+            vbo,
+            atlas,
+            sampler,
+            font,
+            atlas_w: size.width as f32,
+            atlas_h: size.height as f32,
+        })
+    }
+
+    /// Width and height in pixels of `text` drawn at `scale`.
+    pub fn measure(&self, text: &str, scale: f32) -> (f32, f32) {
+        let advance = self.advance() * scale;
+        let chars = text.chars().count() as f32;
+        (
+            chars * advance,
+            self.font.character_size.height as f32 * scale,
+        )
+    }
+
+    /// Draws `text` with its top-left corner at pixel `pos`, window origin top-left.
+    pub fn draw(&self, text: &str, pos: (f32, f32), scale: f32, color: [f32; 4], win: (u32, u32)) {
+        let (x, y) = pos;
+        let glyph = self.font.character_size;
+        let glyphs_per_row = self.font.image.size().width / glyph.width;
+        let (gw, gh) = (glyph.width as f32, glyph.height as f32);
+
+        let mut vertices: Vec<TextVertex> = Vec::with_capacity(text.len() * 6);
+        let mut pen_x = x;
+        for c in text.chars() {
+            let index = self.font.glyph_mapping.index(c) as u32;
+            let u0 = (index % glyphs_per_row) as f32 * gw / self.atlas_w;
+            let v0 = (index / glyphs_per_row) as f32 * gh / self.atlas_h;
+            let u1 = u0 + gw / self.atlas_w;
+            let v1 = v0 + gh / self.atlas_h;
+            push_quad(
+                &mut vertices,
+                [pen_x, y, pen_x + gw * scale, y + gh * scale],
+                [u0, v0, u1, v1],
+                win,
+            );
+            pen_x += self.advance() * scale;
+        }
+        self.submit(&vertices, color, false);
+    }
+
+    /// Fills the pixel rect [x0, y0, x1, y1], e.g. as a backdrop behind text.
+    pub fn fill_rect(&self, rect: [f32; 4], color: [f32; 4], win: (u32, u32)) {
+        let mut vertices: Vec<TextVertex> = Vec::with_capacity(6);
+        push_quad(&mut vertices, rect, [0.0, 0.0, 0.0, 0.0], win);
+        self.submit(&vertices, color, true);
+    }
+
+    fn advance(&self) -> f32 {
+        (self.font.character_size.width + self.font.character_spacing) as f32
+    }
+
+    fn submit(&self, vertices: &[TextVertex], color: [f32; 4], solid: bool) {
+        if vertices.is_empty() {
+            return;
+        }
+        unsafe {
+            gl::UseProgram(self.program.0);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::Uniform4f(
+                gl::GetUniformLocation(self.program.0, c_str!("u_color")),
+                color[0],
+                color[1],
+                color[2],
+                color[3],
+            );
+            gl::Uniform1i(
+                gl::GetUniformLocation(self.program.0, c_str!("u_solid")),
+                solid as GLint,
+            );
+            gl::Uniform1i(gl::GetUniformLocation(self.program.0, c_str!("u_atlas")), 0);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.atlas);
+        }
+        self.sampler.bind(0);
+        self.vao.bind();
+        self.vbo.bind(BufferType::Array);
+        Buffer::data(
+            BufferType::Array,
+            bytemuck::cast_slice(vertices),
+            gl::STREAM_DRAW,
+        );
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, 0, vertices.len() as GLsizei);
+            gl::Disable(gl::BLEND);
+        }
+        Sampler::clear_binding(0);
+        VertexArray::clear_binding();
+    }
+}
+
+/// Appends two triangles covering pixel rect [x0, y0, x1, y1] with texture rect [u0, v0, u1, v1].
+fn push_quad(out: &mut Vec<TextVertex>, rect: [f32; 4], uv: [f32; 4], win: (u32, u32)) {
+    let to_ndc = |px: f32, py: f32| (px / win.0 as f32 * 2.0 - 1.0, 1.0 - py / win.1 as f32 * 2.0);
+    let (x0, y0) = to_ndc(rect[0], rect[1]);
+    let (x1, y1) = to_ndc(rect[2], rect[3]);
+    let [u0, v0, u1, v1] = uv;
+    out.extend_from_slice(&[
+        [x0, y0, u0, v0],
+        [x1, y0, u1, v0],
+        [x1, y1, u1, v1],
+        [x0, y0, u0, v0],
+        [x1, y1, u1, v1],
+        [x0, y1, u0, v1],
+    ]);
+}
+
+/// Draws the tile source credit in the theme's attribution corner.
+pub fn draw_attribution(text: &TextRenderer, theme: &Theme, attribution: &str, win: (u32, u32)) {
+    if attribution.is_empty() {
+        return;
+    }
+    let pad = theme.padding / 2.0;
+    let (w, h) = text.measure(attribution, theme.small_text_scale);
+    let size = (w + pad * 2.0, h + pad * 2.0);
+    // flush with the window edge, like the usual map credit strip
+    let (x, y) = theme.attribution_corner.place(size, 0.0, win);
+    text.fill_rect([x, y, x + size.0, y + size.1], theme.panel, win);
+    text.draw(
+        attribution,
+        (x + pad, y + pad),
+        theme.small_text_scale,
+        theme.text,
+        win,
+    );
+}
+
+/// Coordinates under the mouse in the theme's cursor corner, styled like
+/// the attribution.
+pub fn draw_cursor_readout(text: &TextRenderer, theme: &Theme, readout: &str, win: (u32, u32)) {
+    let pad = theme.padding / 2.0;
+    let (w, h) = text.measure(readout, theme.small_text_scale);
+    let size = (w + pad * 2.0, h + pad * 2.0);
+    let (x, y) = theme.cursor_corner.place(size, 0.0, win);
+    text.fill_rect([x, y, x + size.0, y + size.1], theme.panel, win);
+    text.draw(
+        readout,
+        (x + pad, y + pad),
+        theme.small_text_scale,
+        theme.text,
+        win,
+    );
+}
+
+/// One-line bar across the bottom of the window, `info` on the left and
+/// the attribution on the right.
+pub fn draw_status_bar(
+    text: &TextRenderer,
+    theme: &Theme,
+    info: &str,
+    attribution: &str,
+    win: (u32, u32),
+) {
+    let pad = theme.padding / 2.0;
+    let scale = theme.small_text_scale;
+    let h = text.measure(info, scale).1 + pad * 2.0;
+    let y = win.1 as f32 - h;
+    text.fill_rect([0.0, y, win.0 as f32, win.1 as f32], theme.panel, win);
+    text.draw(info, (pad, y + pad), scale, theme.text, win);
+    let attribution_w = text.measure(attribution, scale).0;
+    text.draw(
+        attribution,
+        (win.0 as f32 - attribution_w - pad, y + pad),
+        scale,
+        theme.muted_text,
+        win,
+    );
+}
+
+/// Vertical line at window x `x` with the names of the sources either side of it.
+pub fn draw_divider(
+    text: &TextRenderer,
+    theme: &Theme,
+    x: f32,
+    names: (&str, &str),
+    win: (u32, u32),
+) {
+    text.fill_rect([x - 1.0, 0.0, x + 1.0, win.1 as f32], theme.accent, win);
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding / 2.0;
+    let (left_w, h) = text.measure(names.0, scale);
+    let right_w = text.measure(names.1, scale).0;
+    let y = theme.padding;
+    let left_x = x - left_w - pad * 3.0;
+    text.fill_rect(
+        [left_x - pad, y - pad, x - pad * 2.0, y + h + pad],
+        theme.panel,
+        win,
+    );
+    text.draw(names.0, (left_x, y), scale, theme.text, win);
+    let right_x = x + pad * 3.0;
+    text.fill_rect(
+        [x + pad * 2.0, y - pad, right_x + right_w + pad, y + h + pad],
+        theme.panel,
+        win,
+    );
+    text.draw(names.1, (right_x, y), scale, theme.text, win);
+}
+
+/// Short text on a panel centred on window pixel `at`, e.g. a route length.
+pub fn draw_label(
+    text: &TextRenderer,
+    theme: &Theme,
+    label: &str,
+    at: (f32, f32),
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding / 2.0;
+    let (w, h) = text.measure(label, scale);
+    let (x, y) = (at.0 - w / 2.0, at.1 - h / 2.0);
+    text.fill_rect(
+        [x - pad, y - pad, x + w + pad, y + h + pad],
+        theme.panel,
+        win,
+    );
+    text.draw(label, (x, y), scale, theme.text, win);
+}
+
+/// A place name with its top-left corner at window pixel `at`, outlined
+/// in the panel colour so it reads on any map.
+pub fn draw_place_name(
+    text: &TextRenderer,
+    theme: &Theme,
+    name: &str,
+    at: (f32, f32),
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    for (dx, dy) in [(-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0)] {
+        text.draw(name, (at.0 + dx, at.1 + dy), scale, theme.panel, win);
+    }
+    text.draw(name, at, scale, theme.text, win);
+}
+
+/// Panel just above window pixel `at` with a `title` and a row per line,
+/// such as the tags of a clicked amenity. Kept inside the window.
+pub fn draw_popup(
+    text: &TextRenderer,
+    theme: &Theme,
+    title: &str,
+    rows: &[String],
+    at: (f32, f32),
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding;
+    let line_h = text.measure("", scale).1 + 4.0;
+    let w = rows
+        .iter()
+        .map(|row| text.measure(row, scale).0)
+        .fold(text.measure(title, scale).0, f32::max)
+        + pad * 2.0;
+    let h = (rows.len() + 1) as f32 * line_h + pad * 2.0;
+    // above the marker, nudged back in where it would leave the window
+    let x = (at.0 - w / 2.0).clamp(0.0, (win.0 as f32 - w).max(0.0));
+    let y = (at.1 - h - pad * 2.0).clamp(0.0, (win.1 as f32 - h).max(0.0));
+    text.fill_rect([x, y, x + w, y + h], theme.panel, win);
+    text.draw(title, (x + pad, y + pad), scale, theme.accent, win);
+    for (i, row) in rows.iter().enumerate() {
+        text.draw(
+            row,
+            (x + pad, y + pad + (i + 1) as f32 * line_h),
+            scale,
+            theme.text,
+            win,
+        );
+    }
+}
+
+/// Stacks status messages of (text, colour) in the theme's HUD corner.
+pub fn draw_hud(text: &TextRenderer, theme: &Theme, lines: &[(&str, [f32; 4])], win: (u32, u32)) {
+    if lines.is_empty() {
+        return;
+    }
+    let scale = theme.text_scale;
+    let line_h = text.measure("", scale).1 + theme.padding;
+    let w = lines
+        .iter()
+        .map(|(line, _)| text.measure(line, scale).0)
+        .fold(0.0, f32::max);
+    let h = lines.len() as f32 * line_h - theme.padding;
+    let (x, y) = theme.hud_corner.place((w, h), theme.padding, win);
+    for (i, (line, color)) in lines.iter().enumerate() {
+        // right-aligned corners keep every line flush with the edge
+        let line_x = match theme.hud_corner {
+            Corner::TopRight | Corner::BottomRight => x + w - text.measure(line, scale).0,
+            Corner::TopLeft | Corner::BottomLeft => x,
+        };
+        text.draw(line, (line_x, y + i as f32 * line_h), scale, *color, win);
+    }
+}
+
+/// Centred panel listing `rows` of (trigger, description), with the source
+/// attribution underneath.
+pub fn draw_help(
+    text: &TextRenderer,
+    theme: &Theme,
+    rows: &[(String, &str)],
+    attribution: &str,
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding * 2.0;
+    let line_h = text.measure("", scale).1 + 4.0;
+    let trigger_w = rows
+        .iter()
+        .map(|(trigger, _)| text.measure(trigger, scale).0)
+        .fold(0.0, f32::max);
+    let description_w = rows
+        .iter()
+        .map(|(_, description)| text.measure(description, scale).0)
+        .fold(0.0, f32::max);
+    let content_w =
+        (trigger_w + pad + description_w).max(text.measure(attribution, theme.small_text_scale).0);
+    let w = content_w + pad * 2.0;
+    let h = (rows.len() + 2) as f32 * line_h + pad * 2.0;
+    let x = ((win.0 as f32 - w) / 2.0).max(0.0);
+    let y = ((win.1 as f32 - h) / 2.0).max(0.0);
+
+    text.fill_rect([x, y, x + w, y + h], theme.panel, win);
+    text.draw("Help", (x + pad, y + pad), scale, theme.accent, win);
+    for (i, (trigger, description)) in rows.iter().enumerate() {
+        let row_y = y + pad + (i + 1) as f32 * line_h;
+        text.draw(trigger, (x + pad, row_y), scale, theme.accent, win);
+        text.draw(
+            description,
+            (x + pad * 2.0 + trigger_w, row_y),
+            scale,
+            theme.text,
+            win,
+        );
+    }
+    if !attribution.is_empty() {
+        text.draw(
+            attribution,
+            (x + pad, y + pad + (rows.len() + 1) as f32 * line_h + 4.0),
+            theme.small_text_scale,
+            theme.muted_text,
+            win,
+        );
+    }
+}
+
+/// Panel in the theme's layer list corner: the base source, then one row of
+/// (name, opacity, visible) per overlay with the `selected` one highlighted.
+pub fn draw_layer_list(
+    text: &TextRenderer,
+    theme: &Theme,
+    base: &str,
+    overlays: &[(String, f32, bool)],
+    selected: usize,
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding;
+    let line_h = text.measure("", scale).1 + 4.0;
+    let mut rows = vec![("Layers".to_string(), theme.accent)];
+    rows.push((format!("base  {}", base), theme.text));
+    for (i, (name, opacity, visible)) in overlays.iter().enumerate() {
+        let marker = if i == selected { '>' } else { ' ' };
+        let row = if *visible {
+            format!("{}{:3.0}%  {}", marker, opacity * 100.0, name)
+        } else {
+            format!("{} off  {}", marker, name)
+        };
+        let color = match (i == selected, *visible) {
+            (true, _) => theme.accent,
+            (false, true) => theme.text,
+            (false, false) => theme.muted_text,
+        };
+        rows.push((row, color));
+    }
+    if overlays.is_empty() {
+        rows.push(("N adds an overlay".to_string(), theme.muted_text));
+    }
+    let w = rows
+        .iter()
+        .map(|(row, _)| text.measure(row, scale).0)
+        .fold(0.0, f32::max)
+        + pad * 2.0;
+    let h = rows.len() as f32 * line_h + pad * 2.0;
+    let (x, y) = theme.layer_list_corner.place((w, h), theme.padding, win);
+    text.fill_rect([x, y, x + w, y + h], theme.panel, win);
+    for (i, (row, color)) in rows.iter().enumerate() {
+        text.draw(
+            row,
+            (x + pad, y + pad + i as f32 * line_h),
+            scale,
+            *color,
+            win,
+        );
+    }
+}
+
+/// Panel in the theme's statistics corner with one measurement per line.
+pub fn draw_stats(text: &TextRenderer, theme: &Theme, lines: &[String], win: (u32, u32)) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding;
+    let line_h = text.measure("", scale).1 + 4.0;
+    let w = lines
+        .iter()
+        .map(|line| text.measure(line, scale).0)
+        .fold(0.0, f32::max)
+        + pad * 2.0;
+    let h = lines.len() as f32 * line_h + pad * 2.0;
+    // keep clear of the attribution strip and status bar along the edges
+    let (x, y) = theme.stats_corner.place((w, h), pad * 4.0, win);
+    text.fill_rect([x, y, x + w, y + h], theme.panel, win);
+    for (i, line) in lines.iter().enumerate() {
+        text.draw(
+            line,
+            (x + pad, y + pad + i as f32 * line_h),
+            scale,
+            theme.text,
+            win,
+        );
+    }
+}
+
+/// Panel in the theme's point list corner with the latest collected points,
+/// `total` counting the ones scrolled out of it.
+pub fn draw_point_list(
+    text: &TextRenderer,
+    theme: &Theme,
+    rows: &[String],
+    total: usize,
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding;
+    let line_h = text.measure("", scale).1 + 4.0;
+    let mut lines = vec![(format!("Points ({})", total), theme.accent)];
+    lines.extend(rows.iter().map(|row| (row.clone(), theme.text)));
+    if rows.is_empty() {
+        lines.push(("Click the map to add one".to_string(), theme.muted_text));
+    }
+    let w = lines
+        .iter()
+        .map(|(line, _)| text.measure(line, scale).0)
+        .fold(0.0, f32::max)
+        + pad * 2.0;
+    let h = lines.len() as f32 * line_h + pad * 2.0;
+    // keep clear of the attribution strip and status bar along the edges
+    let (x, y) = theme.point_list_corner.place((w, h), pad * 4.0, win);
+    text.fill_rect([x, y, x + w, y + h], theme.panel, win);
+    for (i, (line, color)) in lines.iter().enumerate() {
+        text.draw(
+            line,
+            (x + pad, y + pad + i as f32 * line_h),
+            scale,
+            *color,
+            win,
+        );
+    }
+}
+
+/// Panel across the top of the window with the `input` line of a prompt, a
+/// status `message` and any `results` with the `selected` one highlighted.
+pub fn draw_prompt(
+    text: &TextRenderer,
+    theme: &Theme,
+    input: &str,
+    message: Option<&str>,
+    results: &[&str],
+    selected: usize,
+    win: (u32, u32),
+) {
+    let scale = theme.panel_text_scale();
+    let pad = theme.padding;
+    let line_h = text.measure("", scale).1 + 4.0;
+    let mut lines = vec![(input.to_string(), theme.accent)];
+    if let Some(message) = message {
+        lines.push((message.to_string(), theme.muted_text));
+    }
+    for (i, name) in results.iter().enumerate() {
+        let marker = if i == selected { '>' } else { ' ' };
+        let color = if i == selected {
+            theme.accent
+        } else {
+            theme.text
+        };
+        lines.push((format!("{} {}", marker, name), color));
+    }
+    let w = lines
+        .iter()
+        .map(|(line, _)| text.measure(line, scale).0)
+        .fold(win.0 as f32 / 3.0, f32::max)
+        .min(win.0 as f32 - pad * 4.0)
+        + pad * 2.0;
+    let h = lines.len() as f32 * line_h + pad * 2.0;
+    let x = ((win.0 as f32 - w) / 2.0).max(0.0);
+    let y = pad * 4.0;
+    text.fill_rect([x, y, x + w, y + h], theme.panel, win);
+    for (i, (line, color)) in lines.iter().enumerate() {
+        text.draw(
+            line,
+            (x + pad, y + pad + i as f32 * line_h),
+            scale,
+            *color,
+            win,
+        );
+    }
+}
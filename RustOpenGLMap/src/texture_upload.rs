@@ -0,0 +1,226 @@
+use crate::error::MapError;
+use crate::opengl_helper::{self, Buffer, BufferType};
+use crate::tile::{TileLoad, TilePos};
+use crate::wake;
+use gl::types::{GLsizeiptr, GLuint, GLvoid};
+use image::RgbaImage;
+use sdl2::sys;
+use sdl2::video::{GLContext, Window};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use tracing::{debug, debug_span, error, warn};
+
+/// Set to 0 to upload textures on the render thread even if the driver
+/// could share a context.
+const UPLOAD_THREAD_ENV: &str = "MAP_UPLOAD_THREAD";
+/// Longest the upload thread waits for the GPU to finish one texture.
+const FENCE_TIMEOUT_NS: u64 = 1_000_000_000;
+/// Pixel buffers the upload thread cycles through.
+const THREAD_PIXEL_BUFFERS: usize = 2;
+
+/// A ring of pixel unpack buffers tiles are streamed through on their way
+/// into textures. Copying into a buffer is a plain `memcpy`; the transfer
+/// into the texture then runs on the GPU instead of stalling `TexImage2D`
+/// until the driver has taken the pixels. Each buffer is orphaned before it
+/// is refilled, so a transfer still in flight never blocks the next one.
+pub struct PixelBuffers {
+    buffers: Vec<Buffer>,
+    next: usize,
+}
+
+impl PixelBuffers {
+    /// `count` buffers in the current context. If none can be made, textures
+    /// are uploaded straight from memory instead.
+    pub fn new(count: usize) -> Self {
+        Self {
+            buffers: (0..count).map_while(|_| Buffer::new()).collect(),
+            next: 0,
+        }
+    }
+
+    /// Makes a texture of `bitmap`, staged through the next buffer in the ring.
+    pub fn create_texture(&mut self, bitmap: &RgbaImage) -> Result<GLuint, MapError> {
+        let Some(buffer) = self.buffers.get(self.next) else {
+            return opengl_helper::create_texture_from_bitmap(bitmap);
+        };
+        self.next = (self.next + 1) % self.buffers.len();
+        let pixels = bitmap.as_raw();
+        buffer.bind(BufferType::PixelUnpack);
+        let staged = unsafe {
+            gl::BufferData(
+                gl::PIXEL_UNPACK_BUFFER,
+                pixels.len() as GLsizeiptr,
+                std::ptr::null(),
+                gl::STREAM_DRAW,
+            );
+            let mapped = gl::MapBufferRange(
+                gl::PIXEL_UNPACK_BUFFER,
+                0,
+                pixels.len() as GLsizeiptr,
+                gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT,
+            ) as *mut u8;
+            if mapped.is_null() {
+                false
+            } else {
+                std::ptr::copy_nonoverlapping(pixels.as_ptr(), mapped, pixels.len());
+                gl::UnmapBuffer(gl::PIXEL_UNPACK_BUFFER) == gl::TRUE
+            }
+        };
+        // with the buffer bound the pixel pointer is an offset into it
+        let texture = staged.then(|| {
+            opengl_helper::create_texture(
+                bitmap.width(),
+                bitmap.height(),
+                std::ptr::null::<GLvoid>(),
+            )
+        });
+        Buffer::clear_binding(BufferType::PixelUnpack);
+        match texture {
+            Some(Ok(texture)) => Ok(texture),
+            // staging failed, try again straight from memory
+            _ => opengl_helper::create_texture_from_bitmap(bitmap),
+        }
+    }
+}
+
+impl Drop for PixelBuffers {
+    fn drop(&mut self) {
+        for buffer in &self.buffers {
+            unsafe { gl::DeleteBuffers(1, &buffer.0) };
+        }
+    }
+}
+
+/// A tile texture created on the upload thread, ready to be drawn.
+pub struct Uploaded {
+    pub pos: TilePos,
+    /// None if the upload failed, the tile is no longer in flight either way.
+    pub texture: Option<GLuint>,
+    /// An ancestor standing in for a missing tile, see `store_placeholder`.
+    pub placeholder: bool,
+}
+
+/// The second context and the window it renders to, moved to the upload
+/// thread. SDL allows a context to be current on any one thread.
+struct SharedContext {
+    window: *mut sys::SDL_Window,
+    context: GLContext,
+}
+
+unsafe impl Send for SharedContext {}
+
+/// Creates tile textures on a thread of its own, in a GL context sharing
+/// objects with the render context, so decoding and uploading pixels never
+/// holds up a frame.
+pub struct TextureUploader {
+    tile_tx: Sender<(TilePos, RgbaImage, bool)>,
+    uploaded_rx: Receiver<Uploaded>,
+}
+
+impl TextureUploader {
+    /// Starts the thread with a context shared with `main`, which is current
+    /// again when this returns. None if the driver can't share contexts or
+    /// `MAP_UPLOAD_THREAD=0`; tiles are then uploaded by the render thread.
+    pub fn spawn(window: &Window, main: &GLContext) -> Option<Self> {
+        if std::env::var(UPLOAD_THREAD_ENV).is_ok_and(|v| v == "0") {
+            return None;
+        }
+        let gl_attr = window.subsystem().gl_attr();
+        gl_attr.set_share_with_current_context(true);
+        let created = window.gl_create_context();
+        gl_attr.set_share_with_current_context(false);
+        // creating a context makes it current, the render thread needs its own back
+        if let Err(e) = window.gl_make_current(main) {
+            error!("Failed to restore the GL context: {}", e);
+        }
+        let shared = match created {
+            Ok(context) => SharedContext {
+                window: window.raw(),
+                context,
+            },
+            Err(e) => {
+                warn!(
+                    "No shared GL context, uploading on the render thread: {}",
+                    e
+                );
+                return None;
+            }
+        };
+
+        let (tile_tx, tile_rx) = channel::<(TilePos, RgbaImage, bool)>();
+        let (uploaded_tx, uploaded_rx) = channel();
+        thread::spawn(move || {
+            // capture the whole struct, its raw pointer field alone isn't Send
+            let shared = shared;
+            if unsafe { sys::SDL_GL_MakeCurrent(shared.window, shared.context.raw()) } != 0 {
+                error!(
+                    "Failed to make the upload context current: {}",
+                    sdl2::get_error()
+                );
+                return;
+            }
+            let mut staging = PixelBuffers::new(THREAD_PIXEL_BUFFERS);
+            while let Ok((pos, image, placeholder)) = tile_rx.recv() {
+                let _span =
+                    debug_span!("upload", z = pos.z, x = pos.x, y = pos.y, m = pos.m).entered();
+                let texture = match staging.create_texture(&image) {
+                    Ok(texture) => {
+                        // the render context may only sample it once the upload is done
+                        unsafe {
+                            let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+                            gl::ClientWaitSync(
+                                fence,
+                                gl::SYNC_FLUSH_COMMANDS_BIT,
+                                FENCE_TIMEOUT_NS,
+                            );
+                            gl::DeleteSync(fence);
+                        }
+                        debug!("Uploaded");
+                        Some(texture)
+                    }
+                    Err(e) => {
+                        warn!("Failed to upload: {}", e);
+                        None
+                    }
+                };
+                let uploaded = Uploaded {
+                    pos,
+                    texture,
+                    placeholder,
+                };
+                if uploaded_tx.send(uploaded).is_err() {
+                    break;
+                }
+                wake::wake();
+            }
+            drop(staging);
+            unsafe { sys::SDL_GL_MakeCurrent(shared.window, std::ptr::null_mut()) };
+        });
+        Some(Self {
+            tile_tx,
+            uploaded_rx,
+        })
+    }
+
+    /// Queues the image of a finished load for upload.
+    pub fn upload(&self, load: TileLoad) {
+        let job = match load {
+            TileLoad::Loaded {
+                texture,
+                source_tile,
+            } => (source_tile, texture, false),
+            TileLoad::Loading {
+                texture,
+                source_tile,
+                ..
+            } => (source_tile, texture, true),
+            TileLoad::Failed => return,
+        };
+        let _ = self.tile_tx.send(job);
+    }
+
+    /// The next tile that finished uploading or failed to, if any.
+    pub fn poll(&self) -> Option<Uploaded> {
+        self.uploaded_rx.try_recv().ok()
+    }
+}
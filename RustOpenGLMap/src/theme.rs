@@ -0,0 +1,159 @@
+use embedded_graphics::mono_font::MonoFont;
+use embedded_graphics::mono_font::iso_8859_1::{FONT_6X10, FONT_7X13, FONT_9X15, FONT_10X20};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+pub const THEME_PATH: &str = "theme.toml";
+
+/// Window corner a widget is anchored to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    /// Top-left pixel of a `size` box kept `margin` pixels away from this corner.
+    pub fn place(self, size: (f32, f32), margin: f32, win: (u32, u32)) -> (f32, f32) {
+        let right = win.0 as f32 - size.0 - margin;
+        let bottom = win.1 as f32 - size.1 - margin;
+        match self {
+            Self::TopLeft => (margin, margin),
+            Self::TopRight => (right, margin),
+            Self::BottomLeft => (margin, bottom),
+            Self::BottomRight => (right, bottom),
+        }
+    }
+}
+
+/// Colours, font and widget placement of everything drawn over the map.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    /// One of the built-in bitmap fonts: "6x10", "7x13", "9x15" or "10x20".
+    pub font: String,
+    /// Scale of status messages, panels use three quarters of it.
+    pub text_scale: f32,
+    /// Scale of the attribution line.
+    pub small_text_scale: f32,
+    /// Space between widgets and the window edge, and inside panels.
+    pub padding: f32,
+    pub text: [f32; 4],
+    pub muted_text: [f32; 4],
+    pub accent: [f32; 4],
+    pub warning: [f32; 4],
+    pub error: [f32; 4],
+    /// Backdrop of panels and the attribution.
+    pub panel: [f32; 4],
+    pub hud_corner: Corner,
+    pub attribution_corner: Corner,
+    pub layer_list_corner: Corner,
+    pub point_list_corner: Corner,
+    /// Position under the mouse, shown when the status bar is hidden.
+    pub cursor_corner: Corner,
+    /// Frame timing and cache statistics, shown with F3.
+    pub stats_corner: Corner,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            font: "6x10".to_string(),
+            text_scale: 2.0,
+            small_text_scale: 1.0,
+            padding: 6.0,
+            text: [1.0, 1.0, 1.0, 1.0],
+            muted_text: [0.8, 0.8, 0.8, 1.0],
+            accent: [1.0, 0.9, 0.2, 1.0],
+            warning: [1.0, 0.9, 0.2, 1.0],
+            error: [1.0, 0.4, 0.3, 1.0],
+            panel: [0.0, 0.0, 0.0, 0.75],
+            hud_corner: Corner::TopLeft,
+            attribution_corner: Corner::BottomRight,
+            layer_list_corner: Corner::TopRight,
+            point_list_corner: Corner::BottomLeft,
+            cursor_corner: Corner::BottomLeft,
+            stats_corner: Corner::BottomRight,
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            text: [0.1, 0.1, 0.1, 1.0],
+            muted_text: [0.35, 0.35, 0.35, 1.0],
+            accent: [0.1, 0.35, 0.8, 1.0],
+            warning: [0.75, 0.45, 0.0, 1.0],
+            error: [0.8, 0.1, 0.1, 1.0],
+            panel: [1.0, 1.0, 1.0, 0.8],
+            ..Self::dark()
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+
+    /// Parses a theme file: `preset = "light"|"dark"` (dark if omitted)
+    /// followed by any keys of `Theme` that should differ from the preset.
+    pub fn from_toml(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut overrides: toml::Table = toml::from_str(text)?;
+        let preset = match overrides.remove("preset") {
+            Some(toml::Value::String(name)) => name,
+            Some(other) => {
+                return Err(Box::from(format!("preset must be a string, not {}", other)));
+            }
+            None => "dark".to_string(),
+        };
+        let base = Self::preset(&preset).ok_or_else(|| format!("unknown preset {}", preset))?;
+        let mut merged = toml::Table::try_from(base)?;
+        merged.extend(overrides);
+        let theme: Self = toml::Value::Table(merged).try_into()?;
+        theme.mono_font()?;
+        Ok(theme)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads `path` if it exists, falling back to the dark preset.
+    pub fn load_or_default(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("Failed to load {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn mono_font(&self) -> Result<&'static MonoFont<'static>, String> {
+        match self.font.as_str() {
+            "6x10" => Ok(&FONT_6X10),
+            "7x13" => Ok(&FONT_7X13),
+            "9x15" => Ok(&FONT_9X15),
+            "10x20" => Ok(&FONT_10X20),
+            other => Err(format!("unknown font {}", other)),
+        }
+    }
+
+    pub fn panel_text_scale(&self) -> f32 {
+        self.text_scale * 0.75
+    }
+}
@@ -1,4 +1,4 @@
-const MAX_ZOOM: u8 = 19;
+pub const MAX_ZOOM: u8 = 19;
 use image::RgbaImage;
 use std::hash::{Hash, Hasher};
 // Added for managing loading state, optional
@@ -0,0 +1,188 @@
+use gl::types::{GLint, GLuint};
+
+/// Tiles per array texture. Pages are never resized, so growing the atlas
+/// doesn't copy what is already in it.
+const PAGE_LAYERS: i32 = 64;
+
+/// Where a tile lives in the atlas: a layer of one of its array textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasSlot {
+    pub page: usize,
+    pub layer: i32,
+}
+
+/// One `GL_TEXTURE_2D_ARRAY` of equally sized tiles, mipmapped like the
+/// standalone tile textures.
+#[derive(Debug)]
+struct Page {
+    texture: GLuint,
+    size: (i32, i32),
+    free: Vec<i32>,
+}
+
+/// Tile textures packed into array textures, so a whole layer of tiles can
+/// be drawn with one texture bound and one instanced draw per page.
+///
+/// Tiles still arrive as standalone textures (the upload thread can't share
+/// the arrays without fencing every draw); `insert` copies them into a free
+/// layer on the GPU and deletes the original.
+#[derive(Debug)]
+pub struct TileAtlas {
+    pages: Vec<Page>,
+    /// Framebuffer the standalone textures are read through while copying.
+    read_fbo: GLuint,
+}
+
+impl TileAtlas {
+    pub fn new() -> Self {
+        let mut read_fbo = 0;
+        unsafe { gl::GenFramebuffers(1, &mut read_fbo) };
+        Self {
+            pages: Vec::new(),
+            read_fbo,
+        }
+    }
+
+    /// The array texture holding `slot`.
+    pub fn texture(&self, slot: AtlasSlot) -> GLuint {
+        self.pages[slot.page].texture
+    }
+
+    /// Moves the standalone tile `texture` into a free layer, making a new
+    /// page for its size if all are taken, and deletes `texture`.
+    pub fn insert(&mut self, texture: GLuint) -> AtlasSlot {
+        let mut size = (0, 0);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut size.0);
+            gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut size.1);
+        }
+        let page = match self
+            .pages
+            .iter()
+            .position(|page| page.size == size && !page.free.is_empty())
+        {
+            Some(page) => page,
+            None => self.add_page(size),
+        };
+        let layer = self.pages[page].free.pop().expect("page has a free layer");
+        let slot = AtlasSlot { page, layer };
+
+        let mut previous: GLint = 0;
+        unsafe {
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.pages[page].texture);
+            for level in 0..mip_levels(size) {
+                gl::FramebufferTexture2D(
+                    gl::READ_FRAMEBUFFER,
+                    gl::COLOR_ATTACHMENT0,
+                    gl::TEXTURE_2D,
+                    texture,
+                    level,
+                );
+                gl::CopyTexSubImage3D(
+                    gl::TEXTURE_2D_ARRAY,
+                    level,
+                    0,
+                    0,
+                    layer,
+                    0,
+                    0,
+                    (size.0 >> level).max(1),
+                    (size.1 >> level).max(1),
+                );
+            }
+            gl::FramebufferTexture2D(
+                gl::READ_FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                0,
+                0,
+            );
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous as GLuint);
+            gl::DeleteTextures(1, &texture);
+        }
+        slot
+    }
+
+    /// Array textures allocated so far.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Gives the layer of an evicted tile back for the next one.
+    pub fn release(&mut self, slot: AtlasSlot) {
+        self.pages[slot.page].free.push(slot.layer);
+    }
+
+    fn add_page(&mut self, size: (i32, i32)) -> usize {
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+            for level in 0..mip_levels(size) {
+                gl::TexImage3D(
+                    gl::TEXTURE_2D_ARRAY,
+                    level,
+                    gl::RGBA8 as GLint,
+                    (size.0 >> level).max(1),
+                    (size.1 >> level).max(1),
+                    PAGE_LAYERS,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    std::ptr::null(),
+                );
+            }
+            gl::TexParameteri(
+                gl::TEXTURE_2D_ARRAY,
+                gl::TEXTURE_MAX_LEVEL,
+                mip_levels(size) - 1,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D_ARRAY,
+                gl::TEXTURE_WRAP_S,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D_ARRAY,
+                gl::TEXTURE_WRAP_T,
+                gl::CLAMP_TO_EDGE as GLint,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D_ARRAY,
+                gl::TEXTURE_MIN_FILTER,
+                gl::LINEAR_MIPMAP_LINEAR as GLint,
+            );
+            gl::TexParameteri(
+                gl::TEXTURE_2D_ARRAY,
+                gl::TEXTURE_MAG_FILTER,
+                gl::LINEAR as GLint,
+            );
+        }
+        self.pages.push(Page {
+            texture,
+            size,
+            // lowest layers handed out first
+            free: (0..PAGE_LAYERS).rev().collect(),
+        });
+        self.pages.len() - 1
+    }
+}
+
+impl Drop for TileAtlas {
+    fn drop(&mut self) {
+        unsafe {
+            for page in &self.pages {
+                gl::DeleteTextures(1, &page.texture);
+            }
+            gl::DeleteFramebuffers(1, &self.read_fbo);
+        }
+    }
+}
+
+/// Levels of a full mip chain down to 1×1, as `GenerateMipmap` makes them.
+fn mip_levels(size: (i32, i32)) -> i32 {
+    32 - size.0.max(size.1).max(1).leading_zeros() as i32
+}
@@ -0,0 +1,285 @@
+use crate::layers::ZoomRange;
+use crate::tile_source::SourceRegistry;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Opacity steps of the `[` and `]` keys.
+const OPACITY_STEP: f32 = 0.1;
+
+/// A tile source drawn over the base map, e.g. seamarks over imagery.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TileLayer {
+    pub source: u8,
+    /// 0 is invisible, 1 hides everything underneath where the tiles are opaque.
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    #[serde(default)]
+    pub blend: Blend,
+    /// Outline strength behind label text, 0 for none; for labels-only
+    /// layers over imagery.
+    #[serde(default)]
+    pub halo: f32,
+    /// Zooms the layer is drawn at; hidden outside them without being
+    /// switched off.
+    #[serde(flatten)]
+    pub zoom: ZoomRange,
+    /// The opacity on its way to a new value, see `TileLayers::fade_to`.
+    #[serde(skip)]
+    fade: Option<Fade>,
+}
+
+/// An overlay's drawn opacity changing over several frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fade {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration: Duration,
+    /// Switch the layer off once it has faded out.
+    hide: bool,
+}
+
+impl Fade {
+    /// Opacity at `now`, and whether the fade is over.
+    fn at(&self, now: Instant) -> (f32, bool) {
+        let t =
+            now.saturating_duration_since(self.start).as_secs_f32() / self.duration.as_secs_f32();
+        if t >= 1.0 || !t.is_finite() {
+            return (self.to, true);
+        }
+        let e = t * t * (3.0 - 2.0 * t);
+        (self.from + (self.to - self.from) * e, false)
+    }
+}
+
+/// How a layer's tiles combine with what is drawn underneath. Tile pixels
+/// are premultiplied, so each mode is a single blend function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Blend {
+    /// Drawn over, as far as the tiles are opaque.
+    #[default]
+    Normal,
+    /// Darkens, e.g. hillshading over a street map.
+    Multiply,
+    /// Lightens, e.g. light labels over dark imagery.
+    Screen,
+}
+
+impl Blend {
+    /// Sets the blend function for premultiplied colour.
+    pub fn apply(self) {
+        let (src, dst) = match self {
+            Blend::Normal => (gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+            Blend::Multiply => (gl::DST_COLOR, gl::ONE_MINUS_SRC_ALPHA),
+            Blend::Screen => (gl::ONE, gl::ONE_MINUS_SRC_COLOR),
+        };
+        unsafe { gl::BlendFunc(src, dst) };
+    }
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+impl TileLayer {
+    pub fn base(source: u8) -> Self {
+        Self {
+            source,
+            opacity: 1.0,
+            visible: true,
+            blend: Blend::Normal,
+            halo: 0.0,
+            zoom: ZoomRange::default(),
+            fade: None,
+        }
+    }
+
+    /// Opacity drawn at `now`, part way through a fade or 0 while off.
+    pub fn drawn_opacity(&self, now: Instant) -> f32 {
+        match (&self.fade, self.visible) {
+            (Some(fade), _) => fade.at(now).0,
+            (None, true) => self.opacity,
+            (None, false) => 0.0,
+        }
+    }
+
+    /// On and not fading out.
+    pub fn shown(&self) -> bool {
+        self.visible && !self.fade.is_some_and(|fade| fade.hide)
+    }
+}
+
+/// Overlays stacked on the base source, bottom first, and the one the
+/// layer keys act on.
+#[derive(Debug, Clone, Default)]
+pub struct TileLayers {
+    pub overlays: Vec<TileLayer>,
+    pub selected: usize,
+}
+
+impl TileLayers {
+    pub fn new(overlays: Vec<TileLayer>) -> Self {
+        Self {
+            overlays,
+            selected: 0,
+        }
+    }
+
+    /// What to draw at `now` at zoom `z`: the base source, then the visible
+    /// overlays whose zoom range includes `z`, at their drawn opacity.
+    pub fn drawn(&self, base: u8, z: u8, now: Instant) -> Vec<TileLayer> {
+        std::iter::once(TileLayer::base(base))
+            .chain(
+                self.overlays
+                    .iter()
+                    .filter(|layer| layer.visible && layer.zoom.contains(z))
+                    .map(|layer| TileLayer {
+                        opacity: layer.drawn_opacity(now),
+                        fade: None,
+                        ..*layer
+                    })
+                    .filter(|layer| layer.opacity > 0.0),
+            )
+            .collect()
+    }
+
+    /// Takes overlay `index` from its drawn opacity to `opacity` over
+    /// `duration`, switching it on first if it was off.
+    pub fn fade_to(&mut self, index: usize, opacity: f32, duration: Duration) {
+        self.start_fade(index, Some(opacity.clamp(0.0, 1.0)), false, duration);
+    }
+
+    /// Switches overlay `index` on, raising it from its drawn opacity to its
+    /// own over `duration`.
+    pub fn fade_in(&mut self, index: usize, duration: Duration) {
+        self.start_fade(index, None, false, duration);
+    }
+
+    /// Lowers overlay `index` to nothing over `duration`, then switches it
+    /// off. Its opacity is kept for when it is shown again.
+    pub fn fade_out(&mut self, index: usize, duration: Duration) {
+        self.start_fade(index, None, true, duration);
+    }
+
+    /// Fades overlay `index` in or out.
+    pub fn set_shown(&mut self, index: usize, shown: bool, duration: Duration) {
+        if shown {
+            self.fade_in(index, duration);
+        } else {
+            self.fade_out(index, duration);
+        }
+    }
+
+    fn start_fade(&mut self, index: usize, opacity: Option<f32>, hide: bool, duration: Duration) {
+        let Some(layer) = self.overlays.get_mut(index) else {
+            return;
+        };
+        let now = Instant::now();
+        let from = layer.drawn_opacity(now);
+        if let Some(opacity) = opacity {
+            layer.opacity = opacity;
+        }
+        layer.visible = true;
+        layer.fade = Some(Fade {
+            from,
+            to: if hide { 0.0 } else { layer.opacity },
+            start: now,
+            duration,
+            hide,
+        });
+    }
+
+    /// Ends the fades that are over at `now`. Returns whether any layer was
+    /// fading, so the frame needs drawing.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let mut fading = false;
+        for layer in &mut self.overlays {
+            let Some(fade) = layer.fade else {
+                continue;
+            };
+            fading = true;
+            if fade.at(now).1 {
+                layer.fade = None;
+                layer.visible = !fade.hide;
+            }
+        }
+        fading
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.overlays.is_empty() {
+            self.selected = (self.selected + 1) % self.overlays.len();
+        }
+    }
+
+    /// Stacks the next source that isn't drawn yet on top, half transparent,
+    /// fading it in over `fade`. Returns false when every source is already
+    /// in use.
+    pub fn add_next(&mut self, base: u8, sources: &SourceRegistry, fade: Duration) -> bool {
+        let in_use = |id: u8| id == base || self.overlays.iter().any(|layer| layer.source == id);
+        let start = self.overlays.last().map_or(base, |layer| layer.source);
+        let mut id = sources.next_id(start);
+        while id != start {
+            if !in_use(id) {
+                self.overlays.push(TileLayer {
+                    source: id,
+                    opacity: 0.5,
+                    visible: true,
+                    blend: Blend::Normal,
+                    halo: 0.0,
+                    zoom: ZoomRange::default(),
+                    fade: None,
+                });
+                self.fade_in(self.overlays.len() - 1, fade);
+                self.selected = self.overlays.len() - 1;
+                return true;
+            }
+            id = sources.next_id(id);
+        }
+        false
+    }
+
+    pub fn remove_selected(&mut self) {
+        self.remove(self.selected);
+    }
+
+    /// Takes overlay `index` off, keeping the selection on the same layer
+    /// where it can.
+    pub fn remove(&mut self, index: usize) {
+        if index < self.overlays.len() {
+            self.overlays.remove(index);
+            if index < self.selected {
+                self.selected -= 1;
+            }
+            self.selected = self.selected.min(self.overlays.len().saturating_sub(1));
+        }
+    }
+
+    /// Fades the selected overlay out if it is shown, in if not.
+    pub fn toggle_selected(&mut self, duration: Duration) {
+        if let Some(layer) = self.overlays.get(self.selected) {
+            self.set_shown(self.selected, !layer.shown(), duration);
+        }
+    }
+
+    /// Raises (`steps` > 0) or lowers the selected overlay's opacity.
+    pub fn step_opacity(&mut self, steps: i32, duration: Duration) {
+        if let Some(layer) = self.overlays.get(self.selected) {
+            let opacity = layer.opacity + steps as f32 * OPACITY_STEP;
+            self.fade_to(self.selected, opacity, duration);
+        }
+    }
+
+    /// Drops overlays whose source no longer exists.
+    pub fn retain_sources(&mut self, sources: &SourceRegistry) {
+        self.overlays.retain(|layer| sources.contains(layer.source));
+        self.selected = self.selected.min(self.overlays.len().saturating_sub(1));
+    }
+}
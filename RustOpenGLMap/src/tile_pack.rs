@@ -0,0 +1,269 @@
+use crate::disk_cache::{DISK_CACHE, tile_dir};
+use crate::region_download::{self, Region};
+use crate::tile::TilePos;
+use crate::tile_source::SOURCES;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Extension given to packs written without one.
+const EXTENSION: &str = "tilepack";
+/// Layout version written into the manifest; newer packs are refused.
+const FORMAT: u32 = 1;
+const MANIFEST_NAME: &str = "manifest.toml";
+
+/// What a pack holds, stored as `manifest.toml` next to its tiles, which
+/// sit at `tiles/<z>/<x>/<y>.png` with rows numbered from the north (XYZ)
+/// whatever the source's own order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackManifest {
+    pub format: u32,
+    /// Name of the source the tiles came from, matched on import.
+    pub source: String,
+    /// Its preset key, matched before the name.
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// South, west, north, east in degrees.
+    pub bounds: [f64; 4],
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub tiles: u64,
+    pub bytes: u64,
+    /// Seconds since 1970 when the pack was written.
+    pub created: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct PackStats {
+    pub tiles: u64,
+    pub bytes: u64,
+    /// Tiles of the region that weren't cached (export) or were already (import).
+    pub skipped: u64,
+    /// Entries that weren't tiles or didn't look like PNGs (import).
+    pub failed: u64,
+}
+
+fn tile_entry(tile: &TilePos) -> String {
+    format!("tiles/{}/{}/{}.png", tile.z, tile.x, tile.y)
+}
+
+/// The tile an entry name stands for, `None` for anything else.
+fn parse_entry(name: &str, m: u8) -> Option<TilePos> {
+    let rest = name.strip_prefix("tiles/")?.strip_suffix(".png")?;
+    let parts: Vec<&str> = rest.split('/').collect();
+    let [z, x, y] = parts[..] else {
+        return None;
+    };
+    let z: u8 = z.parse().ok().filter(|z| *z <= 19)?;
+    let n = 1u32 << z;
+    let x: u32 = x.parse().ok().filter(|x| *x < n)?;
+    let y: u32 = y.parse().ok().filter(|y| *y < n)?;
+    Some(TilePos { z, x, y, m })
+}
+
+/// Writes the cached tiles of `region` in source `m` into a pack at `out`.
+/// PNGs are stored as they are, they wouldn't shrink any further. Tiles
+/// that aren't cached are counted as skipped.
+pub fn export_pack(region: &Region, m: u8, out: &Path) -> Result<PackStats, Box<dyn Error>> {
+    let (source, preset) = SOURCES
+        .read()
+        .unwrap()
+        .get(m)
+        .map(|source| (source.name.clone(), source.preset.clone()))
+        .ok_or_else(|| format!("Unknown tile source {}", m))?;
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(out)?));
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut stats = PackStats::default();
+    for tile in region.tiles(m) {
+        let Ok(bytes) = fs::read(SOURCES.read().unwrap().file_path(&tile)) else {
+            stats.skipped += 1;
+            continue;
+        };
+        zip.start_file(tile_entry(&tile), stored)?;
+        zip.write_all(&bytes)?;
+        stats.tiles += 1;
+        stats.bytes += bytes.len() as u64;
+    }
+    let manifest = PackManifest {
+        format: FORMAT,
+        source,
+        preset,
+        bounds: [
+            region.min_lat,
+            region.min_lon,
+            region.max_lat,
+            region.max_lon,
+        ],
+        min_zoom: region.min_z,
+        max_zoom: region.max_z,
+        tiles: stats.tiles,
+        bytes: stats.bytes,
+        created: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    zip.start_file(MANIFEST_NAME, SimpleFileOptions::default())?;
+    zip.write_all(toml::to_string(&manifest)?.as_bytes())?;
+    zip.finish()?.flush()?;
+    Ok(stats)
+}
+
+fn manifest_of<R: Read + std::io::Seek>(
+    zip: &mut ZipArchive<R>,
+) -> Result<PackManifest, Box<dyn Error>> {
+    let mut text = String::new();
+    zip.by_name(MANIFEST_NAME)
+        .map_err(|_| "not a tile pack, it has no manifest.toml")?
+        .read_to_string(&mut text)?;
+    let manifest: PackManifest = toml::from_str(&text)?;
+    if manifest.format > FORMAT {
+        return Err(Box::from(format!(
+            "pack format {} is newer than this version reads ({})",
+            manifest.format, FORMAT
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Copies the tiles of the pack at `path` into the tile directory as tiles
+/// of source `m`, or of the source the manifest names. Tiles already
+/// cached are left alone.
+pub fn import_pack(
+    path: &Path,
+    m: Option<u8>,
+) -> Result<(PackManifest, PackStats), Box<dyn Error>> {
+    let mut zip = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let manifest = manifest_of(&mut zip)?;
+    let m = match m {
+        Some(m) => m,
+        None => {
+            let sources = SOURCES.read().unwrap();
+            manifest
+                .preset
+                .as_deref()
+                .and_then(|preset| sources.find(preset))
+                .or_else(|| sources.find(&manifest.source))
+                .ok_or_else(|| {
+                    format!(
+                        "no source named {} in sources.toml, pick one with --source",
+                        manifest.source
+                    )
+                })?
+        }
+    };
+    fs::create_dir_all(tile_dir())?;
+    let mut stats = PackStats::default();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() || entry.name() == MANIFEST_NAME {
+            continue;
+        }
+        let Some(tile) = parse_entry(entry.name(), m) else {
+            stats.failed += 1;
+            continue;
+        };
+        let target = SOURCES.read().unwrap().file_path(&tile);
+        if target.exists() {
+            stats.skipped += 1;
+            continue;
+        }
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+        if !bytes.starts_with(b"\x89PNG") {
+            stats.failed += 1;
+            continue;
+        }
+        fs::write(&target, &bytes)?;
+        DISK_CACHE.lock().unwrap().record(&target);
+        stats.tiles += 1;
+        stats.bytes += bytes.len() as u64;
+    }
+    Ok((manifest, stats))
+}
+
+/// `RustOpenGLMap export-pack <min_lat> <min_lon> <max_lat> <max_lon> <min_z> <max_z> [--source <id>] [--out <file>]`
+///
+/// Only cached tiles go into the pack; run `download` over the same region
+/// first to fill the gaps.
+#[derive(Debug, Args)]
+pub struct ExportPackArgs {
+    #[arg(allow_negative_numbers = true)]
+    min_lat: f64,
+    #[arg(allow_negative_numbers = true)]
+    min_lon: f64,
+    #[arg(allow_negative_numbers = true)]
+    max_lat: f64,
+    #[arg(allow_negative_numbers = true)]
+    max_lon: f64,
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=19))]
+    min_z: u8,
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=19))]
+    max_z: u8,
+    /// Source id whose tiles to pack.
+    #[arg(long, default_value_t = 0)]
+    source: u8,
+    /// Pack to write.
+    #[arg(long, default_value = "region.tilepack")]
+    out: PathBuf,
+}
+
+pub fn run_export_pack_command(args: ExportPackArgs) -> Result<(), String> {
+    let region = region_download::source_region(
+        [args.min_lat, args.min_lon, args.max_lat, args.max_lon],
+        (args.min_z, args.max_z),
+        args.source,
+    )?;
+    let out = match args.out.extension() {
+        Some(_) => args.out,
+        None => args.out.with_extension(EXTENSION),
+    };
+    let stats = export_pack(&region, args.source, &out)
+        .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+    println!(
+        "Packed {} tiles, {:.1} MiB, into {}; {} of the region aren't cached",
+        stats.tiles,
+        stats.bytes as f64 / (1024.0 * 1024.0),
+        out.display(),
+        stats.skipped
+    );
+    Ok(())
+}
+
+/// `RustOpenGLMap import-pack <file> [--source <id>]`
+#[derive(Debug, Args)]
+pub struct ImportPackArgs {
+    /// Pack written by `export-pack`.
+    file: PathBuf,
+    /// Source id to import the tiles as, instead of the one the pack names.
+    #[arg(long)]
+    source: Option<u8>,
+}
+
+pub fn run_import_pack_command(args: ImportPackArgs) -> Result<(), String> {
+    let (manifest, stats) = import_pack(&args.file, args.source)
+        .map_err(|e| format!("Failed to import {}: {}", args.file.display(), e))?;
+    let [south, west, north, east] = manifest.bounds;
+    println!(
+        "{} tiles of {}, z{}-{} in {:.4}, {:.4} to {:.4}, {:.4}",
+        manifest.tiles,
+        manifest.source,
+        manifest.min_zoom,
+        manifest.max_zoom,
+        south,
+        west,
+        north,
+        east
+    );
+    println!(
+        "Imported {} tiles, {:.1} MiB, {} already cached, {} invalid",
+        stats.tiles,
+        stats.bytes as f64 / (1024.0 * 1024.0),
+        stats.skipped,
+        stats.failed
+    );
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use crate::opengl_helper::{self, Buffer, BufferType, Sampler, ShaderProgram, VertexArray};
+use crate::tile_source::Filter;
+use gl::types::{GLsizei, GLuint};
+
+/// Background where no tile is drawn, loud so gaps stand out.
+pub const CLEAR_COLOR: [f32; 4] = [0.7, 0.1, 0.5, 1.0];
+
+type Vertex = [f32; 3 + 3 + 2];
+type TriIndexes = [u32; 3];
+const VERTICES: [Vertex; 4] = [
+    // top right
+    [0.5, 0.5, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0],
+    // bottom right
+    [0.5, -0.5, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0],
+    // bottom left
+    [-0.5, -0.5, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+    // top left
+    [-0.5, 0.5, 0.0, 0.2, 0.3, 0.4, 0.0, 1.0],
+];
+
+const INDICES: [TriIndexes; 2] = [[0, 1, 3], [1, 2, 3]];
+
+const VERT_SHADER: &str = r#"#version 410 core
+layout (location = 0) in vec3 pos;
+layout (location = 2) in vec2 tex;
+// per instance, one per tile drawn
+layout (location = 3) in vec2 offset; // translation in NDC
+layout (location = 4) in vec3 uv;     // (u, v, size) part of the texture to show, for ancestor placeholders
+layout (location = 5) in float alpha; // < 1 while a tile fades in
+layout (location = 6) in float layer; // of the array texture
+
+uniform vec2 u_scale;   // tile-size in NDC
+
+out vec2 v_tex;
+flat out vec3 v_uv;
+flat out float v_alpha;
+flat out float v_layer;
+
+void main() {
+    vec2 scaled     = pos.xy * u_scale;
+    vec2 translated = scaled  + offset;
+    gl_Position = vec4(translated, pos.z, 1.0);
+    v_tex       = uv.xy + tex * uv.z;
+    v_uv        = uv;
+    v_alpha     = alpha;
+    v_layer     = layer;
+}
+
+"#;
+
+// Samples stay half a texel inside the part of the texture shown, so
+// linear filtering never reaches past the tile's (or crop's) edge. Texels
+// are premultiplied, and so is the colour written.
+const FRAG_SHADER: &str = r#"#version 410 core
+uniform sampler2DArray the_texture;
+#pragma halo
+in  vec2 v_tex;
+flat in vec3 v_uv;
+flat in float v_alpha;
+flat in float v_layer;
+out vec4 final_color;
+void main() {
+    vec2 half_texel = 0.5 / vec2(textureSize(the_texture, 0).xy);
+    // a crop narrower than a texel, far past the source's max zoom, keeps
+    // to its middle: clamp() is undefined once lo passes hi
+    vec2 mid    = v_uv.xy + 0.5 * v_uv.z;
+    vec2 lo     = min(v_uv.xy + half_texel, mid);
+    vec2 hi     = max(v_uv.xy + v_uv.z - half_texel, mid);
+    vec2 uv     = clamp(v_tex, lo, hi);
+    vec4 color  = texture(the_texture, vec3(uv, v_layer));
+    final_color = with_halo(color, uv, v_layer, lo, hi) * v_alpha;
+}
+"#;
+
+/// Outlines for label layers over busy imagery: a light halo behind dark
+/// text and a dark one behind light text, as wide as `u_halo` strong
+/// allows. Needs `the_texture` declared; `with_halo_glsl` pastes it in.
+const HALO_GLSL: &str = r#"
+uniform float u_halo;  // 0 for none, 1 for an opaque outline
+
+// premultiplied `color`, sampled at `uv` of `layer`, over the halo of its
+// neighbours; samples stay within `lo`..`hi` like the tile's own
+vec4 with_halo(vec4 color, vec2 uv, float layer, vec2 lo, vec2 hi) {
+    if (u_halo <= 0.0) {
+        return color;
+    }
+    vec2 texel = 1.5 / vec2(textureSize(the_texture, 0).xy);
+    vec4 around = vec4(0.0);
+    float cover = 0.0;
+    for (int i = 0; i < 8; i++) {
+        float angle = float(i) * 0.7853982;
+        vec2 at = clamp(uv + vec2(cos(angle), sin(angle)) * texel, lo, hi);
+        vec4 near = texture(the_texture, vec3(at, layer));
+        around += near;
+        cover = max(cover, near.a);
+    }
+    float luma = dot(around.rgb / max(around.a, 1e-4), vec3(0.299, 0.587, 0.114));
+    float strength = cover * u_halo;
+    vec4 halo = vec4(vec3(step(luma, 0.5)) * strength, strength);
+    return color + halo * (1.0 - color.a);
+}
+"#;
+
+/// `shader` with the label halo function in place of its `#pragma halo` line.
+pub fn with_halo_glsl(shader: &str) -> String {
+    shader.replace("#pragma halo", HALO_GLSL)
+}
+
+/// One tile for `TileQuad::draw_instances`: its offset in NDC, the
+/// (u, v, size) part of the texture shown, its alpha and its atlas layer.
+pub type Instance = [f32; 2 + 3 + 1 + 1];
+
+/// The unit quad every tile is drawn with, the program that textures it
+/// from the tile atlas and the samplers for each `Filter`.
+pub struct TileQuad {
+    pub vao: VertexArray,
+    pub program: ShaderProgram,
+    smooth: Sampler,
+    sharp: Sampler,
+    /// Per-tile attributes, refilled for every draw.
+    instances: Buffer,
+    // kept with the VAO that references them
+    _vbo: Buffer,
+    _ebo: Buffer,
+}
+
+impl TileQuad {
+    pub fn new() -> Result<Self, String> {
+        let vao = VertexArray::new().ok_or("Couldn't make a VAO")?;
+        vao.bind();
+        let vbo = Buffer::new().ok_or("Couldn't make a VBO")?;
+        vbo.bind(BufferType::Array);
+        Buffer::data(
+            BufferType::Array,
+            bytemuck::cast_slice(&VERTICES),
+            gl::STATIC_DRAW,
+        );
+
+        let ebo = Buffer::new().ok_or("Couldn't make the element buffer")?;
+        ebo.bind(BufferType::ElementArray);
+        Buffer::data(
+            BufferType::ElementArray,
+            bytemuck::cast_slice(&INDICES),
+            gl::STATIC_DRAW,
+        );
+
+        let program = ShaderProgram::from_vert_frag(VERT_SHADER, &with_halo_glsl(FRAG_SHADER))?;
+        unsafe {
+            // position
+            gl::VertexAttribPointer(
+                0,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>().try_into().unwrap(),
+                0 as *const _,
+            );
+            gl::EnableVertexAttribArray(0);
+            // colour
+            gl::VertexAttribPointer(
+                1,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>().try_into().unwrap(),
+                size_of::<[f32; 3]>() as *const _,
+            );
+            gl::EnableVertexAttribArray(1);
+            // tex
+            gl::VertexAttribPointer(
+                2,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                size_of::<Vertex>().try_into().unwrap(),
+                size_of::<[f32; 6]>() as *const _,
+            );
+            gl::EnableVertexAttribArray(2);
+        }
+        let instances = Buffer::new().ok_or("Couldn't make the instance buffer")?;
+        instances.bind(BufferType::Array);
+        // offset, uv, alpha, layer; advancing once per tile instead of per vertex
+        let mut start = 0;
+        for (location, floats) in [(3, 2), (4, 3), (5, 1), (6, 1)] {
+            unsafe {
+                gl::VertexAttribPointer(
+                    location,
+                    floats,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    size_of::<Instance>().try_into().unwrap(),
+                    (start * size_of::<f32>()) as *const _,
+                );
+                gl::EnableVertexAttribArray(location);
+                gl::VertexAttribDivisor(location, 1);
+            }
+            start += floats as usize;
+        }
+        opengl_helper::polygon_mode(opengl_helper::PolygonMode::Fill);
+        // tiles clamp at their edges, so filtering can't pull in the opposite side
+        let smooth = Sampler::new(gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR, gl::CLAMP_TO_EDGE)
+            .ok_or("Couldn't make the tile sampler")?;
+        let sharp = Sampler::new(gl::NEAREST, gl::NEAREST, gl::CLAMP_TO_EDGE)
+            .ok_or("Couldn't make the tile sampler")?;
+        Ok(Self {
+            vao,
+            program,
+            smooth,
+            sharp,
+            instances,
+            _vbo: vbo,
+            _ebo: ebo,
+        })
+    }
+
+    /// Draws `instances` with layers of the array `texture`, with the
+    /// program in use and the VAO bound.
+    pub fn draw_instances(&self, texture: GLuint, instances: &[Instance]) {
+        if instances.is_empty() {
+            return;
+        }
+        self.instances.bind(BufferType::Array);
+        Buffer::data(
+            BufferType::Array,
+            bytemuck::cast_slice(instances),
+            gl::STREAM_DRAW,
+        );
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+            gl::DrawElementsInstanced(
+                gl::TRIANGLES,
+                6,
+                gl::UNSIGNED_INT,
+                std::ptr::null(),
+                instances.len() as GLsizei,
+            );
+        }
+    }
+
+    pub fn sampler(&self, filter: Filter) -> &Sampler {
+        match filter {
+            Filter::Linear => &self.smooth,
+            Filter::Nearest => &self.sharp,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::tile::TilePos;
+use std::collections::HashSet;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+
+/// Most tiles waiting for a loader, a download or an upload at once. Past
+/// it new requests are turned away until some finish; the tiles still
+/// missing are asked for again on the next frame.
+const MAX_IN_FLIGHT: usize = 256;
+
+/// The job queue of the tile loaders, coalesced: drawing asks for every
+/// missing tile on every frame, but a tile already on its way is sent only
+/// once. It stays in flight until its texture is stored, its download is
+/// given up or dropped, or it is forgotten, and may then be asked for again.
+#[derive(Clone)]
+pub struct TileRequests {
+    job_tx: Sender<TilePos>,
+    in_flight: Arc<Mutex<HashSet<TilePos>>>,
+}
+
+impl TileRequests {
+    /// The queue and the receiving end the loaders take jobs from.
+    pub fn new() -> (Self, Receiver<TilePos>) {
+        let (job_tx, job_rx) = channel();
+        let requests = Self {
+            job_tx,
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+        };
+        (requests, job_rx)
+    }
+
+    /// Queues `tile` unless it is already in flight or the queue is full.
+    pub fn request(&self, tile: TilePos) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.len() >= MAX_IN_FLIGHT || !in_flight.insert(tile) {
+            return;
+        }
+        if self.job_tx.send(tile).is_err() {
+            in_flight.remove(&tile);
+        }
+    }
+
+    /// Takes `tile` out of flight, so it is asked for again if still needed.
+    pub fn done(&self, tile: &TilePos) {
+        self.in_flight.lock().unwrap().remove(tile);
+    }
+
+    /// Takes every tile of source `m` out of flight, e.g. after the source changed.
+    pub fn forget_source(&self, m: u8) {
+        self.in_flight.lock().unwrap().retain(|tile| tile.m != m);
+    }
+
+    /// Tiles requested and not done yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+}
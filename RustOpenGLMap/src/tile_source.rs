@@ -0,0 +1,505 @@
+use crate::basemap;
+use crate::disk_cache::tile_dir;
+use crate::opengl_helper::{self, USER_AGENT};
+use crate::presets::PRESETS;
+use crate::rate_limit::RateLimit;
+use crate::relief::Relief;
+use crate::reproject::Projection;
+use crate::tile::TilePos;
+use crate::tile_layers::TileLayer;
+use crate::wms::WmsParams;
+use crate::wmts;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// File the tile source definitions are read from (and hot-reloaded from).
+pub const SOURCES_PATH: &str = "sources.toml";
+
+/// How often the watcher stats the sources file for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Largest tile download accepted unless a source sets `max_bytes`.
+const DEFAULT_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+/// All tile sources currently known to the app, keyed by `TilePos::m`.
+pub static SOURCES: Lazy<RwLock<SourceRegistry>> =
+    Lazy::new(|| RwLock::new(SourceRegistry::load_or_builtin(SOURCES_PATH)));
+
+/// A single raster tile provider.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TileSource {
+    /// Value stored in `TilePos::m` for tiles of this source.
+    pub id: u8,
+    pub name: String,
+    /// URL template, `{z}`, `{x}` and `{y}` are substituted per tile.
+    pub url: String,
+    /// Template of double-resolution (`@2x`) tiles, used on HiDPI displays.
+    #[serde(default)]
+    pub url_2x: Option<String>,
+    /// Prefix of the cached file names in the tile directory.
+    pub file_prefix: String,
+    /// Key of the bundled preset this source was built from, if any.
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// Zoom levels the server has tiles for. Views zoomed in past
+    /// `max_zoom` scale its deepest tiles up; none are fetched below
+    /// `min_zoom`.
+    #[serde(default)]
+    pub min_zoom: u8,
+    #[serde(default = "default_max_zoom")]
+    pub max_zoom: u8,
+    /// Credit line shown in the window corner while the source is active.
+    #[serde(default)]
+    pub attribution: String,
+    /// User-Agent sent with tile requests, defaults to `USER_AGENT`.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Referer header, for providers that check where requests come from.
+    #[serde(default)]
+    pub referer: Option<String>,
+    /// Environment variable holding the API key, which replaces `{key}` in
+    /// the URL, `query` and `headers`. Keeps keys out of sources.toml.
+    #[serde(default)]
+    pub key_env: Option<String>,
+    /// Query parameters added to every tile URL.
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    /// Extra HTTP headers sent with every tile request, e.g. `Authorization`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// How rows are numbered, in the URL and in the cached file names.
+    #[serde(default)]
+    pub scheme: Scheme,
+    /// Rows are numbered bottom-up (TMS order) in the URL only; cached
+    /// files keep XYZ names.
+    #[serde(default)]
+    pub flip_rows: bool,
+    /// Tile images arrive upside down.
+    #[serde(default)]
+    pub flip_image: bool,
+    /// How tiles are filtered when drawn bigger or smaller than they are.
+    #[serde(default)]
+    pub filter: Filter,
+    /// Width and height in pixels every downloaded tile must have.
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+    /// Most tile requests sent per second, for servers with a usage policy.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// Responses bigger than this are dropped before they are decoded.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    /// Cached tiles older than this are revalidated with the server, 0 keeps them forever.
+    #[serde(default = "default_ttl_hours")]
+    pub ttl_hours: u64,
+    /// Proxy URL for this source; `""` connects directly even if the
+    /// environment names a proxy. Defaults to `HTTPS_PROXY`/`HTTP_PROXY`.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// PEM bundle of extra CAs, e.g. for a TLS-inspecting corporate proxy.
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    /// Skips TLS certificate checks. Only for servers you trust anyway.
+    #[serde(default)]
+    pub insecure: bool,
+    /// Tiles are elevation data, drawn as shaded relief instead of as images.
+    #[serde(default)]
+    pub relief: Option<Relief>,
+    /// Projection of the tile grid; anything but Web Mercator is warped into the view.
+    #[serde(default)]
+    pub projection: Projection,
+    /// Tiles come from GetMap requests to the WMS server at `url`.
+    #[serde(default)]
+    pub wms: Option<WmsParams>,
+}
+
+/// Row numbering of a tile server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    /// Slippy map order, row 0 at the top.
+    #[default]
+    Xyz,
+    /// Tile Map Service order, row 0 at the bottom.
+    Tms,
+}
+
+/// Texture filtering of a source's tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    /// Smooth, right for maps and imagery.
+    #[default]
+    Linear,
+    /// Blocky, keeps class colours and data pixels exact.
+    Nearest,
+}
+
+fn default_max_zoom() -> u8 {
+    19
+}
+
+fn default_tile_size() -> u32 {
+    256
+}
+
+fn default_max_bytes() -> usize {
+    DEFAULT_MAX_BYTES
+}
+
+fn default_ttl_hours() -> u64 {
+    7 * 24
+}
+
+impl TileSource {
+    /// True while this source serves its `@2x` tiles.
+    pub fn hidpi(&self) -> bool {
+        self.url_2x.is_some() && opengl_helper::hidpi()
+    }
+
+    /// Pixel size of the tiles currently requested from this source.
+    pub fn expected_tile_size(&self) -> u32 {
+        if self.hidpi() {
+            self.tile_size * 2
+        } else {
+            self.tile_size
+        }
+    }
+
+    /// Row of `tile` as this source's scheme numbers it.
+    fn row(&self, tile: &TilePos) -> u32 {
+        match self.scheme {
+            Scheme::Xyz => tile.y,
+            Scheme::Tms => (1u32 << tile.z) - 1 - tile.y,
+        }
+    }
+
+    pub fn url_for(&self, tile: &TilePos) -> String {
+        if let Some(wms) = &self.wms {
+            return wms.get_map_url(&self.url, tile, self.tile_size);
+        }
+        let y = if self.flip_rows {
+            (1u32 << tile.z) - 1 - self.row(tile)
+        } else {
+            self.row(tile)
+        };
+        let template = match &self.url_2x {
+            Some(url_2x) if self.hidpi() => url_2x,
+            _ => &self.url,
+        };
+        template
+            .replace("{z}", &tile.z.to_string())
+            .replace("{x}", &tile.x.to_string())
+            .replace("{y}", &y.to_string())
+    }
+
+    /// The API key from `key_env`, an error if the variable isn't set.
+    pub fn api_key(&self) -> Result<Option<String>, String> {
+        let Some(var) = &self.key_env else {
+            return Ok(None);
+        };
+        std::env::var(var)
+            .map(Some)
+            .map_err(|_| format!("{} needs an API key in ${}", self.name, var))
+    }
+
+    pub fn has_zoom(&self, z: u8) -> bool {
+        (self.min_zoom..=self.max_zoom).contains(&z)
+    }
+
+    pub fn user_agent(&self) -> &str {
+        self.user_agent.as_deref().unwrap_or(&USER_AGENT)
+    }
+
+    /// Proxy for this source's requests: its `proxy` key, else the
+    /// environment variable curl would use for the URL's scheme.
+    pub fn proxy(&self) -> Option<String> {
+        if self.proxy.is_some() {
+            return self.proxy.clone();
+        }
+        let vars: &[&str] = if self.url.starts_with("https:") {
+            &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+        } else {
+            &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+        };
+        vars.iter()
+            .find_map(|var| std::env::var(var).ok())
+            .filter(|proxy| !proxy.is_empty())
+    }
+
+    pub fn file_path(&self, tile: &TilePos) -> PathBuf {
+        // the two resolutions are cached side by side
+        let suffix = if self.hidpi() { "@2x" } else { "" };
+        tile_dir().join(format!(
+            "{}{}_{}_{}_{}.png",
+            self.file_prefix,
+            suffix,
+            tile.z,
+            tile.x,
+            self.row(tile)
+        ))
+    }
+}
+
+/// What happened to a source when the registry was replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceChange {
+    Added(u8),
+    Removed(u8),
+    Changed(u8),
+}
+
+#[derive(Deserialize)]
+struct SourceFile {
+    /// Kept as tables until presets are expanded.
+    #[serde(rename = "source", default)]
+    sources: Vec<toml::Table>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceRegistry {
+    sources: BTreeMap<u8, TileSource>,
+    /// Presets added from the command line or settings, kept across reloads.
+    pinned: Vec<TileSource>,
+}
+
+impl SourceRegistry {
+    /// The OSM and ESRI sources the app always shipped with.
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+        for (id, key) in [(0, "osm"), (1, "esri-imagery")] {
+            registry.insert(PRESETS.source(key, id).expect("bundled preset is invalid"));
+        }
+        registry
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: SourceFile = toml::from_str(text)?;
+        if file.sources.is_empty() {
+            return Err(Box::from("no [[source]] entries".to_string()));
+        }
+        let mut registry = Self::default();
+        for table in file.sources {
+            // one unreachable WMTS server shouldn't take the other sources with it
+            let table = match wmts::expand(PRESETS.expand(table)?) {
+                Ok(Some(table)) => table,
+                // added by the reload once its capabilities arrive
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Leaving out a WMTS source: {}", e);
+                    continue;
+                }
+            };
+            let source: TileSource = toml::Value::Table(table).try_into()?;
+            if registry.sources.contains_key(&source.id) {
+                return Err(Box::from(format!("duplicate source id {}", source.id)));
+            }
+            if source.id == basemap::SOURCE_ID {
+                return Err(Box::from(format!(
+                    "source id {} is kept for the built-in basemap",
+                    source.id
+                )));
+            }
+            if let Some(relief) = &source.relief {
+                relief
+                    .validate()
+                    .map_err(|e| format!("source {}: {}", source.id, e))?;
+            }
+            registry.insert(source);
+        }
+        Ok(registry)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads `path` if it exists, falling back to the built-in sources.
+    pub fn load_or_builtin(path: &str) -> Self {
+        let path = Path::new(path);
+        if !path.exists() {
+            return Self::builtin();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("Failed to load {}: {}", path.display(), e);
+            Self::builtin()
+        })
+    }
+
+    pub fn insert(&mut self, source: TileSource) {
+        self.sources.insert(source.id, source);
+    }
+
+    pub fn get(&self, id: u8) -> Option<&TileSource> {
+        self.sources.get(&id)
+    }
+
+    /// Credits of every drawn layer, each source named once.
+    pub fn attribution(&self, layers: &[TileLayer]) -> String {
+        let mut credits: Vec<&str> = Vec::new();
+        for layer in layers {
+            if let Some(source) = self.get(layer.source)
+                && !source.attribution.is_empty()
+                && !credits.contains(&source.attribution.as_str())
+            {
+                credits.push(&source.attribution);
+            }
+        }
+        credits.join(" | ")
+    }
+
+    pub fn contains(&self, id: u8) -> bool {
+        self.sources.contains_key(&id)
+    }
+
+    /// Source called `name`, by preset key or (case-insensitively) display name.
+    pub fn find(&self, name: &str) -> Option<u8> {
+        self.sources
+            .values()
+            .find(|source| {
+                source.preset.as_deref() == Some(name) || source.name.eq_ignore_ascii_case(name)
+            })
+            .map(|source| source.id)
+    }
+
+    /// Lowest id no source uses yet.
+    pub fn free_id(&self) -> Option<u8> {
+        (0..=u8::MAX).find(|id| !self.sources.contains_key(id))
+    }
+
+    /// Adds `source` and keeps it through reloads of the sources file, as
+    /// long as the file doesn't take its id or define the same preset.
+    pub fn pin(&mut self, source: TileSource) {
+        self.pinned.push(source.clone());
+        self.insert(source);
+    }
+
+    /// Every source, by id.
+    pub fn iter(&self) -> impl Iterator<Item = &TileSource> {
+        self.sources.values()
+    }
+
+    pub fn first_id(&self) -> Option<u8> {
+        self.sources.keys().next().copied()
+    }
+
+    /// The id after `current`, wrapping around to the first source.
+    pub fn next_id(&self, current: u8) -> u8 {
+        self.sources
+            .range(current.saturating_add(1)..)
+            .map(|(id, _)| *id)
+            .next()
+            .or(self.first_id())
+            .unwrap_or(current)
+    }
+
+    /// Cache path of `tile`, also valid for tiles of sources that were removed.
+    pub fn file_path(&self, tile: &TilePos) -> PathBuf {
+        match self.get(tile.m) {
+            Some(source) => source.file_path(tile),
+            None => tile_dir().join(format!(
+                "Source{}_{}_{}_{}.png",
+                tile.m, tile.z, tile.x, tile.y
+            )),
+        }
+    }
+
+    /// Swaps in `other` and reports which sources were added, removed or edited.
+    pub fn replace(&mut self, mut other: SourceRegistry) -> Vec<SourceChange> {
+        for source in &self.pinned {
+            if !other.sources.contains_key(&source.id)
+                && other
+                    .find(source.preset.as_deref().unwrap_or(&source.name))
+                    .is_none()
+            {
+                other.pin(source.clone());
+            }
+        }
+        let mut changes = Vec::new();
+        for (id, source) in &self.sources {
+            match other.sources.get(id) {
+                None => changes.push(SourceChange::Removed(*id)),
+                Some(new_source) if new_source != source => {
+                    changes.push(SourceChange::Changed(*id))
+                }
+                Some(_) => {}
+            }
+        }
+        for id in other.sources.keys() {
+            if !self.sources.contains_key(id) {
+                changes.push(SourceChange::Added(*id));
+            }
+        }
+        self.sources = other.sources;
+        self.pinned = other.pinned;
+        changes
+    }
+}
+
+/// Polls the sources file and applies edits to `SOURCES` while the app runs.
+pub struct SourceWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl SourceWatcher {
+    pub fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let modified = modified_time(&path);
+        Self {
+            path,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Reloads the registry if the file changed since the last poll, or
+    /// WMTS capabilities it was waiting for arrived.
+    /// A file that fails to parse leaves the current sources untouched.
+    pub fn poll(&mut self) -> Vec<SourceChange> {
+        if self.last_check.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_check = Instant::now();
+
+        let arrived = wmts::take_arrived();
+        let modified = modified_time(&self.path);
+        if modified == self.modified && !arrived {
+            return Vec::new();
+        }
+        if modified != self.modified {
+            // an edit may have fixed the URL, or the server is back
+            wmts::forget_failures();
+        }
+        self.modified = modified;
+
+        let registry = if modified.is_some() {
+            match SourceRegistry::load(&self.path) {
+                Ok(registry) => registry,
+                Err(e) => {
+                    warn!("Ignoring invalid {}: {}", self.path.display(), e);
+                    return Vec::new();
+                }
+            }
+        } else {
+            // file deleted, go back to the defaults
+            SourceRegistry::builtin()
+        };
+        let changes = SOURCES.write().unwrap().replace(registry);
+        for change in &changes {
+            info!("Tile source update: {:?}", change);
+        }
+        changes
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
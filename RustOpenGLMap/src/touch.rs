@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// `which` of the mouse events SDL synthesises from touches.
+pub const TOUCH_MOUSE_ID: u32 = u32::MAX;
+
+/// Holding a finger still this long places a point.
+const LONG_PRESS: Duration = Duration::from_millis(500);
+/// Both fingers of a two-finger tap have to lift within this.
+const TAP_TIME: Duration = Duration::from_millis(300);
+/// Longest wait between the taps of a double tap.
+const DOUBLE_TAP_TIME: Duration = Duration::from_millis(300);
+/// Pixels a finger may wander before a press becomes a drag.
+const SLOP_PX: f64 = 12.0;
+/// How close a finger has to land to a vertex to grab it, about the width of a fingertip.
+pub const HANDLE_RADIUS_PX: f64 = 28.0;
+
+/// What a series of touches amounts to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    /// The first finger touched down at this pixel.
+    Press((f64, f64)),
+    /// A single finger moved by `delta` to `at`, after leaving the slop.
+    Drag { at: (f64, f64), delta: (f64, f64) },
+    /// A single finger held still at this pixel.
+    LongPress((f64, f64)),
+    /// Two fingers moved: their midpoint by `delta` to `center`, and they
+    /// spread or pinched far enough for `zoom` levels in (> 0) or out.
+    Pinch {
+        center: (f64, f64),
+        delta: (f64, f64),
+        zoom: i32,
+    },
+    /// Two fingers tapped and lifted together.
+    TwoFingerTap,
+    /// A lone finger tapped twice in the same place, lifting at this pixel.
+    DoubleTap((f64, f64)),
+    /// The last finger lifted.
+    Release,
+}
+
+struct Finger {
+    start: (f64, f64),
+    last: (f64, f64),
+}
+
+/// Turns SDL finger events, in window pixels, into gestures: pinching and
+/// double taps anywhere, dragging and pressing for the drawing tools.
+#[derive(Default)]
+pub struct TouchInput {
+    fingers: HashMap<i64, Finger>,
+    /// When the first finger of the current touch went down.
+    started: Option<Instant>,
+    /// Most fingers down at once during the current touch.
+    most_fingers: usize,
+    /// A finger left the slop, so the touch is no tap or long press.
+    moved: bool,
+    long_pressed: bool,
+    /// Finger distance when the current pinch began, and zoom levels
+    /// given for it so far.
+    pinch: Option<(f64, i32)>,
+    /// When and where the last single tap lifted, waiting for a second one.
+    last_tap: Option<(Instant, (f64, f64))>,
+}
+
+impl TouchInput {
+    pub fn finger_down(&mut self, id: i64, at: (f64, f64)) -> Option<Gesture> {
+        let first = self.fingers.is_empty();
+        self.fingers.insert(
+            id,
+            Finger {
+                start: at,
+                last: at,
+            },
+        );
+        self.most_fingers = self.most_fingers.max(self.fingers.len());
+        if !first {
+            self.pinch = self.pair().map(|(_, distance)| (distance, 0));
+            return None;
+        }
+        self.started = Some(Instant::now());
+        self.moved = false;
+        self.long_pressed = false;
+        Some(Gesture::Press(at))
+    }
+
+    pub fn finger_motion(&mut self, id: i64, at: (f64, f64)) -> Option<Gesture> {
+        let single = self.fingers.len() == 1;
+        let before = self.pair();
+        let finger = self.fingers.get_mut(&id)?;
+        let delta = (at.0 - finger.last.0, at.1 - finger.last.1);
+        finger.last = at;
+        let (dx, dy) = (at.0 - finger.start.0, at.1 - finger.start.1);
+        if dx.hypot(dy) > SLOP_PX {
+            self.moved = true;
+        }
+        if let (Some((from, _)), Some((center, distance)), Some((start, zoomed))) =
+            (before, self.pair(), &mut self.pinch)
+        {
+            // whole zoom levels, so half way between two the nearer one
+            let zoom = (distance / *start).log2().round() as i32;
+            let steps = zoom - *zoomed;
+            *zoomed = zoom;
+            let delta = (center.0 - from.0, center.1 - from.1);
+            return Some(Gesture::Pinch {
+                center,
+                delta,
+                zoom: steps,
+            });
+        }
+        (single && self.moved && !self.long_pressed).then_some(Gesture::Drag { at, delta })
+    }
+
+    /// Midpoint of and distance between the fingers while exactly two are
+    /// down and apart.
+    fn pair(&self) -> Option<((f64, f64), f64)> {
+        let mut fingers = self.fingers.values();
+        let (Some(a), Some(b), None) = (fingers.next(), fingers.next(), fingers.next()) else {
+            return None;
+        };
+        let distance = (a.last.0 - b.last.0).hypot(a.last.1 - b.last.1);
+        let center = ((a.last.0 + b.last.0) / 2.0, (a.last.1 + b.last.1) / 2.0);
+        (distance > 0.0).then_some((center, distance))
+    }
+
+    pub fn finger_up(&mut self, id: i64) -> Option<Gesture> {
+        let finger = self.fingers.remove(&id)?;
+        self.pinch = None;
+        if !self.fingers.is_empty() {
+            return None;
+        }
+        let quick = self.started.is_some_and(|t| t.elapsed() < TAP_TIME);
+        let tapped = quick && !self.moved;
+        let fingers = self.most_fingers;
+        self.most_fingers = 0;
+        self.started = None;
+        if tapped && fingers == 2 {
+            return Some(Gesture::TwoFingerTap);
+        }
+        if !tapped || fingers != 1 {
+            self.last_tap = None;
+            return Some(Gesture::Release);
+        }
+        let at = finger.last;
+        let double = self.last_tap.take().is_some_and(|(when, first)| {
+            when.elapsed() < DOUBLE_TAP_TIME
+                && (at.0 - first.0).hypot(at.1 - first.1) < SLOP_PX * 2.0
+        });
+        if double {
+            return Some(Gesture::DoubleTap(at));
+        }
+        self.last_tap = Some((Instant::now(), at));
+        Some(Gesture::Release)
+    }
+
+    /// Two or more fingers are down, so the mouse SDL makes of the first
+    /// one shouldn't pan or click.
+    pub fn multi_touch(&self) -> bool {
+        self.fingers.len() > 1
+    }
+
+    /// A lone finger is held still but not long enough for a long press yet,
+    /// so `tick` has to keep being called even without events.
+    pub fn pending_long_press(&self) -> bool {
+        !self.long_pressed && !self.moved && self.most_fingers == 1 && !self.fingers.is_empty()
+    }
+
+    /// A long press once a lone finger has been held still long enough;
+    /// call every frame.
+    pub fn tick(&mut self) -> Option<Gesture> {
+        if self.long_pressed || self.moved || self.most_fingers != 1 {
+            return None;
+        }
+        let finger = self.fingers.values().next()?;
+        if self.started?.elapsed() < LONG_PRESS {
+            return None;
+        }
+        self.long_pressed = true;
+        Some(Gesture::LongPress(finger.last))
+    }
+}
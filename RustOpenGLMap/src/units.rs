@@ -0,0 +1,176 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const FOOT: f64 = 0.3048;
+const MILE: f64 = 1609.344;
+const NAUTICAL_MILE: f64 = 1852.0;
+const ACRE: f64 = 4_046.856_422_4;
+
+/// Units in use, switched at runtime, so every formatter agrees without it
+/// being threaded through each layer.
+static CURRENT: AtomicU8 = AtomicU8::new(Units::Metric as u8);
+
+/// Units distances, areas and elevations are shown in: the `units` key of
+/// settings.toml, switched with U while the map runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// Metres and kilometres.
+    #[default]
+    Metric,
+    /// Feet, miles and acres.
+    Imperial,
+    /// Nautical miles, with metres for short distances and heights.
+    Nautical,
+}
+
+impl Units {
+    const ALL: [Units; 3] = [Units::Metric, Units::Imperial, Units::Nautical];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Metric => "metric",
+            Self::Imperial => "imperial",
+            Self::Nautical => "nautical",
+        }
+    }
+
+    /// "850 m", "12.3 km", "950 ft", "4.20 mi" or "1.35 NM".
+    pub fn format_distance(self, metres: f64) -> String {
+        match self {
+            Self::Metric => {
+                if metres < 1000.0 {
+                    return format!("{:.0} m", metres);
+                }
+                large(metres / 1000.0, 1, "km")
+            }
+            Self::Imperial => {
+                if metres < 1000.0 * FOOT {
+                    return format!("{:.0} ft", metres / FOOT);
+                }
+                large(metres / MILE, 2, "mi")
+            }
+            Self::Nautical => {
+                if metres < NAUTICAL_MILE / 10.0 {
+                    return format!("{:.0} m", metres);
+                }
+                large(metres / NAUTICAL_MILE, 2, "NM")
+            }
+        }
+    }
+
+    /// "850 m²", "4.25 km²", "3.2 acres", "12.40 mi²" or "0.75 NM²".
+    pub fn format_area(self, square_metres: f64) -> String {
+        match self {
+            Self::Metric => {
+                if square_metres < 100_000.0 {
+                    return format!("{:.0} m²", square_metres);
+                }
+                large(square_metres / 1e6, 2, "km²")
+            }
+            Self::Imperial => {
+                if square_metres < ACRE {
+                    return format!("{:.0} ft²", square_metres / (FOOT * FOOT));
+                }
+                if square_metres < 640.0 * ACRE {
+                    return format!("{:.1} acres", square_metres / ACRE);
+                }
+                large(square_metres / (MILE * MILE), 2, "mi²")
+            }
+            Self::Nautical => {
+                let nm2 = square_metres / (NAUTICAL_MILE * NAUTICAL_MILE);
+                if nm2 < 0.01 {
+                    return format!("{:.0} m²", square_metres);
+                }
+                large(nm2, 2, "NM²")
+            }
+        }
+    }
+
+    /// "1,234 m" or "4,049 ft" above sea level.
+    pub fn format_elevation(self, metres: f64) -> String {
+        match self {
+            Self::Imperial => format!("{} ft", group_thousands(metres / FOOT)),
+            Self::Metric | Self::Nautical => format!("{} m", group_thousands(metres)),
+        }
+    }
+
+    /// The longest round length (1, 2 or 5 times a power of ten of a unit)
+    /// up to `longest` metres for a scale bar, in metres, and its label.
+    pub fn scale_length(self, longest: f64) -> (f64, String) {
+        let (big, big_name, small, small_name) = match self {
+            Self::Metric => (1000.0, "km", 1.0, "m"),
+            Self::Imperial => (MILE, "mi", FOOT, "ft"),
+            Self::Nautical => (NAUTICAL_MILE, "NM", 1.0, "m"),
+        };
+        let (unit, name) = match longest >= big {
+            true => (big, big_name),
+            false => (small, small_name),
+        };
+        let count = round_down(longest / unit);
+        (count * unit, format!("{} {}", group_thousands(count), name))
+    }
+}
+
+/// Makes `units` the ones every formatter uses from now on.
+pub fn set(units: Units) {
+    CURRENT.store(units as u8, Ordering::Relaxed);
+}
+
+pub fn current() -> Units {
+    Units::ALL[usize::from(CURRENT.load(Ordering::Relaxed))]
+}
+
+/// A distance in the current units.
+pub fn format_distance(metres: f64) -> String {
+    current().format_distance(metres)
+}
+
+/// An area in the current units.
+pub fn format_area(square_metres: f64) -> String {
+    current().format_area(square_metres)
+}
+
+/// A height in the current units.
+pub fn format_elevation(metres: f64) -> String {
+    current().format_elevation(metres)
+}
+
+/// `value` with `decimals` places below 100, whole and grouped above.
+fn large(value: f64, decimals: usize, unit: &str) -> String {
+    if value < 100.0 {
+        return format!("{:.*} {}", decimals, value, unit);
+    }
+    format!("{} {}", group_thousands(value), unit)
+}
+
+/// The largest 1, 2 or 5 times a power of ten up to `value`.
+fn round_down(value: f64) -> f64 {
+    let power = 10f64.powf(value.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|m| m * power)
+        .find(|&round| round <= value)
+        .unwrap_or(power)
+}
+
+/// `value` rounded to an integer with commas between groups of three digits.
+fn group_thousands(value: f64) -> String {
+    let digits = format!("{:.0}", value.abs());
+    let mut grouped = String::new();
+    if value.round() < 0.0 {
+        grouped.push('-');
+    }
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
@@ -0,0 +1,241 @@
+use crate::opengl_helper::{self, USER_AGENT};
+use crate::retry;
+use crate::vector_tile::decode::{self, GEOM_LINE, GEOM_POINT, GEOM_POLYGON};
+use crate::vector_tile::style::{Paint, StyleRule};
+use crate::wake;
+use curl::easy::Easy;
+use lyon_tessellation::math::point;
+use lyon_tessellation::path::Path as LyonPath;
+use lyon_tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, VertexBuffers};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+/// Downloaded tiles are kept here, one file per tile.
+pub const VECTOR_CACHE_DIR: &str = "VectorTiles";
+
+/// Tile units are scaled up to the usual extent for tessellation, where
+/// lyon's tolerances are meant for pixel-like coordinates.
+const TESSELLATION_SCALE: f32 = 4096.0;
+
+/// Source layer whose named points are written on the map, as in the
+/// OpenMapTiles schema.
+const PLACE_LAYER: &str = "place";
+
+/// Place classes from the most to the least important; unlisted ones come last.
+const PLACE_CLASSES: &[&str] = &[
+    "continent",
+    "country",
+    "state",
+    "city",
+    "town",
+    "village",
+    "suburb",
+    "hamlet",
+    "quarter",
+    "neighbourhood",
+];
+
+/// (z, x, y) of a vector tile.
+pub type TileKey = (u8, u32, u32);
+
+/// Vertices of one tile for each style rule, in tile units (0..1 across
+/// the tile): triangles for fill rules, line segment pairs for line rules.
+pub struct TileGeometry {
+    pub rules: Vec<Vec<[f32; 2]>>,
+    pub labels: Vec<TileLabel>,
+}
+
+/// The name of a place in the tile.
+pub struct TileLabel {
+    /// Tile units, like the vertices.
+    pub at: (f32, f32),
+    pub name: String,
+    /// Index of its class in `PLACE_CLASSES`.
+    pub priority: u32,
+}
+
+fn cache_path((z, x, y): TileKey) -> PathBuf {
+    Path::new(VECTOR_CACHE_DIR).join(format!("{}_{}_{}.pbf", z, x, y))
+}
+
+/// The tile from the cache directory, or from `url` if it isn't there yet.
+fn load(url: &str, key: TileKey) -> Result<Vec<u8>, Box<dyn Error>> {
+    let path = cache_path(key);
+    if let Ok(bytes) = std::fs::read(&path) {
+        return Ok(bytes);
+    }
+    if opengl_helper::downloads_paused() || retry::offline() {
+        return Err(Box::from("not cached and downloads are off".to_string()));
+    }
+    let (z, x, y) = key;
+    let url = url
+        .replace("{z}", &z.to_string())
+        .replace("{x}", &x.to_string())
+        .replace("{y}", &y.to_string());
+    let mut easy = Easy::new();
+    let mut body = Vec::new();
+    easy.url(&url)?;
+    easy.useragent(&USER_AGENT)?;
+    // lets curl ask for and undo gzip transfer encoding
+    easy.accept_encoding("")?;
+    easy.follow_location(true)?;
+    easy.timeout(Duration::from_secs(30))?;
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    match easy.response_code()? {
+        200 => {}
+        // servers answer these for tiles without any features
+        204 | 404 => body.clear(),
+        code => return Err(Box::from(format!("HTTP error: {}", code))),
+    }
+    std::fs::create_dir_all(VECTOR_CACHE_DIR)?;
+    std::fs::write(&path, &body)?;
+    Ok(body)
+}
+
+/// Sorts the features of a decoded tile into `rules` and turns them into
+/// vertices the layer can upload as they are.
+fn build(tile: &decode::Tile, rules: &[StyleRule]) -> Result<TileGeometry, Box<dyn Error>> {
+    let mut geometry = TileGeometry {
+        rules: vec![Vec::new(); rules.len()],
+        labels: Vec::new(),
+    };
+    for layer in &tile.layers {
+        let extent = layer.extent.unwrap_or(4096);
+        for feature in &layer.features {
+            let kind = feature.geom_type.unwrap_or(0);
+            if kind == GEOM_POINT
+                && layer.name == PLACE_LAYER
+                && let Some(name) = layer.tag(feature, "name")
+            {
+                let class = layer.tag(feature, "class");
+                let priority = PLACE_CLASSES
+                    .iter()
+                    .position(|c| Some(*c) == class)
+                    .unwrap_or(PLACE_CLASSES.len());
+                // points in the buffer around the tile belong to the neighbour
+                let inside = |(x, y): &(f32, f32)| (0.0..1.0).contains(x) && (0.0..1.0).contains(y);
+                for at in decode::points(&feature.geometry, extent)
+                    .into_iter()
+                    .filter(inside)
+                {
+                    geometry.labels.push(TileLabel {
+                        at,
+                        name: name.to_string(),
+                        priority: priority as u32,
+                    });
+                }
+            }
+            if kind != GEOM_LINE && kind != GEOM_POLYGON {
+                continue;
+            }
+            let class = layer.tag(feature, "class");
+            let mut paths = None;
+            for (i, rule) in rules.iter().enumerate() {
+                if !rule.matches(&layer.name, class) {
+                    continue;
+                }
+                let paths =
+                    paths.get_or_insert_with(|| decode::geometry(&feature.geometry, extent));
+                match rule.paint {
+                    Paint::Fill if kind == GEOM_POLYGON => {
+                        geometry.rules[i].extend(tessellate(paths)?)
+                    }
+                    Paint::Fill => {}
+                    Paint::Line => {
+                        for path in paths.iter() {
+                            segments(path, kind == GEOM_POLYGON, &mut geometry.rules[i]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(geometry)
+}
+
+/// Triangles filling the rings of one polygon feature, holes left open.
+fn tessellate(rings: &[decode::Path]) -> Result<Vec<[f32; 2]>, Box<dyn Error>> {
+    let mut builder = LyonPath::builder();
+    for ring in rings.iter().filter(|r| r.len() >= 3) {
+        let scaled = |p: &(f32, f32)| point(p.0 * TESSELLATION_SCALE, p.1 * TESSELLATION_SCALE);
+        builder.begin(scaled(&ring[0]));
+        for p in &ring[1..] {
+            builder.line_to(scaled(p));
+        }
+        builder.end(true);
+    }
+    let mut buffers: VertexBuffers<[f32; 2], u32> = VertexBuffers::new();
+    FillTessellator::new()
+        .tessellate_path(
+            &builder.build(),
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(&mut buffers, |v: FillVertex| {
+                let p = v.position();
+                [p.x / TESSELLATION_SCALE, p.y / TESSELLATION_SCALE]
+            }),
+        )
+        .map_err(|e| format!("polygon tessellation failed: {:?}", e))?;
+    Ok(buffers
+        .indices
+        .iter()
+        .map(|i| buffers.vertices[*i as usize])
+        .collect())
+}
+
+/// Appends `path` as GL_LINES pairs, back to its start if `closed`.
+fn segments(path: &[(f32, f32)], closed: bool, out: &mut Vec<[f32; 2]>) {
+    for pair in path.windows(2) {
+        out.extend([[pair[0].0, pair[0].1], [pair[1].0, pair[1].1]]);
+    }
+    if closed && let (Some(first), Some(last)) = (path.first(), path.last()) {
+        out.extend([[last.0, last.1], [first.0, first.1]]);
+    }
+}
+
+type Answer = (TileKey, Result<TileGeometry, String>);
+
+/// Fetches, decodes and tessellates tiles on a background thread.
+pub struct VectorTileClient {
+    tile_tx: Sender<TileKey>,
+    result_rx: Receiver<Answer>,
+}
+
+impl VectorTileClient {
+    /// Loads tiles from the `{z}/{x}/{y}` template `url`, built for `rules`.
+    pub fn spawn(url: String, rules: Vec<StyleRule>) -> Self {
+        let (tile_tx, tile_rx) = channel::<TileKey>();
+        let (result_tx, result_rx) = channel();
+        thread::spawn(move || {
+            while let Ok(key) = tile_rx.recv() {
+                let result = load(&url, key)
+                    .and_then(|bytes| decode::decode(&bytes))
+                    .and_then(|tile| build(&tile, &rules))
+                    .map_err(|e| e.to_string());
+                if result_tx.send((key, result)).is_err() {
+                    break;
+                }
+                wake::wake();
+            }
+        });
+        Self { tile_tx, result_rx }
+    }
+
+    pub fn request(&self, key: TileKey) {
+        let _ = self.tile_tx.send(key);
+    }
+
+    /// The next finished tile, if any.
+    pub fn poll(&self) -> Option<Answer> {
+        self.result_rx.try_recv().ok()
+    }
+}
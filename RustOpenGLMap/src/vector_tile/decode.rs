@@ -0,0 +1,149 @@
+use flate2::read::GzDecoder;
+use prost::Message;
+use std::error::Error;
+use std::io::Read;
+
+// The subset of vector_tile.proto (Mapbox Vector Tile spec 2.1) that drawing
+// needs; fields left out are skipped while decoding.
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Tile {
+    #[prost(message, repeated, tag = "3")]
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Layer {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub features: Vec<Feature>,
+    #[prost(string, repeated, tag = "3")]
+    pub keys: Vec<String>,
+    #[prost(message, repeated, tag = "4")]
+    pub values: Vec<Value>,
+    /// Width and height of the tile in geometry units, 4096 if unset.
+    #[prost(uint32, optional, tag = "5")]
+    pub extent: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Feature {
+    /// Pairs of indexes into the layer's keys and values.
+    #[prost(uint32, repeated, tag = "2")]
+    pub tags: Vec<u32>,
+    /// 1 point, 2 line string, 3 polygon.
+    #[prost(int32, optional, tag = "3")]
+    pub geom_type: Option<i32>,
+    /// Drawing commands, see `geometry`.
+    #[prost(uint32, repeated, tag = "4")]
+    pub geometry: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Value {
+    #[prost(string, optional, tag = "1")]
+    pub string_value: Option<String>,
+}
+
+pub const GEOM_POINT: i32 = 1;
+pub const GEOM_LINE: i32 = 2;
+pub const GEOM_POLYGON: i32 = 3;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+/// A ring or line in tile units, 0..1 across the tile.
+pub type Path = Vec<(f32, f32)>;
+
+/// Decodes a tile as served, gzipped or not.
+pub fn decode(bytes: &[u8]) -> Result<Tile, Box<dyn Error>> {
+    // many servers store tiles gzipped and send them without saying so
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut raw = Vec::new();
+        GzDecoder::new(bytes).read_to_end(&mut raw)?;
+        return Ok(Tile::decode(raw.as_slice())?);
+    }
+    Ok(Tile::decode(bytes)?)
+}
+
+impl Layer {
+    /// String value of tag `key` on `feature`, e.g. the `class` of a road.
+    pub fn tag<'a>(&'a self, feature: &Feature, key: &str) -> Option<&'a str> {
+        feature.tags.chunks_exact(2).find_map(|pair| {
+            if self.keys.get(pair[0] as usize)? != key {
+                return None;
+            }
+            self.values.get(pair[1] as usize)?.string_value.as_deref()
+        })
+    }
+}
+
+/// The points of a point feature, scaled from `extent` units to 0..1.
+pub fn points(commands: &[u32], extent: u32) -> Vec<(f32, f32)> {
+    let scale = 1.0 / extent.max(1) as f32;
+    let zigzag = |v: u32| ((v >> 1) as i32) ^ -((v & 1) as i32);
+    let mut points = Vec::new();
+    let (mut x, mut y) = (0i32, 0i32);
+    let mut i = 0;
+    // a single move-to whose count is the number of points
+    while i < commands.len() && commands[i] & 0x7 == CMD_MOVE_TO {
+        let count = commands[i] >> 3;
+        i += 1;
+        for _ in 0..count {
+            let (Some(&dx), Some(&dy)) = (commands.get(i), commands.get(i + 1)) else {
+                return points;
+            };
+            i += 2;
+            x += zigzag(dx);
+            y += zigzag(dy);
+            points.push((x as f32 * scale, y as f32 * scale));
+        }
+    }
+    points
+}
+
+/// The lines or rings of a feature, scaled from `extent` units to 0..1.
+/// Rings come back open, the close-path command only ends them.
+pub fn geometry(commands: &[u32], extent: u32) -> Vec<Path> {
+    let scale = 1.0 / extent.max(1) as f32;
+    let zigzag = |v: u32| ((v >> 1) as i32) ^ -((v & 1) as i32);
+    let mut paths = Vec::new();
+    let mut current = Path::new();
+    let (mut x, mut y) = (0i32, 0i32);
+    let mut i = 0;
+    while i < commands.len() {
+        let (id, count) = (commands[i] & 0x7, commands[i] >> 3);
+        i += 1;
+        match id {
+            CMD_MOVE_TO | CMD_LINE_TO => {
+                for _ in 0..count {
+                    let (Some(&dx), Some(&dy)) = (commands.get(i), commands.get(i + 1)) else {
+                        break;
+                    };
+                    i += 2;
+                    x += zigzag(dx);
+                    y += zigzag(dy);
+                    if id == CMD_MOVE_TO && current.len() > 1 {
+                        paths.push(std::mem::take(&mut current));
+                    } else if id == CMD_MOVE_TO {
+                        current.clear();
+                    }
+                    current.push((x as f32 * scale, y as f32 * scale));
+                }
+            }
+            CMD_CLOSE_PATH => {
+                if current.len() > 2 {
+                    paths.push(std::mem::take(&mut current));
+                }
+            }
+            // unknown command: nothing after it can be trusted
+            _ => break,
+        }
+    }
+    if current.len() > 1 {
+        paths.push(current);
+    }
+    paths
+}
@@ -0,0 +1,281 @@
+use crate::geo;
+use crate::labels::PlaceLabel;
+use crate::layers::{DrawContext, GeometryBuffer, Layer};
+use crate::vector_tile::VectorSettings;
+use crate::vector_tile::client::{TileGeometry, TileKey, VectorTileClient};
+use crate::vector_tile::style::{Paint, StyleRule};
+use lru::LruCache;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use tracing::warn;
+
+/// Decoded tiles kept in memory; a few screens' worth at any zoom.
+const CACHED_TILES: usize = 256;
+
+/// What the vertex buffer was last built for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ViewKey {
+    /// Zoom of the map, which sets how wide lines are in world units.
+    zoom: u8,
+    /// Zoom of the tiles.
+    z: u8,
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    /// Tiles received so far, so an arriving tile triggers a rebuild.
+    received: u64,
+}
+
+/// Vector tiles drawn with the `[vector]` style, right above the raster tiles.
+pub struct VectorTileLayer {
+    pub visible: bool,
+    client: Option<VectorTileClient>,
+    rules: Vec<StyleRule>,
+    background: Option<[f32; 4]>,
+    max_zoom: u8,
+    tiles: LruCache<TileKey, TileGeometry>,
+    /// Asked for, whether it has arrived, failed or is still on its way.
+    requested: HashSet<TileKey>,
+    received: u64,
+    key: Option<ViewKey>,
+    origin: (f64, f64),
+    /// Background vertices, then (rule, first vertex, count) of each rule.
+    background_vertices: usize,
+    ranges: Vec<(usize, usize, usize)>,
+    buffer: Option<GeometryBuffer>,
+}
+
+impl VectorTileLayer {
+    /// A layer drawing nothing until settings with a tile server are applied.
+    pub fn new() -> Self {
+        Self::with_settings(&VectorSettings::default())
+    }
+
+    pub fn with_settings(settings: &VectorSettings) -> Self {
+        let style = settings.style();
+        Self {
+            visible: settings.visible,
+            client: settings
+                .url
+                .clone()
+                .map(|url| VectorTileClient::spawn(url, style.rules.clone())),
+            rules: style.rules,
+            background: style.background.map(|c| c.map(|c| f32::from(c) / 255.0)),
+            max_zoom: settings.max_zoom,
+            tiles: LruCache::new(NonZeroUsize::new(CACHED_TILES).unwrap()),
+            requested: HashSet::new(),
+            received: 0,
+            key: None,
+            origin: (0.0, 0.0),
+            background_vertices: 0,
+            ranges: Vec::new(),
+            buffer: None,
+        }
+    }
+
+    /// False when no tile server is configured.
+    pub fn available(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Takes the tiles the client has finished. True if any arrived.
+    pub fn poll(&mut self) -> bool {
+        let Some(client) = &self.client else {
+            return false;
+        };
+        let mut arrived = false;
+        while let Some((key, result)) = client.poll() {
+            match result {
+                Ok(geometry) => {
+                    // an evicted tile is asked for again when it comes back into view
+                    if let Some((evicted, _)) = self.tiles.push(key, geometry)
+                        && evicted != key
+                    {
+                        self.requested.remove(&evicted);
+                    }
+                    self.received += 1;
+                    arrived = true;
+                }
+                Err(e) => warn!("Failed to load vector tile {:?}: {}", key, e),
+            }
+        }
+        arrived
+    }
+
+    fn rebuild(&mut self, key: ViewKey) -> Result<(), String> {
+        if self.buffer.is_none() {
+            self.buffer = Some(GeometryBuffer::new()?);
+        }
+        let n = geo::world_tiles(key.z);
+        self.origin = (f64::from(key.x0) / n, f64::from(key.y0) / n);
+        let scale = (1.0 / n) as f32;
+        // world units per pixel at the map's zoom
+        let pixel = (1.0 / (geo::world_tiles(key.zoom) * 256.0)) as f32;
+        let mut loaded = Vec::new();
+        for y in key.y0..=key.y1 {
+            for x in key.x0..=key.x1 {
+                if self.tiles.contains(&(key.z, x, y)) {
+                    let offset = ((x - key.x0) as f32 * scale, (y - key.y0) as f32 * scale);
+                    loaded.push(((key.z, x, y), offset));
+                }
+            }
+        }
+
+        let mut vertices: Vec<[f32; 2]> = Vec::new();
+        if self.background.is_some() {
+            for (_, (left, top)) in &loaded {
+                let (right, bottom) = (left + scale, top + scale);
+                vertices.extend([
+                    [*left, *top],
+                    [right, *top],
+                    [right, bottom],
+                    [*left, *top],
+                    [right, bottom],
+                    [*left, bottom],
+                ]);
+            }
+        }
+        self.background_vertices = vertices.len();
+        self.ranges.clear();
+        for rule in 0..self.rules.len() {
+            let first = vertices.len();
+            let half_width = self.rules[rule]
+                .wide()
+                .then(|| self.rules[rule].width / 2.0 * pixel);
+            for (tile, (left, top)) in &loaded {
+                let Some(geometry) = self.tiles.get(tile) else {
+                    continue;
+                };
+                let placed = geometry.rules[rule]
+                    .iter()
+                    .map(|[u, v]| [left + u * scale, top + v * scale]);
+                match half_width {
+                    Some(half_width) => {
+                        let placed: Vec<[f32; 2]> = placed.collect();
+                        for pair in placed.chunks_exact(2) {
+                            widen(pair[0], pair[1], half_width, &mut vertices);
+                        }
+                    }
+                    None => vertices.extend(placed),
+                }
+            }
+            if vertices.len() > first {
+                self.ranges.push((rule, first, vertices.len() - first));
+            }
+        }
+        if let Some(buffer) = &self.buffer {
+            buffer.upload(&vertices);
+        }
+        self.key = Some(key);
+        Ok(())
+    }
+}
+
+/// Two triangles covering the segment from `a` to `b`, `half_width` to each side.
+/// Corners between segments are left unjoined, which is hard to see at a few pixels.
+fn widen(a: [f32; 2], b: [f32; 2], half_width: f32, out: &mut Vec<[f32; 2]>) {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return;
+    }
+    let (nx, ny) = (-dy / length * half_width, dx / length * half_width);
+    let a0 = [a[0] + nx, a[1] + ny];
+    let a1 = [a[0] - nx, a[1] - ny];
+    let b0 = [b[0] + nx, b[1] + ny];
+    let b1 = [b[0] - nx, b[1] - ny];
+    out.extend([a0, b0, b1, a0, b1, a1]);
+}
+
+impl Layer for VectorTileLayer {
+    fn labels(&self) -> Vec<PlaceLabel> {
+        let Some(key) = self.key.filter(|_| self.visible) else {
+            return Vec::new();
+        };
+        let n = geo::world_tiles(key.z);
+        let mut labels = Vec::new();
+        for y in key.y0..=key.y1 {
+            for x in key.x0..=key.x1 {
+                let Some(geometry) = self.tiles.peek(&(key.z, x, y)) else {
+                    continue;
+                };
+                labels.extend(geometry.labels.iter().map(|label| PlaceLabel {
+                    world: (
+                        (f64::from(x) + f64::from(label.at.0)) / n,
+                        (f64::from(y) + f64::from(label.at.1)) / n,
+                    ),
+                    text: label.name.clone(),
+                    // after names from files
+                    priority: label.priority + 1,
+                }));
+            }
+        }
+        labels
+    }
+
+    fn draw(&mut self, ctx: &DrawContext) {
+        if !self.visible {
+            return;
+        }
+        let Some(client) = &self.client else {
+            return;
+        };
+        // past the server's deepest zoom its tiles are scaled up
+        let z = ctx.vp.z.min(self.max_zoom);
+        let n = geo::world_tiles(z);
+        let tile = |v: f64| (v * n).floor().clamp(0.0, n - 1.0) as u32;
+        let (left, top) = ctx.vp.pixel_to_world((0.0, 0.0), ctx.win);
+        let (right, bottom) = ctx
+            .vp
+            .pixel_to_world((f64::from(ctx.win.0), f64::from(ctx.win.1)), ctx.win);
+        let key = ViewKey {
+            zoom: ctx.vp.z,
+            z,
+            x0: tile(left),
+            y0: tile(top),
+            x1: tile(right),
+            y1: tile(bottom),
+            received: self.received,
+        };
+        for y in key.y0..=key.y1 {
+            for x in key.x0..=key.x1 {
+                if self.requested.insert((z, x, y)) {
+                    client.request((z, x, y));
+                }
+            }
+        }
+        if self.key != Some(key)
+            && let Err(e) = self.rebuild(key)
+        {
+            warn!("Failed to build the vector tiles: {}", e);
+            self.visible = false;
+            return;
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        if let Some(color) = self.background {
+            ctx.shader.bind(ctx.vp, ctx.win, self.origin, color);
+            buffer.draw(gl::TRIANGLES, 0, self.background_vertices);
+        }
+        for &(rule, first, count) in &self.ranges {
+            let rule = &self.rules[rule];
+            if !rule.visible_at(ctx.vp.z) {
+                continue;
+            }
+            let mode = match rule.paint {
+                Paint::Fill => gl::TRIANGLES,
+                Paint::Line if rule.wide() => gl::TRIANGLES,
+                Paint::Line => gl::LINES,
+            };
+            ctx.shader.bind(ctx.vp, ctx.win, self.origin, rule.rgba());
+            buffer.draw(mode, first, count);
+        }
+        unsafe { gl::Disable(gl::BLEND) };
+    }
+}
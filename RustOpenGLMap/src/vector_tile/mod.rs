@@ -0,0 +1,67 @@
+pub mod client;
+pub mod decode;
+pub mod layer;
+pub mod style;
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use style::{StyleRule, VectorStyle};
+use tracing::warn;
+
+pub use layer::VectorTileLayer;
+
+/// The `[vector]` table of settings.toml.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorSettings {
+    /// `{z}/{x}/{y}` template of a vector tile server; nothing is drawn without one.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Deepest zoom the server has tiles for, deeper views scale those up.
+    #[serde(default = "default_max_zoom")]
+    pub max_zoom: u8,
+    /// Colour under the features, hiding the raster map. None lets it show through.
+    #[serde(default = "style::default_background")]
+    pub background: Option<[u8; 4]>,
+    /// Drawn from the start instead of after Shift+V.
+    #[serde(default)]
+    pub visible: bool,
+    #[serde(default = "style::default_rules", rename = "rule")]
+    pub rules: Vec<StyleRule>,
+    /// TOML or JSON file whose background and rules replace the ones above.
+    #[serde(default)]
+    pub style: Option<PathBuf>,
+}
+
+fn default_max_zoom() -> u8 {
+    14
+}
+
+impl Default for VectorSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_zoom: default_max_zoom(),
+            background: style::default_background(),
+            visible: false,
+            rules: style::default_rules(),
+            style: None,
+        }
+    }
+}
+
+impl VectorSettings {
+    /// The style file if one is set and loads, otherwise the inline style.
+    pub fn style(&self) -> VectorStyle {
+        let inline = VectorStyle {
+            background: self.background,
+            rules: self.rules.clone(),
+        };
+        let Some(path) = &self.style else {
+            return inline;
+        };
+        VectorStyle::load(path).unwrap_or_else(|e| {
+            warn!("Failed to load {}: {}", path.display(), e);
+            inline
+        })
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// How a rule draws the features it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Paint {
+    /// Polygons as filled areas.
+    Fill,
+    /// Line strings and polygon outlines, `width` pixels wide.
+    Line,
+}
+
+/// One `[[vector.rule]]`: which features of a source layer to draw and how.
+/// Rules draw in order, later ones on top; features no rule matches aren't drawn.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StyleRule {
+    /// Source layer, e.g. "water" or "transportation".
+    pub layer: String,
+    /// Only features whose `class` tag is one of these; every feature if empty.
+    #[serde(default)]
+    pub classes: Vec<String>,
+    pub paint: Paint,
+    pub color: [u8; 4],
+    /// Line width in pixels; ignored by fills.
+    #[serde(default = "default_width")]
+    pub width: f32,
+    /// Map zooms the rule draws at, both included.
+    #[serde(default)]
+    pub min_zoom: u8,
+    #[serde(default = "default_max_zoom")]
+    pub max_zoom: u8,
+}
+
+fn default_width() -> f32 {
+    1.0
+}
+
+fn default_max_zoom() -> u8 {
+    crate::tile::MAX_ZOOM
+}
+
+impl StyleRule {
+    pub fn matches(&self, layer: &str, class: Option<&str>) -> bool {
+        self.layer == layer
+            && (self.classes.is_empty()
+                || class.is_some_and(|class| self.classes.iter().any(|c| c == class)))
+    }
+
+    pub fn visible_at(&self, z: u8) -> bool {
+        (self.min_zoom..=self.max_zoom).contains(&z)
+    }
+
+    /// Lines wider than a pixel, drawn as triangles rather than GL lines.
+    pub fn wide(&self) -> bool {
+        self.paint == Paint::Line && self.width > 1.0
+    }
+
+    pub fn rgba(&self) -> [f32; 4] {
+        self.color.map(|c| f32::from(c) / 255.0)
+    }
+}
+
+fn rule(layer: &str, classes: &[&str], paint: Paint, color: [u8; 4]) -> StyleRule {
+    StyleRule {
+        layer: layer.to_string(),
+        classes: classes.iter().map(|c| c.to_string()).collect(),
+        paint,
+        color,
+        width: default_width(),
+        min_zoom: 0,
+        max_zoom: default_max_zoom(),
+    }
+}
+
+fn road(classes: &[&str], color: [u8; 4], width: f32, min_zoom: u8) -> StyleRule {
+    StyleRule {
+        width,
+        min_zoom,
+        ..rule("transportation", classes, Paint::Line, color)
+    }
+}
+
+/// A style file: the background and rules of `[vector]`, in TOML or, for a
+/// `.json` file, the same keys in JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorStyle {
+    #[serde(default = "default_background")]
+    pub background: Option<[u8; 4]>,
+    #[serde(default = "default_rules", rename = "rule", alias = "rules")]
+    pub rules: Vec<StyleRule>,
+}
+
+pub fn default_background() -> Option<[u8; 4]> {
+    Some([242, 239, 233, 255])
+}
+
+impl VectorStyle {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            Ok(serde_json::from_str(&text)?)
+        } else {
+            Ok(toml::from_str(&text)?)
+        }
+    }
+}
+
+/// A plain light style for tiles in the OpenMapTiles schema, which
+/// OpenFreeMap, MapTiler and most self-hosted tile servers use.
+pub fn default_rules() -> Vec<StyleRule> {
+    use Paint::{Fill, Line};
+    vec![
+        rule("landcover", &["wood", "forest"], Fill, [205, 225, 190, 255]),
+        rule(
+            "landcover",
+            &["grass", "farmland"],
+            Fill,
+            [225, 236, 205, 255],
+        ),
+        rule("landcover", &["ice", "glacier"], Fill, [245, 250, 255, 255]),
+        rule("landcover", &["sand"], Fill, [245, 235, 200, 255]),
+        rule("landuse", &["residential"], Fill, [234, 230, 225, 255]),
+        rule(
+            "landuse",
+            &["industrial", "commercial"],
+            Fill,
+            [236, 224, 228, 255],
+        ),
+        rule("park", &[], Fill, [200, 228, 180, 255]),
+        rule("water", &[], Fill, [170, 205, 235, 255]),
+        rule("waterway", &[], Line, [150, 190, 230, 255]),
+        rule("aeroway", &[], Fill, [225, 220, 230, 255]),
+        StyleRule {
+            min_zoom: 14,
+            ..rule("building", &[], Fill, [216, 206, 196, 255])
+        },
+        road(&["path", "track"], [170, 150, 130, 255], 1.0, 15),
+        road(&["minor", "service"], [180, 180, 180, 255], 1.5, 13),
+        road(&["rail", "transit"], [130, 130, 140, 255], 1.0, 0),
+        road(&["secondary", "tertiary"], [230, 200, 110, 255], 2.0, 0),
+        road(&["primary", "trunk"], [235, 160, 90, 255], 2.5, 0),
+        road(&["motorway"], [220, 110, 80, 255], 3.0, 0),
+        rule("boundary", &[], Line, [150, 110, 170, 255]),
+    ]
+}
@@ -1,14 +1,189 @@
-#[derive(Debug)]
+use crate::geo;
+use crate::reproject::Projection;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Viewport {
     pub z: u8,
     pub center_x: f64,
     pub center_y: f64,
+    /// Plane the tile grid lies on: Web Mercator, or a polar stereographic
+    /// one for the high latitudes Mercator can't show.
+    pub projection: Projection,
 }
 
 impl Viewport {
+    /// Position of the window centre on the view's plane, in zoom 0 tiles.
+    /// `center_x`/`center_y` address tile centres, hence the half-tile shift.
+    pub fn center_plane(&self) -> (f64, f64) {
+        let n = geo::world_tiles(self.z);
+        ((self.center_x + 0.5) / n, (self.center_y + 0.5) / n)
+    }
+
+    /// Normalised Web Mercator position of the window centre.
+    pub fn center_world(&self) -> (f64, f64) {
+        match self.projection {
+            Projection::WebMercator => self.center_plane(),
+            _ => {
+                let (lat, lon) = self.center_latlon();
+                geo::project(lat, lon)
+            }
+        }
+    }
+
+    /// Where a normalised Web Mercator point lies on the view's plane.
+    fn world_to_plane(&self, world: (f64, f64)) -> (f64, f64) {
+        match self.projection {
+            Projection::WebMercator => world,
+            projection => {
+                let (lat, lon) = geo::unproject(world.0, world.1);
+                projection.to_plane(lat, lon)
+            }
+        }
+    }
+
+    /// The same view in `projection`, centred where it is now.
+    pub fn with_projection(&self, projection: Projection) -> Self {
+        let (lat, lon) = self.center_latlon();
+        let (x, y) = projection.to_plane(lat, lon);
+        let n = geo::world_tiles(self.z);
+        Self {
+            z: self.z,
+            center_x: x * n - 0.5,
+            center_y: y * n - 0.5,
+            projection,
+        }
+    }
+
+    /// Viewport at zoom `z` with (lat, lon) in the middle of the window.
+    pub fn centered_on(lat: f64, lon: f64, z: u8) -> Self {
+        let (x, y) = geo::project(lat, lon);
+        let n = geo::world_tiles(z);
+        Self {
+            z,
+            center_x: x * n - 0.5,
+            center_y: y * n - 0.5,
+            projection: Projection::WebMercator,
+        }
+    }
+
+    /// Viewport showing the (south, west, north, east) box in a `win` sized
+    /// window, at the deepest zoom up to `max_z` it fits at.
+    pub fn fitting(bounds: [f64; 4], win: (u32, u32), max_z: u8) -> Self {
+        let [south, west, north, east] = bounds;
+        let (x0, y0) = geo::project(north, west);
+        let (x1, y1) = geo::project(south, east);
+        let (w, h) = ((x1 - x0).abs(), (y1 - y0).abs());
+        let mut z = max_z;
+        while z > 0 {
+            let px_per_unit = geo::world_tiles(z) * 256.0;
+            if w * px_per_unit <= win.0 as f64 && h * px_per_unit <= win.1 as f64 {
+                break;
+            }
+            z -= 1;
+        }
+        let (lat, lon) = geo::unproject((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+        Self::centered_on(lat, lon, z)
+    }
+
+    /// (lat, lon) of the window centre.
+    pub fn center_latlon(&self) -> (f64, f64) {
+        let (x, y) = self.center_plane();
+        self.projection.plane_to_latlon(x, y)
+    }
+
+    /// Window pixel position (origin top-left) of a normalised Web Mercator
+    /// point, on the copy of the world nearest the centre.
+    pub fn world_to_pixel(&self, world: (f64, f64), win: (u32, u32)) -> (f64, f64) {
+        let (x, y) = self.world_to_plane(world);
+        let (cx, cy) = self.center_plane();
+        let px_per_unit = geo::world_tiles(self.z) * 256.0;
+        let dx = match self.projection {
+            Projection::WebMercator => x - cx - (x - cx).round(),
+            _ => x - cx,
+        };
+        (
+            win.0 as f64 / 2.0 + dx * px_per_unit,
+            win.1 as f64 / 2.0 + (y - cy) * px_per_unit,
+        )
+    }
+
+    /// Inverse of `world_to_pixel`. Near the poles of a polar view that is
+    /// clamped to the edge of Mercator; see `pixel_to_latlon`.
+    pub fn pixel_to_world(&self, px: (f64, f64), win: (u32, u32)) -> (f64, f64) {
+        match self.projection {
+            Projection::WebMercator => self.pixel_to_plane(px, win),
+            _ => {
+                let (lat, lon) = self.pixel_to_latlon(px, win);
+                geo::project(lat, lon)
+            }
+        }
+    }
+
+    /// (lat, lon) under window pixel `px`.
+    pub fn pixel_to_latlon(&self, px: (f64, f64), win: (u32, u32)) -> (f64, f64) {
+        let (x, y) = self.pixel_to_plane(px, win);
+        self.projection.plane_to_latlon(x, y)
+    }
+
+    /// Past the antimeridian Web Mercator repeats, so x is brought back
+    /// onto the world there.
+    fn pixel_to_plane(&self, px: (f64, f64), win: (u32, u32)) -> (f64, f64) {
+        let (cx, cy) = self.center_plane();
+        let px_per_unit = geo::world_tiles(self.z) * 256.0;
+        let x = cx + (px.0 - win.0 as f64 / 2.0) / px_per_unit;
+        let y = cy + (px.1 - win.1 as f64 / 2.0) / px_per_unit;
+        match self.projection {
+            Projection::WebMercator => (x.rem_euclid(1.0), y),
+            _ => (x, y),
+        }
+    }
+
+    /// (x, y) of the tile at the current zoom under window pixel `px`.
+    pub fn tile_at_pixel(&self, px: (f64, f64), win: (u32, u32)) -> (u32, u32) {
+        let world = self.pixel_to_world(px, win);
+        let n = geo::world_tiles(self.z);
+        let tile = |v: f64| (v * n).floor().clamp(0.0, n - 1.0) as u32;
+        (tile(world.0), tile(world.1))
+    }
+
     pub fn pan(&mut self, dx: f64, dy: f64) {
         self.center_x += (dx);
         self.center_y += (dy);
+        self.wrap();
+    }
+
+    /// Range of `center_y` keeping a `win` sized window on the world: no
+    /// empty space above or below it, or the whole world in the middle of
+    /// the window once it is the shorter of the two.
+    pub fn y_range(&self, win: (u32, u32)) -> (f64, f64) {
+        let n = geo::world_tiles(self.z);
+        let half = f64::from(win.1) / 2.0 / 256.0;
+        if n <= 2.0 * half {
+            let middle = n / 2.0 - 0.5;
+            (middle, middle)
+        } else {
+            (half - 0.5, n - half - 0.5)
+        }
+    }
+
+    /// Tiles the centre is past `y_range`, negative above the world. Polar
+    /// planes have no top or bottom edge, so never.
+    pub fn overscroll(&self, win: (u32, u32)) -> f64 {
+        if self.projection != Projection::WebMercator {
+            return 0.0;
+        }
+        let (min, max) = self.y_range(win);
+        self.center_y - self.center_y.clamp(min, max)
+    }
+
+    /// Brings the centre back onto the world once it is panned past the
+    /// antimeridian, so the map scrolls round and round. Polar planes don't
+    /// repeat and are left alone.
+    pub fn wrap(&mut self) {
+        if self.projection == Projection::WebMercator {
+            let n = geo::world_tiles(self.z);
+            self.center_x = (self.center_x + 0.5).rem_euclid(n) - 0.5;
+        }
     }
 
     pub fn zoom_in(&mut self) {
@@ -40,6 +215,26 @@ impl Viewport {
         self.pan(dx_tiles, dy_tiles);
     }
 
+    /// Zooms one level in (`zoom_in`) or out, keeping the place under window
+    /// pixel `at` where it is, e.g. between the fingers of a pinch.
+    pub fn zoom_around_pixel(&mut self, win: (u32, u32), at: (f64, f64), zoom_in: bool) {
+        let z = self.z;
+        // tiles from the centre to the pixel, at the old zoom
+        let dx = (at.0 - f64::from(win.0) / 2.0) / 256.0;
+        let dy = (at.1 - f64::from(win.1) / 2.0) / 256.0;
+        if zoom_in {
+            self.zoom_in();
+            if self.z != z {
+                self.pan(dx, dy);
+            }
+        } else {
+            self.zoom_out();
+            if self.z != z {
+                self.pan(-dx / 2.0, -dy / 2.0);
+            }
+        }
+    }
+
     /// Same as `center_on_pixel`, then zoom-in so that the clicked point
     /// stays under the cursor.
     pub fn zoom_in_at_pixel(&mut self, win_w: u32, win_h: u32, px: i32, py: i32) {
@@ -0,0 +1,47 @@
+use once_cell::sync::OnceCell;
+use sdl2::event::{Event, EventSender};
+use sdl2::{EventPump, EventSubsystem};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static SENDER: OnceCell<EventSender> = OnceCell::new();
+/// Set by `wake` until the main loop next sleeps, so a wake-up that comes
+/// before the loop sleeps isn't lost and a busy loop gets at most one event.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+/// The event `wake` pushes; it carries nothing and only ends the wait.
+struct Wake;
+
+/// Lets `wake` push events into the queue of `events`.
+pub fn init(events: &EventSubsystem) -> Result<(), String> {
+    events.register_custom_event::<Wake>()?;
+    let _ = SENDER.set(events.event_sender());
+    Ok(())
+}
+
+/// Called by background threads after handing the main loop something to
+/// show (a tile, a search result), so it draws it without waiting out
+/// `wait`'s timeout.
+pub fn wake() {
+    if !WOKEN.swap(true, Ordering::AcqRel)
+        && let Some(sender) = SENDER.get()
+    {
+        let _ = sender.push_custom_event(Wake);
+    }
+}
+
+pub fn is_wake(event: &Event) -> bool {
+    event.as_user_event_type::<Wake>().is_some()
+}
+
+/// Blocks the main loop while there is nothing to draw: until input comes
+/// in, a background thread calls `wake` or `timeout` passes. Returns the
+/// input event that ended the wait.
+pub fn wait(event_pump: &mut EventPump, timeout: Duration) -> Option<Event> {
+    if WOKEN.swap(false, Ordering::AcqRel) {
+        return None;
+    }
+    let event = event_pump.wait_event_timeout(timeout.as_millis() as u32);
+    WOKEN.store(false, Ordering::Release);
+    event.filter(|event| !is_wake(event))
+}
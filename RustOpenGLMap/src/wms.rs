@@ -0,0 +1,201 @@
+use crate::geo::MERCATOR_EXTENT_M;
+use crate::opengl_helper::USER_AGENT;
+use crate::tile::TilePos;
+use clap::Args;
+use curl::easy::Easy;
+use serde::Deserialize;
+use std::error::Error;
+use std::time::Duration;
+
+/// The `[source.wms]` table: the source's `url` is a WMS 1.3.0 endpoint
+/// and every tile is a GetMap request for its EPSG:3857 bounding box.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WmsParams {
+    /// Comma-separated layer names, as `wms` lists them.
+    pub layers: String,
+    /// Styles for the layers, the server's defaults if empty.
+    #[serde(default)]
+    pub styles: String,
+    #[serde(default = "default_format")]
+    pub format: String,
+    /// Asks for transparent pixels where there is no data, for overlays.
+    #[serde(default = "default_transparent")]
+    pub transparent: bool,
+}
+
+fn default_format() -> String {
+    "image/png".to_string()
+}
+
+fn default_transparent() -> bool {
+    true
+}
+
+impl WmsParams {
+    /// GetMap URL of `tile` on the server at `base`, `size` pixels square.
+    pub fn get_map_url(&self, base: &str, tile: &TilePos, size: u32) -> String {
+        let span = 2.0 * MERCATOR_EXTENT_M / f64::from(1u32 << tile.z);
+        let west = -MERCATOR_EXTENT_M + f64::from(tile.x) * span;
+        let north = MERCATOR_EXTENT_M - f64::from(tile.y) * span;
+        let separator = if base.contains('?') { '&' } else { '?' };
+        format!(
+            "{}{}SERVICE=WMS&VERSION=1.3.0&REQUEST=GetMap&LAYERS={}&STYLES={}&CRS=EPSG:3857\
+             &BBOX={:.3},{:.3},{:.3},{:.3}&WIDTH={}&HEIGHT={}&FORMAT={}&TRANSPARENT={}",
+            base,
+            separator,
+            encode(&self.layers),
+            encode(&self.styles),
+            west,
+            north - span,
+            west + span,
+            north,
+            size,
+            size,
+            encode(&self.format),
+            if self.transparent { "TRUE" } else { "FALSE" }
+        )
+    }
+}
+
+/// Percent-encodes a query parameter value, keeping the commas and colons
+/// WMS lists are written with.
+fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b',' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// A layer the server can draw, as GetCapabilities describes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WmsLayer {
+    pub name: String,
+    pub title: String,
+    /// False if the server can't draw it in Web Mercator.
+    pub mercator: bool,
+}
+
+/// The named layers in a WMS 1.3.0 capabilities document. CRS lists are
+/// inherited from enclosing layers, as the standard has it.
+pub fn parse_capabilities(text: &str) -> Result<Vec<WmsLayer>, Box<dyn Error>> {
+    let doc = roxmltree::Document::parse(text)?;
+    let capability = doc
+        .descendants()
+        .find(|n| n.has_tag_name("Capability"))
+        .ok_or_else(|| "no <Capability> in the document".to_string())?;
+    let mut layers = Vec::new();
+    for layer in capability.children().filter(|n| n.has_tag_name("Layer")) {
+        collect_layers(layer, false, &mut layers);
+    }
+    Ok(layers)
+}
+
+fn collect_layers(node: roxmltree::Node, inherited: bool, out: &mut Vec<WmsLayer>) {
+    let child_text = |tag: &str| {
+        node.children()
+            .find(|n| n.has_tag_name(tag))
+            .and_then(|n| n.text())
+            .map(|text| text.trim().to_string())
+    };
+    let mercator = inherited
+        || node.children().any(|n| {
+            (n.has_tag_name("CRS") || n.has_tag_name("SRS"))
+                && n.text().is_some_and(|crs| {
+                    matches!(crs.trim(), "EPSG:3857" | "EPSG:900913" | "EPSG:102100")
+                })
+        });
+    if let Some(name) = child_text("Name") {
+        out.push(WmsLayer {
+            title: child_text("Title").unwrap_or_default(),
+            name,
+            mercator,
+        });
+    }
+    for child in node.children().filter(|n| n.has_tag_name("Layer")) {
+        collect_layers(child, mercator, out);
+    }
+}
+
+/// Downloads the capabilities document of the server at `url`.
+fn fetch_capabilities(url: &str) -> Result<String, Box<dyn Error>> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let url = format!(
+        "{}{}SERVICE=WMS&VERSION=1.3.0&REQUEST=GetCapabilities",
+        url, separator
+    );
+    let mut easy = Easy::new();
+    let mut body = Vec::new();
+    easy.url(&url)?;
+    easy.useragent(&USER_AGENT)?;
+    easy.follow_location(true)?;
+    easy.timeout(Duration::from_secs(30))?;
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    let code = easy.response_code()?;
+    if code != 200 {
+        return Err(Box::from(format!("server answered HTTP {}", code)));
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// `RustOpenGLMap wms <url>`
+///
+/// Lists the layers a WMS server offers and prints a `[[source]]` to paste
+/// into sources.toml for the first one that works in Web Mercator.
+#[derive(Debug, Args)]
+pub struct WmsArgs {
+    /// Endpoint of the server, without the request parameters.
+    url: String,
+}
+
+pub fn run_wms_command(args: WmsArgs) -> Result<(), String> {
+    let text = fetch_capabilities(&args.url).map_err(|e| e.to_string())?;
+    let layers = parse_capabilities(&text).map_err(|e| e.to_string())?;
+    if layers.is_empty() {
+        return Err("The server lists no named layers".to_string());
+    }
+    let width = layers.iter().map(|l| l.name.len()).max().unwrap_or(0);
+    for layer in &layers {
+        let note = if layer.mercator {
+            ""
+        } else {
+            "  (no EPSG:3857)"
+        };
+        println!(
+            "{:width$}  {}{}",
+            layer.name,
+            layer.title,
+            note,
+            width = width
+        );
+    }
+    if let Some(layer) = layers.iter().find(|l| l.mercator) {
+        println!();
+        println!("[[source]]");
+        println!("id = 10");
+        println!("name = {:?}", layer.title);
+        println!("url = {:?}", args.url);
+        println!("file_prefix = \"Wms{}\"", sanitize(&layer.name));
+        println!();
+        println!("[source.wms]");
+        println!("layers = {:?}", layer.name);
+    }
+    Ok(())
+}
+
+/// Letters and digits of `name`, for a cache file prefix.
+fn sanitize(name: &str) -> String {
+    name.chars().filter(char::is_ascii_alphanumeric).collect()
+}
@@ -0,0 +1,377 @@
+use crate::geo::MERCATOR_EXTENT_M;
+use crate::opengl_helper::USER_AGENT;
+use crate::tile::MAX_ZOOM;
+use crate::wake;
+use curl::easy::Easy;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::info;
+
+/// Metres per pixel the WMTS standard assumes when turning scale
+/// denominators into resolutions.
+const PIXEL_SIZE_M: f64 = 0.00028;
+
+/// A capabilities document, None while it downloads in the background.
+type Document = Option<Result<String, String>>;
+
+/// Capabilities documents by URL, so reloading sources.toml doesn't ask
+/// every server again. Failures are kept until sources.toml changes, or
+/// every reload would start the download over.
+static DOCUMENTS: Lazy<Mutex<HashMap<String, Document>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// Set once the window opens, which mustn't wait for slow servers.
+static IN_BACKGROUND: AtomicBool = AtomicBool::new(false);
+/// Set when a background download finished, until `take_arrived`.
+static ARRIVED: AtomicBool = AtomicBool::new(false);
+
+/// The `[source.wmts]` table: everything but `capabilities` is optional and
+/// picked from the capabilities document.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WmtsParams {
+    /// URL of the server's WMTSCapabilities.xml.
+    pub capabilities: String,
+    /// Layer identifier, the first one with a Web Mercator tile matrix set if unset.
+    #[serde(default)]
+    pub layer: Option<String>,
+    /// Defaults to the layer's default style.
+    #[serde(default)]
+    pub style: Option<String>,
+    /// Tile matrix set identifier, the first Web Mercator one if unset.
+    #[serde(default)]
+    pub matrix_set: Option<String>,
+    /// Image format, the layer's first if unset.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// What a source needs to fetch a WMTS layer like any XYZ source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WmtsSource {
+    pub title: String,
+    /// Layer identifier.
+    pub layer: String,
+    /// URL template with `{z}`, `{x}` and `{y}`.
+    pub url: String,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub tile_size: u32,
+}
+
+/// A tile matrix set that lays its tiles out like slippy map tiles.
+struct MatrixSet {
+    id: String,
+    /// Identifier of the tile matrix for each zoom level it has.
+    matrices: Vec<(u8, String)>,
+    tile_size: u32,
+}
+
+impl MatrixSet {
+    /// Reads a `<TileMatrixSet>`, `None` unless it is Web Mercator with
+    /// the usual power of two grid.
+    fn parse(node: roxmltree::Node) -> Option<Self> {
+        let id = child_text(node, "Identifier")?;
+        let crs = child_text(node, "SupportedCRS")?;
+        if !["3857", "900913", "102100"]
+            .iter()
+            .any(|code| crs.ends_with(&format!(":{}", code)))
+        {
+            return None;
+        }
+        let mut matrices = Vec::new();
+        let mut tile_size = None;
+        for matrix in node.children().filter(|n| n.has_tag_name("TileMatrix")) {
+            let Some((z, size)) = Self::zoom_of(matrix) else {
+                continue;
+            };
+            if *tile_size.get_or_insert(size) != size {
+                return None;
+            }
+            matrices.push((z, child_text(matrix, "Identifier")?));
+        }
+        matrices.sort();
+        Some(Self {
+            id,
+            matrices,
+            tile_size: tile_size?,
+        })
+    }
+
+    /// Zoom level and tile size of a `<TileMatrix>` that covers the world
+    /// from the top-left corner of Web Mercator.
+    fn zoom_of(matrix: roxmltree::Node) -> Option<(u8, u32)> {
+        let number = |tag: &str| child_text(matrix, tag)?.parse::<f64>().ok();
+        let corner: Vec<f64> = child_text(matrix, "TopLeftCorner")?
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        let (width, height) = (number("MatrixWidth")?, number("MatrixHeight")?);
+        let size = number("TileWidth")?;
+        if corner.len() != 2
+            || (corner[0] + MERCATOR_EXTENT_M).abs() > 10.0
+            || (corner[1] - MERCATOR_EXTENT_M).abs() > 10.0
+            || width != height
+            || number("TileHeight")? != size
+        {
+            return None;
+        }
+        let z = width.log2();
+        if z.fract() != 0.0 || !(0.0..=f64::from(MAX_ZOOM)).contains(&z) {
+            return None;
+        }
+        // the resolution has to match the grid too, or tiles would be stretched
+        let resolution = number("ScaleDenominator")? * PIXEL_SIZE_M;
+        let expected = 2.0 * MERCATOR_EXTENT_M / width / size;
+        if (resolution / expected - 1.0).abs() > 0.01 {
+            return None;
+        }
+        Some((z as u8, size as u32))
+    }
+
+    /// What `{TileMatrix}` becomes in the URL template, as long as the
+    /// identifiers are the zoom levels with a common prefix.
+    fn matrix_template(&self) -> Result<String, String> {
+        let prefix = |z: u8, id: &str| id.strip_suffix(&z.to_string()).map(str::to_string);
+        let (z, id) = self
+            .matrices
+            .first()
+            .ok_or_else(|| format!("tile matrix set {} is empty", self.id))?;
+        let first = prefix(*z, id);
+        if first.is_none() || self.matrices.iter().any(|(z, id)| prefix(*z, id) != first) {
+            return Err(format!(
+                "the tile matrices of {} aren't named after their zoom levels",
+                self.id
+            ));
+        }
+        Ok(format!("{}{{z}}", first.unwrap_or_default()))
+    }
+}
+
+/// Text of the first `tag` child of `node`, trimmed.
+fn child_text(node: roxmltree::Node, tag: &str) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name(tag))
+        .and_then(|n| n.text())
+        .map(|text| text.trim().to_string())
+}
+
+/// Picks a layer, style, format and Web Mercator tile matrix set from a
+/// WMTS 1.0.0 capabilities document, as asked for in `params`.
+pub fn resolve(text: &str, params: &WmtsParams) -> Result<WmtsSource, Box<dyn Error>> {
+    let doc = roxmltree::Document::parse(text)?;
+    let contents = doc
+        .descendants()
+        .find(|n| n.has_tag_name("Contents"))
+        .ok_or_else(|| "no <Contents> in the document".to_string())?;
+    let sets: Vec<MatrixSet> = contents
+        .children()
+        .filter(|n| n.has_tag_name("TileMatrixSet"))
+        .filter_map(MatrixSet::parse)
+        .filter(|set| !set.matrices.is_empty())
+        .collect();
+    let linked_set = |layer: roxmltree::Node| {
+        layer
+            .children()
+            .filter(|n| n.has_tag_name("TileMatrixSetLink"))
+            .filter_map(|link| child_text(link, "TileMatrixSet"))
+            .filter(|id| params.matrix_set.as_ref().is_none_or(|wanted| wanted == id))
+            .find_map(|id| sets.iter().find(|set| set.id == id))
+    };
+    let mut layers = contents.children().filter(|n| n.has_tag_name("Layer"));
+    let (layer, set) = match &params.layer {
+        Some(wanted) => {
+            let layer = layers
+                .find(|layer| child_text(*layer, "Identifier").as_ref() == Some(wanted))
+                .ok_or_else(|| format!("no layer {}", wanted))?;
+            let set = linked_set(layer)
+                .ok_or_else(|| format!("layer {} has no Web Mercator tile matrix set", wanted))?;
+            (layer, set)
+        }
+        None => layers
+            .find_map(|layer| Some((layer, linked_set(layer)?)))
+            .ok_or_else(|| "no layer has a Web Mercator tile matrix set".to_string())?,
+    };
+    let id = child_text(layer, "Identifier").unwrap_or_default();
+
+    let styles: Vec<roxmltree::Node> = layer
+        .children()
+        .filter(|n| n.has_tag_name("Style"))
+        .collect();
+    let style = match &params.style {
+        Some(style) => style.clone(),
+        None => styles
+            .iter()
+            .find(|style| style.attribute("isDefault") == Some("true"))
+            .or(styles.first())
+            .and_then(|style| child_text(*style, "Identifier"))
+            .unwrap_or_else(|| "default".to_string()),
+    };
+    let format = match &params.format {
+        Some(format) => format.clone(),
+        None => child_text(layer, "Format").unwrap_or_else(|| "image/png".to_string()),
+    };
+    let matrix = set.matrix_template()?;
+
+    let resource = layer
+        .children()
+        .filter(|n| n.has_tag_name("ResourceURL") && n.attribute("resourceType") == Some("tile"))
+        .max_by_key(|n| n.attribute("format") == Some(format.as_str()))
+        .and_then(|n| n.attribute("template"));
+    let mut url = match resource {
+        Some(template) => template
+            .replace("{TileMatrixSet}", &set.id)
+            .replace("{TileMatrix}", &matrix)
+            .replace("{TileRow}", "{y}")
+            .replace("{TileCol}", "{x}")
+            .replace("{Style}", &style),
+        None => {
+            // servers without RESTful templates take key-value GetTile requests
+            let base = get_tile_href(&doc)
+                .ok_or_else(|| format!("layer {} has no tile URL template", id))?;
+            let separator = if base.contains('?') { '&' } else { '?' };
+            format!(
+                "{}{}SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0&LAYER={}&STYLE={}\
+                 &TILEMATRIXSET={}&TILEMATRIX={}&TILEROW={{y}}&TILECOL={{x}}&FORMAT={}",
+                base, separator, id, style, set.id, matrix, format
+            )
+        }
+    };
+    // dimensions such as {Time} take their default
+    for dimension in layer.children().filter(|n| n.has_tag_name("Dimension")) {
+        if let (Some(name), Some(value)) = (
+            child_text(dimension, "Identifier"),
+            child_text(dimension, "Default"),
+        ) {
+            url = url.replace(&format!("{{{}}}", name), &value);
+        }
+    }
+
+    Ok(WmtsSource {
+        title: child_text(layer, "Title").unwrap_or_else(|| id.clone()),
+        layer: id,
+        url,
+        min_zoom: set.matrices.first().map_or(0, |(z, _)| *z),
+        max_zoom: set.matrices.last().map_or(0, |(z, _)| *z),
+        tile_size: set.tile_size,
+    })
+}
+
+/// The GetTile endpoint in `<ows:OperationsMetadata>`.
+fn get_tile_href(doc: &roxmltree::Document) -> Option<String> {
+    doc.descendants()
+        .find(|n| n.has_tag_name("Operation") && n.attribute("name") == Some("GetTile"))?
+        .descendants()
+        .find(|n| n.has_tag_name("Get"))?
+        .attributes()
+        .find(|a| a.name() == "href")
+        .map(|a| a.value().to_string())
+}
+
+/// Downloads capabilities documents on a thread of their own from now on.
+/// Sources waiting for theirs are left out until it arrives, see
+/// `take_arrived`. Command line tools keep waiting for them instead.
+pub fn fetch_in_background() {
+    IN_BACKGROUND.store(true, Ordering::Relaxed);
+}
+
+/// Whether a background download finished since the last call, so the
+/// sources should be loaded again to add the ones waiting for it.
+pub fn take_arrived() -> bool {
+    ARRIVED.swap(false, Ordering::Relaxed)
+}
+
+/// Forgets the documents that failed to download, to try them again.
+pub fn forget_failures() {
+    DOCUMENTS
+        .lock()
+        .unwrap()
+        .retain(|_, document| !matches!(document, Some(Err(_))));
+}
+
+/// The capabilities document at `url`, downloaded once per run. None while
+/// it is still downloading in the background.
+fn capabilities(url: &str) -> Result<Option<String>, String> {
+    let mut documents = DOCUMENTS.lock().unwrap();
+    match documents.get(url) {
+        Some(Some(document)) => return document.clone().map(Some),
+        Some(None) => return Ok(None),
+        None => {}
+    }
+    if !IN_BACKGROUND.load(Ordering::Relaxed) {
+        drop(documents);
+        let text = download(url).map_err(|e| e.to_string())?;
+        DOCUMENTS
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), Some(Ok(text.clone())));
+        return Ok(Some(text));
+    }
+    info!("Fetching the WMTS capabilities at {}", url);
+    documents.insert(url.to_string(), None);
+    let url = url.to_string();
+    thread::spawn(move || {
+        let document = download(&url).map_err(|e| e.to_string());
+        DOCUMENTS.lock().unwrap().insert(url, Some(document));
+        ARRIVED.store(true, Ordering::Relaxed);
+        wake::wake();
+    });
+    Ok(None)
+}
+
+fn download(url: &str) -> Result<String, Box<dyn Error>> {
+    let mut easy = Easy::new();
+    let mut body = Vec::new();
+    easy.url(url)?;
+    easy.useragent(&USER_AGENT)?;
+    easy.follow_location(true)?;
+    easy.timeout(Duration::from_secs(15))?;
+    {
+        let mut transfer = easy.transfer();
+        transfer.write_function(|data| {
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()?;
+    }
+    let code = easy.response_code()?;
+    if code != 200 {
+        return Err(Box::from(format!("server answered HTTP {}", code)));
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// Fills a `[[source]]` table that has a `wmts` table with the URL
+/// template, zoom range and tile size its server describes, and a name
+/// and file prefix if it has none. Keys given in `table` win. Tables
+/// without one pass through. None while the capabilities are still
+/// downloading in the background.
+pub fn expand(mut table: toml::Table) -> Result<Option<toml::Table>, String> {
+    let Some(wmts) = table.remove("wmts") else {
+        return Ok(Some(table));
+    };
+    let params: WmtsParams = wmts.try_into().map_err(|e| format!("wmts: {}", e))?;
+    let Some(text) = capabilities(&params.capabilities)
+        .map_err(|e| format!("{}: {}", params.capabilities, e))?
+    else {
+        return Ok(None);
+    };
+    let source = resolve(&text, &params).map_err(|e| format!("{}: {}", params.capabilities, e))?;
+    let prefix: String = source
+        .layer
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect();
+    let mut defaults = toml::Table::new();
+    defaults.insert("name".to_string(), source.title.into());
+    defaults.insert("url".to_string(), source.url.into());
+    defaults.insert("file_prefix".to_string(), format!("Wmts{}", prefix).into());
+    defaults.insert("min_zoom".to_string(), i64::from(source.min_zoom).into());
+    defaults.insert("max_zoom".to_string(), i64::from(source.max_zoom).into());
+    defaults.insert("tile_size".to_string(), i64::from(source.tile_size).into());
+    defaults.extend(table);
+    Ok(Some(defaults))
+}
@@ -0,0 +1,324 @@
+use crate::crash_report;
+use crate::error::MapError;
+use crate::opengl_helper;
+use crate::rate_limit;
+use crate::retry::{self, RetryPolicy, RetryQueue};
+use crate::tile::{TileLoad, TilePos};
+use crate::tile_requests::TileRequests;
+use crate::tile_source::SOURCES;
+use crate::wake;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, debug_span, info, warn};
+
+/// Most downloads waiting at once; the oldest went out of view long ago
+/// and drawing asks for them again if not.
+const MAX_QUEUED_DOWNLOADS: usize = 64;
+/// Longest an idle worker sleeps before looking at retries, pauses and
+/// held sources again.
+const IDLE_WAIT: Duration = Duration::from_millis(50);
+
+/// The `[workers]` table of settings.toml.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkerSettings {
+    /// Threads reading the disk cache and downloading, 0 for one per CPU core.
+    pub threads: usize,
+    /// Most downloads running at once, to stay polite to tile servers.
+    pub downloads: usize,
+}
+
+impl Default for WorkerSettings {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            downloads: 2,
+        }
+    }
+}
+
+impl WorkerSettings {
+    /// Worker threads to start, never fewer than two so a slow download
+    /// can't hold up the disk cache.
+    pub fn thread_count(&self) -> usize {
+        let threads = if self.threads == 0 {
+            thread::available_parallelism().map_or(4, |n| n.get())
+        } else {
+            self.threads
+        };
+        threads.max(2)
+    }
+
+    /// Downloads allowed at once, leaving at least one thread for the disk.
+    pub fn download_count(&self) -> usize {
+        self.downloads.clamp(1, self.thread_count() - 1)
+    }
+}
+
+enum Job {
+    /// Read the tile from the disk cache, or find a placeholder.
+    Load(TilePos),
+    /// Download the tile, after this many failed attempts.
+    Fetch(TilePos, u32),
+}
+
+struct Queue {
+    /// Disk reads, oldest first; always taken before downloads as they are quick.
+    loads: VecDeque<TilePos>,
+    /// Downloads, taken newest first as the view has moved on from the older ones.
+    downloads: VecDeque<TilePos>,
+    retries: RetryQueue,
+    /// Downloads running now.
+    fetching: usize,
+    /// The requests are gone; workers stop once the loads are done.
+    closed: bool,
+    /// No new jobs are started, e.g. while the window is minimized; the
+    /// running ones finish.
+    suspended: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+    max_downloads: usize,
+    requests: TileRequests,
+    res_tx: Sender<TileLoad>,
+    missing_tx: Sender<TilePos>,
+}
+
+/// Threads that read tiles from the disk cache and download the ones it
+/// doesn't have. They share one queue, so whichever thread is idle takes
+/// the most urgent job: disk reads first, then due retries, then the newest
+/// downloads, with at most `downloads` of those running at once.
+pub struct WorkerPool {
+    shared: Arc<Shared>,
+}
+
+impl WorkerPool {
+    /// Starts the workers on the tiles `requests` sends to `job_rx`. Tiles
+    /// go to `res_tx`, tiles given up on to `missing_tx`.
+    pub fn spawn(
+        settings: &WorkerSettings,
+        requests: TileRequests,
+        job_rx: Receiver<TilePos>,
+        res_tx: Sender<TileLoad>,
+        missing_tx: Sender<TilePos>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                loads: VecDeque::new(),
+                downloads: VecDeque::new(),
+                retries: RetryQueue::new(RetryPolicy::default()),
+                fetching: 0,
+                closed: false,
+                suspended: false,
+            }),
+            ready: Condvar::new(),
+            max_downloads: settings.download_count(),
+            requests,
+            res_tx,
+            missing_tx,
+        });
+        let threads = settings.thread_count();
+        info!(
+            "{} tile workers, up to {} downloading",
+            threads, shared.max_downloads
+        );
+        for _ in 0..threads {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                while let Some(job) = shared.next_job() {
+                    match job {
+                        Job::Load(tile) => shared.load(tile),
+                        Job::Fetch(tile, attempts) => shared.fetch(tile, attempts),
+                    }
+                }
+            });
+        }
+        {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                while let Ok(tile) = job_rx.recv() {
+                    shared.queue.lock().unwrap().loads.push_back(tile);
+                    shared.ready.notify_one();
+                }
+                shared.queue.lock().unwrap().closed = true;
+                shared.ready.notify_all();
+            });
+        }
+        Self { shared }
+    }
+
+    /// Downloads `tile` without looking in the disk cache first, e.g. to
+    /// refresh it.
+    pub fn download(&self, tile: TilePos) {
+        self.shared.queue_download(tile);
+    }
+
+    /// Holds the queue while `suspended`, letting the jobs already running
+    /// finish, then picks up where it left off.
+    pub fn suspend(&self, suspended: bool) {
+        self.shared.queue.lock().unwrap().suspended = suspended;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl Shared {
+    /// Waits for the next job a worker may take, none once the pool is closed.
+    fn next_job(&self) -> Option<Job> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            for dropped in queue.retries.take_dropped() {
+                self.requests.done(&dropped);
+            }
+            if !queue.suspended
+                && let Some(tile) = queue.loads.pop_front()
+            {
+                return Some(Job::Load(tile));
+            }
+            if queue.closed {
+                return None;
+            }
+            // paused: the queue is kept for later
+            if !queue.suspended
+                && queue.fetching < self.max_downloads
+                && !opengl_helper::downloads_paused()
+                && let Some(job) = self.take_download(&mut queue)
+            {
+                queue.fetching += 1;
+                return Some(job);
+            }
+            opengl_helper::set_pending_downloads(
+                queue.downloads.len() + queue.retries.waiting() + queue.fetching,
+            );
+            queue = self.ready.wait_timeout(queue, IDLE_WAIT).unwrap().0;
+        }
+    }
+
+    /// The next download that may go out now; ones whose source or the
+    /// network is held are put back to wait.
+    fn take_download(&self, queue: &mut Queue) -> Option<Job> {
+        while let Some((tile, attempts)) = queue
+            .retries
+            .pop_due()
+            .or_else(|| queue.downloads.pop_back().map(|tile| (tile, 0)))
+        {
+            let limit = SOURCES
+                .read()
+                .unwrap()
+                .get(tile.m)
+                .and_then(|source| source.rate_limit);
+            // offline: hold everything but the occasional probe
+            let held = retry::source_blocked_for(tile.m)
+                .or_else(|| rate_limit::source_throttled_for(tile.m, limit))
+                .or_else(retry::network_blocked_for);
+            match held {
+                Some(wait) => queue.retries.defer(tile, attempts, wait),
+                None => return Some(Job::Fetch(tile, attempts)),
+            }
+        }
+        None
+    }
+
+    fn queue_download(&self, tile: TilePos) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.downloads.push_back(tile);
+        if queue.downloads.len() > MAX_QUEUED_DOWNLOADS
+            && let Some(dropped) = queue.downloads.pop_front()
+        {
+            self.requests.done(&dropped);
+        }
+        self.ready.notify_one();
+    }
+
+    fn load(&self, tile: TilePos) {
+        let _span = debug_span!("load", z = tile.z, x = tile.x, y = tile.y, m = tile.m).entered();
+        // perform blocking I/O off the main thread
+        let tile_load = opengl_helper::fetch_tile(tile).unwrap_or_else(|e| {
+            warn!("Failed to load the tile: {}", e);
+            TileLoad::Failed
+        });
+        match tile_load {
+            TileLoad::Loaded {
+                texture,
+                source_tile,
+            } => {
+                let _ = self.res_tx.send(TileLoad::Loaded {
+                    texture,
+                    source_tile,
+                });
+                wake::wake();
+                // show the cached copy now, refresh it in the background
+                if opengl_helper::tile_is_stale(&tile) {
+                    crash_report::log_event(format!("revalidate {:?}", tile));
+                    self.queue_download(tile);
+                }
+            }
+            TileLoad::Loading {
+                texture,
+                source_tile,
+                target_tile,
+            } => {
+                let _ = self.res_tx.send(TileLoad::Loading {
+                    texture,
+                    source_tile,
+                    target_tile,
+                });
+                wake::wake();
+                self.queue_download(target_tile);
+            }
+            TileLoad::Failed => {
+                // nothing on disk, queue it for download
+                crash_report::log_event(format!("queue download {:?}", tile));
+                self.queue_download(tile);
+            }
+        }
+    }
+
+    fn fetch(&self, tile: TilePos, attempts: u32) {
+        let _span = debug_span!(
+            "fetch",
+            z = tile.z,
+            x = tile.x,
+            y = tile.y,
+            m = tile.m,
+            attempt = attempts + 1
+        )
+        .entered();
+        let tile_load = opengl_helper::fetch_tile_from_server(&tile);
+        retry::record_result(tile.m, &tile_load);
+        let mut queue = self.queue.lock().unwrap();
+        queue.fetching -= 1;
+        match tile_load {
+            Ok(load) => {
+                crash_report::log_event(format!("downloaded {:?}", tile));
+                let _ = self.res_tx.send(load);
+                wake::wake();
+                debug!("Loaded from the web");
+            }
+            Err(e) => {
+                crash_report::log_event(format!(
+                    "download failed {:?} (attempt {}): {}",
+                    tile,
+                    attempts + 1,
+                    e
+                ));
+                if !queue.retries.retry(tile, attempts + 1, &e) {
+                    warn!("Giving up on the tile: {}", e);
+                    if let MapError::Source(_) = e {
+                        // not a broken tile, the source has none there
+                        self.requests.done(&tile);
+                    } else {
+                        let _ = self.missing_tx.send(tile);
+                        wake::wake();
+                    }
+                }
+            }
+        }
+        self.ready.notify_one();
+    }
+}